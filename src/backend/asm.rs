@@ -1,15 +1,66 @@
+use crate::backend::bare_metal;
+use crate::backend::compress;
 use crate::backend::instruction::Instruction;
+use crate::backend::regalloc::AllocationReport;
+use crate::backend::stack_map::{FrameLayout, StackMap};
 use std::io::Write;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct AsmProgram {
     pub(crate) sections: Vec<AsmSection>,
+    // Begins with the bare-metal startup code
+    pub(crate) startup: bool,
+    pub(crate) allocation_reports: Vec<AllocationReport>,
 }
 
-#[derive(Debug)]
+impl AsmProgram {
+    pub fn stack_map(&self) -> StackMap {
+        let frames = self.sections.iter()
+            .flat_map(|section| section.content.iter())
+            .filter_map(|global| match global {
+                AsmGlobal::AsmFunction(func) => Some(func.frame_layout.clone()),
+                AsmGlobal::AsmVariable(_) => None,
+            })
+            .collect();
+        StackMap { frames }
+    }
+
+    // In the order of the functions, none without `BackendOptions::report_allocation`
+    pub fn allocation_reports(&self) -> &[AllocationReport] {
+        &self.allocation_reports
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AsmSectionType {
     Text,
     Data,
+    // Zero-initialized globals, taking no room in the object
+    Bss,
+    // Literal pools of the functions
+    Rodata,
+}
+
+impl AsmSectionType {
+    pub const ALL: [AsmSectionType; 4] = [AsmSectionType::Text, AsmSectionType::Data, AsmSectionType::Bss, AsmSectionType::Rodata];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AsmSectionType::Text => "text",
+            AsmSectionType::Data => "data",
+            AsmSectionType::Bss => "bss",
+            AsmSectionType::Rodata => "rodata",
+        }
+    }
+}
+
+impl std::str::FromStr for AsmSectionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AsmSectionType::ALL.iter().copied().find(|section| section.name() == s)
+            .ok_or_else(|| format!("unknown section `{}`, expected one of: text, data, bss, rodata", s))
+    }
 }
 
 #[derive(Debug)]
@@ -38,12 +89,25 @@ impl AsmGlobal {
 pub struct AsmVariable {
     pub(crate) label: String,
     pub(crate) init: AsmVariableInit,
+    // Literal pools are private to the object
+    pub(crate) is_global: bool,
 }
 
 #[derive(Debug)]
 pub enum AsmVariableInit {
     Word(i32),
+    Words(Vec<i32>),
     Zero(usize),
+    // The words of an array, its zeros in runs, e.g. `{1, 2}` in an `int[100]`
+    Aggregate(Vec<AsmVariableInit>),
+    // A string literal, padded with zeros after its NUL up to a whole word
+    Asciz(Vec<u8>),
+}
+
+impl AsmVariableInit {
+    pub(crate) fn asciz_padding(bytes: &[u8]) -> usize {
+        (bytes.len() + 1).next_multiple_of(4) - bytes.len() - 1
+    }
 }
 
 #[derive(Debug)]
@@ -52,6 +116,9 @@ pub struct AsmFunction {
     pub(crate) basic_blocks: Vec<AsmBasicBlock>,
     pub(crate) prologue: Vec<Instruction>,
     pub(crate) epilogue: Vec<Instruction>,
+    pub(crate) frame_layout: FrameLayout,
+    // Written with the compressed instructions where they fit, see `compress`
+    pub(crate) compressed: bool,
 }
 
 #[derive(Debug)]
@@ -69,6 +136,17 @@ impl AsmFunction {
             basic_blocks: Vec::new(),
             prologue: Vec::new(),
             epilogue: Vec::new(),
+            frame_layout: FrameLayout::default(),
+            compressed: false,
+        }
+    }
+}
+
+impl AsmFunction {
+    fn emit_instruction(&self, out: &mut impl Write, inst: &Instruction) -> std::io::Result<()> {
+        match self.compressed.then(|| compress::compressed(inst)).flatten() {
+            Some(compressed) => writeln!(out, "    {}", compressed),
+            None => writeln!(out, "    {}", inst),
         }
     }
 }
@@ -95,10 +173,14 @@ pub trait AsmEmitter {
 impl AsmEmitter for AsmProgram {
     // write to an output stream
     fn emit(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        if self.startup {
+            writeln!(out, "{}", bare_metal::STARTUP)?;
+        }
         for section in &self.sections {
-            // Write the section
-
-            section.emit(out)?;
+            // Write the section, an empty one would only be a header
+            if !section.content.is_empty() {
+                section.emit(out)?;
+            }
         }
         Ok(())
     }
@@ -114,11 +196,19 @@ impl AsmEmitter for AsmSection {
             AsmSectionType::Data => {
                 writeln!(out, "   .data")?;
             }
+            AsmSectionType::Bss => {
+                writeln!(out, "   .bss")?;
+            }
+            AsmSectionType::Rodata => {
+                writeln!(out, "   .section .rodata")?;
+            }
         }
 
         // Globals
         for global in &self.content {
-            writeln!(out, "   .globl {}", global.label())?;
+            if !matches!(global, AsmGlobal::AsmVariable(AsmVariable { is_global: false, .. })) {
+                writeln!(out, "   .globl {}", global.label())?;
+            }
         }
 
         // Body
@@ -126,25 +216,54 @@ impl AsmEmitter for AsmSection {
             global.emit(out)?;
         }
 
-        writeln!(out, "")?;
+        writeln!(out)?;
 
         Ok(())
     }
 }
 
+impl AsmEmitter for AsmVariableInit {
+    fn emit(&self, out: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            AsmVariableInit::Word(value) => {
+                writeln!(out, "   .word {}", value)?;
+            }
+            AsmVariableInit::Words(values) => {
+                for value in values {
+                    writeln!(out, "   .word {}", value)?;
+                }
+            }
+            AsmVariableInit::Zero(size) => {
+                writeln!(out, "   .zero {}", size)?;
+            }
+            AsmVariableInit::Aggregate(pieces) => {
+                for piece in pieces {
+                    piece.emit(out)?;
+                }
+            }
+            AsmVariableInit::Asciz(bytes) => {
+                let escaped: String = bytes.iter().map(|&byte| match byte {
+                    b'"' | b'\\' => format!("\\{}", byte as char),
+                    b' '..=b'~' => (byte as char).to_string(),
+                    _ => format!("\\{:03o}", byte),
+                }).collect();
+                writeln!(out, "   .asciz \"{}\"", escaped)?;
+                let padding = AsmVariableInit::asciz_padding(bytes);
+                if padding > 0 {
+                    writeln!(out, "   .zero {}", padding)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl AsmEmitter for AsmGlobal {
     fn emit(&self, out: &mut impl Write) -> std::io::Result<()> {
         match self {
             AsmGlobal::AsmVariable(var) => {
                 writeln!(out, "{}:", var.label)?;
-                match &var.init {
-                    AsmVariableInit::Word(value) => {
-                        writeln!(out, "   .word {}", value)?;
-                    }
-                    AsmVariableInit::Zero(size) => {
-                        writeln!(out, "   .zero {}", size)?;
-                    }
-                }
+                var.init.emit(out)?;
             }
             AsmGlobal::AsmFunction(func) => {
                 for bb in func.basic_blocks.iter() {
@@ -155,19 +274,19 @@ impl AsmEmitter for AsmGlobal {
                     if bb.is_entry {
                         writeln!(out, "    # --- Prologue of {} ---", func.label)?;
                         for inst in &func.prologue {
-                            writeln!(out, "    {}", inst)?;
+                            func.emit_instruction(out, inst)?;
                         }
                         writeln!(out, "    # --- Prologue of {} ---", func.label)?;
                     }
 
                     for inst in &bb.instructions {
-                        writeln!(out, "    {}", inst)?;
+                        func.emit_instruction(out, inst)?;
                     }
 
                     if bb.is_exit {
                         writeln!(out, "    # --- Epilogue of {} ---", func.label)?;
                         for inst in &func.epilogue {
-                            writeln!(out, "    {}", inst)?;
+                            func.emit_instruction(out, inst)?;
                         }
                         writeln!(out, "    # --- Epilogue of {} ---", func.label)?;
                     }
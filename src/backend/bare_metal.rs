@@ -0,0 +1,154 @@
+use std::fmt::Write;
+
+// Where the program lives on a microcontroller without an operating system: code, constants
+// and the initial values of the globals in flash, the globals and the stack in RAM.
+// The program starts at `_start`, see `STARTUP`, and the linker script places it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub flash: Region,
+    pub ram: Region,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub origin: u32,
+    pub length: u32,
+}
+
+// For regions given without a length
+const DEFAULT_FLASH_LENGTH: u32 = 256 * 1024;
+const DEFAULT_RAM_LENGTH: u32 = 64 * 1024;
+
+// Sets up the stack and the globals, then calls `main`. Its return value is left in `a0`
+// for the simulator or debugger stopped by the `ebreak`.
+pub const STARTUP: &str = "   .section .text.start, \"ax\"
+   .globl _start
+_start:
+    la sp, __stack_top
+    andi sp, sp, -16
+    la t0, __data_load
+    la t1, __data_start
+    la t2, __data_end
+.Lcopy_data:
+    bgeu t1, t2, .Lclear_bss
+    lw t3, 0(t0)
+    sw t3, 0(t1)
+    addi t0, t0, 4
+    addi t1, t1, 4
+    j .Lcopy_data
+.Lclear_bss:
+    la t1, __bss_start
+    la t2, __bss_end
+.Lclear_word:
+    bgeu t1, t2, .Lcall_main
+    sw zero, 0(t1)
+    addi t1, t1, 4
+    j .Lclear_word
+.Lcall_main:
+    call main
+.Lhalt:
+    ebreak
+    j .Lhalt
+";
+
+impl MemoryLayout {
+    // For the GNU linker and LLD, providing the symbols `STARTUP` uses
+    pub fn linker_script(&self) -> String {
+        let mut script = String::new();
+        writeln!(script, "OUTPUT_ARCH(riscv)").unwrap();
+        writeln!(script, "ENTRY(_start)").unwrap();
+        writeln!(script).unwrap();
+        writeln!(script, "MEMORY").unwrap();
+        writeln!(script, "{{").unwrap();
+        writeln!(script, "  FLASH (rx) : ORIGIN = {:#x}, LENGTH = {:#x}", self.flash.origin, self.flash.length).unwrap();
+        writeln!(script, "  RAM (rwx) : ORIGIN = {:#x}, LENGTH = {:#x}", self.ram.origin, self.ram.length).unwrap();
+        writeln!(script, "}}").unwrap();
+        script.push_str("
+SECTIONS
+{
+  .text : {
+    KEEP(*(.text.start))
+    *(.text .text.*)
+  } > FLASH
+  .rodata : {
+    *(.rodata .rodata.* .srodata .srodata.*)
+  } > FLASH
+  .data : ALIGN(4) {
+    __data_start = .;
+    *(.data .data.* .sdata .sdata.*)
+    . = ALIGN(4);
+    __data_end = .;
+  } > RAM AT > FLASH
+  __data_load = LOADADDR(.data);
+  .bss (NOLOAD) : ALIGN(4) {
+    __bss_start = .;
+    *(.bss .bss.* .sbss .sbss.* COMMON)
+    . = ALIGN(4);
+    __bss_end = .;
+  } > RAM
+  __stack_top = ORIGIN(RAM) + LENGTH(RAM);
+}
+");
+        script
+    }
+}
+
+// `flash:<origin>[+<length>],ram:<origin>[+<length>]`, numbers in decimal or with `0x`,
+// lengths possibly with a `K` or `M` suffix
+impl std::str::FromStr for MemoryLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut flash, mut ram) = (None, None);
+        for item in s.split(',') {
+            let (name, region) = item.split_once(':')
+                .ok_or_else(|| format!("expected `<region>:<origin>` in the memory layout, found `{}`", item))?;
+            let (slot, default_length) = match name {
+                "flash" => (&mut flash, DEFAULT_FLASH_LENGTH),
+                "ram" => (&mut ram, DEFAULT_RAM_LENGTH),
+                _ => return Err(format!("unknown memory region `{}`, expected flash or ram", name)),
+            };
+            if slot.is_some() {
+                return Err(format!("memory region `{}` is given more than once", name));
+            }
+            let (origin, length) = match region.split_once('+') {
+                Some((origin, length)) => (number(origin)?, size(length)?),
+                None => (number(region)?, default_length),
+            };
+            if length == 0 {
+                return Err(format!("memory region `{}` is empty", name));
+            }
+            if origin.checked_add(length - 1).is_none() {
+                return Err(format!("memory region `{}` does not fit in the address space", name));
+            }
+            *slot = Some(Region { origin, length });
+        }
+        let (Some(flash), Some(ram)) = (flash, ram) else {
+            return Err("the memory layout needs both a flash and a ram region".into());
+        };
+        let overlaps = flash.origin <= ram.origin + (ram.length - 1) && ram.origin <= flash.origin + (flash.length - 1);
+        if overlaps {
+            return Err("the flash and ram regions overlap".into());
+        }
+        Ok(MemoryLayout { flash, ram })
+    }
+}
+
+fn number(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("expected an address in the memory layout, found `{}`", s))
+}
+
+fn size(s: &str) -> Result<u32, String> {
+    let (digits, unit) = match s.strip_suffix(['K', 'k']) {
+        Some(digits) => (digits, 1024),
+        None => match s.strip_suffix(['M', 'm']) {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (s, 1),
+        },
+    };
+    number(digits)?.checked_mul(unit).ok_or_else(|| format!("region length `{}` does not fit in the address space", s))
+}
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
-use koopa::ir::{Function, ValueKind};
+use std::fmt::Write;
+use koopa::ir::{Function, Program, ValueKind};
 
 #[derive(Clone, Debug)]
 pub struct CallGraphBody {
@@ -36,15 +37,95 @@ impl CallGraph {
     }
 
     pub fn add_call(&mut self, caller: Function, callee: Function, num_args: usize) {
-        if !self.graph.contains_key(&caller) {
-            self.graph.insert(caller, CallGraphBody {
-                callee: HashSet::new(),
-                max_args: 0,
-            });
-        }
+        self.graph.entry(caller).or_insert_with(|| CallGraphBody {
+            callee: HashSet::new(),
+            max_args: 0,
+        });
 
         let body = self.graph.get_mut(&caller).unwrap();
         body.callee.insert(callee);
         body.max_args = body.max_args.max(num_args);
     }
-}
\ No newline at end of file
+    // The graph for Graphviz, for `--dump-callgraph`: every function calling or called, with
+    // the most arguments it passes, and the declarations dashed. The functions that may call
+    // themselves, directly or through others, and the calls that may recurse are red.
+    pub fn to_dot(&self, program: &Program) -> String {
+        let funcs: Vec<Function> = program.func_layout().iter().copied()
+            .filter(|func| self.graph.contains_key(func) || self.graph.values().any(|body| body.callee.contains(func)))
+            .collect();
+        let cycles = self.recursion_cycles(&funcs);
+        let cycle_of = |func: &Function| cycles.iter().position(|cycle| cycle.contains(func));
+
+        let mut dot = String::from("digraph \"call graph\" {\n  node [shape=box, fontname=\"monospace\"];\n");
+        for (index, func) in funcs.iter().enumerate() {
+            let func_data = program.func(*func);
+            let mut label = func_data.name()[1..].to_string();
+            if let Some(body) = self.graph.get(func) {
+                write!(label, "\\nmax args: {}", body.max_args).unwrap();
+            }
+            let mut attributes = String::new();
+            if func_data.layout().entry_bb().is_none() {
+                attributes.push_str(", style=dashed");
+            }
+            if cycle_of(func).is_some() {
+                attributes.push_str(", color=red");
+            }
+            writeln!(dot, "  f{} [label=\"{}\"{}];", index, label, attributes).unwrap();
+        }
+        for (index, func) in funcs.iter().enumerate() {
+            let Some(body) = self.graph.get(func) else { continue };
+            // In layout order, the callees being a set
+            for (callee_index, callee) in funcs.iter().enumerate().filter(|(_, callee)| body.callee.contains(callee)) {
+                let recursive = cycle_of(func).is_some() && cycle_of(func) == cycle_of(callee);
+                writeln!(dot, "  f{} -> f{}{};", index, callee_index, if recursive { " [color=red]" } else { "" }).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // The strongly connected components of more than one function, or of one calling itself,
+    // by Tarjan's algorithm
+    fn recursion_cycles(&self, funcs: &[Function]) -> Vec<Vec<Function>> {
+        struct Search<'a> {
+            graph: &'a CallGraph,
+            index: HashMap<Function, usize>,
+            lowlink: HashMap<Function, usize>,
+            stack: Vec<Function>,
+            cycles: Vec<Vec<Function>>,
+        }
+
+        impl Search<'_> {
+            fn visit(&mut self, func: Function) {
+                let index = self.index.len();
+                self.index.insert(func, index);
+                self.lowlink.insert(func, index);
+                self.stack.push(func);
+                let callees: Vec<Function> = self.graph.graph.get(&func).map(|body| body.callee.iter().copied().collect()).unwrap_or_default();
+                for &callee in callees.iter() {
+                    if !self.index.contains_key(&callee) {
+                        self.visit(callee);
+                        self.lowlink.insert(func, self.lowlink[&func].min(self.lowlink[&callee]));
+                    } else if self.stack.contains(&callee) {
+                        self.lowlink.insert(func, self.lowlink[&func].min(self.index[&callee]));
+                    }
+                }
+                if self.lowlink[&func] == index {
+                    let start = self.stack.iter().position(|&other| other == func).unwrap();
+                    let component = self.stack.split_off(start);
+                    if component.len() > 1 || callees.contains(&func) {
+                        self.cycles.push(component);
+                    }
+                }
+            }
+        }
+
+        let mut search = Search { graph: self, index: HashMap::new(), lowlink: HashMap::new(), stack: Vec::new(), cycles: Vec::new() };
+        for &func in funcs {
+            if !search.index.contains_key(&func) {
+                search.visit(func);
+            }
+        }
+        search.cycles
+    }
+}
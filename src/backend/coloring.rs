@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::{FunctionData, Value, ValueKind};
+use crate::backend::regalloc::{is_allocated, Allocation};
+use crate::backend::register::RVRegister;
+use crate::backend::target::{Riscv32, Target};
+use crate::opt::dominators::DominatorTree;
+use crate::opt::liveness::Liveness;
+use crate::opt::loops::LoopInfo;
+
+// Chaitin-Briggs graph coloring. Values interfere when one is live where the other is
+// defined, and the allocatable registers are precolored nodes, values live across a call
+// interfering with the caller-saved ones. Values are coalesced with the argument registers
+// they are moved to and from, at calls, returns and the entry, when George's test finds it safe.
+// The others are simplified away by degree, those left being pushed optimistically by their
+// spill cost, the uses and definitions weighted by loop depth, over their degree. Nodes are
+// then colored in reverse, the registers they are moved to preferred, and a node finding no
+// color is spilled, its uses going through the scratch registers.
+pub fn graph_coloring(func_data: &FunctionData, registers: &[RVRegister]) -> Allocation {
+    let graph = Graph::build(func_data, registers);
+    let mut coloring = Coloring::new(graph);
+    coloring.coalesce();
+    let order = coloring.simplify();
+    coloring.select(order)
+}
+
+// Nodes are the allocatable registers first, in their order of preference, then the values
+struct Graph {
+    registers: Vec<RVRegister>,
+    values: Vec<Value>,
+    adjacent: Vec<HashSet<usize>>,
+    // The uses and definitions of every node, weighted by loop depth
+    costs: Vec<u64>,
+    // A value moved to or from a register, as its node, the register's and the weight of the move
+    moves: Vec<(usize, usize, u64)>,
+}
+
+impl Graph {
+    fn build(func_data: &FunctionData, registers: &[RVRegister]) -> Self {
+        let k = registers.len();
+        let liveness = Liveness::compute(func_data);
+        let loops = LoopInfo::compute(func_data, &DominatorTree::compute(func_data));
+        let entry = func_data.layout().entry_bb().unwrap();
+
+        // Unused parameters need no register
+        let mut values: Vec<Value> = func_data.params().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).copied()
+            .filter(|param| liveness.live_in(entry).contains(param))
+            .collect();
+        for (_, node) in func_data.layout().bbs() {
+            values.extend(node.insts().keys().copied().filter(|&inst| is_allocated(func_data.dfg().value(inst))));
+        }
+        let index: HashMap<Value, usize> = values.iter().enumerate().map(|(i, &value)| (value, k + i)).collect();
+        let register = |register: RVRegister| registers.iter().position(|&other| other == register).unwrap();
+        let caller_saved: Vec<usize> = (0..k).filter(|&reg| !registers[reg].is_callee_saved()).collect();
+        let mut graph = Graph {
+            registers: registers.to_vec(),
+            adjacent: vec![HashSet::new(); k + values.len()],
+            costs: vec![0; k + values.len()],
+            moves: Vec::new(),
+            values,
+        };
+
+        for &bb in func_data.layout().bbs().keys() {
+            let weight = 10u64.pow(loops.depth(bb).min(6) as u32);
+            // Walked from the end of the block, the moves of every instruction are kept in layout order
+            let mut block_moves = Vec::new();
+            let mut insts = liveness.instructions(func_data, bb);
+            while let Some(inst) = insts.next_instruction() {
+                let value_data = func_data.dfg().value(inst);
+                let mut moves = Vec::new();
+                // Kept while the instruction writes its result
+                let live: Vec<usize> = insts.live_out().iter().filter(|&&value| value != inst).filter_map(|value| index.get(value).copied()).collect();
+                if let Some(&def) = index.get(&inst) {
+                    for &other in live.iter() {
+                        graph.add_edge(def, other);
+                    }
+                    graph.costs[def] += weight;
+                }
+                match value_data.kind() {
+                    ValueKind::Call(call) => {
+                        for &other in live.iter() {
+                            for &reg in caller_saved.iter() {
+                                graph.add_edge(other, reg);
+                            }
+                        }
+                        if let Some(&def) = index.get(&inst) {
+                            moves.push((def, register(Riscv32::RETURN_REGISTER), weight));
+                        }
+                        for (i, arg) in call.args().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).enumerate() {
+                            if let Some(&node) = index.get(arg) {
+                                moves.push((node, register(Riscv32::argument_register(i)), weight));
+                            }
+                        }
+                    }
+                    ValueKind::Return(ret) => {
+                        if let Some(&node) = ret.value().and_then(|value| index.get(&value)) {
+                            moves.push((node, register(Riscv32::RETURN_REGISTER), weight));
+                        }
+                    }
+                    _ => {}
+                }
+                for operand in value_data.kind().value_uses() {
+                    if let Some(&node) = index.get(&operand) {
+                        graph.costs[node] += weight;
+                    }
+                }
+                block_moves.push(moves);
+            }
+            graph.moves.extend(block_moves.into_iter().rev().flatten());
+        }
+
+        // The parameters are defined together at the entry, moved from their registers
+        let params: Vec<usize> = func_data.params().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).filter_map(|param| index.get(param).copied()).collect();
+        for (i, &param) in params.iter().enumerate() {
+            for &other in params[i + 1..].iter() {
+                graph.add_edge(param, other);
+            }
+        }
+        for (i, param) in func_data.params().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).enumerate() {
+            if let Some(&node) = index.get(param) {
+                graph.costs[node] += 1;
+                graph.moves.push((node, register(Riscv32::argument_register(i)), 1));
+            }
+        }
+        // The most frequent moves are coalesced first
+        graph.moves.sort_by_key(|&(_, _, weight)| std::cmp::Reverse(weight));
+        graph
+    }
+
+    // The number of colors
+    fn k(&self) -> usize {
+        self.registers.len()
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize) {
+        if a != b {
+            self.adjacent[a].insert(b);
+            self.adjacent[b].insert(a);
+        }
+    }
+}
+
+struct Coloring {
+    graph: Graph,
+    // The register index of every colored node, the registers being their own colors
+    colors: Vec<Option<usize>>,
+}
+
+impl Coloring {
+    fn new(graph: Graph) -> Self {
+        let mut colors = vec![None; graph.adjacent.len()];
+        for (reg, color) in colors.iter_mut().enumerate().take(graph.k()) {
+            *color = Some(reg);
+        }
+        Coloring { graph, colors }
+    }
+
+    // George's test: a value can take the register it is moved to when each of its neighbors
+    // already interferes with the register, or has too few neighbors to be left without a color
+    fn coalesce(&mut self) {
+        let k = self.graph.k();
+        let moves = self.graph.moves.clone();
+        for (node, reg, _) in moves {
+            if self.colors[node].is_some() || self.graph.adjacent[node].contains(&reg) {
+                continue;
+            }
+            let safe = self.graph.adjacent[node].iter()
+                .all(|&other| other < k || self.graph.adjacent[other].contains(&reg) || self.graph.adjacent[other].len() < k);
+            if !safe {
+                continue;
+            }
+            // The register takes the place of the value
+            self.colors[node] = Some(reg);
+            for other in std::mem::take(&mut self.graph.adjacent[node]) {
+                self.graph.adjacent[other].remove(&node);
+                self.graph.add_edge(other, reg);
+            }
+        }
+    }
+
+    // The uncolored values, in the order they are to be colored
+    fn simplify(&self) -> Vec<usize> {
+        let k = self.graph.k();
+        let nodes = self.graph.adjacent.len();
+        let mut removed: Vec<bool> = (0..nodes).map(|node| self.colors[node].is_some()).collect();
+        let mut degrees: Vec<usize> = self.graph.adjacent.iter().map(HashSet::len).collect();
+        let mut stack = Vec::new();
+        loop {
+            let left = (k..nodes).filter(|&node| !removed[node]);
+            let trivial = left.clone().find(|&node| degrees[node] < k);
+            // Optimistically, the cheapest to spill for the neighbors it frees
+            let node = match trivial {
+                Some(node) => node,
+                None => match left.min_by(|&a, &b| (self.graph.costs[a] * degrees[b] as u64).cmp(&(self.graph.costs[b] * degrees[a] as u64))) {
+                    Some(node) => node,
+                    None => break,
+                },
+            };
+            removed[node] = true;
+            for &other in self.graph.adjacent[node].iter() {
+                degrees[other] -= 1;
+            }
+            stack.push(node);
+        }
+        stack.reverse();
+        stack
+    }
+
+    fn select(mut self, order: Vec<usize>) -> Allocation {
+        let k = self.graph.k();
+        let mut allocation = Allocation::default();
+        for node in order {
+            let used: HashSet<usize> = self.graph.adjacent[node].iter().filter_map(|&other| self.colors[other]).collect();
+            let preferred = self.graph.moves.iter()
+                .filter(|&&(moved, reg, _)| moved == node && !used.contains(&reg))
+                .map(|&(_, reg, _)| reg)
+                .next();
+            match preferred.or_else(|| (0..k).find(|reg| !used.contains(reg))) {
+                Some(reg) => self.colors[node] = Some(reg),
+                None => {
+                    allocation.spilled.insert(self.graph.values[node - k]);
+                }
+            }
+        }
+        for (i, &value) in self.graph.values.iter().enumerate() {
+            if let Some(reg) = self.colors[k + i] {
+                allocation.registers.insert(value, self.graph.registers[reg]);
+            }
+        }
+        allocation
+    }
+}
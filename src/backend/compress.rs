@@ -0,0 +1,51 @@
+use crate::backend::encode::register_number;
+use crate::backend::instruction::Instruction;
+use crate::backend::register::RVRegister;
+
+// The RVC form of `inst` for `--march=rv32imc`, when its operands fit one: `c.addi` and the
+// like take the destination as the first source, immediates of 6 bits, and some only the
+// registers `s0`-`a5`. Branches and jumps keep their full form, as how far they go is only
+// known to the assembler, which compresses those it can on its own.
+pub fn compressed(inst: &Instruction) -> Option<String> {
+    use RVRegister::{Ra, Sp, Zero};
+    let fits = |imm: i32, bits: u32| (-(1 << (bits - 1))..1 << (bits - 1)).contains(&imm);
+    // Word offsets, scaled by 4 in the encoding
+    let word_offset = |imm: i32, max: i32| imm % 4 == 0 && (0..=max).contains(&imm);
+    let text = match *inst {
+        Instruction::Mv { rd, rs } if rd != Zero && rs != Zero => format!("c.mv {}, {}", rd, rs),
+        Instruction::Li { rd, imm } if rd != Zero && fits(imm, 6) => format!("c.li {}, {}", rd, imm),
+        Instruction::Addi { rd: Sp, rs: Sp, imm } if imm != 0 && imm % 16 == 0 && fits(imm, 10) => format!("c.addi16sp sp, {}", imm),
+        Instruction::Addi { rd, rs: Sp, imm } if is_compact(rd) && imm != 0 && word_offset(imm, 1020) => format!("c.addi4spn {}, sp, {}", rd, imm),
+        Instruction::Addi { rd, rs, imm } if rd == rs && rd != Zero && imm != 0 && fits(imm, 6) => format!("c.addi {}, {}", rd, imm),
+        Instruction::Lw { rd, rs: Sp, imm } if rd != Zero && word_offset(imm, 252) => format!("c.lwsp {}, {}(sp)", rd, imm),
+        Instruction::Lw { rd, rs, imm } if is_compact(rd) && is_compact(rs) && word_offset(imm, 124) => format!("c.lw {}, {}({})", rd, imm, rs),
+        Instruction::Sw { rs, rd: Sp, imm } if rs != Zero && word_offset(imm, 252) => format!("c.swsp {}, {}(sp)", rs, imm),
+        Instruction::Sw { rs, rd, imm } if is_compact(rs) && is_compact(rd) && word_offset(imm, 124) => format!("c.sw {}, {}({})", rs, imm, rd),
+        Instruction::Add { rd, rs1, rs2 } if rd != Zero && rs1 != Zero && rs2 != Zero && (rd == rs1 || rd == rs2) => {
+            format!("c.add {}, {}", rd, if rd == rs1 { rs2 } else { rs1 })
+        }
+        Instruction::Sub { rd, rs1, rs2 } if rd == rs1 && is_compact(rd) && is_compact(rs2) => format!("c.sub {}, {}", rd, rs2),
+        Instruction::And { rd, rs1, rs2 } | Instruction::Or { rd, rs1, rs2 } | Instruction::Xor { rd, rs1, rs2 }
+            if (rd == rs1 || rd == rs2) && is_compact(rs1) && is_compact(rs2) => {
+            let name = match inst {
+                Instruction::And { .. } => "c.and",
+                Instruction::Or { .. } => "c.or",
+                _ => "c.xor",
+            };
+            format!("{} {}, {}", name, rd, if rd == rs1 { rs2 } else { rs1 })
+        }
+        Instruction::Andi { rd, rs, imm } if rd == rs && is_compact(rd) && fits(imm, 6) => format!("c.andi {}, {}", rd, imm),
+        Instruction::Slli { rd, rs, shamt } if rd == rs && rd != Zero && shamt != 0 => format!("c.slli {}, {}", rd, shamt),
+        Instruction::Srli { rd, rs, shamt } if rd == rs && is_compact(rd) && shamt != 0 => format!("c.srli {}, {}", rd, shamt),
+        Instruction::Srai { rd, rs, shamt } if rd == rs && is_compact(rd) && shamt != 0 => format!("c.srai {}, {}", rd, shamt),
+        Instruction::Ret => format!("c.jr {}", Ra),
+        Instruction::Ebreak => "c.ebreak".to_string(),
+        _ => return None,
+    };
+    Some(text)
+}
+
+// `s0`, `s1` and `a0`-`a5`, the registers the 3-bit fields of RVC name
+fn is_compact(register: RVRegister) -> bool {
+    (8..16).contains(&register_number(register))
+}
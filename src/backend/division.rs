@@ -0,0 +1,92 @@
+use crate::backend::instruction::Instruction;
+use crate::backend::register::RVRegister;
+
+// Division by a constant as a multiplication by its "magic" reciprocal, after Hacker's
+// Delight 10-4: `x / d` is the high word of `x * m`, shifted right by `shift`, and rounded
+// towards zero by adding one for a negative quotient. `div` takes tens of cycles on the
+// target, the sequence a few.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Magic {
+    pub multiplier: i32,
+    pub shift: u32,
+}
+
+// Divisors worth it: powers of two are left to a cheaper sequence, and 0 and ±1 to `div`
+pub fn is_magic_divisor(d: i32) -> bool {
+    d.unsigned_abs() > 1 && !d.unsigned_abs().is_power_of_two()
+}
+
+// `d` must satisfy `is_magic_divisor`
+pub fn magic(d: i32) -> Magic {
+    const TWO_31: u32 = 1 << 31;
+    let ad = d.unsigned_abs();
+    let t = TWO_31 + (d as u32 >> 31);
+    // The largest dividend for which the remainder is `|d| - 1`
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (TWO_31 / anc, TWO_31 % anc);
+    let (mut q2, mut r2) = (TWO_31 / ad, TWO_31 % ad);
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+    let multiplier = q2.wrapping_add(1) as i32;
+    Magic {
+        multiplier: if d < 0 { multiplier.wrapping_neg() } else { multiplier },
+        shift: p - 32,
+    }
+}
+
+// What the generated sequence computes, for checking `magic`
+pub fn divide(x: i32, d: i32) -> i32 {
+    let Magic { multiplier, shift } = magic(d);
+    let mut q = ((x as i64 * multiplier as i64) >> 32) as i32;
+    if d > 0 && multiplier < 0 {
+        q = q.wrapping_add(x);
+    } else if d < 0 && multiplier > 0 {
+        q = q.wrapping_sub(x);
+    }
+    q >>= shift;
+    q.wrapping_add((q as u32 >> 31) as i32)
+}
+
+// `rd = x / d`, or `x % d` as `x - x / d * d` for `remainder`. `temp` is clobbered, and
+// neither it nor `rd` may be `x`.
+pub fn divide_by_constant(rd: RVRegister, x: RVRegister, d: i32, remainder: bool, temp: RVRegister) -> Vec<Instruction> {
+    let Magic { multiplier, shift } = magic(d);
+    let mut instructions = vec![
+        Instruction::Li { rd: temp, imm: multiplier },
+        Instruction::Mulh { rd, rs1: x, rs2: temp },
+    ];
+    if d > 0 && multiplier < 0 {
+        instructions.push(Instruction::Add { rd, rs1: rd, rs2: x });
+    } else if d < 0 && multiplier > 0 {
+        instructions.push(Instruction::Sub { rd, rs1: rd, rs2: x });
+    }
+    if shift > 0 {
+        instructions.push(Instruction::Srai { rd, rs: rd, shamt: shift });
+    }
+    instructions.push(Instruction::Srli { rd: temp, rs: rd, shamt: 31 });
+    instructions.push(Instruction::Add { rd, rs1: rd, rs2: temp });
+    if remainder {
+        instructions.push(Instruction::Li { rd: temp, imm: d });
+        instructions.push(Instruction::Mul { rd: temp, rs1: rd, rs2: temp });
+        instructions.push(Instruction::Sub { rd, rs1: x, rs2: temp });
+    }
+    instructions
+}
@@ -0,0 +1,240 @@
+use crate::backend::instruction::Instruction;
+use crate::backend::register::RVRegister;
+
+// Machine code of the RV32IM instructions used by the backend, as produced by an assembler.
+// Pseudo instructions expand to the same sequences as in GNU as and llvm-mc.
+
+// A reference to a label or symbol left in the encoded words, resolved by the object writer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fixup {
+    // B-type offset to a label in the same function
+    Branch(String),
+    // J-type offset to a label in the same function
+    Jump(String),
+    // `auipc ra` + `jalr ra` pair calling a symbol, possibly defined elsewhere
+    Call(String),
+    // `auipc` + `addi` pair computing the address of a symbol
+    PcrelAddress(String),
+}
+
+#[derive(Debug)]
+pub struct MachineCode {
+    pub words: Vec<u32>,
+    // The index in `words` of the first word the fixup applies to
+    pub fixup: Option<(usize, Fixup)>,
+}
+
+impl MachineCode {
+    fn word(word: u32) -> Self {
+        MachineCode { words: vec![word], fixup: None }
+    }
+
+    pub fn size(&self) -> usize {
+        self.words.len() * 4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    ImmediateOutOfRange(String),
+    OffsetOutOfRange(String),
+    UndefinedLabel(String),
+    // Referenced by an executable, which is linked on its own
+    UndefinedSymbol(String),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EncodeError::ImmediateOutOfRange(inst) => write!(f, "immediate out of range in `{}`", inst),
+            EncodeError::OffsetOutOfRange(label) => write!(f, "label `{}` is out of the range of the jump", label),
+            EncodeError::UndefinedLabel(label) => write!(f, "jump to undefined label `{}`", label),
+            EncodeError::UndefinedSymbol(symbol) => write!(f, "undefined symbol `{}`", symbol),
+        }
+    }
+}
+
+pub fn register_number(register: RVRegister) -> u32 {
+    match register {
+        RVRegister::Zero => 0,
+        RVRegister::Ra => 1,
+        RVRegister::Sp => 2,
+        RVRegister::T0 => 5,
+        RVRegister::T1 => 6,
+        RVRegister::T2 => 7,
+        RVRegister::S0 => 8,
+        RVRegister::S1 => 9,
+        RVRegister::A0 => 10,
+        RVRegister::A1 => 11,
+        RVRegister::A2 => 12,
+        RVRegister::A3 => 13,
+        RVRegister::A4 => 14,
+        RVRegister::A5 => 15,
+        RVRegister::A6 => 16,
+        RVRegister::A7 => 17,
+        RVRegister::S2 => 18,
+        RVRegister::S3 => 19,
+        RVRegister::S4 => 20,
+        RVRegister::S5 => 21,
+        RVRegister::S6 => 22,
+        RVRegister::S7 => 23,
+        RVRegister::S8 => 24,
+        RVRegister::S9 => 25,
+        RVRegister::S10 => 26,
+        RVRegister::S11 => 27,
+        RVRegister::T3 => 28,
+        RVRegister::T4 => 29,
+        RVRegister::T5 => 30,
+        RVRegister::T6 => 31,
+    }
+}
+
+const OP_IMM: u32 = 0b0010011;
+const OP: u32 = 0b0110011;
+const LOAD: u32 = 0b0000011;
+const STORE: u32 = 0b0100011;
+const BRANCH: u32 = 0b1100011;
+const JAL: u32 = 0b1101111;
+const JALR: u32 = 0b1100111;
+const LUI: u32 = 0b0110111;
+const AUIPC: u32 = 0b0010111;
+const SYSTEM: u32 = 0b1110011;
+
+fn r_type(funct7: u32, rs2: RVRegister, rs1: RVRegister, funct3: u32, rd: RVRegister) -> u32 {
+    funct7 << 25 | register_number(rs2) << 20 | register_number(rs1) << 15 | funct3 << 12 | register_number(rd) << 7 | OP
+}
+
+fn i_type(imm: i32, rs1: RVRegister, funct3: u32, rd: RVRegister, opcode: u32) -> u32 {
+    (imm as u32 & 0xfff) << 20 | register_number(rs1) << 15 | funct3 << 12 | register_number(rd) << 7 | opcode
+}
+
+fn s_type(imm: i32, rs2: RVRegister, rs1: RVRegister, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 5 & 0x7f) << 25 | register_number(rs2) << 20 | register_number(rs1) << 15 | funct3 << 12 | (imm & 0x1f) << 7 | STORE
+}
+
+fn u_type(imm: u32, rd: RVRegister, opcode: u32) -> u32 {
+    (imm & 0xfffff) << 12 | register_number(rd) << 7 | opcode
+}
+
+// `bne rs1, rs2` or `beq rs1, rs2` with the offset left zero
+fn b_type(funct3: u32, rs1: RVRegister, rs2: RVRegister) -> u32 {
+    register_number(rs2) << 20 | register_number(rs1) << 15 | funct3 << 12 | BRANCH
+}
+
+pub fn fits_i12(imm: i32) -> bool {
+    (-(1 << 11)..(1 << 11)).contains(&imm)
+}
+
+// Splits `value` for a `lui`/`auipc` + 12-bit immediate pair, the low part being sign-extended
+pub fn split_hi_lo(value: i32) -> (u32, i32) {
+    let hi = (value as u32).wrapping_add(0x800) >> 12;
+    let lo = value.wrapping_sub((hi << 12) as i32);
+    (hi, lo)
+}
+
+// Fills the offset of a B-type instruction, `offset` must be even and within ±4 KiB
+pub fn patch_branch(word: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    word & 0x01fff07f
+        | (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3f) << 25
+        | (imm >> 1 & 0xf) << 8 | (imm >> 11 & 1) << 7
+}
+
+// Fills the offset of a J-type instruction, `offset` must be even and within ±1 MiB
+pub fn patch_jump(word: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    word & 0xfff
+        | (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3ff) << 21
+        | (imm >> 11 & 1) << 20 | (imm >> 12 & 0xff) << 12
+}
+
+pub fn branch_in_range(offset: i32) -> bool {
+    (-(1 << 12)..(1 << 12)).contains(&offset)
+}
+
+pub fn jump_in_range(offset: i32) -> bool {
+    (-(1 << 20)..(1 << 20)).contains(&offset)
+}
+
+// `far` is only meaningful for conditional branches: the target is out of the ±4 KiB of a
+// B-type offset, so the condition is inverted to skip over a `j`, like assemblers relax them
+pub fn encode(inst: &Instruction, far: bool) -> Result<MachineCode, EncodeError> {
+    let check = |imm: i32| if fits_i12(imm) { Ok(imm) } else { Err(EncodeError::ImmediateOutOfRange(inst.to_string())) };
+    let zero = RVRegister::Zero;
+    let code = match inst {
+        Instruction::Addi { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b000, *rd, OP_IMM)),
+        Instruction::Li { rd, imm } if fits_i12(*imm) => MachineCode::word(i_type(*imm, zero, 0b000, *rd, OP_IMM)),
+        Instruction::Li { rd, imm } => {
+            let (hi, lo) = split_hi_lo(*imm);
+            let mut words = vec![u_type(hi, *rd, LUI)];
+            if lo != 0 {
+                words.push(i_type(lo, *rd, 0b000, *rd, OP_IMM));
+            }
+            MachineCode { words, fixup: None }
+        }
+        Instruction::Lw { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b010, *rd, LOAD)),
+        Instruction::La { rd, label } | Instruction::Lla { rd, label } => MachineCode {
+            words: vec![u_type(0, *rd, AUIPC), i_type(0, *rd, 0b000, *rd, OP_IMM)],
+            fixup: Some((0, Fixup::PcrelAddress(label.clone()))),
+        },
+        Instruction::Sw { rs, rd, imm } => MachineCode::word(s_type(check(*imm)?, *rs, *rd, 0b010)),
+        Instruction::Mv { rd, rs } => MachineCode::word(i_type(0, *rs, 0b000, *rd, OP_IMM)),
+        Instruction::Add { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b000, *rd)),
+        Instruction::Sub { rd, rs1, rs2 } => MachineCode::word(r_type(0b0100000, *rs2, *rs1, 0b000, *rd)),
+        Instruction::Mul { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000001, *rs2, *rs1, 0b000, *rd)),
+        Instruction::Mulh { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000001, *rs2, *rs1, 0b001, *rd)),
+        Instruction::Div { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000001, *rs2, *rs1, 0b100, *rd)),
+        Instruction::Rem { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000001, *rs2, *rs1, 0b110, *rd)),
+        Instruction::And { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b111, *rd)),
+        Instruction::Or { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b110, *rd)),
+        Instruction::Xor { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b100, *rd)),
+        Instruction::Sll { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b001, *rd)),
+        Instruction::Srl { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b101, *rd)),
+        Instruction::Sra { rd, rs1, rs2 } => MachineCode::word(r_type(0b0100000, *rs2, *rs1, 0b101, *rd)),
+        // The shift amount in the low 5 bits of the immediate, `srai` telling itself apart by bit 10
+        Instruction::Srai { rd, rs, shamt } => MachineCode::word(i_type((0x400 | shamt & 0x1f) as i32, *rs, 0b101, *rd, OP_IMM)),
+        Instruction::Srli { rd, rs, shamt } => MachineCode::word(i_type((shamt & 0x1f) as i32, *rs, 0b101, *rd, OP_IMM)),
+        Instruction::Slli { rd, rs, shamt } => MachineCode::word(i_type((shamt & 0x1f) as i32, *rs, 0b001, *rd, OP_IMM)),
+        Instruction::Slt { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs2, *rs1, 0b010, *rd)),
+        Instruction::Slti { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b010, *rd, OP_IMM)),
+        Instruction::Xori { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b100, *rd, OP_IMM)),
+        Instruction::Ori { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b110, *rd, OP_IMM)),
+        Instruction::Andi { rd, rs, imm } => MachineCode::word(i_type(check(*imm)?, *rs, 0b111, *rd, OP_IMM)),
+        // `sgt rd, rs1, rs2` is `slt rd, rs2, rs1`
+        Instruction::Sgt { rd, rs1, rs2 } => MachineCode::word(r_type(0b0000000, *rs1, *rs2, 0b010, *rd)),
+        // `sltiu rd, rs, 1`
+        Instruction::Seqz { rd, rs } => MachineCode::word(i_type(1, *rs, 0b011, *rd, OP_IMM)),
+        // `sltu rd, x0, rs`
+        Instruction::Snez { rd, rs } => MachineCode::word(r_type(0b0000000, *rs, zero, 0b011, *rd)),
+        Instruction::Bnez { rs, label } if far => MachineCode {
+            // `beqz rs, .+8` then `j label`
+            words: vec![patch_branch(b_type(0b000, *rs, zero), 8), JAL],
+            fixup: Some((1, Fixup::Jump(label.clone()))),
+        },
+        Instruction::Bnez { rs, label } => MachineCode {
+            words: vec![b_type(0b001, *rs, zero)],
+            fixup: Some((0, Fixup::Branch(label.clone()))),
+        },
+        Instruction::Beqz { rs, label } if far => MachineCode {
+            words: vec![patch_branch(b_type(0b001, *rs, zero), 8), JAL],
+            fixup: Some((1, Fixup::Jump(label.clone()))),
+        },
+        Instruction::Beqz { rs, label } => MachineCode {
+            words: vec![b_type(0b000, *rs, zero)],
+            fixup: Some((0, Fixup::Branch(label.clone()))),
+        },
+        // `jal x0, label`
+        Instruction::J { label } => MachineCode { words: vec![JAL], fixup: Some((0, Fixup::Jump(label.clone()))) },
+        Instruction::Call { label, .. } => MachineCode {
+            words: vec![u_type(0, RVRegister::Ra, AUIPC), i_type(0, RVRegister::Ra, 0b000, RVRegister::Ra, JALR)],
+            fixup: Some((0, Fixup::Call(label.clone()))),
+        },
+        // `jalr x0, 0(ra)`
+        Instruction::Ret => MachineCode::word(i_type(0, RVRegister::Ra, 0b000, zero, JALR)),
+        Instruction::Ebreak => MachineCode::word(1 << 20 | SYSTEM),
+        Instruction::Ecall => MachineCode::word(SYSTEM),
+        Instruction::Comment(_) => MachineCode { words: vec![], fixup: None },
+    };
+    Ok(code)
+}
@@ -1,14 +1,20 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::hash::Hash;
 use std::rc::Rc;
-use koopa::ir::{BasicBlock, Function, Program};
-use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, Program, Value, ValueKind};
 use crate::backend::asm::AsmBasicBlock;
+use crate::backend::BackendOptions;
 use crate::backend::call_graph::CallGraph;
 use crate::backend::instruction::Instruction;
+use crate::backend::literal_pool::{LiteralPool, POOL_BASE};
+use crate::backend::regalloc::{Allocation, Segment};
 use crate::backend::register::{RVRegister, RVRegisterPool};
+use crate::backend::target::{Riscv32, Target};
+use crate::backend::stack_map::FrameLayout;
+use crate::util::interner::Name;
 use crate::util::name_generator::NameGenerator;
+use crate::common::alloc_scopes::AllocScopes;
+use crate::get_func_from_ir_env;
 
 #[derive(Debug, Clone)]
 pub struct FunctionPrologueInfo {
@@ -16,6 +22,16 @@ pub struct FunctionPrologueInfo {
     // Whether the function needs to save `ra`
     pub is_leaf: bool,
     pub args_stack_size: i32,
+    // Whether the function saves the base register of its literal pool
+    pub has_literal_pool: bool,
+    // The callee-saved registers given to values, saved after the pool base
+    pub saved_registers: Vec<RVRegister>,
+}
+
+impl Default for FunctionPrologueInfo {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FunctionPrologueInfo {
@@ -24,18 +40,28 @@ impl FunctionPrologueInfo {
             stack_size: 0,
             is_leaf: false,
             args_stack_size: 0,
+            has_literal_pool: false,
+            saved_registers: Vec::new(),
         }
     }
 
     pub fn get_aligned_stack_size(&self) -> i32 {
-        let stack_size = self.stack_size + self.args_stack_size + (self.is_leaf as i32) * 4;
-        // Align to 16 bytes
-        let remainder = stack_size % 16;
-        if remainder == 0 {
-            stack_size
-        } else {
-            stack_size + 16 - remainder
-        }
+        Riscv32::align_frame(self.saved_register_offset(0) + self.saved_registers.len() as i32 * Riscv32::WORD_SIZE)
+    }
+
+    // Above the outgoing arguments and the slots
+    pub fn ra_offset(&self) -> i32 {
+        self.args_stack_size + self.stack_size
+    }
+
+    // The saved `ra` comes first, then the pool base
+    pub fn pool_base_offset(&self) -> i32 {
+        self.ra_offset() + (!self.is_leaf as i32) * 4
+    }
+
+    // The slot of the `i`-th of `saved_registers`
+    pub fn saved_register_offset(&self, i: usize) -> i32 {
+        self.pool_base_offset() + (self.has_literal_pool as i32) * 4 + i as i32 * 4
     }
 }
 
@@ -51,6 +77,10 @@ pub struct ROContext<'a> {
     pub program: &'a Program,
     pub current_func: Option<Function>,
     pub current_bb: Option<BasicBlock>,
+    // The source statement of the instructions, for the comments in the assembly
+    pub source_lines: Option<&'a HashMap<Value, String>>,
+    // The scopes of the locals, for them to share stack slots
+    pub alloc_scopes: Option<&'a AllocScopes>,
 }
 
 #[derive(Clone)]
@@ -60,33 +90,52 @@ pub struct IRAnalysisResult {
 
 pub struct AsmEnvironment<'a> {
     pub context: ROContext<'a>,
-    // Map from Value to its result register
-    pub presence_table: HashMap<*const ValueData, ValueStorage>,
+    // Map from Value to its result register, globals included
+    pub presence_table: HashMap<Value, ValueStorage>,
     pub function_prologue_info: FunctionPrologueInfo,
     pub analysis_result: IRAnalysisResult,
     pub(crate) register_pool: RVRegisterPool,
+    pub(crate) allocation: Allocation,
+    // Where the values in a segment are kept outside of it, see `Segment`
+    pub(crate) spill_homes: HashMap<Value, ValueStorage>,
+    // The offset of every alloc from the bottom of their area, when they share slots
+    pub(crate) alloc_slots: HashMap<Value, i32>,
     pub(crate) name_generator: Rc<RefCell<NameGenerator>>,
-    pub(crate) name_map: HashMap<BasicBlock, String>,
-    pub(crate) stack_frame_size: usize,
+    pub(crate) name_map: HashMap<BasicBlock, Name>,
+    // The frame of the function, its size and argument areas known before its body is generated
+    pub(crate) frame_layout: FrameLayout,
+    pub(crate) options: BackendOptions,
+    // The pool the function loads its large constants from, if any
+    pub(crate) literal_pool: Option<LiteralPool>,
+    // Shared by the functions of the module, see `LiteralPool::plan`
+    pub(crate) module_pool: LiteralPool,
 }
 
 impl<'a> AsmEnvironment<'a> {
-    pub fn new(program: &'a Program) -> Self {
+    pub fn new(program: &'a Program, options: BackendOptions) -> Self {
         AsmEnvironment {
             context: ROContext {
                 program,
                 current_func: None,
                 current_bb: None,
+                source_lines: None,
+                alloc_scopes: None,
             },
             presence_table: std::collections::HashMap::new(),
             function_prologue_info: FunctionPrologueInfo::new(),
             analysis_result: IRAnalysisResult {
                 call_graph: CallGraph::build(program),
             },
-            register_pool: RVRegisterPool::new_temp_pool(),
+            register_pool: RVRegisterPool::new_scratch_pool(),
+            allocation: Allocation::default(),
+            spill_homes: HashMap::new(),
+            alloc_slots: HashMap::new(),
             name_generator: Rc::new(RefCell::from(NameGenerator::new())),
             name_map: HashMap::new(),
-            stack_frame_size: 0,
+            frame_layout: FrameLayout::default(),
+            options,
+            literal_pool: None,
+            module_pool: LiteralPool::default(),
         }
     }
 
@@ -94,78 +143,132 @@ impl<'a> AsmEnvironment<'a> {
         self.context.current_bb = Some(bb);
     }
 
-    pub fn is_present(&self, value: &ValueData) -> bool {
-        self.presence_table.contains_key(&(value as *const ValueData))
+    pub fn is_present(&self, value: Value) -> bool {
+        self.presence_table.contains_key(&value)
+    }
+
+    pub fn load_data(&mut self, target: &mut AsmBasicBlock, value: Value) -> RVRegister {
+        match self.presence_table.get(&value) {
+            Some(ValueStorage::Register(register)) => *register,
+            Some(ValueStorage::Immediate(0)) => RVRegister::Zero,
+            Some(_) => {
+                let register = self.scratch_register();
+                self.load_data_to(target, value, register);
+                register
+            }
+            None => panic!("Value {:?} not present in presence table", value),
+        }
     }
 
-    pub fn load_data(&mut self, target: &mut AsmBasicBlock, value: &ValueData) -> RVRegister {
-        match self.presence_table.get(&(value as *const ValueData)) {
+    // Puts `value` in `rd`, which is not a scratch register taken from the pool
+    pub fn load_data_to(&mut self, target: &mut AsmBasicBlock, value: Value, rd: RVRegister) {
+        match self.presence_table.get(&value) {
             Some(storage) => match storage {
-                ValueStorage::Register(register) => register.clone(),
+                ValueStorage::Register(register) => {
+                    if *register != rd {
+                        target.add_instruction(Riscv32::mv(rd, *register));
+                    }
+                }
                 ValueStorage::Stack(offset) => {
-                    // Try to apply a register
-                    let register = self.register_pool.next().unwrap();
-                    // Load from stack to register
-                    target.instructions.extend(self.generate_lw(register.clone(), RVRegister::Sp, *offset));
-                    register
+                    let offset = *offset;
+                    target.instructions.extend(self.generate_lw(rd, Riscv32::STACK_POINTER, offset));
                 }
                 ValueStorage::Immediate(imm) => {
-                    if *imm == 0 {
-                        RVRegister::Zero
-                    } else {
-                        let register = self.register_pool.next().unwrap();
-                        target.add_instruction(Instruction::Li {
-                            rd: register.clone(),
+                    match self.literal_pool.as_ref().and_then(|pool| pool.offset_of(*imm)) {
+                        Some(offset) => target.add_instruction(Instruction::Lw {
+                            rd,
+                            rs: POOL_BASE,
+                            imm: offset,
+                        }),
+                        None => target.add_instruction(Instruction::Li {
+                            rd,
                             imm: *imm,
-                        });
-                        register
+                        }),
                     }
                 }
                 ValueStorage::Global(ident) => {
-                    let global_addr_register = self.register_pool.next().unwrap();
-                    let register = self.register_pool.next().unwrap();
-                    target.add_instruction(Instruction::La {
-                        rd: global_addr_register.clone(),
-                        label: ident.clone(),
-                    });
+                    target.add_instruction(self.la(rd, ident.clone()));
                     target.add_instruction(Instruction::Lw {
-                        rd: register.clone(),
-                        rs: global_addr_register.clone(),
+                        rd,
+                        rs: rd,
                         imm: 0,
                     });
-                    // Free the global address register
-                    self.register_pool.release(global_addr_register);
-                    register
                 }
             },
             None => panic!("Value {:?} not present in presence table", value),
         }
     }
 
-    pub fn store_data(&mut self, target: &mut AsmBasicBlock, value: &ValueData, register: Option<RVRegister>) {
-        match self.presence_table.get(&(value as *const ValueData)) {
+    // The address of a symbol, PC-relative without a GOT in position-independent code
+    pub fn la(&self, rd: RVRegister, label: String) -> Instruction {
+        if self.options.pic {
+            Instruction::Lla { rd, label }
+        } else {
+            Instruction::La { rd, label }
+        }
+    }
+
+    // Whether `pointer` is an alloc or a global, whose storage is the memory it points to,
+    // rather than a computed address, e.g. from `getelemptr`, held like any other value
+    pub fn is_address(&self, pointer: Value) -> bool {
+        let func_data = get_func_from_ir_env!(self);
+        match self.presence_table.get(&pointer) {
+            Some(ValueStorage::Global(_)) => true,
+            Some(ValueStorage::Stack(_)) => matches!(
+                func_data.dfg().values().get(&pointer).map(|value_data| value_data.kind()),
+                Some(ValueKind::Alloc(_))
+            ),
+            _ => false,
+        }
+    }
+
+    // Puts the address `pointer` holds in a register
+    pub fn load_address(&mut self, target: &mut AsmBasicBlock, pointer: Value) -> RVRegister {
+        if !self.is_address(pointer) {
+            return self.load_data(target, pointer);
+        }
+        let register = self.scratch_register();
+        self.load_address_to(target, pointer, register);
+        register
+    }
+
+    // Puts the address `pointer` holds in `rd`, as `load_data_to`
+    pub fn load_address_to(&mut self, target: &mut AsmBasicBlock, pointer: Value, rd: RVRegister) {
+        match self.presence_table.get(&pointer) {
+            Some(ValueStorage::Global(label)) if self.is_address(pointer) => target.add_instruction(self.la(rd, label.clone())),
+            Some(&ValueStorage::Stack(offset)) if self.is_address(pointer) => target.instructions.extend(self.generate_addi(rd, Riscv32::STACK_POINTER, offset)),
+            _ => self.load_data_to(target, pointer, rd),
+        }
+    }
+
+    pub fn store_data(&mut self, target: &mut AsmBasicBlock, value: Value, register: Option<RVRegister>) {
+        match self.presence_table.get(&value) {
             Some(storage) => match storage {
-                ValueStorage::Register(_reg_prev) => unimplemented!(),
+                ValueStorage::Register(rd) => {
+                    let (rd, register) = (*rd, register.unwrap());
+                    if rd != register {
+                        target.add_instruction(Riscv32::mv(rd, register));
+                        self.register_pool.release(register);
+                    }
+                }
                 ValueStorage::Stack(_offset) => {
                     // Store from register to stack
                     let register = register.unwrap();
                     let offset = *_offset;
-                    target.instructions.extend(self.generate_sw(register.clone(), RVRegister::Sp, offset));
+                    target.instructions.extend(self.generate_sw(register, Riscv32::STACK_POINTER, offset));
 
                     // Free the register
                     self.register_pool.release(register);
                 }
                 ValueStorage::Immediate(_) => unimplemented!(),
                 ValueStorage::Global(label) => {
-                    let global_addr_register = self.register_pool.next().unwrap();
-                    target.add_instruction(Instruction::La {
-                        rd: global_addr_register.clone(),
-                        label: label.clone(),
-                    });
+                    let label = label.clone();
+                    let global_addr_register = self.scratch_register();
+                    target.add_instruction(self.la(global_addr_register, label));
                     let register = register.unwrap();
                     target.add_instruction(Instruction::Sw {
                         rs: register,
-                        rd: global_addr_register.clone(),
+                        rd: global_addr_register,
                         imm: 0,
                     });
                     // Free the global address register
@@ -178,22 +281,81 @@ impl<'a> AsmEnvironment<'a> {
         }
     }
 
-    pub fn bind_data_storage(&mut self, value: &ValueData, storage: ValueStorage) {
-        self.presence_table.insert(value as *const ValueData, storage);
+    pub fn bind_data_storage(&mut self, value: Value, storage: ValueStorage) {
+        self.presence_table.insert(value, storage);
     }
 
-    pub fn alloc_stack_storage(&mut self, value_data: &ValueData, size: i32) {
+    pub fn alloc_stack_storage(&mut self, value: Value, size: i32) {
         // Save to the storage mapping
-        let position = self.function_prologue_info.stack_size + self.function_prologue_info.args_stack_size;
-        self.presence_table.insert(value_data as *const ValueData, ValueStorage::Stack(position));
+        let position = self.function_prologue_info.ra_offset();
+        self.presence_table.insert(value, ValueStorage::Stack(position));
         // Update the stack size
         self.function_prologue_info.stack_size += size;
     }
 
-    pub fn apply_register(&mut self, _value: &ValueData) -> RVRegister {
+    // Gives the result of `value` the register it was allocated, or a stack slot if spilled
+    pub fn bind_result(&mut self, value: Value) {
+        match self.allocation.register(value) {
+            Some(register) => self.bind_data_storage(value, ValueStorage::Register(register)),
+            None => self.alloc_stack_storage(value, 4),
+        }
+    }
+
+    // Where to compute the result of `value`: its own register, or that of a segment starting
+    // there or a scratch register, which `store_data` then saves to its slot
+    pub fn result_register(&mut self, value: Value) -> RVRegister {
+        match self.presence_table.get(&value) {
+            Some(ValueStorage::Register(register)) => *register,
+            _ => match self.allocation.defining_segment(value) {
+                Some(register) => register,
+                None => self.scratch_register(),
+            },
+        }
+    }
+
+    // Loads the values whose segments start before `inst` into their registers
+    pub fn begin_segments(&mut self, target: &mut AsmBasicBlock, inst: Value) {
+        let segments: Vec<Segment> = self.allocation.segments.iter()
+            .filter(|segment| segment.start == inst && segment.value != inst)
+            .copied()
+            .collect();
+        for segment in segments {
+            self.load_data_to(target, segment.value, segment.register);
+            self.enter_segment(segment);
+        }
+    }
+
+    // Values computed by `inst` in the register of their segment are read from there, and
+    // those whose segments end at `inst` from their slots again
+    pub fn end_segments(&mut self, inst: Value) {
+        let segments: Vec<Segment> = self.allocation.segments.iter()
+            .filter(|segment| segment.start == inst && segment.value == inst || segment.end == inst)
+            .copied()
+            .collect();
+        // The segments of other values starting at `inst` were entered by `begin_segments`
+        for segment in segments.iter().filter(|segment| segment.start == inst && segment.value == inst) {
+            self.enter_segment(*segment);
+        }
+        for segment in segments.iter().filter(|segment| segment.end == inst) {
+            let home = self.spill_homes.remove(&segment.value).unwrap();
+            self.bind_data_storage(segment.value, home);
+        }
+    }
+
+    fn enter_segment(&mut self, segment: Segment) {
+        let home = self.presence_table.insert(segment.value, ValueStorage::Register(segment.register)).unwrap();
+        self.spill_homes.insert(segment.value, home);
+    }
+
+    pub fn apply_register(&mut self, _value: Value) -> RVRegister {
         // println!("Applying register for {:?}", value);
-        let register = self.register_pool.next().unwrap();
-        register
+        self.scratch_register()
+    }
+
+    // However many values are live, they are in their own registers or slots, and an
+    // instruction takes at most three scratch registers: two operands and a large offset
+    fn scratch_register(&mut self) -> RVRegister {
+        self.register_pool.acquire().expect("out of scratch registers, an instruction takes at most three")
     }
 
     pub fn free_register(&mut self, register: RVRegister) {
@@ -201,33 +363,64 @@ impl<'a> AsmEnvironment<'a> {
         self.register_pool.release(register);
     }
 
-    pub fn lookup_name(&mut self, bb: &BasicBlock) -> String {
+    pub fn lookup_name(&mut self, bb: &BasicBlock) -> Name {
         match self.name_map.get(bb) {
-            Some(name) => name.clone(),
+            Some(&name) => name,
             None => {
                 // Generate a new name
                 let name = self.name_generator.borrow_mut().generate("func_");
-                self.bind_name(bb, name.clone());
+                self.bind_name(bb, name);
                 name
             }
         }
     }
 
-    pub fn bind_name(&mut self, bb: &BasicBlock, name: String) {
-        self.name_map.insert(bb.clone(), name);
+    pub fn bind_name(&mut self, bb: &BasicBlock, name: Name) {
+        self.name_map.insert(*bb, name);
+    }
+
+    // Sequentializes the moves `(rd, rs)` meant to happen at once, the `rd` being distinct.
+    // A cycle, e.g. swapping `a0` and `a1`, is broken through a temporary register.
+    pub fn generate_parallel_mv(&mut self, mut moves: Vec<(RVRegister, RVRegister)>) -> Vec<Instruction> {
+        moves.retain(|(rd, rs)| rd != rs);
+        let mut instructions = Vec::new();
+        let mut temps = Vec::new();
+        while !moves.is_empty() {
+            // A destination no other move reads can be overwritten now
+            match moves.iter().position(|&(rd, _)| moves.iter().all(|&(_, rs)| rs != rd)) {
+                Some(index) => {
+                    let (rd, rs) = moves.remove(index);
+                    instructions.push(Riscv32::mv(rd, rs));
+                }
+                None => {
+                    // Only cycles are left, save a source and read it from the copy
+                    let saved = moves[0].1;
+                    let temp = self.scratch_register();
+                    instructions.push(Riscv32::mv(temp, saved));
+                    for (_, rs) in moves.iter_mut().filter(|(_, rs)| *rs == saved) {
+                        *rs = temp;
+                    }
+                    temps.push(temp);
+                }
+            }
+        }
+        for temp in temps {
+            self.free_register(temp);
+        }
+        instructions
     }
 
     pub fn generate_sw(&mut self, rs: RVRegister, rd: RVRegister, imm: i32) -> Vec<Instruction> {
         // Immediate is always 12-bit, meaning we need to check if it fits in 12-bit
-        if imm >= -(1 << 11) && imm < (1 << 11) {
+        if (-(1 << 11)..(1 << 11)).contains(&imm) {
             vec![ Instruction::Sw { rs, rd, imm } ]
         } else {
             // If it doesn't fit, we need to use a temporary register to store the immediate
-            let temp = self.register_pool.next().unwrap();
+            let temp = self.scratch_register();
             let instructions = vec![
-                Instruction::Li { rd: temp.clone(), imm },
-                Instruction::Add { rd: temp.clone(), rs1: temp.clone(), rs2: rd },
-                Instruction::Sw { rs, rd: temp.clone(), imm: 0 },
+                Instruction::Li { rd: temp, imm },
+                Instruction::Add { rd: temp, rs1: temp, rs2: rd },
+                Instruction::Sw { rs, rd: temp, imm: 0 },
             ];
             self.free_register(temp);
             instructions
@@ -236,15 +429,15 @@ impl<'a> AsmEnvironment<'a> {
 
     pub fn generate_lw(&mut self, rd: RVRegister, rs: RVRegister, imm: i32) -> Vec<Instruction> {
         // Immediate is always 12-bit, meaning we need to check if it fits in 12-bit
-        if imm >= -(1 << 11) && imm < (1 << 11) {
+        if (-(1 << 11)..(1 << 11)).contains(&imm) {
             vec![ Instruction::Lw { rd, rs, imm } ]
         } else {
             // If it doesn't fit, we need to use a temporary register to store the immediate
-            let temp = self.register_pool.next().unwrap();
+            let temp = self.scratch_register();
             let instructions = vec![
-                Instruction::Li { rd: temp.clone(), imm },
-                Instruction::Add { rd: temp.clone(), rs1: temp.clone(), rs2: rs },
-                Instruction::Lw { rd, rs: temp.clone(), imm: 0 },
+                Instruction::Li { rd: temp, imm },
+                Instruction::Add { rd: temp, rs1: temp, rs2: rs },
+                Instruction::Lw { rd, rs: temp, imm: 0 },
             ];
             self.free_register(temp);
             instructions
@@ -253,14 +446,14 @@ impl<'a> AsmEnvironment<'a> {
 
     pub fn generate_addi(&mut self, rd: RVRegister, rs: RVRegister, imm: i32) -> Vec<Instruction> {
         // Immediate is always 12-bit, meaning we need to check if it fits in 12-bit
-        if imm >= -(1 << 11) && imm < (1 << 11) {
+        if (-(1 << 11)..(1 << 11)).contains(&imm) {
             vec![ Instruction::Addi { rd, rs, imm } ]
         } else {
             // If it doesn't fit, we need to use a temporary register to store the immediate
-            let temp = self.register_pool.next().unwrap();
+            let temp = self.scratch_register();
             let instructions = vec![
-                Instruction::Li { rd: temp.clone(), imm },
-                Instruction::Add { rd, rs1: rs, rs2: temp.clone() },
+                Instruction::Li { rd: temp, imm },
+                Instruction::Add { rd, rs1: rs, rs2: temp },
             ];
             self.free_register(temp);
             instructions
@@ -1,13 +1,22 @@
-use std::cmp::max;
-use std::fmt::Pointer;
 use crate::backend::instruction::Instruction;
-use crate::backend::register::RVRegister::A0;
 use crate::backend::environment::{AsmEnvironment, FunctionPrologueInfo, ROContext, ValueStorage};
-use koopa::ir::{BinaryOp, FunctionData, Program, ValueKind};
-use koopa::ir::entities::ValueData;
+use koopa::ir::{BinaryOp, FunctionData, Program, Type, TypeKind, Value, ValueKind};
 use crate::backend::asm::{AsmBasicBlock, AsmFunction, AsmGlobal, AsmVariable, AsmVariableInit};
 use crate::backend::register::{RVRegister, RVRegisterPool};
+use crate::backend::stack_map::{FrameLayout, StackSlot};
+use crate::backend::target::{Riscv32, Target};
+use crate::backend::literal_pool::{LiteralPool, POOL_BASE};
+use crate::backend::division;
+use crate::backend::encode;
+use crate::backend::moves;
+use crate::backend::layout;
+use crate::backend::schedule;
+use crate::backend::relax;
+use crate::backend::slots;
+use crate::backend::regalloc::{self, has_call_result, AllocationReport};
 use crate::get_func_from_ir_env;
+use crate::ir::library::{is_string_literal, unpack_string};
+use crate::common::value_number::is_commutative;
 
 pub trait GenerateAsm {
     type Target;
@@ -18,7 +27,7 @@ pub trait GenerateAsm {
 pub trait ValueGenerateAsm {
     type Target;
 
-    fn generate_value<'b, 'a: 'b>(&'a self, target: &mut Self::Target, env: &mut AsmEnvironment<'b>);
+    fn generate_value(&self, target: &mut Self::Target, env: &mut AsmEnvironment);
 }
 
 impl GenerateAsm for Program {
@@ -29,39 +38,69 @@ impl GenerateAsm for Program {
             section_type: crate::backend::asm::AsmSectionType::Data,
             content: Vec::new(),
         };
+        let mut bss_section = crate::backend::asm::AsmSection {
+            section_type: crate::backend::asm::AsmSectionType::Bss,
+            content: Vec::new(),
+        };
         let mut text_section = crate::backend::asm::AsmSection {
             section_type: crate::backend::asm::AsmSectionType::Text,
             content: Vec::new(),
         };
+        let mut rodata_section = crate::backend::asm::AsmSection {
+            section_type: crate::backend::asm::AsmSectionType::Rodata,
+            content: Vec::new(),
+        };
 
         // Traverse the global variables
         for &global_h in self.inst_layout() {
             let global = self.borrow_value(global_h);
-            match global.kind() {
-                ValueKind::GlobalAlloc(alloc) => {
-                    let name = &global.name().clone().unwrap()[1..];
-
-                    // Add to presence table
-                    env.presence_table.insert(&*global as *const ValueData, ValueStorage::Global(name.to_string()));
-
-                    let initial_value_data = self.borrow_value(alloc.init());
-
-                    let init = match initial_value_data.kind() {
-                        ValueKind::Integer(int) => AsmVariableInit::Word(int.value()),
-                        ValueKind::ZeroInit(_) => AsmVariableInit::Zero(initial_value_data.ty().size()),
-                        _ => unreachable!(),
+            if let ValueKind::GlobalAlloc(alloc) = global.kind() {
+                let name = &global.name().clone().unwrap()[1..];
+
+                // Add to presence table
+                env.presence_table.insert(global_h, ValueStorage::Global(name.to_string()));
+
+                let initial_value_data = self.borrow_value(alloc.init());
+
+                // Read-only, in the layout the runtime library expects of a `char *`
+                if is_string_literal(name) {
+                    let words: Vec<i32> = match initial_value_data.kind() {
+                        ValueKind::Aggregate(aggregate) => aggregate.elems().iter().map(|&elem| match self.borrow_value(elem).kind() {
+                            ValueKind::Integer(int) => int.value(),
+                            _ => 0,
+                        }).collect(),
+                        _ => vec![0],
                     };
+                    rodata_section.content.push(AsmGlobal::AsmVariable(AsmVariable {
+                        label: name.to_string(),
+                        init: AsmVariableInit::Asciz(unpack_string(&words)),
+                        is_global: false,
+                    }));
+                    continue;
+                }
+
+                let init = match initial_value_data.kind() {
+                    ValueKind::Integer(int) => AsmVariableInit::Word(int.value()),
+                    ValueKind::ZeroInit(_) => AsmVariableInit::Zero(initial_value_data.ty().size()),
+                    _ => {
+                        let mut pieces = Vec::new();
+                        flatten_init(self, alloc.init(), &mut pieces);
+                        AsmVariableInit::Aggregate(pieces)
+                    }
+                };
 
-                    let asm_global = AsmGlobal::AsmVariable(
-                        AsmVariable {
-                            label: name.to_string(),
-                            init,
-                        }
-                    );
+                let asm_global = AsmGlobal::AsmVariable(
+                    AsmVariable {
+                        label: name.to_string(),
+                        init,
+                        is_global: true,
+                    }
+                );
 
-                    data_section.content.push(asm_global);
+                match initial_value_data.kind() {
+                    ValueKind::ZeroInit(_) => bss_section.content.push(asm_global),
+                    _ => data_section.content.push(asm_global),
                 }
-                _ => {}
             }
         }
 
@@ -74,26 +113,50 @@ impl GenerateAsm for Program {
             }
 
             let mut asm_func = AsmFunction::new(&func_data.name()[1..]);
-            func_data.generate(&mut asm_func, &mut AsmEnvironment {
+            let mut func_env = AsmEnvironment {
                 context: ROContext {
                     program: self,
                     current_func: Some(func_h),
                     current_bb: None,
+                    source_lines: env.context.source_lines,
+                    alloc_scopes: env.context.alloc_scopes,
                 },
                 presence_table: env.presence_table.clone(),
                 function_prologue_info: FunctionPrologueInfo::new(),
                 analysis_result: env.analysis_result.clone(),
-                register_pool: RVRegisterPool::new_temp_pool(),
+                register_pool: RVRegisterPool::new_scratch_pool(),
+                allocation: Default::default(),
+                spill_homes: std::collections::HashMap::new(),
+                alloc_slots: std::collections::HashMap::new(),
                 name_map: std::collections::HashMap::new(),
                 name_generator: env.name_generator.clone(),
-                stack_frame_size: 0,
-            });
+                frame_layout: FrameLayout::default(),
+                options: env.options,
+                literal_pool: None,
+                module_pool: std::mem::take(&mut env.module_pool),
+            };
+            func_data.generate(&mut asm_func, &mut func_env);
+            if env.options.report_allocation {
+                target.allocation_reports.push(AllocationReport::of(func_data, &func_env.allocation));
+            }
 
+            env.module_pool = std::mem::take(&mut func_env.module_pool);
             text_section.content.push(AsmGlobal::AsmFunction(asm_func));
         }
 
+        if !env.module_pool.values.is_empty() {
+            let pool = std::mem::take(&mut env.module_pool);
+            rodata_section.content.push(AsmGlobal::AsmVariable(AsmVariable {
+                label: pool.label,
+                init: AsmVariableInit::Words(pool.values),
+                is_global: false,
+            }));
+        }
+
         target.sections.push(data_section);
+        target.sections.push(bss_section);
         target.sections.push(text_section);
+        target.sections.push(rodata_section);
     }
 }
 
@@ -108,35 +171,64 @@ impl GenerateAsm for FunctionData {
         let call_graph = &env.analysis_result.call_graph.graph;
         if call_graph.contains_key(&self_handle) {
             let body = call_graph.get(&self_handle).unwrap();
-            prologue_info.args_stack_size = max(0, body.max_args as i32 - 8) * 4;
+            prologue_info.args_stack_size = FrameLayout::outgoing_args_size(body.max_args);
             prologue_info.is_leaf = body.callee.is_empty();
         } else {
             prologue_info.args_stack_size = 0;
             prologue_info.is_leaf = true;
         }
+        if env.options.literal_pools {
+            env.literal_pool = LiteralPool::plan(self, &mut env.module_pool);
+            prologue_info.has_literal_pool = env.literal_pool.is_some();
+        }
+        let registers = regalloc::allocatable(prologue_info.has_literal_pool);
+        env.allocation = regalloc::allocate(self, env.options.register_allocator, &registers);
+        if cfg!(debug_assertions) {
+            if let Err(message) = regalloc::check(self, &env.allocation) {
+                panic!("invalid register allocation of `{}`: {}", self.name(), message);
+            }
+        }
+        prologue_info.saved_registers = env.allocation.callee_saved();
+        // The shared slots of the allocs come first, the spilled values after them
+        let mut alloc_area = None;
+        if let Some(scopes) = env.context.alloc_scopes.filter(|_| env.options.share_stack_slots) {
+            let (slots, area) = slots::share_slots(self, scopes);
+            env.alloc_slots = slots;
+            alloc_area = Some(area);
+        }
         env.function_prologue_info = prologue_info.clone();
+        env.function_prologue_info.stack_size = alloc_area.unwrap_or(0);
 
         // Estimate the stack frame size, save to the outside `prologue_info`
         let estimated_stack_size = env.context.program.func(self_handle).dfg().values().iter().fold(
             0usize, |stack_size, (&value_h, value_data)| {
+                // Only the values spilled by the allocator have a slot
+                let spilled = env.allocation.is_spilled(value_h) as usize * 4;
                 stack_size + match value_data.kind() {
-                    ValueKind::FuncArgRef(_) => 0,
+                    ValueKind::FuncArgRef(_) => spilled,
                     ValueKind::BlockArgRef(_) => unreachable!(),
-                    ValueKind::Alloc(_) => 4,
+                    ValueKind::Alloc(_) if alloc_area.is_some() => 0,
+                    ValueKind::Alloc(_) => pointee_size(value_data.ty()),
                     ValueKind::GlobalAlloc(_) => unreachable!(),
-                    ValueKind::Load(_) => 4,
-                    ValueKind::GetPtr(_) => unreachable!(),
-                    ValueKind::GetElemPtr(_) => unreachable!(),
-                    ValueKind::Binary(_) => 4,
+                    ValueKind::Load(_) => spilled,
+                    ValueKind::GetPtr(_) => spilled,
+                    ValueKind::GetElemPtr(_) => spilled,
+                    ValueKind::Binary(_) => spilled,
                     ValueKind::Jump(_) => 0,
-                    ValueKind::Call(_) => 4,
+                    ValueKind::Call(_) => spilled,
                     ValueKind::Return(_) => 0,
                     _ => 0
                 }
             }
-        );
+        ) + alloc_area.unwrap_or(0) as usize;
         prologue_info.stack_size = estimated_stack_size as i32;
-        env.stack_frame_size = prologue_info.get_aligned_stack_size() as usize;
+        env.frame_layout = FrameLayout {
+            function: self.name()[1..].to_string(),
+            frame_size: prologue_info.get_aligned_stack_size(),
+            outgoing_args_size: prologue_info.args_stack_size,
+            ra_offset: (!prologue_info.is_leaf).then_some(prologue_info.ra_offset()),
+            locals: Vec::new(),
+        };
 
         // Traverse the basic blocks and corresponding instructions
         for (i, (&bb_h, node)) in self.layout().bbs().iter().enumerate() {
@@ -146,20 +238,51 @@ impl GenerateAsm for FunctionData {
             if i == 0 {
                 bb.label = Some(self.name()[1..].to_string());
                 bb.is_entry = true;
+                bind_params(self, &mut bb, env);
             }
 
             env.enter_bb(bb_h);
 
             // Inside a basic block
+            let mut source_line = None;
             for &inst_h in node.insts().keys() {
-                let value_data = self.dfg().value(inst_h);
+                let start = bb.instructions.len();
                 // Access the instruction, updating environment to basic block level
-                value_data.generate_value(&mut bb, env);
+                env.begin_segments(&mut bb, inst_h);
+                inst_h.generate_value(&mut bb, env);
+                env.end_segments(inst_h);
+                // The statement is named again in every block, and when it changes
+                let line = env.context.source_lines.and_then(|lines| lines.get(&inst_h));
+                if let Some(text) = line.filter(|_| line != source_line && bb.instructions.len() > start) {
+                    bb.instructions.insert(start, Instruction::Comment(text.clone()));
+                    source_line = line;
+                }
+            }
+
+            // A block without a terminator, e.g. the end of a non-void function that is
+            // never returned from, would run into the code that happens to follow.
+            // Debug builds trap there instead so that the simulator stops at the fault.
+            let is_terminated = node.insts().back_key().is_some_and(|&inst_h| matches!(
+                self.dfg().value(inst_h).kind(),
+                ValueKind::Return(_) | ValueKind::Jump(_) | ValueKind::Branch(_)
+            ));
+            if cfg!(debug_assertions) && !is_terminated {
+                bb.add_instruction(Instruction::Ebreak);
             }
 
             target.basic_blocks.push(bb);
         }
 
+        if env.options.eliminate_moves {
+            moves::eliminate_moves(target);
+        }
+        if env.options.schedule {
+            schedule::schedule(target);
+        }
+        if env.options.layout_blocks {
+            layout::layout_blocks(target);
+        }
+
         let aligned_stack_size = prologue_info.get_aligned_stack_size();
 
         // Now we have the stack size that is calculated in two ways,
@@ -167,66 +290,135 @@ impl GenerateAsm for FunctionData {
         assert_eq!(prologue_info.stack_size, env.function_prologue_info.stack_size);
         assert_eq!(prologue_info.args_stack_size, env.function_prologue_info.args_stack_size);
         assert_eq!(prologue_info.is_leaf, env.function_prologue_info.is_leaf);
+        assert_eq!(prologue_info.has_literal_pool, env.function_prologue_info.has_literal_pool);
 
         // Prologue
-        target.prologue.extend(env.generate_addi(RVRegister::Sp, RVRegister::Sp, -aligned_stack_size));
+        target.prologue.extend(env.generate_addi(Riscv32::STACK_POINTER, Riscv32::STACK_POINTER, -aligned_stack_size));
         // Save the `ra` register if applicable
         if !prologue_info.is_leaf {
-            target.prologue.extend(env.generate_sw(RVRegister::Ra, RVRegister::Sp, prologue_info.stack_size + prologue_info.args_stack_size));
+            target.prologue.extend(env.generate_sw(Riscv32::RETURN_ADDRESS, Riscv32::STACK_POINTER, prologue_info.ra_offset()));
+        }
+        // Point the callee-saved pool base at the literal pool
+        if let Some(label) = env.literal_pool.as_ref().map(|pool| pool.label.clone()) {
+            target.prologue.extend(env.generate_sw(POOL_BASE, Riscv32::STACK_POINTER, prologue_info.pool_base_offset()));
+            target.prologue.push(env.la(POOL_BASE, label));
+        }
+        // Save the callee-saved registers the values take
+        for (i, &register) in prologue_info.saved_registers.iter().enumerate() {
+            target.prologue.extend(env.generate_sw(register, Riscv32::STACK_POINTER, prologue_info.saved_register_offset(i)));
         }
 
         // Epilogue
+        for (i, &register) in prologue_info.saved_registers.iter().enumerate() {
+            target.epilogue.extend(env.generate_lw(register, Riscv32::STACK_POINTER, prologue_info.saved_register_offset(i)));
+        }
+        // Restore the pool base of the caller
+        if env.literal_pool.is_some() {
+            target.epilogue.extend(env.generate_lw(POOL_BASE, Riscv32::STACK_POINTER, prologue_info.pool_base_offset()));
+        }
         // Restore the `ra` register if applicable
         if !prologue_info.is_leaf {
-            target.epilogue.extend(env.generate_lw(RVRegister::Ra, RVRegister::Sp, prologue_info.stack_size + prologue_info.args_stack_size));
+            target.epilogue.extend(env.generate_lw(Riscv32::RETURN_ADDRESS, Riscv32::STACK_POINTER, prologue_info.ra_offset()));
         }
-        target.epilogue.extend(env.generate_addi(RVRegister::Sp, RVRegister::Sp, aligned_stack_size));
+        target.epilogue.extend(env.generate_addi(Riscv32::STACK_POINTER, Riscv32::STACK_POINTER, aligned_stack_size));
         target.epilogue.push(Instruction::Ret);
+
+        // Once the sizes of all the code are known. Compressing only brings branches closer.
+        relax::relax_branches(target);
+        target.compressed = env.options.compressed;
+
+        // Named allocs are the source-level variables, see `IRContext::set_value_name`
+        let locals = self.layout().bbs().iter()
+            .flat_map(|(_, node)| node.insts().keys())
+            .filter_map(|&inst_h| {
+                let value_data = self.dfg().value(inst_h);
+                match (value_data.kind(), value_data.name(), env.presence_table.get(&inst_h)) {
+                    (ValueKind::Alloc(_), Some(name), Some(ValueStorage::Stack(offset))) => Some(StackSlot {
+                        name: name[1..].to_string(),
+                        offset: *offset,
+                        size: pointee_size(value_data.ty()),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+        // The layout the body was generated for
+        assert_eq!(env.frame_layout.frame_size, aligned_stack_size);
+        target.frame_layout = FrameLayout { locals, ..std::mem::take(&mut env.frame_layout) };
     }
 }
 
-impl ValueGenerateAsm for ValueData {
+// Values are keyed by their handles, globals being present from the start
+impl ValueGenerateAsm for Value {
     type Target = AsmBasicBlock;
 
-    fn generate_value<'b, 'a: 'b>(&'a self, target: &mut Self::Target, env: &mut AsmEnvironment<'b>) {
-        if env.is_present(self) {
-            println!("Value already present in presence table");
+    fn generate_value(&self, target: &mut Self::Target, env: &mut AsmEnvironment) {
+        // Already generated, e.g. an operand used more than once
+        if env.is_present(*self) {
             return;
         }
 
         let func_data = get_func_from_ir_env!(env);
+        let value_data = func_data.dfg().value(*self);
 
-        match self.kind() {
+        match value_data.kind() {
             ValueKind::Integer(int) => {
-                env.bind_data_storage(&self, ValueStorage::Immediate(int.value()));
+                env.bind_data_storage(*self, ValueStorage::Immediate(int.value()));
             }
             ValueKind::Return(ret) => {
-                match ret.value() {
-                    Some(value_h) => {
-                        func_data.dfg().value(value_h).generate_value(target, env);
-                        let rs = env.load_data(target, func_data.dfg().value(value_h));
-                        target.instructions.push(Instruction::Mv {
-                            rd: A0,
-                            rs
-                        });
-                        env.free_register(rs);
-                    }
-                    None => {}
+                if let Some(value_h) = ret.value() {
+                    value_h.generate_value(target, env);
+                    env.load_data_to(target, value_h, Riscv32::RETURN_REGISTER);
                 }
 
                 target.is_exit = true;
             }
             ValueKind::Binary(bin) => {
-                // HAS return, allocate stack space
-                env.alloc_stack_storage(self, 4);
+                env.bind_result(*self);
+
+                bin.lhs().generate_value(target, env);
+                bin.rhs().generate_value(target, env);
+
+                if let (BinaryOp::Div | BinaryOp::Mod, ValueKind::Integer(d)) = (bin.op(), func_data.dfg().value(bin.rhs()).kind()) {
+                    if env.options.magic_division && division::is_magic_divisor(d.value()) {
+                        let x = env.load_data(target, bin.lhs());
+                        // The sequence writes `rd` before its last read of `x`
+                        let rd = match env.result_register(*self) {
+                            rd if rd == x => env.apply_register(*self),
+                            rd => rd,
+                        };
+                        let temp = env.apply_register(*self);
+                        target.instructions.extend(division::divide_by_constant(rd, x, d.value(), bin.op() == BinaryOp::Mod, temp));
+                        env.free_register(temp);
+                        env.free_register(x);
+                        env.store_data(target, *self, Some(rd));
+                        return;
+                    }
+                }
 
-                func_data.dfg().value(bin.lhs()).generate_value(target, env);
-                func_data.dfg().value(bin.rhs()).generate_value(target, env);
+                // A constant operand is encoded in the instruction where it fits, on either side
+                // of a commutative operator
+                let immediate = |value: Value| match env.presence_table.get(&value) {
+                    Some(&ValueStorage::Immediate(imm)) => Some(imm),
+                    _ => None,
+                };
+                let (lhs, rhs) = match (immediate(bin.lhs()), immediate(bin.rhs())) {
+                    (Some(_), None) if is_commutative(bin.op()) => (bin.rhs(), bin.lhs()),
+                    _ => (bin.lhs(), bin.rhs()),
+                };
+                if let Some(imm) = immediate(rhs).filter(|&imm| has_immediate_form(bin.op(), imm)) {
+                    let rs = env.load_data(target, lhs);
+                    let rd = env.result_register(*self);
+                    target.instructions.extend(binary_immediate(bin.op(), rd, rs, imm));
+                    env.free_register(rs);
+                    env.store_data(target, *self, Some(rd));
+                    return;
+                }
 
-                let rs1 = env.load_data(target, func_data.dfg().value(bin.lhs()));
-                let rs2 = env.load_data(target, func_data.dfg().value(bin.rhs()));
+                let rs1 = env.load_data(target, bin.lhs());
+                let rs2 = env.load_data(target, bin.rhs());
 
-                let rd = env.apply_register(self);
+                let rd = env.result_register(*self);
                 let instructions = match bin.op() {
                     BinaryOp::NotEq => {
                         vec![
@@ -251,114 +443,261 @@ impl ValueGenerateAsm for ValueData {
                     BinaryOp::Mod => { vec![Instruction::Rem { rd, rs1, rs2 }] }
                     BinaryOp::And => { vec![Instruction::And { rd, rs1, rs2 }] }
                     BinaryOp::Or => { vec![Instruction::Or { rd, rs1, rs2 }] }
-                    // BinaryOp::Xor => {}
-                    // BinaryOp::Shl => {}
-                    // BinaryOp::Shr => {}
-                    // BinaryOp::Sar => {}
-                    _ => unreachable!()
+                    BinaryOp::Xor => { vec![Instruction::Xor { rd, rs1, rs2 }] }
+                    BinaryOp::Shl => { vec![Instruction::Sll { rd, rs1, rs2 }] }
+                    BinaryOp::Shr => { vec![Instruction::Srl { rd, rs1, rs2 }] }
+                    BinaryOp::Sar => { vec![Instruction::Sra { rd, rs1, rs2 }] }
                 };
 
                 target.instructions.extend(instructions);
 
                 env.free_register(rs1);
                 env.free_register(rs2);
-                env.store_data(target, self, Some(rd));
-            }
-            ValueKind::Alloc(_) => {
-                env.alloc_stack_storage(self, 4);
+                env.store_data(target, *self, Some(rd));
             }
+            ValueKind::Alloc(_) => match env.alloc_slots.get(self) {
+                Some(&offset) => {
+                    let position = env.function_prologue_info.args_stack_size + offset;
+                    env.bind_data_storage(*self, ValueStorage::Stack(position));
+                }
+                None => env.alloc_stack_storage(*self, pointee_size(value_data.ty()) as i32),
+            },
             ValueKind::Load(load) => {
-                // Trivially, load should write to another stack space
-                // just as what we did in binary
-                env.alloc_stack_storage(self, 4);
+                env.bind_result(*self);
 
-                let x = env.context.program.borrow_values();
-                let from = x.get(&load.src()).unwrap_or_else(
-                    || func_data.dfg().value(load.src())
-                );
-                // let from = func_data.dfg().value(load.src());
-                let rs = env.load_data(target, &*from);
-                env.store_data(target, self, Some(rs));
+                let rd = env.result_register(*self);
+                if env.is_address(load.src()) {
+                    env.load_data_to(target, load.src(), rd);
+                } else {
+                    // Through a computed pointer
+                    let rs = env.load_data(target, load.src());
+                    target.add_instruction(Instruction::Lw { rd, rs, imm: 0 });
+                    env.free_register(rs);
+                }
+                env.store_data(target, *self, Some(rd));
             }
             ValueKind::Store(store) => {
-                let src_value_data = func_data.dfg().value(store.value());
+                store.value().generate_value(target, env);
 
-                src_value_data.generate_value(target, env);
-
-                let src = env.load_data(target, src_value_data);
-
-                let x = env.context.program.borrow_values();
-                let to = x.get(&store.dest()).unwrap_or_else(
-                    || func_data.dfg().value(store.dest())
-                );
-                env.store_data(target, to, Some(src));
+                let src = env.load_data(target, store.value());
+                if env.is_address(store.dest()) {
+                    env.store_data(target, store.dest(), Some(src));
+                } else {
+                    let rd = env.load_data(target, store.dest());
+                    target.add_instruction(Instruction::Sw { rs: src, rd, imm: 0 });
+                    env.free_register(rd);
+                    env.free_register(src);
+                }
+            }
+            ValueKind::GetElemPtr(gep) => {
+                generate_element_address(*self, gep.src(), gep.index(), target, env);
+            }
+            ValueKind::GetPtr(get_ptr) => {
+                generate_element_address(*self, get_ptr.src(), get_ptr.index(), target, env);
             }
             ValueKind::Branch(branch) => {
-                let cond_value_data = func_data.dfg().value(branch.cond());
-                cond_value_data.generate_value(target, env);
+                branch.cond().generate_value(target, env);
 
-                let rs = env.load_data(target, cond_value_data);
+                let rs = env.load_data(target, branch.cond());
                 target.instructions.push(Instruction::Bnez {
                     rs,
                     label: env.lookup_name(&branch.true_bb()).to_string(),
                 });
-                target.instructions.push(Instruction::J {
-                    label: env.lookup_name(&branch.false_bb()).to_string(),
-                });
+                target.instructions.push(Riscv32::jump(env.lookup_name(&branch.false_bb()).to_string()));
 
                 env.free_register(rs);
             }
             ValueKind::Jump(jump) => {
-                target.instructions.push(Instruction::J {
-                    label: env.lookup_name(&jump.target()).to_string(),
-                });
+                target.instructions.push(Riscv32::jump(env.lookup_name(&jump.target()).to_string()));
             }
             ValueKind::Call(call) => {
-                // TODO: check preparation correctness
-                // Prepare arguments
-                for (i, &arg) in call.args().iter().enumerate() {
-                    let arg_value_data = func_data.dfg().value(arg);
-                    arg_value_data.generate_value(target, env);
-
-                    if i < 8 {
-                        let rs = env.load_data(target, arg_value_data);
-                        target.instructions.push(Instruction::Mv {
-                            rd: RVRegister::get_arg_reg(i),
-                            rs,
-                        });
-                        env.free_register(rs);
-                    } else {
-                        let rs = env.load_data(target, arg_value_data);
-                        target.instructions.push(Instruction::Sw {
-                            rs,
-                            rd: RVRegister::Sp,
-                            imm: (i - 8) as i32 * 4,
-                        });
-                        env.free_register(rs);
+                // Prepare arguments. They are staged: every argument is evaluated before the
+                // first argument register is written, as the parameters of the current
+                // function may still live in those registers.
+                let args = call.args();
+                for arg in args.iter() {
+                    arg.generate_value(target, env);
+                }
+
+                // Arguments beyond the registers go to the outgoing area, overwriting no register
+                for (i, &arg) in args.iter().enumerate().skip(Riscv32::ARGUMENT_REGISTERS.len()) {
+                    let rs = env.load_address(target, arg);
+                    let instructions = env.generate_sw(rs, Riscv32::STACK_POINTER, FrameLayout::outgoing_arg_offset(i));
+                    target.instructions.extend(instructions);
+                    env.free_register(rs);
+                }
+
+                // Arguments already in registers are permuted into place at once...
+                let registers: Vec<Option<RVRegister>> = args.iter().take(Riscv32::ARGUMENT_REGISTERS.len())
+                    .map(|arg| match env.presence_table.get(arg) {
+                        Some(ValueStorage::Register(register)) => Some(*register),
+                        _ => None,
+                    })
+                    .collect();
+                let moves = registers.iter().enumerate()
+                    .filter_map(|(i, register)| register.map(|rs| (Riscv32::argument_register(i), rs)))
+                    .collect();
+                let instructions = env.generate_parallel_mv(moves);
+                target.instructions.extend(instructions);
+
+                // ...then the others are loaded, which reads no argument register. Allocs and
+                // globals are passed by address.
+                for (i, &arg) in args.iter().enumerate().take(Riscv32::ARGUMENT_REGISTERS.len()) {
+                    if registers[i].is_some() {
+                        continue;
                     }
+                    env.load_address_to(target, arg, Riscv32::argument_register(i));
                 }
 
                 // Call!
                 let callee = env.context.program.func(call.callee()).name()[1..].to_string();
-                target.instructions.push(Instruction::Call {
-                    label: callee,
-                });
+                target.instructions.push(Riscv32::call(callee, env.options.pic));
 
                 // Handle return by saving `a0`
-                env.alloc_stack_storage(self, 4);
-                env.store_data(target, self, Some(RVRegister::A0));
-            }
-            ValueKind::FuncArgRef(arg) => {
-                let arg_index = arg.index() as i32;
-                if arg_index < 8 {
-                    env.bind_data_storage(&self, ValueStorage::Register(RVRegister::get_arg_reg(arg.index())));
-                } else {
-                    // Compensate for the current stack frame
-                    let position = (arg.index() - 8) * 4 + env.stack_frame_size;
-                    env.bind_data_storage(&self, ValueStorage::Stack(position as i32));
+                if has_call_result(value_data) {
+                    env.bind_result(*self);
+                    env.store_data(target, *self, Some(Riscv32::RETURN_REGISTER));
                 }
             }
             _ => unreachable!(),
         }
     }
-}
\ No newline at end of file
+}
+
+// Parameters in registers are moved to theirs, all at once as they may be permuted, or stored
+// first if spilled. The others are in the caller's outgoing area.
+fn bind_params(func_data: &FunctionData, entry: &mut AsmBasicBlock, env: &mut AsmEnvironment) {
+    let mut moves = Vec::new();
+    for (i, &param) in func_data.params().iter().enumerate() {
+        if !Riscv32::is_passed_in_register(i) {
+            env.bind_data_storage(param, ValueStorage::Stack(env.frame_layout.incoming_arg_offset(i)));
+        } else if env.allocation.is_spilled(param) {
+            env.alloc_stack_storage(param, 4);
+            env.store_data(entry, param, Some(Riscv32::argument_register(i)));
+        } else {
+            // Unused parameters are given no register
+            let register = env.allocation.register(param).unwrap_or(Riscv32::argument_register(i));
+            env.bind_data_storage(param, ValueStorage::Register(register));
+            moves.push((register, Riscv32::argument_register(i)));
+        }
+    }
+    let instructions = env.generate_parallel_mv(moves);
+    entry.instructions.extend(instructions);
+}
+
+// Whether `rs op imm` is computed by an I-type instruction, zero being the register `x0`
+// otherwise, unless it saves the `seqz` of `<=` and `>`
+fn has_immediate_form(op: BinaryOp, imm: i32) -> bool {
+    match op {
+        BinaryOp::Add | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor |
+        BinaryOp::Lt | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::NotEq => imm != 0 && encode::fits_i12(imm),
+        BinaryOp::Sub => imm != 0 && encode::fits_i12(-imm),
+        // `x <= c` is `x < c + 1`
+        BinaryOp::Le | BinaryOp::Gt => imm.checked_add(1).is_some_and(encode::fits_i12),
+        BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar => true,
+        _ => false,
+    }
+}
+
+// `rd = rs op imm`, reading `rs` before writing `rd`
+fn binary_immediate(op: BinaryOp, rd: RVRegister, rs: RVRegister, imm: i32) -> Vec<Instruction> {
+    match op {
+        BinaryOp::Add => vec![Instruction::Addi { rd, rs, imm }],
+        BinaryOp::Sub => vec![Instruction::Addi { rd, rs, imm: -imm }],
+        BinaryOp::And => vec![Instruction::Andi { rd, rs, imm }],
+        BinaryOp::Or => vec![Instruction::Ori { rd, rs, imm }],
+        BinaryOp::Xor => vec![Instruction::Xori { rd, rs, imm }],
+        BinaryOp::Lt => vec![Instruction::Slti { rd, rs, imm }],
+        BinaryOp::Ge => vec![Instruction::Slti { rd, rs, imm }, Instruction::Seqz { rd, rs: rd }],
+        BinaryOp::Le => vec![Instruction::Slti { rd, rs, imm: imm + 1 }],
+        BinaryOp::Gt => vec![Instruction::Slti { rd, rs, imm: imm + 1 }, Instruction::Seqz { rd, rs: rd }],
+        BinaryOp::Eq => vec![Instruction::Xori { rd, rs, imm }, Instruction::Seqz { rd, rs: rd }],
+        BinaryOp::NotEq => vec![Instruction::Xori { rd, rs, imm }, Instruction::Snez { rd, rs: rd }],
+        // By the low 5 bits, as the register forms
+        BinaryOp::Shl => vec![Instruction::Slli { rd, rs, shamt: imm as u32 & 0x1f }],
+        BinaryOp::Shr => vec![Instruction::Srli { rd, rs, shamt: imm as u32 & 0x1f }],
+        BinaryOp::Sar => vec![Instruction::Srai { rd, rs, shamt: imm as u32 & 0x1f }],
+        _ => unreachable!(),
+    }
+}
+
+// Appends the words of a global initializer, in order, the zeros merged into runs
+fn flatten_init(program: &Program, init: Value, pieces: &mut Vec<AsmVariableInit>) {
+    let init_data = program.borrow_value(init);
+    match init_data.kind() {
+        ValueKind::Integer(int) if int.value() != 0 => match pieces.last_mut() {
+            Some(AsmVariableInit::Words(values)) => values.push(int.value()),
+            _ => pieces.push(AsmVariableInit::Words(vec![int.value()])),
+        },
+        ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+            let size = init_data.ty().size();
+            match pieces.last_mut() {
+                Some(AsmVariableInit::Zero(run)) => *run += size,
+                _ => pieces.push(AsmVariableInit::Zero(size)),
+            }
+        }
+        ValueKind::Aggregate(aggregate) => {
+            for &elem in aggregate.elems() {
+                flatten_init(program, elem, pieces);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+// The size of what a pointer type points to
+fn pointee_size(ty: &Type) -> usize {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.size(),
+        _ => unreachable!(),
+    }
+}
+
+// `value = src + index * size`, the size being that of the elements `value` points to, which
+// for `getelemptr` are those of the array `src` points to and for `getptr` its own pointee.
+// The offset is computed before the base is taken, so that at most three scratch registers
+// are held at once: the offset, the base and the result.
+fn generate_element_address(value: Value, src: Value, index: Value, target: &mut AsmBasicBlock, env: &mut AsmEnvironment) {
+    index.generate_value(target, env);
+    env.bind_result(value);
+
+    let func_data = get_func_from_ir_env!(env);
+    let size = pointee_size(func_data.dfg().value(value).ty()) as i32;
+    match env.presence_table.get(&index) {
+        Some(&ValueStorage::Immediate(index)) => {
+            let base = env.load_address(target, src);
+            let rd = env.result_register(value);
+            let instructions = env.generate_addi(rd, base, index * size);
+            target.instructions.extend(instructions);
+            env.free_register(base);
+            env.store_data(target, value, Some(rd));
+        }
+        _ => {
+            let rs = env.load_data(target, index);
+            let offset = env.apply_register(index);
+            if size.count_ones() == 1 {
+                target.add_instruction(Instruction::Slli { rd: offset, rs, shamt: size.trailing_zeros() });
+            } else if !env.options.m_extension {
+                // Horner's rule over the bits of the size, from the highest
+                target.add_instruction(Riscv32::mv(offset, rs));
+                for bit in (0..31 - size.leading_zeros()).rev() {
+                    target.add_instruction(Instruction::Slli { rd: offset, rs: offset, shamt: 1 });
+                    if size & 1 << bit != 0 {
+                        target.add_instruction(Instruction::Add { rd: offset, rs1: offset, rs2: rs });
+                    }
+                }
+            } else {
+                target.add_instruction(Instruction::Li { rd: offset, imm: size });
+                target.add_instruction(Instruction::Mul { rd: offset, rs1: rs, rs2: offset });
+            }
+            env.free_register(rs);
+
+            let base = env.load_address(target, src);
+            let rd = env.result_register(value);
+            target.add_instruction(Instruction::Add { rd, rs1: base, rs2: offset });
+            env.free_register(base);
+            env.free_register(offset);
+            env.store_data(target, value, Some(rd));
+        }
+    }
+}
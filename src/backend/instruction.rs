@@ -1,4 +1,6 @@
+use crate::backend::encode::register_number;
 use crate::backend::register::RVRegister;
+use crate::backend::target::{Riscv32, Target};
 
 #[derive(Debug)]
 pub enum Instruction {
@@ -6,25 +8,154 @@ pub enum Instruction {
     Li { rd: RVRegister, imm: i32 },
     Lw { rd: RVRegister, rs: RVRegister, imm: i32 },
     La { rd: RVRegister, label: String },
+    // `la` that never loads the address from the GOT, even when assembling position-independent
+    // code: `auipc` + `addi` with `%pcrel_hi`/`%pcrel_lo`, for symbols defined in the executable
+    Lla { rd: RVRegister, label: String },
     Sw { rs: RVRegister, rd: RVRegister, imm: i32 },
     Mv { rd: RVRegister, rs: RVRegister },
     Add { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Sub { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Mul { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    // High word of the signed product
+    Mulh { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Div { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Rem { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     And { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Or { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Xor { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    // Shifts by the low 5 bits of `rs2`
+    Sll { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    Srl { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    Sra { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    Srai { rd: RVRegister, rs: RVRegister, shamt: u32 },
+    Srli { rd: RVRegister, rs: RVRegister, shamt: u32 },
+    Slli { rd: RVRegister, rs: RVRegister, shamt: u32 },
     Slt { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
+    Slti { rd: RVRegister, rs: RVRegister, imm: i32 },
+    Andi { rd: RVRegister, rs: RVRegister, imm: i32 },
+    Ori { rd: RVRegister, rs: RVRegister, imm: i32 },
+    Xori { rd: RVRegister, rs: RVRegister, imm: i32 },
     Sgt { rd: RVRegister, rs1: RVRegister, rs2: RVRegister },
     Seqz { rd: RVRegister, rs: RVRegister },
     Snez { rd: RVRegister, rs: RVRegister },
     // Branch instructions
     Bnez { rs: RVRegister, label: String },
+    Beqz { rs: RVRegister, label: String },
     J { label: String },
-    Call { label: String },
+    // `call label@plt` when `plt`, which is the same code relocated through the PLT
+    Call { label: String, plt: bool },
     Ret,
+    // Breakpoint trap, stops the simulator
+    Ebreak,
+    // System call, numbered by `a7`, taking its arguments from and returning in `a0`-`a2`
+    Ecall,
+    // `# text`, the source statement of the code that follows, no code of its own
+    Comment(String),
+}
+
+// Registers as bit masks of their numbers, for liveness over the generated code
+pub type RegisterSet = u32;
+
+pub fn register_set(registers: &[RVRegister]) -> RegisterSet {
+    registers.iter().fold(0, |set, &register| set | 1 << register_number(register)) & !1
+}
+
+impl Instruction {
+    // The register written by an instruction computing a single value
+    pub fn dest(&self) -> Option<RVRegister> {
+        match self {
+            Instruction::Addi { rd, .. } | Instruction::Li { rd, .. } | Instruction::Lw { rd, .. } |
+            Instruction::La { rd, .. } | Instruction::Lla { rd, .. } | Instruction::Mv { rd, .. } | Instruction::Add { rd, .. } |
+            Instruction::Sub { rd, .. } | Instruction::Mul { rd, .. } | Instruction::Mulh { rd, .. } |
+            Instruction::Div { rd, .. } | Instruction::Rem { rd, .. } | Instruction::And { rd, .. } |
+            Instruction::Or { rd, .. } | Instruction::Xor { rd, .. } | Instruction::Srai { rd, .. } |
+            Instruction::Sll { rd, .. } | Instruction::Srl { rd, .. } | Instruction::Sra { rd, .. } |
+            Instruction::Srli { rd, .. } | Instruction::Slli { rd, .. } | Instruction::Slt { rd, .. } | Instruction::Sgt { rd, .. } |
+            Instruction::Slti { rd, .. } | Instruction::Andi { rd, .. } | Instruction::Ori { rd, .. } | Instruction::Xori { rd, .. } |
+            Instruction::Seqz { rd, .. } | Instruction::Snez { rd, .. } => Some(*rd),
+            _ => None,
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut RVRegister> {
+        match self {
+            Instruction::Addi { rd, .. } | Instruction::Li { rd, .. } | Instruction::Lw { rd, .. } |
+            Instruction::La { rd, .. } | Instruction::Lla { rd, .. } | Instruction::Mv { rd, .. } | Instruction::Add { rd, .. } |
+            Instruction::Sub { rd, .. } | Instruction::Mul { rd, .. } | Instruction::Mulh { rd, .. } |
+            Instruction::Div { rd, .. } | Instruction::Rem { rd, .. } | Instruction::And { rd, .. } |
+            Instruction::Or { rd, .. } | Instruction::Xor { rd, .. } | Instruction::Srai { rd, .. } |
+            Instruction::Sll { rd, .. } | Instruction::Srl { rd, .. } | Instruction::Sra { rd, .. } |
+            Instruction::Srli { rd, .. } | Instruction::Slli { rd, .. } | Instruction::Slt { rd, .. } | Instruction::Sgt { rd, .. } |
+            Instruction::Slti { rd, .. } | Instruction::Andi { rd, .. } | Instruction::Ori { rd, .. } | Instruction::Xori { rd, .. } |
+            Instruction::Seqz { rd, .. } | Instruction::Snez { rd, .. } => Some(rd),
+            _ => None,
+        }
+    }
+
+    // Reads `to` wherever the instruction names `from` as an operand
+    pub fn replace_uses(&mut self, from: RVRegister, to: RVRegister) {
+        let replace = |register: &mut RVRegister| if *register == from { *register = to };
+        match self {
+            Instruction::Addi { rs, .. } | Instruction::Lw { rs, .. } | Instruction::Mv { rs, .. } |
+            Instruction::Srai { rs, .. } | Instruction::Srli { rs, .. } | Instruction::Slli { rs, .. } | Instruction::Seqz { rs, .. } |
+            Instruction::Slti { rs, .. } | Instruction::Andi { rs, .. } | Instruction::Ori { rs, .. } | Instruction::Xori { rs, .. } |
+            Instruction::Snez { rs, .. } | Instruction::Bnez { rs, .. } | Instruction::Beqz { rs, .. } => replace(rs),
+            Instruction::Sw { rs, rd, .. } => {
+                replace(rs);
+                replace(rd);
+            }
+            Instruction::Add { rs1, rs2, .. } | Instruction::Sub { rs1, rs2, .. } | Instruction::Mul { rs1, rs2, .. } |
+            Instruction::Mulh { rs1, rs2, .. } | Instruction::Div { rs1, rs2, .. } | Instruction::Rem { rs1, rs2, .. } |
+            Instruction::And { rs1, rs2, .. } | Instruction::Or { rs1, rs2, .. } | Instruction::Xor { rs1, rs2, .. } |
+            Instruction::Sll { rs1, rs2, .. } | Instruction::Srl { rs1, rs2, .. } | Instruction::Sra { rs1, rs2, .. } |
+            Instruction::Slt { rs1, rs2, .. } | Instruction::Sgt { rs1, rs2, .. } => {
+                replace(rs1);
+                replace(rs2);
+            }
+            _ => {}
+        }
+    }
+
+    // The registers read as operands, as written
+    pub fn operands(&self) -> Vec<RVRegister> {
+        match self {
+            Instruction::Li { .. } | Instruction::La { .. } | Instruction::Lla { .. } | Instruction::J { .. } | Instruction::Ebreak |
+            Instruction::Ecall | Instruction::Call { .. } | Instruction::Ret | Instruction::Comment(_) => vec![],
+            Instruction::Addi { rs, .. } | Instruction::Lw { rs, .. } | Instruction::Mv { rs, .. } |
+            Instruction::Srai { rs, .. } | Instruction::Srli { rs, .. } | Instruction::Slli { rs, .. } | Instruction::Seqz { rs, .. } |
+            Instruction::Slti { rs, .. } | Instruction::Andi { rs, .. } | Instruction::Ori { rs, .. } | Instruction::Xori { rs, .. } |
+            Instruction::Snez { rs, .. } | Instruction::Bnez { rs, .. } | Instruction::Beqz { rs, .. } => vec![*rs],
+            // `rd` is the base address
+            Instruction::Sw { rs, rd, .. } => vec![*rs, *rd],
+            Instruction::Add { rs1, rs2, .. } | Instruction::Sub { rs1, rs2, .. } | Instruction::Mul { rs1, rs2, .. } |
+            Instruction::Mulh { rs1, rs2, .. } | Instruction::Div { rs1, rs2, .. } | Instruction::Rem { rs1, rs2, .. } |
+            Instruction::And { rs1, rs2, .. } | Instruction::Or { rs1, rs2, .. } | Instruction::Xor { rs1, rs2, .. } |
+            Instruction::Sll { rs1, rs2, .. } | Instruction::Srl { rs1, rs2, .. } | Instruction::Sra { rs1, rs2, .. } |
+            Instruction::Slt { rs1, rs2, .. } | Instruction::Sgt { rs1, rs2, .. } => vec![*rs1, *rs2],
+        }
+    }
+
+    pub fn uses(&self) -> RegisterSet {
+        match self {
+            // Whichever arguments the callee takes
+            Instruction::Call { .. } => register_set(Riscv32::ARGUMENT_REGISTERS) | register_set(&[RVRegister::Sp]),
+            Instruction::Ret => register_set(&[RVRegister::A0, RVRegister::Ra]),
+            Instruction::Ecall => register_set(&[RVRegister::A0, RVRegister::A1, RVRegister::A2, RVRegister::A7]),
+            _ => register_set(&self.operands()),
+        }
+    }
+
+    pub fn defs(&self) -> RegisterSet {
+        match self {
+            // The caller-saved registers
+            Instruction::Call { .. } => register_set(Riscv32::ARGUMENT_REGISTERS) | register_set(&[
+                RVRegister::Ra, RVRegister::T0, RVRegister::T1, RVRegister::T2, RVRegister::T3,
+                RVRegister::T4, RVRegister::T5, RVRegister::T6,
+            ]),
+            Instruction::Ecall => register_set(&[RVRegister::A0]),
+            _ => self.dest().map_or(0, |rd| register_set(&[rd])),
+        }
+    }
 }
 
 // Impl Write for Instruction
@@ -35,24 +166,41 @@ impl std::fmt::Display for Instruction {
             Instruction::Li { rd, imm } => write!(f, "li {}, {}", rd, imm),
             Instruction::Lw { rd, rs, imm } => write!(f, "lw {}, {}({})", rd, imm, rs),
             Instruction::La { rd, label } => write!(f, "la {}, {}", rd, label),
+            Instruction::Lla { rd, label } => write!(f, "lla {}, {}", rd, label),
             Instruction::Sw { rs, rd, imm } => write!(f, "sw {}, {}({})", rs, imm, rd),
             Instruction::Mv { rd, rs } => write!(f, "mv {}, {}", rd, rs),
             Instruction::Add { rd, rs1, rs2 } => write!(f, "add {}, {}, {}", rd, rs1, rs2),
             Instruction::Sub { rd, rs1, rs2 } => write!(f, "sub {}, {}, {}", rd, rs1, rs2),
             Instruction::Mul { rd, rs1, rs2 } => write!(f, "mul {}, {}, {}", rd, rs1, rs2),
+            Instruction::Mulh { rd, rs1, rs2 } => write!(f, "mulh {}, {}, {}", rd, rs1, rs2),
             Instruction::Div { rd, rs1, rs2 } => write!(f, "div {}, {}, {}", rd, rs1, rs2),
             Instruction::Rem { rd, rs1, rs2 } => write!(f, "rem {}, {}, {}", rd, rs1, rs2),
             Instruction::And { rd, rs1, rs2 } => write!(f, "and {}, {}, {}", rd, rs1, rs2),
             Instruction::Or { rd, rs1, rs2 } => write!(f, "or {}, {}, {}", rd, rs1, rs2),
             Instruction::Xor { rd, rs1, rs2 } => write!(f, "xor {}, {}, {}", rd, rs1, rs2),
+            Instruction::Sll { rd, rs1, rs2 } => write!(f, "sll {}, {}, {}", rd, rs1, rs2),
+            Instruction::Srl { rd, rs1, rs2 } => write!(f, "srl {}, {}, {}", rd, rs1, rs2),
+            Instruction::Sra { rd, rs1, rs2 } => write!(f, "sra {}, {}, {}", rd, rs1, rs2),
+            Instruction::Srai { rd, rs, shamt } => write!(f, "srai {}, {}, {}", rd, rs, shamt),
+            Instruction::Srli { rd, rs, shamt } => write!(f, "srli {}, {}, {}", rd, rs, shamt),
+            Instruction::Slli { rd, rs, shamt } => write!(f, "slli {}, {}, {}", rd, rs, shamt),
             Instruction::Slt { rd, rs1, rs2 } => write!(f, "slt {}, {}, {}", rd, rs1, rs2),
+            Instruction::Slti { rd, rs, imm } => write!(f, "slti {}, {}, {}", rd, rs, imm),
+            Instruction::Andi { rd, rs, imm } => write!(f, "andi {}, {}, {}", rd, rs, imm),
+            Instruction::Ori { rd, rs, imm } => write!(f, "ori {}, {}, {}", rd, rs, imm),
+            Instruction::Xori { rd, rs, imm } => write!(f, "xori {}, {}, {}", rd, rs, imm),
             Instruction::Sgt { rd, rs1, rs2 } => write!(f, "sgt {}, {}, {}", rd, rs1, rs2),
             Instruction::Seqz { rd, rs } => write!(f, "seqz {}, {}", rd, rs),
             Instruction::Snez { rd, rs } => write!(f, "snez {}, {}", rd, rs),
             Instruction::Bnez { rs, label } => write!(f, "bnez {}, {}", rs, label),
+            Instruction::Beqz { rs, label } => write!(f, "beqz {}, {}", rs, label),
             Instruction::J { label } => write!(f, "j {}", label),
-            Instruction::Call { label } => write!(f, "call {}", label),
+            Instruction::Call { label, plt: false } => write!(f, "call {}", label),
+            Instruction::Call { label, plt: true } => write!(f, "call {}@plt", label),
             Instruction::Ret => write!(f, "ret"),
+            Instruction::Ebreak => write!(f, "ebreak"),
+            Instruction::Ecall => write!(f, "ecall"),
+            Instruction::Comment(text) => write!(f, "# {}", text),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use crate::backend::asm::{AsmBasicBlock, AsmFunction};
+use crate::backend::instruction::Instruction;
+
+// Orders the blocks of a function so that most jumps fall through, then removes the jumps to
+// the block right after. From the entry, every block is followed by a successor not placed
+// yet: the target of a `bnez`, the then-branch or the body of a loop being the likely one,
+// the branch inverted to fall through to it, or else the target of the final `j`. A block
+// left without one is followed by the first block not placed, in the original order.
+pub fn layout_blocks(function: &mut AsmFunction) {
+    let mut blocks = std::mem::take(&mut function.basic_blocks);
+    // Falling through to the next block is made explicit, as that block may move
+    for i in 0..blocks.len().saturating_sub(1) {
+        let falls_through = !blocks[i].is_exit && !matches!(blocks[i].instructions.last(), Some(Instruction::J { .. } | Instruction::Ebreak));
+        if falls_through {
+            let label = blocks[i + 1].label.clone().unwrap();
+            blocks[i].instructions.push(Instruction::J { label });
+        }
+    }
+    let index: HashMap<String, usize> = blocks.iter().enumerate()
+        .filter_map(|(i, bb)| bb.label.clone().map(|label| (label, i)))
+        .collect();
+
+    let mut placed = vec![false; blocks.len()];
+    let mut order = Vec::with_capacity(blocks.len());
+    let mut next = Some(0);
+    while order.len() < blocks.len() {
+        let i = next.filter(|&i| !placed[i]).unwrap_or_else(|| placed.iter().position(|&placed| !placed).unwrap());
+        placed[i] = true;
+        order.push(i);
+        next = successor(&mut blocks[i], &index, &placed);
+    }
+
+    let mut blocks: Vec<Option<AsmBasicBlock>> = blocks.into_iter().map(Some).collect();
+    function.basic_blocks = order.into_iter().map(|i| blocks[i].take().unwrap()).collect();
+    for i in 1..function.basic_blocks.len() {
+        let (before, after) = function.basic_blocks.split_at_mut(i);
+        let previous = &mut before[i - 1].instructions;
+        if matches!(previous.last(), Some(Instruction::J { label }) if after[0].label.as_ref() == Some(label)) {
+            previous.pop();
+        }
+    }
+}
+
+// The block to place after `block`, inverting its branch if that makes it fall through
+fn successor(block: &mut AsmBasicBlock, index: &HashMap<String, usize>, placed: &[bool]) -> Option<usize> {
+    let unplaced = |label: &String| index.get(label).copied().filter(|&i| !placed[i]);
+    match block.instructions.as_mut_slice() {
+        [.., Instruction::Bnez { rs, label: taken }, Instruction::J { label: not_taken }] => {
+            match unplaced(taken) {
+                Some(i) if taken != not_taken => {
+                    let (rs, taken, not_taken) = (*rs, taken.clone(), not_taken.clone());
+                    let len = block.instructions.len();
+                    block.instructions[len - 2] = Instruction::Beqz { rs, label: not_taken };
+                    block.instructions[len - 1] = Instruction::J { label: taken };
+                    Some(i)
+                }
+                _ => unplaced(not_taken),
+            }
+        }
+        [.., Instruction::J { label }] => unplaced(label),
+        _ => None,
+    }
+}
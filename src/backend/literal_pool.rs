@@ -0,0 +1,77 @@
+use koopa::ir::{FunctionData, ValueKind};
+use crate::backend::encode::split_hi_lo;
+use crate::backend::register::RVRegister;
+
+// Large constants kept in a `.rodata` pool shared by the functions of the module, each use
+// loading its word with one `lw` from a base register set up by the prologue, instead of a
+// `lui` + `addi` pair. A constant is stored once however many functions use it.
+#[derive(Debug, Clone)]
+pub struct LiteralPool {
+    pub label: String,
+    pub values: Vec<i32>,
+}
+
+// Callee-saved, so that the base survives the calls of the function
+pub const POOL_BASE: RVRegister = RVRegister::S11;
+
+// `lw` reaches the first 2 KiB of the pool
+const MAX_POOL_SIZE: usize = 512;
+
+impl Default for LiteralPool {
+    fn default() -> Self {
+        LiteralPool { label: ".Lliterals".into(), values: Vec::new() }
+    }
+}
+
+impl LiteralPool {
+    pub fn offset_of(&self, value: i32) -> Option<i32> {
+        self.values.iter().position(|&v| v == value).map(|index| index as i32 * 4)
+    }
+
+    // Whether `func_data` loads its large constants from the pool of the module, adding those
+    // it needs, and the pool as seen from the function if so.
+    // The cost model compares code and data sizes, in words. A constant built by `li` costs
+    // two words per use, a pooled one a word per use, and its slot in the pool unless another
+    // function already added it. On top of that the pool costs the `la` of the base, and saving
+    // and restoring it at every return.
+    pub fn plan(func_data: &FunctionData, module: &mut LiteralPool) -> Option<LiteralPool> {
+        let mut candidates: Vec<(i32, usize)> = Vec::new();
+        let mut returns = 0;
+        for value_data in func_data.dfg().values().values() {
+            match value_data.kind() {
+                ValueKind::Integer(int) if needs_two_words(int.value()) => {
+                    let uses = value_data.used_by().len();
+                    match candidates.iter_mut().find(|(value, _)| *value == int.value()) {
+                        Some((_, total)) => *total += uses,
+                        None => candidates.push((int.value(), uses)),
+                    }
+                }
+                ValueKind::Return(_) => returns += 1,
+                _ => {}
+            }
+        }
+
+        let (shared, new): (Vec<_>, Vec<_>) = candidates.into_iter()
+            .partition(|&(value, _)| module.offset_of(value).is_some());
+        // A new constant used once costs the same either way
+        let mut added: Vec<(i32, usize)> = new.into_iter().filter(|&(_, uses)| uses > 1).collect();
+        // Most used first, those are worth the most when the pool is full
+        added.sort_by_key(|&(value, uses)| (std::cmp::Reverse(uses), value));
+        added.truncate(MAX_POOL_SIZE - module.values.len());
+
+        let savings: usize = shared.iter().map(|&(_, uses)| uses).sum::<usize>()
+            + added.iter().map(|&(_, uses)| uses - 1).sum::<usize>();
+        let overhead = 2 + 1 + returns;
+        if savings <= overhead {
+            return None;
+        }
+        module.values.extend(added.into_iter().map(|(value, _)| value));
+        Some(module.clone())
+    }
+}
+
+// `li` expands to `lui` alone when the low 12 bits are zero
+fn needs_two_words(value: i32) -> bool {
+    let (_, lo) = split_hi_lo(value);
+    !(-(1 << 11)..(1 << 11)).contains(&value) && lo != 0
+}
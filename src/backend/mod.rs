@@ -1,10 +1,155 @@
-pub(crate) mod generate_asm;
-pub(crate) mod asm;
-pub(crate) mod register;
-pub(crate) mod instruction;
-pub(crate) mod environment;
-mod call_graph;
+use std::collections::HashMap;
+use koopa::ir::{Program, Value};
+use crate::common::alloc_scopes::AllocScopes;
+use crate::backend::asm::{AsmProgram, AsmSectionType};
+use crate::backend::bare_metal::MemoryLayout;
+use crate::backend::environment::AsmEnvironment;
+use crate::backend::generate_asm::GenerateAsm;
+use crate::backend::regalloc::RegisterAllocator;
+use crate::opt::OptLevel;
+
+pub mod asm;
+pub mod register;
+pub mod instruction;
+pub mod stack_map;
+pub mod slots;
+pub mod target;
+pub mod encode;
+pub mod object;
+pub mod runtime;
+pub mod soft_mul_div;
+pub mod literal_pool;
+pub mod bare_metal;
+pub mod division;
+pub mod regalloc;
+pub mod coloring;
+pub mod split;
+pub mod moves;
+pub mod layout;
+pub mod schedule;
+pub mod relax;
+pub mod compress;
+#[doc(hidden)]
+pub mod generate_asm;
+#[doc(hidden)]
+pub mod environment;
+pub mod call_graph;
 
 pub enum BackendError {
     Unimplemented,
-}
\ No newline at end of file
+}
+
+// Choices of the code generator that do not change the behavior of the program
+#[derive(Debug, Clone, Copy)]
+pub struct BackendOptions {
+    // Load large constants from a `.rodata` pool shared by the functions where the cost model finds it smaller
+    pub literal_pools: bool,
+    // Order of the sections in the assembly, every section listed once
+    pub section_order: [AsmSectionType; 4],
+    // Start the program at `_start` on a bare-metal target, rather than at `main` under a
+    // runtime. The linker script comes from the same layout.
+    pub memory_layout: Option<MemoryLayout>,
+    // Divide by constants with `mulh` sequences rather than `div`, see `division`
+    pub magic_division: bool,
+    // Remove the moves made redundant by the register allocation, see `moves`
+    pub eliminate_moves: bool,
+    // Order the blocks so that jumps fall through, see `layout`
+    pub layout_blocks: bool,
+    // Reorder the instructions of every block to hide latencies, see `schedule`
+    pub schedule: bool,
+    // How values are given registers, see `regalloc`
+    pub register_allocator: RegisterAllocator,
+    // Position-independent code, for PIE executables: addresses are taken with `lla`, which
+    // the assembler never turns into a GOT load as it may `la`. Calls are `call`, which is
+    // relocated through the PLT either way.
+    pub pic: bool,
+    // The M extension. Without it, the program is expected to be lowered by `soft_mul_div`,
+    // the offsets into arrays being computed with shifts, as `generate_code` does.
+    pub m_extension: bool,
+    // Write the compressed form of the instructions whose operands fit one, see `compress`
+    pub compressed: bool,
+    // Give the locals of disjoint scopes the same stack slot, see `slots`
+    pub share_stack_slots: bool,
+    // Describe the register allocation of every function, see `AsmProgram::allocation_reports`
+    pub report_allocation: bool,
+}
+
+impl Default for BackendOptions {
+    fn default() -> Self {
+        BackendOptions {
+            literal_pools: false,
+            section_order: [AsmSectionType::Data, AsmSectionType::Bss, AsmSectionType::Text, AsmSectionType::Rodata],
+            memory_layout: None,
+            magic_division: false,
+            eliminate_moves: false,
+            layout_blocks: false,
+            schedule: false,
+            register_allocator: RegisterAllocator::LinearScan,
+            pic: false,
+            m_extension: true,
+            compressed: false,
+            share_stack_slots: false,
+            report_allocation: false,
+        }
+    }
+}
+
+impl BackendOptions {
+    // With the code generation `opt_level` turns on, for the binary and `Compiler` alike: -O1
+    // removes redundant moves, orders the blocks and shares stack slots, -O2 also divides by
+    // constants with multiplications and schedules the instructions
+    pub fn at_level(self, opt_level: OptLevel) -> Self {
+        BackendOptions {
+            eliminate_moves: opt_level >= OptLevel::O1,
+            layout_blocks: opt_level >= OptLevel::O1,
+            magic_division: opt_level >= OptLevel::O2,
+            schedule: opt_level >= OptLevel::O2,
+            share_stack_slots: opt_level >= OptLevel::O1,
+            ..self
+        }
+    }
+}
+
+pub fn generate_asm(program: &Program, options: &BackendOptions) -> AsmProgram {
+    generate_asm_with_source_lines(program, options, &HashMap::new())
+}
+
+// As `generate_asm`, with a `# line 12: while (i < n)` comment before the code of every
+// statement, given the statement of the instructions, see `IRComments::source_lines`
+pub fn generate_asm_with_source_lines(program: &Program, options: &BackendOptions, source_lines: &HashMap<Value, String>) -> AsmProgram {
+    generate_asm_with(program, options, source_lines, &AllocScopes::default())
+}
+
+// As `generate_asm_with_source_lines`, given the scopes of the locals, which share stack slots
+// with `share_stack_slots`, see `frontend::generate_ir`
+pub fn generate_asm_with(program: &Program, options: &BackendOptions, source_lines: &HashMap<Value, String>, alloc_scopes: &AllocScopes) -> AsmProgram {
+    let mut asm_program = AsmProgram::default();
+    let mut env = AsmEnvironment::new(program, *options);
+    env.context.source_lines = Some(source_lines);
+    env.context.alloc_scopes = Some(alloc_scopes);
+    program.generate(&mut asm_program, &mut env);
+    asm_program.startup = options.memory_layout.is_some();
+    asm_program.sections.sort_by_key(|section| options.section_order.iter().position(|&kind| kind == section.section_type));
+    asm_program
+}
+
+// The code of `program` for the ISA of `options`, as the binary and `Compiler` generate it:
+// without the M extension, the multiplications and divisions are first lowered to calls of
+// the routines of `soft_mul_div`, which `link` adds
+pub fn generate_code(program: &mut Program, options: &BackendOptions, source_lines: &HashMap<Value, String>, alloc_scopes: &AllocScopes) -> AsmProgram {
+    if !options.m_extension {
+        soft_mul_div::lower(program);
+    }
+    generate_asm_with(program, options, source_lines, alloc_scopes)
+}
+
+// Adds to the code of `generate_code` the runtime library with `runtime`, and the routines
+// it calls without the M extension, those of the library included
+pub fn link(program: &mut AsmProgram, options: &BackendOptions, runtime: bool) {
+    if runtime {
+        runtime::link_runtime(program, options);
+    }
+    if !options.m_extension {
+        soft_mul_div::link(program, options);
+    }
+}
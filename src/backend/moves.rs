@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use crate::backend::asm::AsmFunction;
+use crate::backend::instruction::{register_set, Instruction, RegisterSet};
+use crate::backend::register::RVRegister;
+use crate::backend::target::{Riscv32, Target};
+
+// Removes the moves the code generator leaves, once registers are known: `mv r, r`, moves
+// whose destination is never read, and copies of a value computed just before into a
+// register, e.g. `add t0, ...; mv a0, t0` becoming `add a0, ...`. The readers of a copy read
+// the original while both are unchanged, chains `mv x, y; mv z, x` included, which leaves
+// the move dead when they were its only readers, e.g. a call result moved out of `a0` to be
+// stored.
+pub fn eliminate_moves(function: &mut AsmFunction) {
+    loop {
+        let live_out = live_out(function);
+        let mut changed = false;
+        for (bb, live_out) in function.basic_blocks.iter_mut().zip(live_out) {
+            changed |= simplify_block(&mut bb.instructions, live_out);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+// What the epilogue and the caller read: the result, and the registers the function preserves
+fn exit_live() -> RegisterSet {
+    register_set(&[Riscv32::RETURN_REGISTER, Riscv32::RETURN_ADDRESS, Riscv32::STACK_POINTER]) | register_set(Riscv32::CALLEE_SAVED)
+}
+
+// The registers live at the end of every block
+fn live_out(function: &AsmFunction) -> Vec<RegisterSet> {
+    let blocks = &function.basic_blocks;
+    let index: HashMap<&str, usize> = blocks.iter().enumerate()
+        .filter_map(|(i, bb)| bb.label.as_deref().map(|label| (label, i)))
+        .collect();
+    let successors: Vec<Vec<usize>> = blocks.iter().enumerate()
+        .map(|(i, bb)| {
+            let mut successors: Vec<usize> = bb.instructions.iter()
+                .filter_map(|inst| match inst {
+                    Instruction::J { label } | Instruction::Bnez { label, .. } | Instruction::Beqz { label, .. } => index.get(label.as_str()).copied(),
+                    _ => None,
+                })
+                .collect();
+            // A block without a jump at its end falls through
+            let falls_through = !bb.is_exit && !matches!(bb.instructions.last(), Some(Instruction::J { .. } | Instruction::Ebreak));
+            if falls_through && i + 1 < blocks.len() {
+                successors.push(i + 1);
+            }
+            successors
+        })
+        .collect();
+
+    let mut live_in: Vec<RegisterSet> = vec![0; blocks.len()];
+    let mut live_out: Vec<RegisterSet> = vec![0; blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..blocks.len()).rev() {
+            let out = successors[i].iter().fold(if blocks[i].is_exit { exit_live() } else { 0 }, |set, &succ| set | live_in[succ]);
+            let live = blocks[i].instructions.iter().rev().fold(out, |live, inst| (live & !inst.defs()) | inst.uses());
+            live_out[i] = out;
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+// One round of rewrites, each leaving the registers live elsewhere as they were
+fn simplify_block(instructions: &mut Vec<Instruction>, live_out: RegisterSet) -> bool {
+    // The registers live after every instruction
+    let mut live_after = vec![0; instructions.len()];
+    let mut live = live_out;
+    for (i, inst) in instructions.iter().enumerate().rev() {
+        live_after[i] = live;
+        live = (live & !inst.defs()) | inst.uses();
+    }
+
+    let is_dead = |register: RVRegister, live: RegisterSet| live & register_set(&[register]) == 0;
+    let mut removed = vec![false; instructions.len()];
+    let mut changed = false;
+    for i in 0..instructions.len() {
+        if let Instruction::Mv { rd, rs } = instructions[i] {
+            if rd == rs || is_dead(rd, live_after[i]) {
+                removed[i] = true;
+                changed = true;
+                continue;
+            }
+            // The instruction before, past source comments
+            let previous = (0..i).rev().find(|&j| !matches!(instructions[j], Instruction::Comment(_)));
+            if let Some(previous) = previous.filter(|&j| !removed[j] && rs != RVRegister::Zero && is_dead(rs, live_after[i]) && instructions[j].dest() == Some(rs)) {
+                // The value was computed for the move only
+                *instructions[previous].dest_mut().unwrap() = rd;
+                removed[i] = true;
+                changed = true;
+                continue;
+            }
+        }
+        let copy = instructions[i].operands().into_iter()
+            .find_map(|register| copied_from(instructions, &removed, i, register).map(|source| (register, source)));
+        if let Some((register, source)) = copy {
+            instructions[i].replace_uses(register, source);
+            changed = true;
+            // `source` is live for longer, the rest of the block waits for the next round
+            break;
+        }
+    }
+
+    let mut removed = removed.into_iter();
+    instructions.retain(|_| !removed.next().unwrap());
+    changed
+}
+
+// Whether `rs`, read at `at`, is still the copy of another register made by an earlier move
+fn copied_from(instructions: &[Instruction], removed: &[bool], at: usize, rs: RVRegister) -> Option<RVRegister> {
+    let rs_set = register_set(&[rs]);
+    for j in (0..at).rev() {
+        if removed[j] || instructions[j].defs() & rs_set == 0 {
+            continue;
+        }
+        let Instruction::Mv { rs: source, .. } = instructions[j] else { return None };
+        // Neither register changes in between
+        let changes = instructions[j + 1..at].iter().any(|inst| inst.defs() & register_set(&[source]) != 0);
+        return (source != rs && !changes).then_some(source);
+    }
+    None
+}
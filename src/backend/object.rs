@@ -0,0 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use crate::backend::asm::{AsmFunction, AsmGlobal, AsmProgram, AsmSectionType, AsmVariableInit};
+use crate::backend::encode::{self, EncodeError, Fixup};
+use crate::backend::instruction::Instruction;
+
+// Writes an ELF32 relocatable object for RV32IM, the output of `as -c` on the emitted assembly.
+// Branches between the blocks of a function are resolved here, calls and addresses of globals
+// are left to the linker as relocations.
+pub fn write_object(program: &AsmProgram) -> Result<Vec<u8>, EncodeError> {
+    Ok(assemble(program)?.finish())
+}
+
+// Writes a static ELF32 executable for Linux starting at `_start`, the object of `program`
+// linked on its own: the runtime library has to be part of it, see `runtime`
+pub fn write_executable(program: &AsmProgram) -> Result<Vec<u8>, EncodeError> {
+    assemble(program)?.link()
+}
+
+fn assemble(program: &AsmProgram) -> Result<ObjectBuilder, EncodeError> {
+    let functions: Vec<&AsmFunction> = globals_of(program, AsmSectionType::Text)
+        .filter_map(|global| match global {
+            AsmGlobal::AsmFunction(func) => Some(func),
+            AsmGlobal::AsmVariable(_) => None,
+        })
+        .collect();
+    let mut object = ObjectBuilder::default();
+    object.assemble_text(&functions)?;
+    object.assemble_data(program);
+    Ok(object)
+}
+
+fn globals_of(program: &AsmProgram, section_type: AsmSectionType) -> impl Iterator<Item = &AsmGlobal> {
+    program.sections.iter()
+        .filter(move |section| section.section_type == section_type)
+        .flat_map(|section| section.content.iter())
+}
+
+// ELF constants, see the System V ABI and the RISC-V ELF psABI
+const EM_RISCV: u16 = 243;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 0x1;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+const SHF_INFO_LINK: u32 = 0x40;
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const R_RISCV_CALL_PLT: u32 = 19;
+const R_RISCV_PCREL_HI20: u32 = 23;
+const R_RISCV_PCREL_LO12_I: u32 = 24;
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+const HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+// Where executables are loaded, as with GNU ld
+const BASE_ADDRESS: u32 = 0x10000;
+const PAGE_SIZE: u32 = 0x1000;
+
+// Section header indices, in the order the sections are written
+const TEXT_INDEX: u16 = 1;
+const DATA_INDEX: u16 = 2;
+const RODATA_INDEX: u16 = 3;
+const BSS_INDEX: u16 = 4;
+const SYMTAB_INDEX: u32 = 5;
+const STRTAB_INDEX: u32 = 6;
+
+struct Symbol {
+    name: String,
+    value: u32,
+    size: u32,
+    binding: u8,
+    kind: u8,
+    // 0 for undefined symbols
+    section: u16,
+}
+
+// A section header, the content is written right after the previous section
+struct Section<'a> {
+    name: &'a str,
+    kind: u32,
+    flags: u32,
+    // 0 in an object
+    address: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entry_size: u32,
+    content: &'a [u8],
+    // Not in the file, the size of `.bss`
+    reserved: u32,
+}
+
+struct Relocation {
+    offset: u32,
+    kind: u32,
+    symbol: String,
+}
+
+#[derive(Default)]
+struct ObjectBuilder {
+    text: Vec<u8>,
+    data: Vec<u8>,
+    rodata: Vec<u8>,
+    // The zeros `.bss` stands for, never written
+    bss: Vec<u8>,
+    symbols: Vec<Symbol>,
+    relocations: Vec<Relocation>,
+    // Number of `.Lpcrel_hi` labels so far
+    pcrel_anchors: usize,
+}
+
+// A flattened function body, in the order the assembly lists it
+enum Item<'a> {
+    Label(&'a str),
+    Inst(&'a Instruction),
+}
+
+fn items_of(func: &AsmFunction) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    for bb in func.basic_blocks.iter() {
+        if let Some(label) = &bb.label {
+            items.push(Item::Label(label));
+        }
+        if bb.is_entry {
+            items.extend(func.prologue.iter().map(Item::Inst));
+        }
+        items.extend(bb.instructions.iter().map(Item::Inst));
+        if bb.is_exit {
+            items.extend(func.epilogue.iter().map(Item::Inst));
+        }
+    }
+    items
+}
+
+impl ObjectBuilder {
+    fn assemble_text(&mut self, functions: &[&AsmFunction]) -> Result<(), EncodeError> {
+        let bodies: Vec<Vec<Item>> = functions.iter().map(|func| items_of(func)).collect();
+        let items: Vec<&Item> = bodies.iter().flatten().collect();
+
+        // Conditional branches start short and are made far while one is out of range.
+        // Growing a branch only moves the others apart, so this terminates.
+        let mut far = HashSet::new();
+        let (offsets, labels) = loop {
+            let (offsets, labels) = Self::layout(&items, &far)?;
+            let mut changed = false;
+            for (i, item) in items.iter().enumerate() {
+                if let Item::Inst(Instruction::Bnez { label, .. } | Instruction::Beqz { label, .. }) = item {
+                    let target = *labels.get(label.as_str()).ok_or_else(|| EncodeError::UndefinedLabel(label.clone()))?;
+                    if !far.contains(&i) && !encode::branch_in_range(target as i32 - offsets[i] as i32) {
+                        far.insert(i);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break (offsets, labels);
+            }
+        };
+
+        let function_labels: HashSet<&str> = functions.iter().map(|func| func.label.as_str()).collect();
+        for (i, item) in items.iter().enumerate() {
+            match item {
+                Item::Label(label) if !function_labels.contains(label) => {
+                    self.symbols.push(Symbol { name: label.to_string(), value: offsets[i], size: 0, binding: STB_LOCAL, kind: STT_NOTYPE, section: TEXT_INDEX });
+                }
+                Item::Label(_) => {}
+                Item::Inst(inst) => self.assemble_inst(inst, offsets[i], far.contains(&i), &labels)?,
+            }
+        }
+
+        let end = self.text.len() as u32;
+        for func in functions.iter() {
+            let start = labels[func.label.as_str()];
+            // Functions are laid out in order, each one ends where the next begins
+            let next = functions.iter().map(|other| labels[other.label.as_str()]).filter(|&offset| offset > start).min();
+            let size = next.unwrap_or(end) - start;
+            self.symbols.push(Symbol { name: func.label.clone(), value: start, size, binding: STB_GLOBAL, kind: STT_FUNC, section: TEXT_INDEX });
+        }
+        Ok(())
+    }
+
+    // The offset of every item, and of every label
+    fn layout<'a>(items: &[&'a Item], far: &HashSet<usize>) -> Result<(Vec<u32>, HashMap<&'a str, u32>), EncodeError> {
+        let mut offsets = Vec::with_capacity(items.len());
+        let mut labels = HashMap::new();
+        let mut offset = 0;
+        for (i, item) in items.iter().enumerate() {
+            offsets.push(offset);
+            match item {
+                Item::Label(label) => {
+                    labels.insert(*label, offset);
+                }
+                Item::Inst(inst) => offset += encode::encode(inst, far.contains(&i))?.size() as u32,
+            }
+        }
+        Ok((offsets, labels))
+    }
+
+    fn assemble_inst(&mut self, inst: &Instruction, offset: u32, far: bool, labels: &HashMap<&str, u32>) -> Result<(), EncodeError> {
+        let mut code = encode::encode(inst, far)?;
+        if let Some((index, fixup)) = code.fixup.take() {
+            let at = offset + index as u32 * 4;
+            let resolve = |label: &str| labels.get(label)
+                .map(|&target| target as i32 - at as i32)
+                .ok_or_else(|| EncodeError::UndefinedLabel(label.to_string()));
+            match fixup {
+                Fixup::Branch(label) => {
+                    // Ranges were checked by the layout
+                    code.words[index] = encode::patch_branch(code.words[index], resolve(&label)?);
+                }
+                Fixup::Jump(label) => {
+                    let distance = resolve(&label)?;
+                    if !encode::jump_in_range(distance) {
+                        return Err(EncodeError::OffsetOutOfRange(label));
+                    }
+                    code.words[index] = encode::patch_jump(code.words[index], distance);
+                }
+                Fixup::Call(symbol) => {
+                    self.relocations.push(Relocation { offset: at, kind: R_RISCV_CALL_PLT, symbol });
+                }
+                Fixup::PcrelAddress(symbol) => {
+                    // The low part refers to the `auipc` through a local label, as with `%pcrel_lo`
+                    let anchor = format!(".Lpcrel_hi{}", self.pcrel_anchors);
+                    self.pcrel_anchors += 1;
+                    self.symbols.push(Symbol { name: anchor.clone(), value: at, size: 0, binding: STB_LOCAL, kind: STT_NOTYPE, section: TEXT_INDEX });
+                    self.relocations.push(Relocation { offset: at, kind: R_RISCV_PCREL_HI20, symbol });
+                    self.relocations.push(Relocation { offset: at + 4, kind: R_RISCV_PCREL_LO12_I, symbol: anchor });
+                }
+            }
+        }
+        for word in code.words {
+            self.text.extend_from_slice(&word.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn assemble_data(&mut self, program: &AsmProgram) {
+        for (section_type, index) in [(AsmSectionType::Data, DATA_INDEX), (AsmSectionType::Rodata, RODATA_INDEX), (AsmSectionType::Bss, BSS_INDEX)] {
+            let bytes = match section_type {
+                AsmSectionType::Data => &mut self.data,
+                AsmSectionType::Rodata => &mut self.rodata,
+                _ => &mut self.bss,
+            };
+            for global in globals_of(program, section_type) {
+                if let AsmGlobal::AsmVariable(var) = global {
+                    let start = bytes.len();
+                    put_init(bytes, &var.init);
+                    let size = (bytes.len() - start) as u32;
+                    bytes.resize((start + size as usize).next_multiple_of(4), 0);
+                    let binding = if var.is_global { STB_GLOBAL } else { STB_LOCAL };
+                    self.symbols.push(Symbol { name: var.label.clone(), value: start as u32, size, binding, kind: STT_OBJECT, section: index });
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // Referenced but not defined here, e.g. the runtime library
+        let defined: HashSet<String> = self.symbols.iter().map(|symbol| symbol.name.clone()).collect();
+        let mut undefined: Vec<&String> = self.relocations.iter().map(|reloc| &reloc.symbol).filter(|name| !defined.contains(*name)).collect();
+        let mut seen = HashSet::new();
+        undefined.retain(|name| seen.insert(*name));
+        let undefined: Vec<Symbol> = undefined.into_iter()
+            .map(|name| Symbol { name: name.clone(), value: 0, size: 0, binding: STB_GLOBAL, kind: STT_NOTYPE, section: 0 })
+            .collect();
+        self.symbols.extend(undefined);
+        let (symtab, strtab, first_global) = self.symbol_table(|_| 0);
+
+        let symbol_indices: HashMap<&str, u32> = self.symbols.iter().enumerate()
+            .map(|(i, symbol)| (symbol.name.as_str(), i as u32 + 1))
+            .collect();
+        let mut rela = Vec::new();
+        for reloc in self.relocations.iter() {
+            put_u32(&mut rela, reloc.offset);
+            put_u32(&mut rela, symbol_indices[reloc.symbol.as_str()] << 8 | reloc.kind);
+            put_u32(&mut rela, 0);
+        }
+
+        let sections = [
+            Section { name: ".text", kind: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR, address: 0, link: 0, info: 0, align: 4, entry_size: 0, content: &self.text, reserved: 0 },
+            Section { name: ".data", kind: SHT_PROGBITS, flags: SHF_WRITE | SHF_ALLOC, address: 0, link: 0, info: 0, align: 4, entry_size: 0, content: &self.data, reserved: 0 },
+            Section { name: ".rodata", kind: SHT_PROGBITS, flags: SHF_ALLOC, address: 0, link: 0, info: 0, align: 4, entry_size: 0, content: &self.rodata, reserved: 0 },
+            Section { name: ".bss", kind: SHT_NOBITS, flags: SHF_WRITE | SHF_ALLOC, address: 0, link: 0, info: 0, align: 4, entry_size: 0, content: &[], reserved: self.bss.len() as u32 },
+            Section { name: ".symtab", kind: SHT_SYMTAB, flags: 0, address: 0, link: STRTAB_INDEX, info: first_global, align: 4, entry_size: 16, content: &symtab, reserved: 0 },
+            Section { name: ".strtab", kind: SHT_STRTAB, flags: 0, address: 0, link: 0, info: 0, align: 1, entry_size: 0, content: &strtab, reserved: 0 },
+            Section { name: ".rela.text", kind: SHT_RELA, flags: SHF_INFO_LINK, address: 0, link: SYMTAB_INDEX, info: TEXT_INDEX as u32, align: 4, entry_size: 12, content: &rela, reserved: 0 },
+        ];
+        let mut out = vec![0u8; HEADER_SIZE];
+        let (section_headers_offset, section_count) = put_sections(&mut out, &sections);
+        let header = elf_header(ET_REL, 0, 0, section_headers_offset, section_count);
+        out[..HEADER_SIZE].copy_from_slice(&header);
+        out
+    }
+
+    // Lays the sections out and resolves the relocations. The text is loaded with the
+    // headers, the data, the read-only data and `.bss` in a writable segment starting on the
+    // next page of the file, so that both are mapped as they are written.
+    fn link(mut self) -> Result<Vec<u8>, EncodeError> {
+        let text_offset = (HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE) as u32;
+        let data_offset = (text_offset + self.text.len() as u32).next_multiple_of(PAGE_SIZE);
+        let rodata_offset = data_offset + self.data.len() as u32;
+        let end_offset = rodata_offset + self.rodata.len() as u32;
+        let section_address = |section: u16| BASE_ADDRESS + match section {
+            TEXT_INDEX => text_offset,
+            DATA_INDEX => data_offset,
+            RODATA_INDEX => rodata_offset,
+            _ => end_offset,
+        };
+
+        let addresses: HashMap<&str, u32> = self.symbols.iter()
+            .map(|symbol| (symbol.name.as_str(), section_address(symbol.section) + symbol.value))
+            .collect();
+        let address = |name: &str| addresses.get(name).copied().ok_or_else(|| EncodeError::UndefinedSymbol(name.to_string()));
+        // The distance to its symbol from every `auipc` of an address, by the offset of the
+        // `auipc`, for the `addi` adding the low part
+        let mut distances = HashMap::new();
+        let mut patches = Vec::new();
+        for reloc in self.relocations.iter() {
+            let distance = match reloc.kind {
+                // The symbol is the label of the `auipc`
+                R_RISCV_PCREL_LO12_I => distances[&(address(&reloc.symbol)? - section_address(TEXT_INDEX))],
+                _ => address(&reloc.symbol)?.wrapping_sub(section_address(TEXT_INDEX) + reloc.offset) as i32,
+            };
+            let (hi, lo) = encode::split_hi_lo(distance);
+            match reloc.kind {
+                R_RISCV_CALL_PLT => {
+                    patches.push((reloc.offset, hi << 12));
+                    patches.push((reloc.offset + 4, (lo as u32) << 20));
+                }
+                R_RISCV_PCREL_HI20 => {
+                    patches.push((reloc.offset, hi << 12));
+                    distances.insert(reloc.offset, distance);
+                }
+                _ => patches.push((reloc.offset, (lo as u32) << 20)),
+            }
+        }
+        let entry = address("_start")?;
+        for (offset, bits) in patches {
+            let at = offset as usize;
+            let word = u32::from_le_bytes(self.text[at..at + 4].try_into().unwrap()) | bits;
+            self.text[at..at + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let (symtab, strtab, first_global) = self.symbol_table(section_address);
+        let sections = [
+            Section { name: ".text", kind: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR, address: section_address(TEXT_INDEX), link: 0, info: 0, align: 4, entry_size: 0, content: &self.text, reserved: 0 },
+            Section { name: ".data", kind: SHT_PROGBITS, flags: SHF_WRITE | SHF_ALLOC, address: section_address(DATA_INDEX), link: 0, info: 0, align: PAGE_SIZE, entry_size: 0, content: &self.data, reserved: 0 },
+            Section { name: ".rodata", kind: SHT_PROGBITS, flags: SHF_ALLOC, address: section_address(RODATA_INDEX), link: 0, info: 0, align: 4, entry_size: 0, content: &self.rodata, reserved: 0 },
+            Section { name: ".bss", kind: SHT_NOBITS, flags: SHF_WRITE | SHF_ALLOC, address: section_address(BSS_INDEX), link: 0, info: 0, align: 4, entry_size: 0, content: &[], reserved: self.bss.len() as u32 },
+            Section { name: ".symtab", kind: SHT_SYMTAB, flags: 0, address: 0, link: STRTAB_INDEX, info: first_global, align: 4, entry_size: 16, content: &symtab, reserved: 0 },
+            Section { name: ".strtab", kind: SHT_STRTAB, flags: 0, address: 0, link: 0, info: 0, align: 1, entry_size: 0, content: &strtab, reserved: 0 },
+        ];
+        let mut out = vec![0u8; text_offset as usize];
+        let (section_headers_offset, section_count) = put_sections(&mut out, &sections);
+
+        let mut program_headers = Vec::with_capacity(2 * PROGRAM_HEADER_SIZE);
+        let segments = [
+            (0, text_offset + self.text.len() as u32, 0, PF_R | PF_X),
+            (data_offset, end_offset - data_offset, self.bss.len() as u32, PF_R | PF_W),
+        ];
+        for (offset, file_size, reserved, flags) in segments {
+            for field in [PT_LOAD, offset, BASE_ADDRESS + offset, BASE_ADDRESS + offset, file_size, file_size + reserved, flags, PAGE_SIZE] {
+                put_u32(&mut program_headers, field);
+            }
+        }
+        let header = elf_header(ET_EXEC, entry, 2, section_headers_offset, section_count);
+        out[..HEADER_SIZE].copy_from_slice(&header);
+        out[HEADER_SIZE..text_offset as usize].copy_from_slice(&program_headers);
+        Ok(out)
+    }
+
+    // The symbol table and its strings, the local symbols first as ELF requires, and the
+    // index of the first global one. `section_address` gives the value of the symbols.
+    fn symbol_table(&mut self, section_address: impl Fn(u16) -> u32) -> (Vec<u8>, Vec<u8>, u32) {
+        self.symbols.sort_by_key(|symbol| symbol.binding != STB_LOCAL);
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for symbol in self.symbols.iter() {
+            let value = if symbol.section == 0 { 0 } else { section_address(symbol.section) + symbol.value };
+            put_u32(&mut symtab, add_string(&mut strtab, &symbol.name));
+            put_u32(&mut symtab, value);
+            put_u32(&mut symtab, symbol.size);
+            symtab.push(symbol.binding << 4 | symbol.kind);
+            symtab.push(0);
+            symtab.extend_from_slice(&symbol.section.to_le_bytes());
+        }
+        let first_global = self.symbols.iter().position(|symbol| symbol.binding != STB_LOCAL).unwrap_or(self.symbols.len()) as u32 + 1;
+        (symtab, strtab, first_global)
+    }
+}
+
+// Writes the sections, then their headers after the names of the sections, returning the
+// offset and the number of the headers
+fn put_sections(out: &mut Vec<u8>, sections: &[Section]) -> (u32, u16) {
+    let mut shstrtab = vec![0u8];
+    let mut headers = vec![0u8; 40];
+    let shstrtab_name = add_string(&mut shstrtab, ".shstrtab");
+    for section in sections.iter() {
+        let name = add_string(&mut shstrtab, section.name);
+        out.resize(out.len().next_multiple_of(section.align as usize), 0);
+        let offset = out.len() as u32;
+        out.extend_from_slice(section.content);
+        let size = section.content.len() as u32 + section.reserved;
+        for field in [name, section.kind, section.flags, section.address, offset, size, section.link, section.info, section.align, section.entry_size] {
+            put_u32(&mut headers, field);
+        }
+    }
+    let offset = out.len() as u32;
+    out.extend_from_slice(&shstrtab);
+    for field in [shstrtab_name, SHT_STRTAB, 0, 0, offset, shstrtab.len() as u32, 0, 0, 1, 0] {
+        put_u32(&mut headers, field);
+    }
+    out.resize(out.len().next_multiple_of(4), 0);
+    let headers_offset = out.len() as u32;
+    out.extend_from_slice(&headers);
+    (headers_offset, sections.len() as u16 + 2)
+}
+
+// 32-bit little-endian, soft-float ABI without compressed code, the section names last
+fn elf_header(kind: u16, entry: u32, program_headers: u16, section_headers_offset: u32, section_count: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    header.extend_from_slice(&kind.to_le_bytes());
+    header.extend_from_slice(&EM_RISCV.to_le_bytes());
+    put_u32(&mut header, 1);
+    put_u32(&mut header, entry);
+    put_u32(&mut header, if program_headers == 0 { 0 } else { HEADER_SIZE as u32 });
+    put_u32(&mut header, section_headers_offset);
+    put_u32(&mut header, 0);
+    for half in [HEADER_SIZE as u16, PROGRAM_HEADER_SIZE as u16, program_headers, 40, section_count, section_count - 1] {
+        header.extend_from_slice(&half.to_le_bytes());
+    }
+    header
+}
+
+fn put_init(out: &mut Vec<u8>, init: &AsmVariableInit) {
+    match init {
+        AsmVariableInit::Word(value) => out.extend_from_slice(&value.to_le_bytes()),
+        AsmVariableInit::Words(values) => values.iter().for_each(|value| out.extend_from_slice(&value.to_le_bytes())),
+        AsmVariableInit::Zero(size) => out.resize(out.len() + size, 0),
+        AsmVariableInit::Aggregate(pieces) => pieces.iter().for_each(|piece| put_init(out, piece)),
+        AsmVariableInit::Asciz(bytes) => {
+            out.extend_from_slice(bytes);
+            out.resize(out.len() + 1 + AsmVariableInit::asciz_padding(bytes), 0);
+        }
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+// Appends a NUL-terminated string, returning its offset
+fn add_string(table: &mut Vec<u8>, string: &str) -> u32 {
+    let offset = table.len() as u32;
+    table.extend_from_slice(string.as_bytes());
+    table.push(0);
+    offset
+}
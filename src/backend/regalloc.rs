@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use koopa::ir::{FunctionData, Value, ValueKind};
+use koopa::ir::entities::ValueData;
+use crate::backend::coloring;
+use crate::backend::split::{self, Occupancy, Span};
+use crate::backend::literal_pool::POOL_BASE;
+use crate::backend::register::{RVRegister, RVRegisterPool};
+use crate::backend::target::{Riscv32, Target};
+use crate::opt::liveness::Liveness;
+
+// The registers values may be given, in order of preference: the caller-saved ones are free,
+// the callee-saved ones cost a save and a restore, but survive calls. `s11` is taken by the
+// literal pool of the function when it has one.
+pub fn allocatable(literal_pool: bool) -> Vec<RVRegister> {
+    Riscv32::CALLER_SAVED.iter().chain(Riscv32::CALLEE_SAVED.iter()).copied()
+        .filter(|&register| !(literal_pool && register == POOL_BASE))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterAllocator {
+    // Quick, the default
+    LinearScan,
+    // Slower, with fewer moves and better spill choices, see `coloring`
+    GraphColoring,
+}
+
+impl RegisterAllocator {
+    pub const ALL: [RegisterAllocator; 2] = [RegisterAllocator::LinearScan, RegisterAllocator::GraphColoring];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegisterAllocator::LinearScan => "linear",
+            RegisterAllocator::GraphColoring => "color",
+        }
+    }
+}
+
+impl std::str::FromStr for RegisterAllocator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RegisterAllocator::ALL.iter().copied().find(|allocator| allocator.name() == s)
+            .ok_or_else(|| format!("unknown register allocator `{}`, expected one of: linear, color", s))
+    }
+}
+
+// `registers` as given by `allocatable`. The values left without a register are then split,
+// see `split`.
+pub fn allocate(func_data: &FunctionData, allocator: RegisterAllocator, registers: &[RVRegister]) -> Allocation {
+    let mut allocation = match allocator {
+        RegisterAllocator::LinearScan => linear_scan(func_data, registers),
+        RegisterAllocator::GraphColoring => coloring::graph_coloring(func_data, registers),
+    };
+    split::split_live_ranges(func_data, &mut allocation, registers);
+    allocation
+}
+
+// Where the values of a function are kept: a register for its whole lifetime, or a stack
+// slot for the spilled ones, with the segments of a block they are kept in a register over
+#[derive(Debug, Clone, Default)]
+pub struct Allocation {
+    pub(crate) registers: HashMap<Value, RVRegister>,
+    pub(crate) spilled: HashSet<Value>,
+    pub(crate) segments: Vec<Segment>,
+}
+
+// A value without a register kept in one over part of a block: loaded before `start`, or
+// computed in it when `start` is the value itself, and read from it up to `end`. Its slot is
+// kept up to date, the value being read from there again after `end`.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub value: Value,
+    pub register: RVRegister,
+    pub start: Value,
+    pub end: Value,
+}
+
+impl Allocation {
+    pub fn register(&self, value: Value) -> Option<RVRegister> {
+        self.registers.get(&value).copied()
+    }
+
+    pub fn is_spilled(&self, value: Value) -> bool {
+        self.spilled.contains(&value)
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    // The register `value` is computed in, when a segment starts at its definition
+    pub fn defining_segment(&self, value: Value) -> Option<RVRegister> {
+        self.segments.iter().find(|segment| segment.value == value && segment.start == value).map(|segment| segment.register)
+    }
+
+    // The callee-saved registers given to values, to be saved by the function, in order
+    pub fn callee_saved(&self) -> Vec<RVRegister> {
+        RVRegister::CALLEE_SAVED.iter().copied()
+            .filter(|register| self.registers.values().chain(self.segments.iter().map(|segment| &segment.register)).any(|other| other == register))
+            .collect()
+    }
+}
+
+// What the allocation of a function came to, printed by `--reg-report` to tune the allocators
+#[derive(Debug, Clone, Default)]
+pub struct AllocationReport {
+    pub function: String,
+    // The most values needing storage live at once, see `is_allocated`
+    pub max_live: usize,
+    pub spilled: usize,
+    // The segments spilled values are kept in a register over, see `split`
+    pub segments: usize,
+    // Caller-saved first, as given to values
+    pub registers: Vec<RVRegister>,
+}
+
+impl AllocationReport {
+    pub fn of(func_data: &FunctionData, allocation: &Allocation) -> Self {
+        let liveness = Liveness::compute(func_data);
+        let counted = |value: &Value| func_data.dfg().values().get(value).is_some_and(is_allocated);
+        let mut max_live = 0;
+        for &bb in func_data.layout().bbs().keys() {
+            max_live = max_live.max(liveness.live_out(bb).iter().filter(|value| counted(value)).count());
+            let mut insts = liveness.instructions(func_data, bb);
+            while let Some(inst) = insts.next_instruction() {
+                // The result is written while the values live after it are kept
+                let written = insts.live_out().iter().filter(|&&value| value != inst && counted(&value)).count() + usize::from(counted(&inst));
+                max_live = max_live.max(written).max(insts.live_in().iter().filter(|value| counted(value)).count());
+            }
+        }
+        let used = |register: &RVRegister| allocation.registers.values().chain(allocation.segments.iter().map(|segment| &segment.register)).any(|other| other == register);
+        AllocationReport {
+            function: func_data.name()[1..].to_string(),
+            max_live,
+            spilled: allocation.spilled.len(),
+            segments: allocation.segments.len(),
+            registers: Riscv32::CALLER_SAVED.iter().chain(Riscv32::CALLEE_SAVED.iter()).copied().filter(used).collect(),
+        }
+    }
+
+    // A table with a line for every function
+    pub fn render(reports: &[AllocationReport]) -> String {
+        let mut out = String::from("Register allocation:\n");
+        let width = reports.iter().map(|report| report.function.len()).max().unwrap_or(0).max("function".len());
+        writeln!(out, "  {:<width$}  max live  spilled  segments  registers", "function").unwrap();
+        for report in reports {
+            let registers: Vec<String> = report.registers.iter().map(RVRegister::to_string).collect();
+            writeln!(out, "  {:<width$}  {:>8}  {:>7}  {:>8}  {}", report.function, report.max_live, report.spilled, report.segments, registers.join(" ")).unwrap();
+        }
+        out
+    }
+}
+
+// The positions over which a value is live, in the layout order of the instructions: from its
+// definition to its last use, widened to the blocks it is live through. An instruction at `p`
+// reads its operands at `p` and defines its result at `p + 1`, parameters are defined at 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    pub value: Value,
+    pub start: usize,
+    pub end: usize,
+    // Live across a call, which clobbers the caller-saved registers
+    pub crosses_call: bool,
+}
+
+// A void call leaves nothing in `a0`, and an ignored result, e.g. of `getint();`, needs no storage
+pub fn has_call_result(value_data: &ValueData) -> bool {
+    !value_data.ty().is_unit() && !value_data.used_by().is_empty()
+}
+
+// The values needing storage of their own: parameters passed in registers and the results
+// of instructions, addresses included. Allocs live on the stack, constants are materialized where used.
+pub(crate) fn is_allocated(value_data: &ValueData) -> bool {
+    match value_data.kind() {
+        ValueKind::FuncArgRef(arg) => Riscv32::is_passed_in_register(arg.index()),
+        ValueKind::Binary(_) | ValueKind::Load(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) => true,
+        ValueKind::Call(_) => has_call_result(value_data),
+        _ => false,
+    }
+}
+
+// In the order of the parameters, then of the definitions, so that the allocation is the same on every run
+pub fn live_intervals(func_data: &FunctionData) -> Vec<Interval> {
+    let liveness = Liveness::compute(func_data);
+    let mut order: Vec<Value> = Vec::new();
+    let mut spans: HashMap<Value, (usize, usize)> = HashMap::new();
+    let extend = |spans: &mut HashMap<Value, (usize, usize)>, value: Value, position: usize| {
+        if let Some((start, end)) = spans.get_mut(&value) {
+            *start = (*start).min(position);
+            *end = (*end).max(position);
+        }
+    };
+    for &param in func_data.params() {
+        if is_allocated(func_data.dfg().value(param)) {
+            order.push(param);
+            spans.insert(param, (0, 0));
+        }
+    }
+
+    // Definitions first, a use may come before its definition in the layout
+    let mut calls = Vec::new();
+    let mut position = 1;
+    for (_, node) in func_data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            let value_data = func_data.dfg().value(inst);
+            if let ValueKind::Call(_) = value_data.kind() {
+                calls.push(position);
+            }
+            if is_allocated(value_data) {
+                order.push(inst);
+                spans.insert(inst, (position + 1, position + 1));
+            }
+            position += 2;
+        }
+    }
+    let mut position = 1;
+    for (&bb, node) in func_data.layout().bbs() {
+        for &value in liveness.live_in(bb) {
+            extend(&mut spans, value, position);
+        }
+        for &inst in node.insts().keys() {
+            for operand in func_data.dfg().value(inst).kind().value_uses() {
+                extend(&mut spans, operand, position);
+            }
+            position += 2;
+        }
+        for &value in liveness.live_out(bb) {
+            extend(&mut spans, value, position - 1);
+        }
+    }
+
+    order.into_iter()
+        .map(|value| {
+            let (start, end) = spans[&value];
+            // The first call after the start, `calls` being sorted
+            let next_call = calls.partition_point(|&call| call <= start);
+            let crosses_call = calls.get(next_call).is_some_and(|&call| call < end);
+            Interval { value, start, end, crosses_call }
+        })
+        .collect()
+}
+
+// Linear scan, after Poletto and Sarkar: the intervals are visited by their start, those
+// ended give their registers back, and when none is free the interval ending last is spilled.
+// Values live across a call only take callee-saved registers. Parameters stay in their
+// argument registers, unless live across a call, and the other values take the argument
+// register they are moved to or from when it is free.
+pub fn linear_scan(func_data: &FunctionData, registers: &[RVRegister]) -> Allocation {
+    let mut intervals = live_intervals(func_data);
+    intervals.sort_by_key(|interval| interval.start);
+
+    let mut allocation = Allocation::default();
+    let params: HashMap<Value, RVRegister> = func_data.params().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).enumerate()
+        .map(|(i, &param)| (param, Riscv32::argument_register(i)))
+        .collect();
+    let mut free = RVRegisterPool::new(registers);
+    for &register in params.values() {
+        free.take(register);
+    }
+    // The intervals holding a register, with whether it is a parameter's own
+    let mut active: Vec<(Interval, RVRegister, bool)> = Vec::new();
+
+    for interval in intervals {
+        active.retain(|&(other, register, _)| {
+            let ended = other.end < interval.start;
+            if ended {
+                free.release(register);
+            }
+            !ended
+        });
+
+        if let Some(&register) = params.get(&interval.value) {
+            if !interval.crosses_call {
+                allocation.registers.insert(interval.value, register);
+                active.push((interval, register, true));
+                continue;
+            }
+            // Moved to a callee-saved register at the entry, if any is left
+            free.release(register);
+        }
+
+        let usable = |register: RVRegister| !interval.crosses_call || register.is_callee_saved();
+        if let Some(register) = free.acquire_preferring(argument_register(func_data, interval.value), usable) {
+            allocation.registers.insert(interval.value, register);
+            active.push((interval, register, false));
+            continue;
+        }
+        // Registers ran out, the interval ending last gives way
+        let victim = active.iter().enumerate()
+            .filter(|(_, (_, register, fixed))| !fixed && usable(*register))
+            .max_by_key(|(_, (other, _, _))| other.end)
+            .map(|(index, _)| index);
+        match victim {
+            Some(index) if active[index].0.end > interval.end => {
+                let (other, register, _) = active.remove(index);
+                allocation.registers.remove(&other.value);
+                allocation.spilled.insert(other.value);
+                allocation.registers.insert(interval.value, register);
+                active.push((interval, register, false));
+            }
+            _ => {
+                allocation.spilled.insert(interval.value);
+            }
+        }
+    }
+    allocation
+}
+
+// The argument register a value is moved from as a call result, or to as an argument or the
+// result of the function, the first argument it is passed as if several
+fn argument_register(func_data: &FunctionData, value: Value) -> Option<RVRegister> {
+    let value_data = func_data.dfg().value(value);
+    if let ValueKind::Call(_) = value_data.kind() {
+        return Some(Riscv32::RETURN_REGISTER);
+    }
+    value_data.used_by().iter()
+        .filter_map(|&user| match func_data.dfg().value(user).kind() {
+            ValueKind::Call(call) => call.args().iter().take(Riscv32::ARGUMENT_REGISTERS.len()).position(|&arg| arg == value),
+            ValueKind::Return(_) => Some(0),
+            _ => None,
+        })
+        .min()
+        .map(Riscv32::argument_register)
+}
+
+// Checks that `allocation` keeps every value: no two values live at once share a register,
+// no value live across a call is in a register the call clobbers, and segments hold theirs
+// where no other value does, up to a call at the most
+pub fn check(func_data: &FunctionData, allocation: &Allocation) -> Result<(), String> {
+    let liveness = Liveness::compute(func_data);
+    let clash = |live: &HashSet<Value>, value: Value| -> Result<(), String> {
+        let Some(register) = allocation.register(value) else { return Ok(()) };
+        match live.iter().find(|&&other| other != value && allocation.register(other) == Some(register)) {
+            Some(_) => Err(format!("two values live at once share `{}`", register)),
+            None => Ok(()),
+        }
+    };
+    for (&bb, node) in func_data.layout().bbs() {
+        let mut live = liveness.live_out(bb).clone();
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        for &inst in insts.iter().rev() {
+            let value_data = func_data.dfg().value(inst);
+            live.remove(&inst);
+            clash(&live, inst)?;
+            if let ValueKind::Call(_) = value_data.kind() {
+                let clobbered = live.iter().filter_map(|&value| allocation.register(value)).find(|register| !register.is_callee_saved());
+                if let Some(register) = clobbered {
+                    return Err(format!("a value live across a call is in `{}`, which the call clobbers", register));
+                }
+            }
+            live.extend(value_data.kind().value_uses());
+        }
+    }
+    let entry = func_data.layout().entry_bb().unwrap();
+    for &param in func_data.params() {
+        if liveness.live_in(entry).contains(&param) {
+            clash(liveness.live_in(entry), param)?;
+        }
+    }
+
+    for (&bb, node) in func_data.layout().bbs() {
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        let position: HashMap<Value, usize> = insts.iter().enumerate().map(|(k, &inst)| (inst, k)).collect();
+        let mut occupancy = Occupancy::compute(func_data, &liveness, allocation, bb, &insts);
+        for segment in allocation.segments.iter() {
+            let (Some(&start), Some(&end)) = (position.get(&segment.start), position.get(&segment.end)) else { continue };
+            let span = Span { start, end, defined: segment.start == segment.value };
+            if !occupancy.is_free(span, segment.register) {
+                return Err(format!("a segment of a spilled value shares `{}`", segment.register));
+            }
+            occupancy.take(span, segment.register);
+            let crosses_call = insts[start..end].iter().any(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Call(_)));
+            if crosses_call {
+                return Err(format!("a segment of a spilled value holds `{}` across a call", segment.register));
+            }
+        }
+    }
+    Ok(())
+}
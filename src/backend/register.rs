@@ -1,34 +1,28 @@
-use std::collections::HashSet;
+use crate::backend::target::{Riscv32, Target};
 
-#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub enum RVRegister {
     Ra, Sp,
     A0, A1, A2, A3, A4, A5, A6, A7,
     T0, T1, T2, T3, T4, T5, T6,
+    S0, S1, S2, S3, S4, S5, S6, S7, S8, S9, S10, S11,
     Zero,
 }
 
 impl RVRegister {
-    pub fn is_temp(&self) -> bool {
-        match self {
-            RVRegister::T0 | RVRegister::T1 | RVRegister::T2 | RVRegister::T3 |
-            RVRegister::T4 | RVRegister::T5 | RVRegister::T6 => true,
-            _ => false,
-        }
+    // Preserved across calls, a function using one saves it in its prologue
+    pub const CALLEE_SAVED: [RVRegister; 12] = [
+        RVRegister::S0, RVRegister::S1, RVRegister::S2, RVRegister::S3,
+        RVRegister::S4, RVRegister::S5, RVRegister::S6, RVRegister::S7,
+        RVRegister::S8, RVRegister::S9, RVRegister::S10, RVRegister::S11,
+    ];
+
+    pub fn is_callee_saved(&self) -> bool {
+        RVRegister::CALLEE_SAVED.contains(self)
     }
 
-    pub fn get_arg_reg(index: usize) -> RVRegister {
-        match index {
-            0 => RVRegister::A0,
-            1 => RVRegister::A1,
-            2 => RVRegister::A2,
-            3 => RVRegister::A3,
-            4 => RVRegister::A4,
-            5 => RVRegister::A5,
-            6 => RVRegister::A6,
-            7 => RVRegister::A7,
-            _ => panic!("Invalid argument index: {}", index),
-        }
+    pub fn is_scratch(&self) -> bool {
+        Riscv32::SCRATCH.contains(self)
     }
 }
 
@@ -54,44 +48,83 @@ impl std::fmt::Display for RVRegister {
             RVRegister::T5 => write!(f, "t5"),
             RVRegister::T6 => write!(f, "t6"),
 
+            RVRegister::S0 => write!(f, "s0"),
+            RVRegister::S1 => write!(f, "s1"),
+            RVRegister::S2 => write!(f, "s2"),
+            RVRegister::S3 => write!(f, "s3"),
+            RVRegister::S4 => write!(f, "s4"),
+            RVRegister::S5 => write!(f, "s5"),
+            RVRegister::S6 => write!(f, "s6"),
+            RVRegister::S7 => write!(f, "s7"),
+            RVRegister::S8 => write!(f, "s8"),
+            RVRegister::S9 => write!(f, "s9"),
+            RVRegister::S10 => write!(f, "s10"),
+            RVRegister::S11 => write!(f, "s11"),
+
             RVRegister::Zero => write!(f, "x0"),
         }
     }
 }
 
-// TODO: Temporary solution
-// An iterator that iterates over t0-t6
-
+// Registers handed out in a fixed order of priority, rather than by their names, so that the
+// output is the same on every run. The code generator's pool holds the scratch registers
+// `t4`-`t6`, for operands loaded from the stack and large offsets; `regalloc` keeps the
+// registers it gives values in one, a value taking the register it prefers when that is free.
 #[derive(Clone)]
 pub struct RVRegisterPool {
-    avail: HashSet<RVRegister>
+    // By priority, with whether each is free
+    registers: Vec<(RVRegister, bool)>,
 }
 
 impl RVRegisterPool {
-    pub fn new_temp_pool() -> Self {
+    pub fn new(registers: &[RVRegister]) -> Self {
         RVRegisterPool {
-            avail: vec![
-                RVRegister::T0, RVRegister::T1, RVRegister::T2, RVRegister::T3,
-                RVRegister::T4, RVRegister::T5, RVRegister::T6
-            ].into_iter().collect()
+            registers: registers.iter().map(|&register| (register, true)).collect(),
         }
     }
 
-    pub fn next(&mut self) -> Option<RVRegister> {
-        let register = self.avail.iter().next().cloned();
-        if let Some(register) = register {
-            // println!("Allocating register: {}", register);
-            self.avail.remove(&register);
+    pub fn new_scratch_pool() -> Self {
+        RVRegisterPool::new(Riscv32::SCRATCH)
+    }
+
+    pub fn acquire(&mut self) -> Option<RVRegister> {
+        self.acquire_where(|_| true)
+    }
+
+    // The free register of highest priority satisfying `usable`
+    pub fn acquire_where(&mut self, usable: impl Fn(RVRegister) -> bool) -> Option<RVRegister> {
+        let (register, free) = self.registers.iter_mut().find(|(register, free)| *free && usable(*register))?;
+        *free = false;
+        Some(*register)
+    }
+
+    // `preferred` if it is free, the free register of highest priority satisfying `usable` otherwise
+    pub fn acquire_preferring(&mut self, preferred: Option<RVRegister>, usable: impl Fn(RVRegister) -> bool) -> Option<RVRegister> {
+        match preferred {
+            Some(register) if usable(register) && self.take(register) => Some(register),
+            _ => self.acquire_where(usable),
+        }
+    }
+
+    // Takes `register` out of the pool, returning whether it was free
+    pub fn take(&mut self, register: RVRegister) -> bool {
+        match self.registers.iter_mut().find(|(other, free)| *other == register && *free) {
+            Some((_, free)) => {
+                *free = false;
+                true
+            }
+            None => false,
         }
-        register
     }
 
+    pub fn is_free(&self, register: RVRegister) -> bool {
+        self.registers.contains(&(register, true))
+    }
+
+    // Registers of values are not the pool's, releasing them does nothing
     pub fn release(&mut self, register: RVRegister) {
-        if register.is_temp() {
-            // println!("Releasing register: {}", register);
-            self.avail.insert(register);
-        } else {
-            // println!("Trying to release a non-temporary register: {}", register);
+        if let Some((_, free)) = self.registers.iter_mut().find(|(other, _)| *other == register) {
+            *free = true;
         }
     }
 }
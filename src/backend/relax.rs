@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use crate::backend::asm::{AsmBasicBlock, AsmFunction};
+use crate::backend::encode;
+use crate::backend::instruction::Instruction;
+
+// Rewrites the conditional branches whose targets are out of the ±4 KiB of a B-type offset,
+// which the assembler would reject: `bnez rs, label` becomes `beqz rs, .Lskip; j label`, and
+// `beqz` the other way around, the rest of the block going to a new block `.Lskip`. Every
+// rewrite only moves the others apart, so the branches are measured again until none is out
+// of range.
+pub fn relax_branches(function: &mut AsmFunction) {
+    let mut count = 0;
+    while let Some((bb, i)) = far_branch(function) {
+        let skip = format!(".L{}_far{}", function.label, count);
+        count += 1;
+        let block = &mut function.basic_blocks[bb];
+        let rest = block.instructions.split_off(i + 1);
+        let (inverted, label) = match block.instructions.pop() {
+            Some(Instruction::Bnez { rs, label }) => (Instruction::Beqz { rs, label: skip.clone() }, label),
+            Some(Instruction::Beqz { rs, label }) => (Instruction::Bnez { rs, label: skip.clone() }, label),
+            _ => unreachable!(),
+        };
+        block.instructions.push(inverted);
+        block.instructions.push(Instruction::J { label });
+        // The epilogue follows the rest
+        let is_exit = std::mem::take(&mut block.is_exit);
+        function.basic_blocks.insert(bb + 1, AsmBasicBlock {
+            label: Some(skip),
+            instructions: rest,
+            is_entry: false,
+            is_exit,
+        });
+    }
+}
+
+// The first branch out of range, by its block and index
+fn far_branch(function: &AsmFunction) -> Option<(usize, usize)> {
+    // An instruction the encoder rejects is an error of the assembler either way
+    let size = |inst: &Instruction| encode::encode(inst, false).map_or(4, |code| code.size()) as i32;
+    let block_size = |bb: &AsmBasicBlock| {
+        let prologue = if bb.is_entry { function.prologue.as_slice() } else { &[] };
+        let epilogue = if bb.is_exit { function.epilogue.as_slice() } else { &[] };
+        prologue.iter().chain(bb.instructions.iter()).chain(epilogue.iter()).map(size).sum::<i32>()
+    };
+
+    let mut labels: HashMap<&str, i32> = HashMap::new();
+    let mut offset = 0;
+    for bb in function.basic_blocks.iter() {
+        if let Some(label) = &bb.label {
+            labels.insert(label, offset);
+        }
+        offset += block_size(bb);
+    }
+
+    let mut offset = 0;
+    for (bb, block) in function.basic_blocks.iter().enumerate() {
+        if block.is_entry {
+            offset += function.prologue.iter().map(size).sum::<i32>();
+        }
+        for (i, inst) in block.instructions.iter().enumerate() {
+            if let Instruction::Bnez { label, .. } | Instruction::Beqz { label, .. } = inst {
+                // Labels of other functions are not branched to
+                let far = labels.get(label.as_str()).is_some_and(|&target| !encode::branch_in_range(target - offset));
+                if far {
+                    return Some((bb, i));
+                }
+            }
+            offset += size(inst);
+        }
+        if block.is_exit {
+            offset += function.epilogue.iter().map(size).sum::<i32>();
+        }
+    }
+    None
+}
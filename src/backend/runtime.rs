@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use koopa::front::Driver;
+use crate::backend::{self, soft_mul_div, BackendOptions};
+use crate::backend::asm::{AsmBasicBlock, AsmFunction, AsmGlobal, AsmProgram, AsmSection, AsmSectionType};
+use crate::backend::instruction::Instruction;
+use crate::backend::register::RVRegister;
+
+// The SysY runtime library bundled into `--emit=exe`, for Linux on RV32. It is written in
+// Koopa IR, as SysY has no pointers to pass to the kernel, and compiled along with the
+// program. Only `__sysy_syscall` is written in assembly. Output is unbuffered, one `write` a
+// character, and characters are kept one a word, which is their first byte on a little-endian
+// machine.
+const SOURCE: &str = "
+decl @__sysy_syscall(i32, i32, *i32, i32): i32
+
+global @__sysy_byte = alloc i32, zeroinit
+// A character read too far by `getint`, -2 for none
+global @__sysy_unread = alloc i32, -2
+
+fun @getch(): i32 {
+%entry:
+  %unread = load @__sysy_unread
+  %has_unread = ne %unread, -2
+  br %has_unread, %reread, %read
+%reread:
+  store -2, @__sysy_unread
+  ret %unread
+%read:
+  store 0, @__sysy_byte
+  %count = call @__sysy_syscall(63, 0, @__sysy_byte, 1)
+  %has_byte = eq %count, 1
+  br %has_byte, %byte, %eof
+%byte:
+  %c = load @__sysy_byte
+  ret %c
+%eof:
+  ret -1
+}
+
+fun @getint(): i32 {
+%entry:
+  %c = alloc i32
+  %negative = alloc i32
+  %value = alloc i32
+  %first = call @getch()
+  store %first, %c
+  jump %space
+%space:
+  %s = load %c
+  %is_blank = eq %s, 32
+  %above_tab = ge %s, 9
+  %below_cr = le %s, 13
+  %is_control = and %above_tab, %below_cr
+  %is_space = or %is_blank, %is_control
+  br %is_space, %skip, %sign
+%skip:
+  %next_space = call @getch()
+  store %next_space, %c
+  jump %space
+%sign:
+  %is_minus = eq %s, 45
+  %is_plus = eq %s, 43
+  %is_sign = or %is_minus, %is_plus
+  store %is_minus, %negative
+  br %is_sign, %signed, %number
+%signed:
+  %after_sign = call @getch()
+  store %after_sign, %c
+  jump %number
+%number:
+  store 0, %value
+  jump %digits
+%digits:
+  %d = load %c
+  %above_zero = ge %d, 48
+  %below_nine = le %d, 57
+  %is_digit = and %above_zero, %below_nine
+  br %is_digit, %digit, %done
+%digit:
+  %old = load %value
+  %shifted = mul %old, 10
+  %digit_value = sub %d, 48
+  %new = add %shifted, %digit_value
+  store %new, %value
+  %next_digit = call @getch()
+  store %next_digit, %c
+  jump %digits
+%done:
+  store %d, @__sysy_unread
+  %result = load %value
+  %is_negative = load %negative
+  br %is_negative, %negate, %positive
+%negate:
+  %negated = sub 0, %result
+  ret %negated
+%positive:
+  ret %result
+}
+
+fun @getarray(%a: *i32): i32 {
+%entry:
+  %i = alloc i32
+  %n = call @getint()
+  store 0, %i
+  jump %loop
+%loop:
+  %index = load %i
+  %more = lt %index, %n
+  br %more, %body, %end
+%body:
+  %value = call @getint()
+  %element = getptr %a, %index
+  store %value, %element
+  %next = add %index, 1
+  store %next, %i
+  jump %loop
+%end:
+  ret %n
+}
+
+fun @putch(%c: i32) {
+%entry:
+  store %c, @__sysy_byte
+  %count = call @__sysy_syscall(64, 1, @__sysy_byte, 1)
+  ret
+}
+
+fun @putint(%x: i32) {
+%entry:
+  %digits = alloc [i32, 10]
+  %rest = alloc i32
+  %n = alloc i32
+  %is_negative = lt %x, 0
+  br %is_negative, %minus, %plus
+%minus:
+  call @putch(45)
+  store %x, %rest
+  jump %convert
+%plus:
+  // Negated, as -2147483648 has no positive counterpart
+  %negated = sub 0, %x
+  store %negated, %rest
+  jump %convert
+%convert:
+  store 0, %n
+  jump %divide
+%divide:
+  %value = load %rest
+  %count = load %n
+  %remainder = mod %value, 10
+  %digit = sub 0, %remainder
+  %slot = getelemptr %digits, %count
+  store %digit, %slot
+  %quotient = div %value, 10
+  store %quotient, %rest
+  %new_count = add %count, 1
+  store %new_count, %n
+  %more = ne %quotient, 0
+  br %more, %divide, %print
+%print:
+  %left = load %n
+  %any = gt %left, 0
+  br %any, %print_digit, %end
+%print_digit:
+  %index = sub %left, 1
+  store %index, %n
+  %digit_slot = getelemptr %digits, %index
+  %d = load %digit_slot
+  %char = add %d, 48
+  call @putch(%char)
+  jump %print
+%end:
+  ret
+}
+
+fun @putarray(%n: i32, %a: *i32) {
+%entry:
+  %i = alloc i32
+  call @putint(%n)
+  call @putch(58)
+  store 0, %i
+  jump %loop
+%loop:
+  %index = load %i
+  %more = lt %index, %n
+  br %more, %body, %end
+%body:
+  call @putch(32)
+  %element = getptr %a, %index
+  %value = load %element
+  call @putint(%value)
+  %next = add %index, 1
+  store %next, %i
+  jump %loop
+%end:
+  call @putch(10)
+  ret
+}
+
+// The byte at `index` of a string, packed four a word
+fun @__sysy_byte_at(%s: *i32, %index: i32): i32 {
+%entry:
+  %word_index = shr %index, 2
+  %word_slot = getptr %s, %word_index
+  %word = load %word_slot
+  %byte_index = and %index, 3
+  %shift = shl %byte_index, 3
+  %shifted = shr %word, %shift
+  %byte = and %shifted, 255
+  ret %byte
+}
+
+// The variadic arguments are taken as the parameters after the format, so only the first 15
+// are printed, the next conversions printing whatever is in memory after them
+fun @putf(%format: *i32, %v0: i32, %v1: i32, %v2: i32, %v3: i32, %v4: i32, %v5: i32, %v6: i32, %v7: i32, %v8: i32, %v9: i32, %v10: i32, %v11: i32, %v12: i32, %v13: i32, %v14: i32) {
+%entry:
+  %values = alloc [i32, 15]
+  %i = alloc i32
+  %n = alloc i32
+  %slot_0 = getelemptr %values, 0
+  store %v0, %slot_0
+  %slot_1 = getelemptr %values, 1
+  store %v1, %slot_1
+  %slot_2 = getelemptr %values, 2
+  store %v2, %slot_2
+  %slot_3 = getelemptr %values, 3
+  store %v3, %slot_3
+  %slot_4 = getelemptr %values, 4
+  store %v4, %slot_4
+  %slot_5 = getelemptr %values, 5
+  store %v5, %slot_5
+  %slot_6 = getelemptr %values, 6
+  store %v6, %slot_6
+  %slot_7 = getelemptr %values, 7
+  store %v7, %slot_7
+  %slot_8 = getelemptr %values, 8
+  store %v8, %slot_8
+  %slot_9 = getelemptr %values, 9
+  store %v9, %slot_9
+  %slot_10 = getelemptr %values, 10
+  store %v10, %slot_10
+  %slot_11 = getelemptr %values, 11
+  store %v11, %slot_11
+  %slot_12 = getelemptr %values, 12
+  store %v12, %slot_12
+  %slot_13 = getelemptr %values, 13
+  store %v13, %slot_13
+  %slot_14 = getelemptr %values, 14
+  store %v14, %slot_14
+  store 0, %i
+  store 0, %n
+  jump %loop
+%loop:
+  %index = load %i
+  %c = call @__sysy_byte_at(%format, %index)
+  br %c, %byte, %end
+%byte:
+  %next = add %index, 1
+  store %next, %i
+  %is_percent = eq %c, 37
+  br %is_percent, %conversion, %literal
+%literal:
+  call @putch(%c)
+  jump %loop
+%conversion:
+  %kind = call @__sysy_byte_at(%format, %next)
+  %after = add %next, 1
+  store %after, %i
+  %is_escape = eq %kind, 37
+  br %is_escape, %percent, %value
+%percent:
+  call @putch(37)
+  jump %loop
+%value:
+  br %kind, %print_value, %end
+%print_value:
+  %count = load %n
+  %slot = getelemptr %values, %count
+  %v = load %slot
+  %new_count = add %count, 1
+  store %new_count, %n
+  %is_int = eq %kind, 100
+  br %is_int, %int, %char
+%int:
+  call @putint(%v)
+  jump %loop
+%char:
+  call @putch(%v)
+  jump %loop
+%end:
+  ret
+}
+
+fun @starttime() {
+%entry:
+  ret
+}
+
+fun @stoptime() {
+%entry:
+  ret
+}
+";
+
+const SYSCALL: &str = "__sysy_syscall";
+const SYS_EXIT: i32 = 93;
+
+// Adds the runtime library and `_start` to `program`, which then links on its own, along with
+// the routines of `soft_mul_div` without the M extension
+pub fn link_runtime(program: &mut AsmProgram, options: &BackendOptions) {
+    let mut runtime = Driver::from(SOURCE).generate_program().expect("the runtime library is valid IR");
+    if !options.m_extension {
+        soft_mul_div::lower(&mut runtime);
+    }
+    let mut runtime = backend::generate_asm(&runtime, &BackendOptions { m_extension: options.m_extension, ..BackendOptions::default() });
+    for global in runtime.sections.iter_mut().flat_map(|section| section.content.iter_mut()) {
+        if let AsmGlobal::AsmFunction(func) = global {
+            rename_blocks(func, ".L__sysy_");
+        }
+    }
+    program.sections.append(&mut runtime.sections);
+    program.sections.push(AsmSection {
+        section_type: AsmSectionType::Text,
+        content: vec![AsmGlobal::AsmFunction(syscall()), AsmGlobal::AsmFunction(start())],
+    });
+}
+
+// Gives the blocks names of their own, starting with `prefix`, as the program has its own
+// `func_1` and so on
+pub(crate) fn rename_blocks(func: &mut AsmFunction, prefix: &str) {
+    let renamed: HashMap<String, String> = func.basic_blocks.iter()
+        .filter_map(|bb| bb.label.clone())
+        .filter(|label| *label != func.label)
+        .map(|label| (label.clone(), format!("{}{}", prefix, label)))
+        .collect();
+    for bb in func.basic_blocks.iter_mut() {
+        if let Some(label) = bb.label.as_mut().filter(|label| renamed.contains_key(*label)) {
+            *label = renamed[label].clone();
+        }
+        for inst in bb.instructions.iter_mut() {
+            if let Instruction::Bnez { label, .. } | Instruction::Beqz { label, .. } | Instruction::J { label } = inst {
+                if let Some(new) = renamed.get(label) {
+                    *label = new.clone();
+                }
+            }
+        }
+    }
+}
+
+// `__sysy_syscall(number, a0, a1, a2)`, passing the arguments on to the kernel
+fn syscall() -> AsmFunction {
+    let mut func = AsmFunction::new(SYSCALL);
+    let mut bb = AsmBasicBlock::new(SYSCALL);
+    bb.is_entry = true;
+    bb.is_exit = true;
+    bb.add_instruction(Instruction::Mv { rd: RVRegister::A7, rs: RVRegister::A0 });
+    bb.add_instruction(Instruction::Mv { rd: RVRegister::A0, rs: RVRegister::A1 });
+    bb.add_instruction(Instruction::Mv { rd: RVRegister::A1, rs: RVRegister::A2 });
+    bb.add_instruction(Instruction::Mv { rd: RVRegister::A2, rs: RVRegister::A3 });
+    bb.add_instruction(Instruction::Ecall);
+    func.basic_blocks.push(bb);
+    func.epilogue.push(Instruction::Ret);
+    func
+}
+
+// The entry point: exits with the result of `main`, the stack being set up by the kernel
+fn start() -> AsmFunction {
+    let mut func = AsmFunction::new("_start");
+    let mut bb = AsmBasicBlock::new("_start");
+    bb.is_entry = true;
+    bb.add_instruction(Instruction::Call { label: "main".to_string(), plt: false });
+    bb.add_instruction(Instruction::Li { rd: RVRegister::A7, imm: SYS_EXIT });
+    bb.add_instruction(Instruction::Ecall);
+    func.basic_blocks.push(bb);
+    func
+}
@@ -0,0 +1,174 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::backend::asm::AsmFunction;
+use crate::backend::instruction::{Instruction, RegisterSet};
+
+// List scheduling within every block, once registers are known, so that the instructions
+// waiting for a load or a multiplication are moved after independent ones. An instruction
+// follows those whose registers it reads or writes, or that read the registers it writes,
+// and stores stay in order with the other memory accesses. Calls and jumps are barriers.
+// Among the instructions whose operands are ready first, the one with the longest path to
+// the end of the block goes first, the original order breaking ties. A source comment goes
+// before the first of the instructions it was written for.
+pub fn schedule(function: &mut AsmFunction) {
+    for bb in function.basic_blocks.iter_mut() {
+        // The comments, with the index of the first instruction after them, those written for
+        // instructions since removed kept with the next
+        let mut comments: Vec<(usize, Vec<Instruction>)> = Vec::new();
+        let mut instructions = Vec::with_capacity(bb.instructions.len());
+        for inst in std::mem::take(&mut bb.instructions) {
+            match inst {
+                Instruction::Comment(_) => match comments.last_mut() {
+                    Some((start, group)) if *start == instructions.len() => group.push(inst),
+                    _ => comments.push((instructions.len(), vec![inst])),
+                },
+                _ => instructions.push(inst),
+            }
+        }
+        let order = schedule_block(&instructions);
+
+        // The statement of every instruction, numbered by its comments
+        let mut statement: Vec<Option<usize>> = Vec::with_capacity(instructions.len());
+        let mut next = 0;
+        for i in 0..instructions.len() {
+            while next < comments.len() && comments[next].0 <= i {
+                next += 1;
+            }
+            statement.push(next.checked_sub(1));
+        }
+        let mut comments: Vec<Vec<Instruction>> = comments.into_iter().map(|(_, group)| group).collect();
+        let mut instructions: Vec<Option<Instruction>> = instructions.into_iter().map(Some).collect();
+        for i in order {
+            if let Some(c) = statement[i] {
+                bb.instructions.append(&mut comments[c]);
+            }
+            bb.instructions.push(instructions[i].take().unwrap());
+        }
+        // Those written for no instruction at all
+        bb.instructions.extend(comments.into_iter().flatten());
+    }
+}
+
+// Cycles until the result can be read, on a simple in-order pipeline
+fn latency(inst: &Instruction) -> u32 {
+    match inst {
+        Instruction::Lw { .. } => 2,
+        Instruction::Mul { .. } | Instruction::Mulh { .. } => 3,
+        Instruction::Div { .. } | Instruction::Rem { .. } => 10,
+        _ => 1,
+    }
+}
+
+fn is_barrier(inst: &Instruction) -> bool {
+    matches!(inst, Instruction::Call { .. } | Instruction::Bnez { .. } | Instruction::Beqz { .. } |
+        Instruction::J { .. } | Instruction::Ret | Instruction::Ebreak | Instruction::Ecall)
+}
+
+// The numbers of the registers in `set`
+fn registers(set: RegisterSet) -> impl Iterator<Item = usize> {
+    (0..RegisterSet::BITS as usize).filter(move |&register| set & (1 << register) != 0)
+}
+
+// The instructions every instruction has to stay after. Those an earlier one already has to
+// follow are left out: the last writer of a register stands for the ones before it, the last
+// store and barrier for the accesses and instructions before them.
+fn dependences(instructions: &[Instruction]) -> Vec<Vec<usize>> {
+    let mut writer: Vec<Option<usize>> = vec![None; RegisterSet::BITS as usize];
+    // The instructions reading every register since its last write
+    let mut readers: Vec<Vec<usize>> = vec![Vec::new(); RegisterSet::BITS as usize];
+    let mut store = None;
+    let mut loads = Vec::new();
+    let mut barrier = None;
+    let mut since_barrier = Vec::new();
+    let mut predecessors = Vec::with_capacity(instructions.len());
+    for (j, inst) in instructions.iter().enumerate() {
+        let mut before: Vec<usize> = barrier.into_iter().collect();
+        if is_barrier(inst) {
+            before.append(&mut since_barrier);
+        }
+        for register in registers(inst.uses() | inst.defs()) {
+            before.extend(writer[register]);
+        }
+        for register in registers(inst.defs()) {
+            before.append(&mut readers[register]);
+        }
+        match inst {
+            Instruction::Lw { .. } => before.extend(store),
+            Instruction::Sw { .. } => {
+                before.extend(store);
+                before.append(&mut loads);
+            }
+            _ => {}
+        }
+        before.sort_unstable();
+        before.dedup();
+        predecessors.push(before);
+
+        for register in registers(inst.uses()) {
+            readers[register].push(j);
+        }
+        for register in registers(inst.defs()) {
+            writer[register] = Some(j);
+        }
+        match inst {
+            Instruction::Lw { .. } => loads.push(j),
+            Instruction::Sw { .. } => store = Some(j),
+            _ => {}
+        }
+        if is_barrier(inst) {
+            barrier = Some(j);
+        } else {
+            since_barrier.push(j);
+        }
+    }
+    predecessors
+}
+
+// The order of `instructions`, as indices
+fn schedule_block(instructions: &[Instruction]) -> Vec<usize> {
+    let n = instructions.len();
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut waiting = vec![0; n];
+    for (j, before) in dependences(instructions).into_iter().enumerate() {
+        for i in before {
+            successors[i].push(j);
+            waiting[j] += 1;
+        }
+    }
+    // The longest path from every instruction to the end of the block, its own latency included
+    let mut priority = vec![0; n];
+    for i in (0..n).rev() {
+        priority[i] = latency(&instructions[i]) + successors[i].iter().map(|&j| priority[j]).max().unwrap_or(0);
+    }
+
+    // The cycle the operands of every instruction are ready at, final once it waits for nothing
+    let mut ready = vec![0; n];
+    // Those waiting for nothing, by the cycle their operands are ready at, and those of them
+    // ready by the current cycle, by priority
+    let mut pending: BinaryHeap<Reverse<(u32, usize)>> = (0..n).filter(|&j| waiting[j] == 0).map(|j| Reverse((0, j))).collect();
+    let mut available: BinaryHeap<(u32, Reverse<usize>)> = BinaryHeap::new();
+    let mut order = Vec::with_capacity(n);
+    let mut cycle = 0;
+    while order.len() < n {
+        // Nothing ready yet, the earliest are issued once their operands are
+        if available.is_empty() {
+            let Reverse((at, _)) = *pending.peek().unwrap();
+            cycle = cycle.max(at);
+        }
+        while let Some(&Reverse((_, j))) = pending.peek().filter(|Reverse((at, _))| *at <= cycle) {
+            pending.pop();
+            available.push((priority[j], Reverse(j)));
+        }
+        let (_, Reverse(next)) = available.pop().unwrap();
+        order.push(next);
+        for &j in successors[next].iter() {
+            ready[j] = ready[j].max(cycle + latency(&instructions[next]));
+            waiting[j] -= 1;
+            if waiting[j] == 0 {
+                pending.push(Reverse((ready[j], j)));
+            }
+        }
+        cycle += 1;
+    }
+    order
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use koopa::ir::{FunctionData, TypeKind, Value, ValueKind};
+use crate::common::alloc_scopes::AllocScopes;
+
+// The stack slots of the allocs of `func_data`, an alloc sharing the first slot all the allocs
+// of which are declared in scopes disjoint from its own, see `AllocScopes`. A shared slot is
+// as large as the largest of its allocs. Returns the offset of every alloc from the bottom of
+// the area the slots take, in layout order, and the size of the area.
+pub fn share_slots(func_data: &FunctionData, scopes: &AllocScopes) -> (HashMap<Value, i32>, i32) {
+    let mut slots: Vec<(Vec<Value>, i32)> = Vec::new();
+    let mut slot_of = Vec::new();
+    for (_, node) in func_data.layout().bbs().iter() {
+        for &inst in node.insts().keys() {
+            let value_data = func_data.dfg().value(inst);
+            let (ValueKind::Alloc(_), TypeKind::Pointer(base)) = (value_data.kind(), value_data.ty().kind()) else {
+                continue;
+            };
+            let size = base.size() as i32;
+            let slot = match slots.iter().position(|(allocs, _)| allocs.iter().all(|&alloc| scopes.are_disjoint(alloc, inst))) {
+                Some(slot) => slot,
+                None => {
+                    slots.push((Vec::new(), 0));
+                    slots.len() - 1
+                }
+            };
+            slots[slot].0.push(inst);
+            slots[slot].1 = slots[slot].1.max(size);
+            slot_of.push((inst, slot));
+        }
+    }
+
+    let mut offsets = Vec::with_capacity(slots.len());
+    let mut area = 0;
+    for (_, size) in slots.iter() {
+        offsets.push(area);
+        area += size;
+    }
+    (slot_of.into_iter().map(|(alloc, slot)| (alloc, offsets[slot])).collect(), area)
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use koopa::front::Driver;
+use koopa::ir::{BinaryOp, FunctionData, Program, Type, ValueKind};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use crate::backend::{self, BackendOptions};
+use crate::backend::asm::{AsmGlobal, AsmProgram};
+use crate::backend::instruction::Instruction;
+use crate::backend::runtime::rename_blocks;
+
+// Multiplication and division for `--march=rv32i`, without the M extension: `mul`, `div` and
+// `mod` are replaced by calls to routines named as those of libgcc, which are written into the
+// output, as a bare-metal target has no library to provide them. They are written in Koopa IR
+// with shifts, Koopa having no unsigned comparison, and compiled along with the program.
+const SOURCE: &str = "
+fun @__mulsi3(%a: i32, %b: i32): i32 {
+%entry:
+  %product = alloc i32
+  %x = alloc i32
+  %y = alloc i32
+  store 0, %product
+  store %a, %x
+  store %b, %y
+  jump %loop
+%loop:
+  %m = load %y
+  %more = ne %m, 0
+  br %more, %step, %end
+%step:
+  %n = load %x
+  %bit = and %m, 1
+  // All ones when the bit is set
+  %mask = sub 0, %bit
+  %addend = and %n, %mask
+  %old = load %product
+  %sum = add %old, %addend
+  store %sum, %product
+  %doubled = shl %n, 1
+  store %doubled, %x
+  %halved = shr %m, 1
+  store %halved, %y
+  jump %loop
+%end:
+  %result = load %product
+  ret %result
+}
+
+// Unsigned long division, one bit of the quotient a step
+fun @__udivmodsi4(%n: i32, %d: i32, %rem: *i32): i32 {
+%entry:
+  %q = alloc i32
+  %r = alloc i32
+  %i = alloc i32
+  %sign = shl 1, 31
+  %biased_d = xor %d, %sign
+  store 0, %q
+  store 0, %r
+  store 31, %i
+  jump %loop
+%loop:
+  %k = load %i
+  %more = ge %k, 0
+  br %more, %step, %end
+%step:
+  %old_r = load %r
+  %shifted_r = shl %old_r, 1
+  %n_bits = shr %n, %k
+  %bit = and %n_bits, 1
+  %next_r = or %shifted_r, %bit
+  // The remainder is at least the divisor when it overflowed, or else compared unsigned,
+  // which is signed with the sign bits flipped
+  %carry = shr %old_r, 31
+  %biased_r = xor %next_r, %sign
+  %above = ge %biased_r, %biased_d
+  %fits = or %carry, %above
+  br %fits, %subtract, %keep
+%subtract:
+  %diff = sub %next_r, %d
+  store %diff, %r
+  %old_q = load %q
+  %q_bit = shl 1, %k
+  %new_q = or %old_q, %q_bit
+  store %new_q, %q
+  jump %next
+%keep:
+  store %next_r, %r
+  jump %next
+%next:
+  %next_k = sub %k, 1
+  store %next_k, %i
+  jump %loop
+%end:
+  %remainder = load %r
+  store %remainder, %rem
+  %quotient = load %q
+  ret %quotient
+}
+
+// Rounded toward zero, as `div`. The magnitude of -2147483648 is itself, unsigned.
+fun @__divsi3(%a: i32, %b: i32): i32 {
+%entry:
+  %remainder = alloc i32
+  %sa = sar %a, 31
+  %sb = sar %b, 31
+  %xa = xor %a, %sa
+  %ua = sub %xa, %sa
+  %xb = xor %b, %sb
+  %ub = sub %xb, %sb
+  %uq = call @__udivmodsi4(%ua, %ub, %remainder)
+  %s = xor %sa, %sb
+  %xq = xor %uq, %s
+  %q = sub %xq, %s
+  ret %q
+}
+
+// The sign of the dividend, as `rem`
+fun @__modsi3(%a: i32, %b: i32): i32 {
+%entry:
+  %remainder = alloc i32
+  %sa = sar %a, 31
+  %sb = sar %b, 31
+  %xa = xor %a, %sa
+  %ua = sub %xa, %sa
+  %xb = xor %b, %sb
+  %ub = sub %xb, %sb
+  %uq = call @__udivmodsi4(%ua, %ub, %remainder)
+  %ur = load %remainder
+  %xr = xor %ur, %sa
+  %r = sub %xr, %sa
+  ret %r
+}
+";
+
+const ROUTINES: [&str; 3] = ["__mulsi3", "__divsi3", "__modsi3"];
+
+// Replaces the multiplications, divisions and remainders of `program` with calls, declaring
+// the routines used. Multiplications by a power of two become shifts instead.
+pub fn lower(program: &mut Program) {
+    let mut declared = HashMap::new();
+    for func_h in program.func_layout().to_vec() {
+        let func_data = program.func(func_h);
+        let binaries: Vec<_> = func_data.layout().bbs().iter()
+            .flat_map(|(_, node)| node.insts().keys())
+            .filter_map(|&inst| match func_data.dfg().value(inst).kind() {
+                ValueKind::Binary(bin) if matches!(bin.op(), BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod) => Some((inst, bin.op(), bin.lhs(), bin.rhs())),
+                _ => None,
+            })
+            .collect();
+
+        for (inst, op, lhs, rhs) in binaries {
+            let dfg = program.func_mut(func_h).dfg_mut();
+            let power_of_two = |value| match dfg.value(value).kind() {
+                ValueKind::Integer(int) if int.value() > 0 && int.value().count_ones() == 1 => Some(int.value().trailing_zeros()),
+                _ => None,
+            };
+            let shift = match op {
+                BinaryOp::Mul => power_of_two(rhs).map(|shamt| (lhs, shamt)).or_else(|| power_of_two(lhs).map(|shamt| (rhs, shamt))),
+                _ => None,
+            };
+            if let Some((value, shamt)) = shift {
+                let shamt = dfg.new_value().integer(shamt as i32);
+                dfg.replace_value_with(inst).binary(BinaryOp::Shl, value, shamt);
+                continue;
+            }
+
+            let name = match op {
+                BinaryOp::Mul => ROUTINES[0],
+                BinaryOp::Div => ROUTINES[1],
+                _ => ROUTINES[2],
+            };
+            let callee = *declared.entry(name).or_insert_with(|| {
+                program.new_func(FunctionData::new_decl(format!("@{}", name), vec![Type::get_i32(), Type::get_i32()], Type::get_i32()))
+            });
+            program.func_mut(func_h).dfg_mut().replace_value_with(inst).call(callee, vec![lhs, rhs]);
+        }
+    }
+}
+
+// Adds the routines to `program` when it calls them
+pub fn link(program: &mut AsmProgram, options: &BackendOptions) {
+    let calls_routine = program.sections.iter()
+        .flat_map(|section| section.content.iter())
+        .filter_map(|global| match global {
+            AsmGlobal::AsmFunction(func) => Some(func),
+            AsmGlobal::AsmVariable(_) => None,
+        })
+        .flat_map(|func| func.basic_blocks.iter().flat_map(|bb| bb.instructions.iter()))
+        .any(|inst| matches!(inst, Instruction::Call { label, .. } if ROUTINES.contains(&label.as_str())));
+    if !calls_routine {
+        return;
+    }
+
+    let routines = Driver::from(SOURCE).generate_program().expect("the routines are valid IR");
+    let mut routines = backend::generate_asm(&routines, options);
+    for global in routines.sections.iter_mut().flat_map(|section| section.content.iter_mut()) {
+        if let AsmGlobal::AsmFunction(func) = global {
+            rename_blocks(func, ".L__soft_");
+        }
+    }
+    program.sections.append(&mut routines.sections);
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use crate::backend::instruction::{register_set, RegisterSet};
+use crate::backend::regalloc::{Allocation, Segment};
+use crate::backend::register::RVRegister;
+use crate::backend::target::{Riscv32, Target};
+use crate::opt::dominators::DominatorTree;
+use crate::opt::liveness::Liveness;
+use crate::opt::loops::LoopInfo;
+
+// Live-range splitting, after either allocator: a value left without a register, spilled or
+// passed on the stack, is still kept in one over the parts of a block where one is free. The
+// uses of the value in a block are cut at every call, which clobbers the caller-saved
+// registers, and each run of them reading the value more than once, or right after its
+// definition, is a segment: the value is loaded once at its start, or computed in the
+// register and stored, and read from the register until its end. Segments are given
+// registers by the loads they save, weighted by loop depth, so that values are only read
+// from memory in the regions, around calls or in loops, where registers actually run out.
+pub fn split_live_ranges(func_data: &FunctionData, allocation: &mut Allocation, registers: &[RVRegister]) {
+    let liveness = Liveness::compute(func_data);
+    let loops = LoopInfo::compute(func_data, &DominatorTree::compute(func_data));
+    // Globals are not values of the function
+    let unallocated = |value: Value| allocation.is_spilled(value) || matches!(
+        func_data.dfg().values().get(&value).map(|value_data| value_data.kind()),
+        Some(ValueKind::FuncArgRef(arg)) if !Riscv32::is_passed_in_register(arg.index())
+    );
+
+    // The candidate segments of every block, with the loads they save
+    let mut candidates: Vec<(u64, BasicBlock, Value, Span)> = Vec::new();
+    let mut blocks: HashMap<BasicBlock, (Vec<Value>, Occupancy)> = HashMap::new();
+    for (&bb, node) in func_data.layout().bbs() {
+        let weight = 10u64.pow(loops.depth(bb).min(6) as u32);
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        // The runs of reads of every value, in the order the values are first seen
+        let mut runs: Vec<(Value, Span, u64)> = Vec::new();
+        let mut open: HashMap<Value, usize> = HashMap::new();
+        for (k, &inst) in insts.iter().enumerate() {
+            let value_data = func_data.dfg().value(inst);
+            for operand in value_data.kind().value_uses() {
+                if !unallocated(operand) {
+                    continue;
+                }
+                match open.get(&operand) {
+                    Some(&run) => {
+                        runs[run].1.end = k;
+                        runs[run].2 += 1;
+                    }
+                    None => {
+                        open.insert(operand, runs.len());
+                        runs.push((operand, Span { start: k, end: k, defined: false }, 1));
+                    }
+                }
+            }
+            if let ValueKind::Call(_) = value_data.kind() {
+                open.clear();
+            }
+            // Computed in the register of its segment, which a call result is not
+            if unallocated(inst) && matches!(value_data.kind(), ValueKind::Binary(_) | ValueKind::Load(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_)) {
+                open.insert(inst, runs.len());
+                runs.push((inst, Span { start: k, end: k, defined: true }, 0));
+            }
+        }
+        for (value, span, reads) in runs {
+            let saved = reads - !span.defined as u64;
+            if saved > 0 {
+                candidates.push((saved * weight, bb, value, span));
+            }
+        }
+        let occupancy = Occupancy::compute(func_data, &liveness, allocation, bb, &insts);
+        blocks.insert(bb, (insts, occupancy));
+    }
+
+    // The most profitable first, in layout order otherwise
+    candidates.sort_by_key(|&(saved, _, _, _)| std::cmp::Reverse(saved));
+    let already_saved = allocation.callee_saved();
+    for (saved, bb, value, span) in candidates {
+        let (insts, occupancy) = blocks.get_mut(&bb).unwrap();
+        // A callee-saved register not saved yet costs a save and a restore
+        let register = registers.iter().copied()
+            .filter(|register| !register.is_callee_saved() || saved > 2 || already_saved.contains(register))
+            .find(|&register| occupancy.is_free(span, register));
+        let Some(register) = register else { continue };
+        occupancy.take(span, register);
+        allocation.segments.push(Segment { value, register, start: insts[span.start], end: insts[span.end] });
+    }
+}
+
+// Where a segment holds its register, by the indices of the instructions in its block
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Span {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    // Written at `start` rather than loaded before it
+    pub(crate) defined: bool,
+}
+
+impl Span {
+    // The instructions reading the register, and those after which it is still held
+    fn ranges(self) -> (std::ops::RangeInclusive<usize>, std::ops::Range<usize>) {
+        let first_read = if self.defined { self.start + 1 } else { self.start };
+        (first_read..=self.end, self.start..self.end)
+    }
+}
+
+// The registers held while every instruction of a block reads its operands, and once it has
+// written its result
+pub(crate) struct Occupancy {
+    before: Vec<RegisterSet>,
+    after: Vec<RegisterSet>,
+}
+
+impl Occupancy {
+    pub(crate) fn compute(func_data: &FunctionData, liveness: &Liveness, allocation: &Allocation, bb: BasicBlock, insts: &[Value]) -> Self {
+        let held = |value: &Value| allocation.register(*value).map_or(0, |register| register_set(&[register]));
+        let mut live = liveness.live_out(bb).clone();
+        let mut occupancy = Occupancy { before: vec![0; insts.len()], after: vec![0; insts.len()] };
+        for (k, &inst) in insts.iter().enumerate().rev() {
+            occupancy.after[k] = live.iter().fold(held(&inst), |set, value| set | held(value));
+            live.remove(&inst);
+            live.extend(func_data.dfg().value(inst).kind().value_uses());
+            occupancy.before[k] = live.iter().fold(0, |set, value| set | held(value));
+        }
+        occupancy
+    }
+
+    pub(crate) fn is_free(&self, span: Span, register: RVRegister) -> bool {
+        let bit = register_set(&[register]);
+        let (reads, holds) = span.ranges();
+        reads.into_iter().all(|k| self.before[k] & bit == 0) && holds.into_iter().all(|k| self.after[k] & bit == 0)
+    }
+
+    pub(crate) fn take(&mut self, span: Span, register: RVRegister) {
+        let bit = register_set(&[register]);
+        let (reads, holds) = span.ranges();
+        reads.for_each(|k| self.before[k] |= bit);
+        holds.for_each(|k| self.after[k] |= bit);
+    }
+}
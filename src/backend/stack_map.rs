@@ -0,0 +1,71 @@
+use std::fmt::Write;
+use crate::backend::target::{Riscv32, Target};
+
+// Frame layout of a generated function, so that tools inspecting a paused program
+// (debuggers, visualizers) can find the variables without DWARF information.
+// All offsets are in bytes, relative to `sp` after the prologue.
+#[derive(Debug, Clone, Default)]
+pub struct FrameLayout {
+    pub function: String,
+    pub frame_size: i32,
+    // Outgoing arguments beyond the eighth occupy `[0, outgoing_args_size)`, the spilled
+    // values and the locals coming above them
+    pub outgoing_args_size: i32,
+    // Slot of the saved return address, `None` for leaf functions
+    pub ra_offset: Option<i32>,
+    // Named locals and the parameters assigned to, the others staying in their registers,
+    // in declaration order.
+    // A shadowed identifier appears once per declaration.
+    pub locals: Vec<StackSlot>,
+}
+
+// Arguments beyond the eighth are passed on the stack, one word each in order, at the bottom
+// of the caller's frame. The callee finds them right above its own frame.
+impl FrameLayout {
+    // The area a function needs for the calls it makes, the one passing the most arguments
+    pub fn outgoing_args_size(max_args: usize) -> i32 {
+        Riscv32::outgoing_args_size(max_args)
+    }
+
+    // Where a call puts its `index`-th argument, counting from 0
+    pub fn outgoing_arg_offset(index: usize) -> i32 {
+        Riscv32::outgoing_arg_offset(index)
+    }
+
+    // Where the function reads its `index`-th parameter
+    pub fn incoming_arg_offset(&self, index: usize) -> i32 {
+        self.frame_size + Self::outgoing_arg_offset(index)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StackSlot {
+    pub name: String,
+    pub offset: i32,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StackMap {
+    pub frames: Vec<FrameLayout>,
+}
+
+impl StackMap {
+    // Identifiers only contain `[_a-zA-Z0-9]`, so the strings need no escaping
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"functions\": [");
+        for (i, frame) in self.frames.iter().enumerate() {
+            json.push_str(if i == 0 { "\n" } else { ",\n" });
+            let ra_offset = frame.ra_offset.map_or("null".to_string(), |offset| offset.to_string());
+            write!(json, "    {{\n      \"name\": \"{}\",\n      \"frame_size\": {},\n      \"outgoing_args_size\": {},\n      \"ra_offset\": {},\n      \"locals\": [",
+                   frame.function, frame.frame_size, frame.outgoing_args_size, ra_offset).unwrap();
+            for (j, slot) in frame.locals.iter().enumerate() {
+                json.push_str(if j == 0 { "\n" } else { ",\n" });
+                write!(json, "        {{ \"name\": \"{}\", \"offset\": {}, \"size\": {} }}", slot.name, slot.offset, slot.size).unwrap();
+            }
+            json.push_str(if frame.locals.is_empty() { "]\n    }" } else { "\n      ]\n    }" });
+        }
+        json.push_str(if self.frames.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        json
+    }
+}
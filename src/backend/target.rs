@@ -0,0 +1,94 @@
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use crate::backend::instruction::Instruction;
+use crate::backend::register::RVRegister;
+
+// What the code generator knows of the machine it generates code for: its registers and
+// instructions, the calling convention, and the layout of a frame. The traversal in
+// `generate_asm` asks the target for these rather than naming registers and offsets, so that
+// another target, e.g. RV64 or a virtual machine, implements this trait and its instructions.
+pub trait Target {
+    type Register: Copy + Eq + Hash + Debug + Display + 'static;
+    type Instruction: Display;
+
+    // The registers the first arguments are passed in, in order, the others going on the stack
+    const ARGUMENT_REGISTERS: &'static [Self::Register];
+    const RETURN_REGISTER: Self::Register;
+    const STACK_POINTER: Self::Register;
+    const RETURN_ADDRESS: Self::Register;
+    // Preserved across calls, a function using one saves it in its prologue
+    const CALLEE_SAVED: &'static [Self::Register];
+    // Clobbered by calls, the ones values may be given
+    const CALLER_SAVED: &'static [Self::Register];
+    // Left to the code generator, for operands loaded from the stack and large offsets
+    const SCRATCH: &'static [Self::Register];
+    // The size of a stack slot, and of an argument passed on the stack
+    const WORD_SIZE: i32;
+    const STACK_ALIGNMENT: i32;
+
+    fn argument_register(index: usize) -> Self::Register {
+        Self::ARGUMENT_REGISTERS[index]
+    }
+
+    fn is_passed_in_register(index: usize) -> bool {
+        index < Self::ARGUMENT_REGISTERS.len()
+    }
+
+    // The outgoing area a function needs for its calls, the one passing the most arguments
+    fn outgoing_args_size(max_args: usize) -> i32 {
+        max_args.saturating_sub(Self::ARGUMENT_REGISTERS.len()) as i32 * Self::WORD_SIZE
+    }
+
+    // Where a call puts its `index`-th argument, at the bottom of the caller's frame
+    fn outgoing_arg_offset(index: usize) -> i32 {
+        assert!(!Self::is_passed_in_register(index), "argument {} is passed in a register", index);
+        (index - Self::ARGUMENT_REGISTERS.len()) as i32 * Self::WORD_SIZE
+    }
+
+    fn align_frame(size: i32) -> i32 {
+        (size + Self::STACK_ALIGNMENT - 1) / Self::STACK_ALIGNMENT * Self::STACK_ALIGNMENT
+    }
+
+    fn mv(rd: Self::Register, rs: Self::Register) -> Self::Instruction;
+    fn jump(label: String) -> Self::Instruction;
+    // `plt`: written to go through the PLT, for position-independent code
+    fn call(label: String, plt: bool) -> Self::Instruction;
+}
+
+// RV32IM with the standard calling convention, the only target so far
+pub struct Riscv32;
+
+impl Target for Riscv32 {
+    type Register = RVRegister;
+    type Instruction = Instruction;
+
+    const ARGUMENT_REGISTERS: &'static [RVRegister] = &[
+        RVRegister::A0, RVRegister::A1, RVRegister::A2, RVRegister::A3,
+        RVRegister::A4, RVRegister::A5, RVRegister::A6, RVRegister::A7,
+    ];
+    const RETURN_REGISTER: RVRegister = RVRegister::A0;
+    const STACK_POINTER: RVRegister = RVRegister::Sp;
+    const RETURN_ADDRESS: RVRegister = RVRegister::Ra;
+    const CALLEE_SAVED: &'static [RVRegister] = &RVRegister::CALLEE_SAVED;
+    // `t4`-`t6` are the scratch registers
+    const CALLER_SAVED: &'static [RVRegister] = &[
+        RVRegister::T0, RVRegister::T1, RVRegister::T2, RVRegister::T3,
+        RVRegister::A0, RVRegister::A1, RVRegister::A2, RVRegister::A3,
+        RVRegister::A4, RVRegister::A5, RVRegister::A6, RVRegister::A7,
+    ];
+    const SCRATCH: &'static [RVRegister] = &[RVRegister::T4, RVRegister::T5, RVRegister::T6];
+    const WORD_SIZE: i32 = 4;
+    const STACK_ALIGNMENT: i32 = 16;
+
+    fn mv(rd: RVRegister, rs: RVRegister) -> Instruction {
+        Instruction::Mv { rd, rs }
+    }
+
+    fn jump(label: String) -> Instruction {
+        Instruction::J { label }
+    }
+
+    fn call(label: String, plt: bool) -> Instruction {
+        Instruction::Call { label, plt }
+    }
+}
@@ -0,0 +1,576 @@
+// Command line interface of the compiler driver
+
+use sysy_compiler::backend::BackendOptions;
+use sysy_compiler::backend::asm::AsmSectionType;
+use sysy_compiler::common::session::{LintLevel, Session};
+use std::path::Path;
+use std::time::Duration;
+use sysy_compiler::opt::{self, OptLevel, OptLimits};
+use sysy_compiler::opt::pass_manager::Pipeline;
+
+pub const USAGE: &str = "\
+Usage: SysY-Compiler build [options] <input_file>... -o <output_file>
+       SysY-Compiler check [options] <input_file>...
+       SysY-Compiler run [options] <input_file>...
+       SysY-Compiler test [options] <test_file>...
+Use `-` as <input_file> or <output_file> for the standard input or output.
+Several input files are compiled together into one program.
+
+Commands:
+  build            Compile the program to the output given by --emit
+  check            Report the errors and warnings of the program, writing nothing
+  run              Compile and run the program, with stdin and stdout forwarded
+                   to it. The exit status is the one of the program. It is built
+                   and run with the RISC-V toolchain of the course environment
+                   (clang, ld.lld, qemu-riscv32-static and libsysy.a under
+                   $CDE_LIBRARY_PATH/riscv32) when all of it is found, otherwise
+                   it is interpreted.
+  test             Run every <test_file> as a program of its own, comparing what
+                   it prints and returns with the expected output. `dir/t.c` is
+                   expected to print the contents of `dir/t.out` followed by its
+                   exit status on a line of its own, reading `dir/t.in` if
+                   there is one.
+  help             Print this message
+
+Options:
+  --emit=<kind>    Output of `build`: ast, ast-json, symbols-json, xref (the
+                   definitions, references and types of the names of all the
+                   input files, as JSON), koopa, riscv (the default), obj (an
+                   ELF relocatable object) or exe (a static executable for
+                   RISC-V Linux, with a runtime library of its own)
+  -o <file>        Write the output of `build` to <file>
+  -O0, -O1, -O2    Optimization level (default: -O1). -O1 also removes redundant
+                   moves from the generated code, orders its blocks so that
+                   jumps fall through and gives the locals of disjoint scopes
+                   the same stack slot, and -O2 divides by constants with
+                   multiplications and schedules the instructions of every
+                   block around the latencies of loads and multiplications
+  --passes=<list>  Run these optimization passes instead of those of the level,
+                   e.g. const-fold,(gvn,const-fold),dce. A group in parentheses
+                   is repeated until it changes nothing. Passes required by
+                   others are added before them.
+  --print-passes   Print the optimization passes run at the given level, or
+                   given by --passes, and exit
+  --opt-timeout=<ms>
+                   Skip the remaining optimization passes, with a warning, once
+                   optimizing took longer than <ms> milliseconds
+  --opt-instruction-limit=<n>
+                   Skip the optimization passes, with a warning, on functions
+                   of more than <n> instructions. By default only the passes
+                   slow on large functions have a limit.
+  --ir-comments    Annotate the Koopa IR with the source statements
+  --asm-comments   Annotate the assembly with the line and text of the source
+                   statement of the code that follows (with --emit=riscv)
+  --check-ir-round-trip
+                   Write the IR generated from the source as text and parse it
+                   back, failing with an internal compiler error when the koopa
+                   parser rejects it or reads a different program from it
+  --dump-cfg=<dir>  Write the control flow graph of every function of the optimized
+                   IR to <dir>/<function>.dot, for Graphviz: the blocks with
+                   their terminator, and the true, false and jump edges, those
+                   of loops going back dashed. At -O0 it is the IR as lowered
+                   from the source.
+  --dump-callgraph=<file>
+                   Write the call graph of the optimized IR to <file>, for
+                   Graphviz: which function calls which, the most arguments
+                   each passes, and in red the functions that may recurse and
+                   the calls between them. Library functions are dashed.
+  --stack-map=<file>
+                   Write the frame layout of every function to <file> as JSON
+                   (with --emit=riscv, obj or exe)
+  --literal-pools  Load large constants used several times from a pool in
+                   .rodata, shared by the functions, where that makes the code
+                   smaller
+                   (with --emit=riscv, obj or exe)
+  --march=<isa>    Instruction set: rv32im (the default), rv32imc, which writes
+                   the compressed form of the instructions whose operands fit
+                   one, e.g. c.addi, c.lw and c.mv (with --emit=riscv), or
+                   rv32i and rv32ic, without the M extension: multiplications
+                   and divisions call __mulsi3, __divsi3 and __modsi3, which
+                   are written into the output (with --emit=riscv, obj or exe)
+  -fpic, -fPIC     Generate position-independent code, which links into PIE
+                   executables: the addresses of globals are taken relative to
+                   the pc with `lla`, never loaded from a GOT, and calls go
+                   through the PLT (with --emit=riscv or obj, objects being
+                   position-independent either way)
+  --regalloc=<allocator>
+                   Register allocator: linear (the default), a linear scan, or
+                   color, a graph coloring that is slower but leaves fewer moves
+                   and spills
+  --reg-report     Print, for every function, the most values live at once, the
+                   values spilled, the segments of them kept in registers, and
+                   the registers used, to stderr (with --emit=riscv, obj or exe)
+  --section-order=<sections>
+                   Order of the sections in the assembly, a comma-separated list
+                   of text, data, bss and rodata. Sections left out follow in
+                   the default order data, bss, text, rodata. Empty sections are
+                   omitted.
+                   (with --emit=riscv)
+  --memory-layout=flash:<origin>[+<length>],ram:<origin>[+<length>]
+                   Compile for a bare-metal target: the assembly starts with a
+                   `_start` that sets up the stack at the end of RAM, copies the
+                   initial values of the globals from flash and calls `main`,
+                   stopping at an `ebreak` once it returns. A matching linker
+                   script is written next to the output file, with the `.ld`
+                   extension. Lengths default to 256K of flash and 64K of RAM.
+                   (with --emit=riscv)
+  --native         Make `run` fail rather than interpret without a toolchain
+  --interpret      Make `run` interpret the program even with a toolchain
+  --linker-script=<file>
+                   Write the linker script of --memory-layout to <file> instead
+  -A <lint>        Allow <lint>, silencing it
+  -W <lint>        Warn about <lint> (the default for all lints)
+  -D <lint>        Deny <lint>, reporting it as an error
+                   <lint> is one of the lints below, or `warnings` for all of them.
+                   When a lint is given several times, the last level wins.
+  --verbose        Print the progress of the compilation to stderr
+  --stats          Print the time spent in each phase and optimization pass, and
+                   the size of the optimized IR, to stderr (not with `test`)
+  --ir-stats       Print, for every function of the optimized IR, its basic
+                   blocks, the most instructions in one of them, and how many
+                   instructions of each kind it has, to stderr (not with `test`)
+  --help           Print this message
+
+Without a command, the output kind is given by one of the flags below, with the
+other options as above:
+  SysY-Compiler -koopa <input_file>... -o <output_file>
+  -ast             Same as `build --emit=ast`
+  -koopa           Same as `build --emit=koopa`
+  -riscv           Same as `build --emit=riscv`
+  -c               Same as `build --emit=obj`
+  -run             Same as `run`
+
+Lints:
+  unused-variable  A local variable or constant is never read
+  shadow           A local declaration hides one of an enclosing scope
+  dead-code        Unreachable code, or an expression statement without effect
+  const-overflow   A constant expression overflows and wraps around
+  missing-return   Control may reach the end of a non-void function";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Ast,
+    AstJson,
+    // Definitions and references of every name, for editors
+    SymbolsJson,
+    // The same across all the input files, see `frontend::xref`
+    Xref,
+    Koopa,
+    Riscv,
+    Obj,
+    // Linked with the bundled runtime library
+    Exe,
+    // Interpret the IR, there is no output file
+    Run,
+    // Only report the diagnostics
+    Check,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ast" => Ok(Emit::Ast),
+            "ast-json" => Ok(Emit::AstJson),
+            "symbols-json" => Ok(Emit::SymbolsJson),
+            "xref" => Ok(Emit::Xref),
+            "koopa" => Ok(Emit::Koopa),
+            "riscv" => Ok(Emit::Riscv),
+            "obj" => Ok(Emit::Obj),
+            "exe" => Ok(Emit::Exe),
+            _ => Err(format!("unknown output kind `{}`, expected one of: ast, ast-json, symbols-json, xref, koopa, riscv, obj, exe", s)),
+        }
+    }
+}
+
+pub struct Options {
+    pub emit: Emit,
+    // At least one, and only one for the AST and symbol outputs
+    pub input_files: Vec<String>,
+    pub output_file: String,
+    pub opt_level: OptLevel,
+    pub opt_limits: OptLimits,
+    // From `--passes`, replacing those of `opt_level`
+    pub passes: Option<Pipeline>,
+    // Annotate the Koopa output with the source statements
+    pub ir_comments: bool,
+    // Annotate the assembly with the source lines
+    pub asm_comments: bool,
+    // Check that the generated IR reads back as itself
+    pub check_round_trip: bool,
+    // Directory the control flow graphs are written to
+    pub dump_cfg: Option<String>,
+    // File the call graph is written to
+    pub dump_callgraph: Option<String>,
+    // Sidecar file describing the stack frames of the generated code
+    pub stack_map: Option<String>,
+    // Literal pools, section order and the bare-metal memory layout
+    pub backend: BackendOptions,
+    // Where the linker script of `backend.memory_layout` goes
+    pub linker_script: Option<String>,
+    pub run_mode: RunMode,
+    pub verbose: bool,
+    // Print the timing of the phases and the size of the IR
+    pub stats: bool,
+    // Print what the optimized IR of every function is made of
+    pub ir_stats: bool,
+    // Carries the lint levels given on the command line
+    pub session: Session,
+}
+
+// How `run` executes the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    // Natively when a toolchain is found
+    Auto,
+    Native,
+    Interpret,
+}
+
+pub enum Command {
+    Compile(Options),
+    // Every input file is a test, run with `Emit::Run`
+    Test(Options),
+    Help,
+    PrintPasses(Pipeline),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subcommand {
+    Build,
+    Check,
+    Run,
+    Test,
+}
+
+impl Subcommand {
+    fn name(&self) -> &'static str {
+        match self {
+            Subcommand::Build => "build",
+            Subcommand::Check => "check",
+            Subcommand::Run => "run",
+            Subcommand::Test => "test",
+        }
+    }
+}
+
+// `args` excludes the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let subcommand = match args.first().map(String::as_str) {
+        Some("build") => Subcommand::Build,
+        Some("check") => Subcommand::Check,
+        Some("run") => Subcommand::Run,
+        Some("test") => Subcommand::Test,
+        Some("help") => return Ok(Command::Help),
+        // The flags of the first versions, where the output kind is an option
+        _ => return parse_options(None, args),
+    };
+    parse_options(Some(subcommand), &args[1..])
+}
+
+fn parse_options(subcommand: Option<Subcommand>, args: &[String]) -> Result<Command, String> {
+    let mut emit = None;
+    let mut input_files: Vec<String> = Vec::new();
+    let mut output_file = None;
+    let mut opt_level = OptLevel::O1;
+    let mut opt_limits = OptLimits::default();
+    let mut passes: Option<Pipeline> = None;
+    let mut ir_comments = false;
+    let mut asm_comments = false;
+    let mut check_round_trip = false;
+    let mut dump_cfg = None;
+    let mut dump_callgraph = None;
+    let mut stack_map = None;
+    let mut backend = BackendOptions::default();
+    let mut section_order_given = false;
+    let mut linker_script = None;
+    let mut run_mode = None;
+    let mut verbose = false;
+    let mut stats = false;
+    let mut ir_stats = false;
+    let mut print_passes = false;
+    let mut session = Session::new();
+
+    let mut set_emit = |kind: Emit| match emit.replace(kind) {
+        Some(previous) if previous != kind => Err("conflicting output kinds, specify only one of --emit, -ast, -koopa, -riscv, -c and -run".to_string()),
+        _ => Ok(()),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "-ast" | "-koopa" | "-riscv" | "-c" | "-run" if subcommand.is_some() => {
+                return Err(format!("`{}` is only accepted without a command, use `{}` instead", arg, replacement(arg)));
+            }
+            "-ast" => set_emit(Emit::Ast)?,
+            "-koopa" => set_emit(Emit::Koopa)?,
+            "-riscv" => set_emit(Emit::Riscv)?,
+            "-c" => set_emit(Emit::Obj)?,
+            "-run" => set_emit(Emit::Run)?,
+            "-o" => match args.next() {
+                Some(file) if output_file.is_none() => output_file = Some(file.clone()),
+                Some(_) => return Err("the output file is given more than once".into()),
+                None => return Err("`-o` must be followed by the output file".into()),
+            },
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            "-O2" => opt_level = OptLevel::O2,
+            "--ir-comments" => ir_comments = true,
+            "--asm-comments" => asm_comments = true,
+            "--check-ir-round-trip" => check_round_trip = true,
+            "--literal-pools" => backend.literal_pools = true,
+            "-fpic" | "-fPIC" => backend.pic = true,
+            "--native" | "--interpret" => {
+                let mode = if arg == "--native" { RunMode::Native } else { RunMode::Interpret };
+                if run_mode.replace(mode).is_some_and(|previous| previous != mode) {
+                    return Err("`--native` and `--interpret` contradict each other".into());
+                }
+            }
+            "--verbose" | "-v" => verbose = true,
+            "--stats" => stats = true,
+            "--ir-stats" => ir_stats = true,
+            "--reg-report" => backend.report_allocation = true,
+            "--print-passes" => print_passes = true,
+            "-A" | "-W" | "-D" => match args.next() {
+                Some(lint) => session.set_lint_level_by_name(lint, lint_level(arg))?,
+                None => return Err(format!("`{}` must be followed by a lint name", arg)),
+            },
+            _ => {
+                if let Some(kind) = arg.strip_prefix("--emit=") {
+                    if let Some(subcommand) = subcommand.filter(|subcommand| *subcommand != Subcommand::Build) {
+                        return Err(format!("`--emit` selects the output of `build`, `{}` has none", subcommand.name()));
+                    }
+                    set_emit(kind.parse()?)?;
+                } else if arg == "--emit" {
+                    return Err("`--emit` expects a value, e.g. --emit=koopa".into());
+                } else if let Some(list) = arg.strip_prefix("--passes=") {
+                    passes = Some(list.parse()?);
+                } else if arg == "--passes" {
+                    return Err("`--passes` expects a list of passes, e.g. --passes=const-fold,dce".into());
+                } else if let Some(ms) = arg.strip_prefix("--opt-timeout=") {
+                    let ms = ms.parse().map_err(|_| format!("`--opt-timeout` expects a number of milliseconds, found `{}`", ms))?;
+                    opt_limits.timeout = Some(Duration::from_millis(ms));
+                } else if let Some(n) = arg.strip_prefix("--opt-instruction-limit=") {
+                    let n = n.parse().map_err(|_| format!("`--opt-instruction-limit` expects a number of instructions, found `{}`", n))?;
+                    opt_limits.instruction_limit = Some(n);
+                } else if let Some(dir) = arg.strip_prefix("--dump-cfg=") {
+                    dump_cfg = Some(dir.to_string());
+                } else if arg == "--dump-cfg" {
+                    return Err("`--dump-cfg` expects a directory, e.g. --dump-cfg=cfg/".into());
+                } else if let Some(file) = arg.strip_prefix("--dump-callgraph=") {
+                    dump_callgraph = Some(file.to_string());
+                } else if arg == "--dump-callgraph" {
+                    return Err("`--dump-callgraph` expects a file, e.g. --dump-callgraph=calls.dot".into());
+                } else if let Some(file) = arg.strip_prefix("--stack-map=") {
+                    stack_map = Some(file.to_string());
+                } else if arg == "--stack-map" {
+                    return Err("`--stack-map` expects a file, e.g. --stack-map=out.json".into());
+                } else if let Some(sections) = arg.strip_prefix("--section-order=") {
+                    backend.section_order = section_order(sections)?;
+                    section_order_given = true;
+                } else if let Some(isa) = arg.strip_prefix("--march=") {
+                    (backend.m_extension, backend.compressed) = match isa {
+                        "rv32im" => (true, false),
+                        "rv32imc" => (true, true),
+                        "rv32i" => (false, false),
+                        "rv32ic" => (false, true),
+                        _ => return Err(format!("unknown instruction set `{}`, expected one of: rv32im, rv32imc, rv32i, rv32ic", isa)),
+                    };
+                } else if arg == "--march" {
+                    return Err("`--march` expects an instruction set, e.g. --march=rv32imc".into());
+                } else if let Some(allocator) = arg.strip_prefix("--regalloc=") {
+                    backend.register_allocator = allocator.parse()?;
+                } else if arg == "--regalloc" {
+                    return Err("`--regalloc` expects an allocator, e.g. --regalloc=color".into());
+                } else if let Some(layout) = arg.strip_prefix("--memory-layout=") {
+                    backend.memory_layout = Some(layout.parse()?);
+                } else if arg == "--memory-layout" {
+                    return Err("`--memory-layout` expects the regions, e.g. --memory-layout=flash:0x20000000,ram:0x80000000".into());
+                } else if let Some(file) = arg.strip_prefix("--linker-script=") {
+                    linker_script = Some(file.to_string());
+                } else if arg == "--linker-script" {
+                    return Err("`--linker-script` expects a file, e.g. --linker-script=out.ld".into());
+                } else if arg == "--section-order" {
+                    return Err("`--section-order` expects a list of sections, e.g. --section-order=text,data".into());
+                } else if let Some(lint) = ["-A", "-W", "-D"].iter().find_map(|flag| arg.strip_prefix(flag)) {
+                    // `-Dwarnings` is the same as `-D warnings`
+                    session.set_lint_level_by_name(lint, lint_level(&arg[..2]))?;
+                } else if arg.starts_with("-O") {
+                    return Err(format!("unknown optimization level `{}`, expected one of: -O0, -O1, -O2", arg));
+                } else if arg.starts_with('-') && arg != "-" {
+                    return Err(format!("unknown option `{}`", arg));
+                } else if input_files.contains(arg) {
+                    return Err(format!("input file `{}` is given more than once", arg));
+                } else {
+                    input_files.push(arg.clone());
+                }
+            }
+        }
+    }
+
+    let backend = backend.at_level(opt_level);
+
+    // Needs no input, but the `-O` level may come after it
+    if print_passes {
+        return Ok(Command::PrintPasses(passes.unwrap_or_else(|| opt::pipeline(opt_level))));
+    }
+
+    let emit = match subcommand {
+        Some(Subcommand::Build) => emit.unwrap_or(Emit::Riscv),
+        Some(Subcommand::Check) => Emit::Check,
+        Some(Subcommand::Run | Subcommand::Test) => Emit::Run,
+        None => emit.ok_or("no command or output kind given, use `build`, `check`, `run` or `test`, see --help")?,
+    };
+    if dump_cfg.is_some() && matches!(emit, Emit::Ast | Emit::AstJson | Emit::SymbolsJson | Emit::Xref) {
+        return Err("`--dump-cfg` draws the generated IR, which is not generated for this output".into());
+    }
+    if dump_callgraph.is_some() && matches!(emit, Emit::Ast | Emit::AstJson | Emit::SymbolsJson | Emit::Xref) {
+        return Err("`--dump-callgraph` draws the generated IR, which is not generated for this output".into());
+    }
+    if ir_stats && matches!(emit, Emit::Ast | Emit::AstJson | Emit::SymbolsJson | Emit::Xref) {
+        return Err("`--ir-stats` describes the generated IR, which is not generated for this output".into());
+    }
+    if stack_map.is_some() && !matches!(emit, Emit::Riscv | Emit::Obj | Emit::Exe) {
+        return Err("`--stack-map` describes the generated code and requires --emit=riscv, obj or exe".into());
+    }
+    if backend.report_allocation && !matches!(emit, Emit::Riscv | Emit::Obj | Emit::Exe) {
+        return Err("`--reg-report` describes the generated code and requires --emit=riscv, obj or exe".into());
+    }
+    if backend.literal_pools && !matches!(emit, Emit::Riscv | Emit::Obj | Emit::Exe) {
+        return Err("`--literal-pools` changes the generated code and requires --emit=riscv, obj or exe".into());
+    }
+    if check_round_trip && matches!(emit, Emit::Ast | Emit::AstJson | Emit::SymbolsJson | Emit::Xref) {
+        return Err("`--check-ir-round-trip` checks the generated IR, which is not generated for this output".into());
+    }
+    if asm_comments && emit != Emit::Riscv {
+        return Err("`--asm-comments` annotates the assembly and requires --emit=riscv".into());
+    }
+    // The object writer encodes the full instructions only
+    if backend.compressed && emit != Emit::Riscv {
+        return Err("`--march` with the C extension selects the instructions written in the assembly and requires --emit=riscv".into());
+    }
+    if !backend.m_extension && !matches!(emit, Emit::Riscv | Emit::Obj | Emit::Exe) {
+        return Err("`--march` without the M extension changes the generated code and requires --emit=riscv, obj or exe".into());
+    }
+    // Executables are linked at a fixed address
+    if backend.pic && !matches!(emit, Emit::Riscv | Emit::Obj) {
+        return Err("`-fpic` changes the generated code and requires --emit=riscv or obj".into());
+    }
+    // Objects have a fixed layout of sections
+    if section_order_given && emit != Emit::Riscv {
+        return Err("`--section-order` arranges the assembly and requires --emit=riscv".into());
+    }
+
+    // The object writer knows nothing of the startup code
+    if backend.memory_layout.is_some() && emit != Emit::Riscv {
+        return Err("`--memory-layout` adds startup code to the assembly and requires --emit=riscv".into());
+    }
+    if backend.pic && backend.memory_layout.is_some() {
+        return Err("`-fpic` and `--memory-layout` contradict each other, a bare-metal program is loaded at the addresses of its layout".into());
+    }
+    if linker_script.is_some() && backend.memory_layout.is_none() {
+        return Err("`--linker-script` names the linker script of `--memory-layout`, which is not given".into());
+    }
+
+    if let Some(mode) = run_mode.filter(|_| emit != Emit::Run || subcommand == Some(Subcommand::Test)) {
+        let flag = if mode == RunMode::Native { "--native" } else { "--interpret" };
+        return Err(format!("`{}` chooses how `run` executes the program", flag));
+    }
+
+    if input_files.is_empty() {
+        return Err("no input file given".into());
+    }
+    if input_files.len() > 1 && matches!(emit, Emit::Ast | Emit::AstJson | Emit::SymbolsJson) {
+        return Err("the AST and symbol outputs describe a single file, give only one input file".into());
+    }
+    if subcommand == Some(Subcommand::Test) {
+        if input_files.iter().any(|file| file == "-") {
+            return Err("tests are found next to their expected output, `-` cannot be a test".into());
+        }
+        // Every test has its own session
+        if stats {
+            return Err("`--stats` is not available with `test`".into());
+        }
+        if ir_stats {
+            return Err("`--ir-stats` is not available with `test`".into());
+        }
+    }
+
+    let output_file = match (emit, output_file) {
+        (Emit::Run, Some(_)) => return Err("running the program writes no output file, it prints to stdout".into()),
+        (Emit::Check, Some(_)) => return Err("`check` writes no output file".into()),
+        (Emit::Run | Emit::Check, None) => String::new(),
+        (_, output_file) => output_file.ok_or("no output file given, use -o <file>")?,
+    };
+    if backend.memory_layout.is_some() && linker_script.is_none() {
+        if output_file == "-" {
+            return Err("the linker script cannot go next to the standard output, give it a file with --linker-script=<file>".into());
+        }
+        linker_script = Some(Path::new(&output_file).with_extension("ld").to_string_lossy().into_owned());
+    }
+
+    let options = Options {
+        emit,
+        input_files,
+        output_file,
+        opt_level,
+        opt_limits,
+        passes,
+        ir_comments,
+        asm_comments,
+        check_round_trip,
+        dump_cfg,
+        dump_callgraph,
+        stack_map,
+        backend,
+        linker_script,
+        run_mode: run_mode.unwrap_or(RunMode::Auto),
+        verbose,
+        stats,
+        ir_stats,
+        session,
+    };
+    match subcommand {
+        Some(Subcommand::Test) => Ok(Command::Test(options)),
+        _ => Ok(Command::Compile(options)),
+    }
+}
+
+// The command line equivalent to a flag of the first versions
+fn replacement(flag: &str) -> &'static str {
+    match flag {
+        "-ast" => "build --emit=ast",
+        "-koopa" => "build --emit=koopa",
+        "-riscv" => "build --emit=riscv",
+        "-c" => "build --emit=obj",
+        _ => "run",
+    }
+}
+
+// The listed sections first, then the others in the default order
+fn section_order(list: &str) -> Result<[AsmSectionType; 4], String> {
+    let mut order = Vec::new();
+    for name in list.split(',') {
+        let section: AsmSectionType = name.parse()?;
+        if order.contains(&section) {
+            return Err(format!("section `{}` is listed more than once", name));
+        }
+        order.push(section);
+    }
+    for section in BackendOptions::default().section_order {
+        if !order.contains(&section) {
+            order.push(section);
+        }
+    }
+    Ok(order.try_into().unwrap())
+}
+
+fn lint_level(flag: &str) -> LintLevel {
+    match flag {
+        "-A" => LintLevel::Allow,
+        "-W" => LintLevel::Warn,
+        _ => LintLevel::Deny,
+    }
+}
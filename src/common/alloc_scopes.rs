@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use koopa::ir::Value;
+
+// The scope every local `alloc` of the source is declared in, recorded by the frontend as
+// Koopa IR has no metadata slot. The variables of two scopes neither of which encloses the
+// other are never live at the same time, so the backend may give them the same stack slot.
+// The allocs the optimizations create are not recorded and keep slots of their own.
+#[derive(Debug, Clone, Default)]
+pub struct AllocScopes {
+    // The scope enclosing every scope, `None` for the outermost ones
+    parents: Vec<Option<usize>>,
+    // The scopes open while the IR is generated, innermost last
+    open: Vec<usize>,
+    scopes: HashMap<Value, usize>,
+}
+
+impl AllocScopes {
+    pub fn enter_scope(&mut self) {
+        self.parents.push(self.open.last().copied());
+        self.open.push(self.parents.len() - 1);
+    }
+
+    pub fn exit_scope(&mut self) {
+        self.open.pop().expect("a scope is open");
+    }
+
+    // `alloc` is declared in the innermost open scope
+    pub fn record(&mut self, alloc: Value) {
+        if let Some(&scope) = self.open.last() {
+            self.scopes.insert(alloc, scope);
+        }
+    }
+
+    // Whether `a` and `b` are declared in scopes neither of which encloses the other
+    pub fn are_disjoint(&self, a: Value, b: Value) -> bool {
+        match (self.scopes.get(&a), self.scopes.get(&b)) {
+            (Some(&a), Some(&b)) => !self.encloses(a, b) && !self.encloses(b, a),
+            _ => false,
+        }
+    }
+
+    fn encloses(&self, outer: usize, scope: usize) -> bool {
+        std::iter::successors(Some(scope), |&scope| self.parents[scope]).any(|scope| scope == outer)
+    }
+}
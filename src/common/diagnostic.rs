@@ -0,0 +1,86 @@
+// Byte range in the source text, as reported by the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    // 1-based (line, column) of the start of the span
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let prefix = &source[..self.start.min(source.len())];
+        let line = prefix.matches('\n').count() + 1;
+        let column = prefix.len() - prefix.rfind('\n').map_or(0, |pos| pos + 1) + 1;
+        (line, column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+            Level::Note => write!(f, "note"),
+        }
+    }
+}
+
+// A message reported to the user, optionally pointing at a location in the source
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+    // Related locations, e.g. a previous declaration
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic { level: Level::Error, message: message.into(), span, notes: Vec::new() }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Option<Span>) -> Self {
+        Diagnostic { level: Level::Warning, message: message.into(), span, notes: Vec::new() }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Option<Span>) -> Self {
+        self.notes.push(Diagnostic { level: Level::Note, message: message.into(), span, notes: Vec::new() });
+        self
+    }
+
+    // Format as `file:line:col: level: message`, followed by the notes on their own lines
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut rendered = self.render_line(file_name, source);
+        for note in self.notes.iter() {
+            rendered.push('\n');
+            rendered.push_str(&note.render(file_name, source));
+        }
+        rendered
+    }
+
+    fn render_line(&self, file_name: &str, source: &str) -> String {
+        match self.span {
+            Some(span) => {
+                let (line, column) = span.line_col(source);
+                format!("{}:{}:{}: {}: {}", file_name, line, column, self.level, self.message)
+            }
+            None => format!("{}: {}: {}", file_name, self.level, self.message),
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod alloc_scopes;
+pub mod diagnostic;
+pub mod session;
+pub mod stats;
+pub mod value_number;
@@ -0,0 +1,139 @@
+use crate::common::diagnostic::{Diagnostic, Level};
+use crate::common::stats::Stats;
+
+// Warnings that can be silenced or turned into errors by name, e.g. `-A shadow` or `-D dead-code`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    // A local variable or constant that is never read
+    UnusedVariable,
+    // A local declaration hiding one of an enclosing scope
+    Shadow,
+    // Unreachable statements and expression statements without effect
+    DeadCode,
+    // Constant expressions wrapping around
+    ConstOverflow,
+    // Control reaching the end of a non-void function
+    MissingReturn,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [Lint::UnusedVariable, Lint::Shadow, Lint::DeadCode, Lint::ConstOverflow, Lint::MissingReturn];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::Shadow => "shadow",
+            Lint::DeadCode => "dead-code",
+            Lint::ConstOverflow => "const-overflow",
+            Lint::MissingReturn => "missing-return",
+        }
+    }
+
+    pub fn default_level(&self) -> LintLevel {
+        LintLevel::Warn
+    }
+}
+
+impl std::str::FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = Lint::ALL.iter().map(Lint::name).collect();
+            format!("unknown lint `{}`, expected `warnings` or one of: {}", s, names.join(", "))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    // The command line flag selecting the level
+    pub fn flag(&self) -> &'static str {
+        match self {
+            LintLevel::Allow => "-A",
+            LintLevel::Warn => "-W",
+            LintLevel::Deny => "-D",
+        }
+    }
+}
+
+// State shared by the whole compilation: the configured lint levels and the diagnostics
+// reported so far, by the frontend as well as by the optimization passes
+#[derive(Debug, Clone)]
+pub struct Session {
+    lint_levels: Vec<(Lint, LintLevel)>,
+    diagnostics: Vec<Diagnostic>,
+    // Timing of the phases, always collected and printed on request
+    pub stats: Stats,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            lint_levels: Lint::ALL.iter().map(|lint| (*lint, lint.default_level())).collect(),
+            diagnostics: Vec::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn lint_level(&self, lint: Lint) -> LintLevel {
+        self.lint_levels.iter().find(|(l, _)| *l == lint).map_or(lint.default_level(), |(_, level)| *level)
+    }
+
+    pub fn set_lint_level(&mut self, lint: Lint, level: LintLevel) {
+        for (l, current) in self.lint_levels.iter_mut() {
+            if *l == lint {
+                *current = level;
+            }
+        }
+    }
+
+    // `name` is a lint name, or `warnings` for all of them. Later settings override earlier ones.
+    pub fn set_lint_level_by_name(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        if name == "warnings" {
+            for lint in Lint::ALL {
+                self.set_lint_level(lint, level);
+            }
+        } else {
+            self.set_lint_level(name.parse()?, level);
+        }
+        Ok(())
+    }
+
+    // Reports `diagnostic` as a warning or an error depending on the level of `lint`, or drops it
+    pub fn lint(&mut self, lint: Lint, mut diagnostic: Diagnostic) {
+        let level = self.lint_level(lint);
+        diagnostic.level = match level {
+            LintLevel::Allow => return,
+            LintLevel::Warn => Level::Warning,
+            LintLevel::Deny => Level::Error,
+        };
+        diagnostic.message = format!("{} [{} {}]", diagnostic.message, level.flag(), lint.name());
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    // The diagnostics reported since the last call, in order
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+}
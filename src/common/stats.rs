@@ -0,0 +1,144 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use koopa::ir::{Program, ValueKind};
+
+// Time spent in each phase of the compilation, printed by `--stats` to guide performance
+// work on the compiler itself. Phases are listed in the order they first ran.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    phases: Vec<(String, Duration)>,
+}
+
+impl Stats {
+    // A phase running several times, e.g. parsing every input file, adds up
+    pub fn record(&mut self, phase: &str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase.to_string(), elapsed)),
+        }
+    }
+
+    pub fn time<T>(&mut self, phase: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    pub fn render(&self, counts: Option<&ProgramCounts>) -> String {
+        let mut out = String::from("Statistics:\n");
+        let width = self.phases.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("total".len());
+        let mut total = Duration::ZERO;
+        for (name, elapsed) in self.phases.iter() {
+            writeln!(out, "  {:<width$}  {:>10.3} ms", name, elapsed.as_secs_f64() * 1000.0).unwrap();
+            total += *elapsed;
+        }
+        writeln!(out, "  {:<width$}  {:>10.3} ms", "total", total.as_secs_f64() * 1000.0).unwrap();
+        if let Some(counts) = counts {
+            writeln!(out, "  functions:    {}", counts.functions).unwrap();
+            writeln!(out, "  basic blocks: {}", counts.basic_blocks).unwrap();
+            writeln!(out, "  instructions: {}", counts.instructions).unwrap();
+        }
+        out
+    }
+}
+
+// Size of the IR, counting the functions with a body
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgramCounts {
+    pub functions: usize,
+    pub basic_blocks: usize,
+    pub instructions: usize,
+}
+
+impl ProgramCounts {
+    pub fn of(program: &Program) -> Self {
+        let mut counts = ProgramCounts::default();
+        for func_data in program.funcs().values() {
+            if func_data.layout().entry_bb().is_none() {
+                continue;
+            }
+            counts.functions += 1;
+            for (_, node) in func_data.layout().bbs() {
+                counts.basic_blocks += 1;
+                counts.instructions += node.insts().len();
+            }
+        }
+        counts
+    }
+}
+
+// What the IR of every function with a body is made of, printed by `--ir-stats` to spot
+// lowerings generating much more code than they should
+#[derive(Debug, Clone, Default)]
+pub struct IrStats {
+    pub functions: Vec<FunctionIrStats>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FunctionIrStats {
+    pub name: String,
+    pub basic_blocks: usize,
+    pub instructions: usize,
+    // The most instructions in one block
+    pub max_block_size: usize,
+    // The instructions of each kind present, in the order of `ValueKind`
+    pub kinds: Vec<(&'static str, usize)>,
+}
+
+const KINDS: [&str; 10] = ["alloc", "load", "store", "getptr", "getelemptr", "binary", "branch", "jump", "call", "return"];
+
+impl IrStats {
+    pub fn of(program: &Program) -> Self {
+        let functions = program.func_layout().iter()
+            .map(|&func| program.func(func))
+            .filter(|func_data| func_data.layout().entry_bb().is_some())
+            .map(|func_data| {
+                let mut stats = FunctionIrStats { name: func_data.name().to_string(), ..FunctionIrStats::default() };
+                let mut counts = [0; KINDS.len()];
+                for (_, node) in func_data.layout().bbs() {
+                    stats.basic_blocks += 1;
+                    stats.instructions += node.insts().len();
+                    stats.max_block_size = stats.max_block_size.max(node.insts().len());
+                    for &inst in node.insts().keys() {
+                        if let Some(kind) = kind_index(func_data.dfg().value(inst).kind()) {
+                            counts[kind] += 1;
+                        }
+                    }
+                }
+                stats.kinds = KINDS.iter().copied().zip(counts).filter(|&(_, count)| count > 0).collect();
+                stats
+            })
+            .collect();
+        IrStats { functions }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::from("IR statistics:\n");
+        for func in self.functions.iter() {
+            writeln!(
+                out, "  {}: {} basic blocks, {} instructions, at most {} in a block",
+                func.name, func.basic_blocks, func.instructions, func.max_block_size,
+            ).unwrap();
+            let kinds: Vec<_> = func.kinds.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+            writeln!(out, "    {}", kinds.join(", ")).unwrap();
+        }
+        out
+    }
+}
+
+fn kind_index(kind: &ValueKind) -> Option<usize> {
+    Some(match kind {
+        ValueKind::Alloc(_) => 0,
+        ValueKind::Load(_) => 1,
+        ValueKind::Store(_) => 2,
+        ValueKind::GetPtr(_) => 3,
+        ValueKind::GetElemPtr(_) => 4,
+        ValueKind::Binary(_) => 5,
+        ValueKind::Branch(_) => 6,
+        ValueKind::Jump(_) => 7,
+        ValueKind::Call(_) => 8,
+        ValueKind::Return(_) => 9,
+        _ => return None,
+    })
+}
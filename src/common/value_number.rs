@@ -0,0 +1,85 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use koopa::ir::{BinaryOp, FunctionData, Value, ValueKind};
+
+// An operand as compared between expressions: integers by their value, anything else by
+// the value itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Integer(i32),
+    Value(Value),
+}
+
+impl Operand {
+    pub fn of(func_data: &FunctionData, value: Value) -> Self {
+        if value.is_global() {
+            return Operand::Value(value);
+        }
+        match func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Operand::Integer(int.value()),
+            _ => Operand::Value(value),
+        }
+    }
+}
+
+// What an instruction computing a value from its operands alone computes, for finding the
+// instructions computing the same. Two keys are equal when their operators and operands
+// are, the operands of a commutative operator in either order.
+#[derive(Debug, Clone, Copy)]
+pub enum ExprKey {
+    Binary(BinaryOp, Operand, Operand),
+    GetPtr(Operand, Operand),
+    GetElemPtr(Operand, Operand),
+}
+
+impl ExprKey {
+    // `None` for the instructions reading or writing memory, calls and terminators
+    pub fn of(func_data: &FunctionData, inst: Value) -> Option<Self> {
+        let operand = |value| Operand::of(func_data, value);
+        match func_data.dfg().value(inst).kind() {
+            ValueKind::Binary(binary) => Some(ExprKey::Binary(binary.op(), operand(binary.lhs()), operand(binary.rhs()))),
+            ValueKind::GetPtr(gp) => Some(ExprKey::GetPtr(operand(gp.src()), operand(gp.index()))),
+            ValueKind::GetElemPtr(gep) => Some(ExprKey::GetElemPtr(operand(gep.src()), operand(gep.index()))),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for ExprKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (ExprKey::Binary(op, a, b), ExprKey::Binary(other_op, c, d)) => {
+                op == other_op && ((a, b) == (c, d) || (is_commutative(op) && (a, b) == (d, c)))
+            }
+            (ExprKey::GetPtr(a, b), ExprKey::GetPtr(c, d)) | (ExprKey::GetElemPtr(a, b), ExprKey::GetElemPtr(c, d)) => (a, b) == (c, d),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ExprKey {}
+
+// The operands of a commutative operator are hashed so that their order does not matter
+impl Hash for ExprKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match *self {
+            ExprKey::Binary(op, a, b) if is_commutative(op) => {
+                op.hash(state);
+                state.write_u64(hash_of(a).wrapping_add(hash_of(b)));
+            }
+            ExprKey::Binary(op, a, b) => (op, a, b).hash(state),
+            ExprKey::GetPtr(a, b) | ExprKey::GetElemPtr(a, b) => (a, b).hash(state),
+        }
+    }
+}
+
+fn hash_of(operand: Operand) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    operand.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn is_commutative(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq)
+}
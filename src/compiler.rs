@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use koopa::ir::Program;
+use crate::backend::{self, BackendOptions};
+use crate::backend::asm::AsmEmitter;
+use crate::common::alloc_scopes::AllocScopes;
+use crate::common::diagnostic::Diagnostic;
+use crate::common::session::{Lint, LintLevel, Session};
+use crate::frontend::{self, ast::CompUnit, comments::IRComments, symbol_index::SymbolIndex};
+use crate::ir::KoopaGenerator;
+use crate::opt::{self, OptLevel, OptLimits};
+use crate::opt::pass_manager::Pipeline;
+
+// The optimized IR and the scopes of its locals, see `Compiler::lower`
+type Lowered = (Compiled<Rc<RefCell<Program>>>, AllocScopes);
+
+// The pipeline of the binary on a single source string, for tools embedding the compiler
+// instead of running it. Diagnostics are returned rather than printed, see `CompileError::render`.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    opt_level: OptLevel,
+    opt_limits: OptLimits,
+    // Replaces the passes of `opt_level`
+    passes: Option<Pipeline>,
+    // Only carries the lint levels, every compilation starts from a copy
+    session: Session,
+    backend: BackendOptions,
+}
+
+// A successful compilation, with the warnings reported along the way
+#[derive(Debug)]
+pub struct Compiled<T> {
+    pub output: T,
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    // Every syntax error of the source
+    Syntax(Vec<Diagnostic>),
+    // Every semantic error and denied lint, with the warnings
+    Semantic(Vec<Diagnostic>),
+    // A failure of the compiler itself on a program that passed the checks
+    Internal(String),
+}
+
+impl CompileError {
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            CompileError::Syntax(diagnostics) | CompileError::Semantic(diagnostics) => diagnostics,
+            CompileError::Internal(_) => &[],
+        }
+    }
+
+    // As printed by the binary, `file_name` being the name to show for `source`
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        match self {
+            CompileError::Internal(message) => format!("error: {}", message),
+            _ => self.diagnostics().iter().map(|diagnostic| diagnostic.render(file_name, source)).collect::<Vec<_>>().join("\n"),
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompileError::Internal(message) => write!(f, "internal error: {}", message),
+            _ => {
+                let errors: Vec<&str> = self.diagnostics().iter()
+                    .filter(|diagnostic| diagnostic.is_error())
+                    .map(|diagnostic| diagnostic.message.as_str())
+                    .collect();
+                write!(f, "{}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    // `-O1` with the default lint levels and code generation, as the binary
+    pub fn new() -> Self {
+        Compiler {
+            opt_level: OptLevel::O1,
+            opt_limits: OptLimits::default(),
+            passes: None,
+            session: Session::new(),
+            backend: BackendOptions::default(),
+        }
+    }
+
+    pub fn opt_level(mut self, level: OptLevel) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    // Unlimited by default
+    pub fn opt_limits(mut self, limits: OptLimits) -> Self {
+        self.opt_limits = limits;
+        self
+    }
+
+    // The optimization passes to run instead of those of the level, which still applies to
+    // the code generation
+    pub fn passes(mut self, pipeline: Pipeline) -> Self {
+        self.passes = Some(pipeline);
+        self
+    }
+
+    pub fn lint_level(mut self, lint: Lint, level: LintLevel) -> Self {
+        self.session.set_lint_level(lint, level);
+        self
+    }
+
+    // The lint levels of `session`, e.g. as set by a command line
+    pub fn lint_levels_of(mut self, session: &Session) -> Self {
+        for lint in Lint::ALL {
+            self.session.set_lint_level(lint, session.lint_level(lint));
+        }
+        self
+    }
+
+    pub fn backend_options(mut self, options: BackendOptions) -> Self {
+        self.backend = options;
+        self
+    }
+
+    // Only parses, the program is not checked
+    pub fn compile_to_ast(&self, source: &str) -> Result<Compiled<CompUnit>, CompileError> {
+        let ast = frontend::parser::parse(source).map_err(CompileError::Syntax)?;
+        Ok(Compiled { output: ast, warnings: Vec::new() })
+    }
+
+    // Name resolution of the source, for tools such as editors. Only the source itself is
+    // checked, not its linkage, so that it need not define `main`.
+    pub fn symbol_index(&self, source: &str) -> Result<Compiled<SymbolIndex>, CompileError> {
+        let ast = frontend::parser::parse(source).map_err(CompileError::Syntax)?;
+        let mut session = self.session.clone();
+        let checked = frontend::semant::check(&ast, &mut session);
+        if session.has_errors() {
+            return Err(CompileError::Semantic(session.take_diagnostics()));
+        }
+        Ok(Compiled { output: checked.symbol_index, warnings: session.take_diagnostics() })
+    }
+
+    pub fn compile_to_koopa(&self, source: &str) -> Result<Compiled<String>, CompileError> {
+        let Compiled { output: program, warnings } = self.compile_to_program(source)?;
+        let mut gen = KoopaGenerator::new(Vec::new());
+        gen.generate_on(&program.borrow()).map_err(|error| CompileError::Internal(error.to_string()))?;
+        let text = String::from_utf8(gen.writer()).map_err(|error| CompileError::Internal(error.to_string()))?;
+        Ok(Compiled { output: text, warnings })
+    }
+
+    pub fn compile_to_riscv(&self, source: &str) -> Result<Compiled<String>, CompileError> {
+        let (Compiled { output: program, warnings }, alloc_scopes) = self.lower(source)?;
+        let options = self.backend.at_level(self.opt_level);
+        let mut asm_program = backend::generate_code(&mut program.borrow_mut(), &options, &HashMap::new(), &alloc_scopes);
+        backend::link(&mut asm_program, &options, false);
+        let mut assembly = Vec::new();
+        asm_program.emit(&mut assembly).map_err(|error| CompileError::Internal(error.to_string()))?;
+        let text = String::from_utf8(assembly).map_err(|error| CompileError::Internal(error.to_string()))?;
+        Ok(Compiled { output: text, warnings })
+    }
+
+    // The optimized IR
+    pub fn compile_to_program(&self, source: &str) -> Result<Compiled<Rc<RefCell<Program>>>, CompileError> {
+        self.lower(source).map(|(program, _)| program)
+    }
+
+    // The optimized IR and the scopes of its locals
+    fn lower(&self, source: &str) -> Result<Lowered, CompileError> {
+        let ast = frontend::parser::parse(source).map_err(CompileError::Syntax)?;
+        let mut session = self.session.clone();
+
+        let checked = frontend::semant::check(&ast, &mut session);
+        if session.has_errors() {
+            return Err(CompileError::Semantic(session.take_diagnostics()));
+        }
+        let linkage_errors = frontend::semant::check_linkage(&[("<input>", source, &checked.interface)]);
+        if !linkage_errors.is_empty() {
+            let mut diagnostics = session.take_diagnostics();
+            diagnostics.extend(linkage_errors.into_iter().map(|(_, diagnostic)| diagnostic));
+            return Err(CompileError::Semantic(diagnostics));
+        }
+
+        let comments = Rc::new(RefCell::new(IRComments::new(false)));
+        let (program, alloc_scopes) = frontend::generate_ir(&[ast], &comments).map_err(|error| CompileError::Internal(error.to_string()))?;
+        let pipeline = self.passes.clone().unwrap_or_else(|| opt::pipeline(self.opt_level));
+        opt::run_pipeline(&mut program.borrow_mut(), &pipeline, &self.opt_limits, &mut session)
+            .map_err(|error| CompileError::Internal(error.to_string()))?;
+        if session.has_errors() {
+            return Err(CompileError::Semantic(session.take_diagnostics()));
+        }
+        Ok((Compiled { output: program, warnings: session.take_diagnostics() }, alloc_scopes))
+    }
+}
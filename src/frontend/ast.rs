@@ -1,7 +1,8 @@
-use koopa::ir::Type;
+use crate::common::diagnostic::Span;
 use crate::frontend::environment::IREnvironment;
 use crate::frontend::FrontendError;
 use crate::frontend::FrontendError::{BindingNonConstExpr, ConstEvalDivZero};
+use crate::frontend::types::Ty;
 use crate::frontend::symbol::SymbolTableEntry;
 
 #[derive(Debug)]
@@ -12,15 +13,28 @@ pub struct CompUnit {
 #[derive(Debug)]
 pub enum CompElement {
     Decl(Decl),
+    FuncDecl(FuncDecl),
     FuncDef(FuncDef),
 }
 
+#[derive(Debug)]
+pub struct FuncDecl {
+    pub func_type: FuncType,
+    pub ident: String,
+    pub params: Vec<FuncFParam>,
+    pub span: Span,
+    pub ident_span: Span,
+}
+
 #[derive(Debug)]
 pub struct FuncDef {
     pub func_type: FuncType,
     pub ident: String,
     pub params: Vec<FuncFParam>,
     pub block: Block,
+    // The function header, from the return type to the closing parenthesis
+    pub span: Span,
+    pub ident_span: Span,
 }
 
 #[derive(Debug)]
@@ -30,10 +44,10 @@ pub enum FuncType {
 }
 
 impl FuncType {
-    pub fn to(&self) -> Type {
+    pub fn ty(&self) -> Ty {
         match self {
-            FuncType::Int => Type::get_i32(),
-            FuncType::Void => Type::get_unit(),
+            FuncType::Int => Ty::Int,
+            FuncType::Void => Ty::Void,
         }
     }
 
@@ -50,9 +64,9 @@ pub enum BType {
 }
 
 impl BType {
-    pub fn to(&self) -> Type {
+    pub fn ty(&self) -> Ty {
         match self {
-            BType::Int => Type::get_i32(),
+            BType::Int => Ty::Int,
         }
     }
 
@@ -68,6 +82,7 @@ impl BType {
 pub struct FuncFParam {
     pub btype: BType,
     pub ident: String,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -75,18 +90,60 @@ pub struct Block {
     pub items: Vec<BlockItem>
 }
 
+impl Block {
+    pub fn can_complete_normally(&self) -> bool {
+        self.items.iter().all(|item| match item {
+            BlockItem::Decl(_) => true,
+            BlockItem::Stmt(stmt) => stmt.can_complete_normally(),
+        })
+    }
+
+    // Whether the variable named `ident` outside the block is assigned in it, a declaration
+    // of the same name hiding it from there on
+    pub fn assigns(&self, ident: &str) -> bool {
+        for item in self.items.iter() {
+            match item {
+                BlockItem::Decl(decl) if decl.declares(ident) => return false,
+                BlockItem::Stmt(stmt) if stmt.assigns(ident) => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
 #[derive(Debug)]
 pub enum BlockItem {
     Decl(Decl),
     Stmt(Stmt),
 }
 
+impl BlockItem {
+    // A declaration is located at its first definition
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            BlockItem::Decl(Decl::ConstDecl(const_decl)) => const_decl.defs.first().map(|def| def.span),
+            BlockItem::Decl(Decl::VarDecl(var_decl)) => var_decl.defs.first().map(|def| def.span),
+            BlockItem::Stmt(stmt) => Some(stmt.span),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Decl {
     ConstDecl(ConstDecl),
     VarDecl(VarDecl),
 }
 
+impl Decl {
+    pub fn declares(&self, ident: &str) -> bool {
+        match self {
+            Decl::ConstDecl(const_decl) => const_decl.defs.iter().any(|def| def.ident == ident),
+            Decl::VarDecl(var_decl) => var_decl.defs.iter().any(|def| def.ident == ident),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ConstDecl {
     pub btype: BType,
@@ -97,6 +154,8 @@ pub struct ConstDecl {
 pub struct ConstDef {
     pub ident: String,
     pub init_val: ConstInitVal,
+    // The defined identifier
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -111,13 +170,66 @@ pub struct VarDecl {
 }
 
 #[derive(Debug)]
-pub enum VarDef {
-    Ident(String),
-    Init(String, Expr),
+pub struct VarDef {
+    pub ident: String,
+    pub init_val: Option<InitVal>,
+    // The defined identifier
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, start: usize, end: usize) -> Self {
+        Stmt { kind, span: Span::new(start, end) }
+    }
+
+    // Whether control can flow past the end of the statement,
+    // i.e. it does not always end in `return`, `break`, `continue` or an infinite loop
+    pub fn can_complete_normally(&self) -> bool {
+        match &self.kind {
+            StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue => false,
+            StmtKind::Block(block) => block.can_complete_normally(),
+            StmtKind::If(_, _) => true,
+            StmtKind::IfElse(_, then_stmt, else_stmt) => then_stmt.can_complete_normally() || else_stmt.can_complete_normally(),
+            StmtKind::While(cond, body) => {
+                let is_infinite = matches!(cond.kind, ExprKind::Num(num) if num != 0);
+                !is_infinite || body.contains_break()
+            }
+            StmtKind::Assign(_, _) | StmtKind::Expr(_) | StmtKind::Empty => true,
+        }
+    }
+
+    pub fn assigns(&self, ident: &str) -> bool {
+        match &self.kind {
+            StmtKind::Assign(lval, _) => lval.ident() == ident,
+            StmtKind::Block(block) => block.assigns(ident),
+            StmtKind::If(_, then_stmt) | StmtKind::While(_, then_stmt) => then_stmt.assigns(ident),
+            StmtKind::IfElse(_, then_stmt, else_stmt) => then_stmt.assigns(ident) || else_stmt.assigns(ident),
+            StmtKind::Return(_) | StmtKind::Expr(_) | StmtKind::Empty | StmtKind::Break | StmtKind::Continue => false,
+        }
+    }
+
+    // Whether the statement contains a `break` leaving the enclosing loop
+    fn contains_break(&self) -> bool {
+        match &self.kind {
+            StmtKind::Break => true,
+            StmtKind::Block(block) => block.items.iter().any(|item| matches!(item, BlockItem::Stmt(stmt) if stmt.contains_break())),
+            StmtKind::If(_, then_stmt) => then_stmt.contains_break(),
+            StmtKind::IfElse(_, then_stmt, else_stmt) => then_stmt.contains_break() || else_stmt.contains_break(),
+            // A nested loop captures its own `break`s
+            StmtKind::While(_, _) => false,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub enum Stmt {
+pub enum StmtKind {
     Return(Option<Expr>),
     Assign(LVal, Expr),
     Expr(Expr),
@@ -149,7 +261,19 @@ impl LVal {
 }
 
 #[derive(Debug)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, start: usize, end: usize) -> Self {
+        Expr { kind, span: Span::new(start, end) }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
     Num(i32),
     LVal(LVal),
     Pos(Box<Expr>),
@@ -170,87 +294,240 @@ pub enum Expr {
     Land(Box<Expr>, Box<Expr>),
     Lor(Box<Expr>, Box<Expr>),
     Call(String, Vec<Expr>),
+    // Only the format of `putf`, see `ir::library`
+    Str(String),
 }
 
 // macro rule for binary
 macro_rules! binary_expr_eval_rule {
-    ($env:expr, $lhs:expr, $rhs:expr, $op:expr) => {{
-        let lhs_val = $lhs.try_const_eval($env)?;
-        let rhs_val = $rhs.try_const_eval($env)?;
+    ($lookup:expr, $on_overflow:expr, $lhs:expr, $rhs:expr, $op:expr) => {{
+        let lhs_val = $lhs.const_eval($lookup, $on_overflow)?;
+        let rhs_val = $rhs.const_eval($lookup, $on_overflow)?;
         Ok($op(lhs_val, rhs_val))
     }};
 }
 
+// Arithmetic that can leave the range of `i32`: `$checked` detects the overflow,
+// `$wrapping` computes the result the generated instruction would produce
+macro_rules! wrapping_expr_eval_rule {
+    ($expr:expr, $lookup:expr, $on_overflow:expr, $lhs:expr, $rhs:expr, $checked:ident, $wrapping:ident) => {{
+        let lhs_val = $lhs.const_eval($lookup, $on_overflow)?;
+        let rhs_val = $rhs.const_eval($lookup, $on_overflow)?;
+        Ok(lhs_val.$checked(rhs_val).unwrap_or_else(|| {
+            let wrapped = lhs_val.$wrapping(rhs_val);
+            $on_overflow($expr, wrapped);
+            wrapped
+        }))
+    }};
+}
+
 impl Expr {
     pub fn has_side_effect(&self) -> bool {
-        match self {
-            Expr::Num(_) => false,
-            Expr::LVal(_) => false,
-            Expr::Pos(sub) => sub.has_side_effect(),
-            Expr::Neg(sub) => sub.has_side_effect(),
-            Expr::Not(sub) => sub.has_side_effect(),
-            Expr::Add(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Sub(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Mul(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Div(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Mod(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Lt(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Gt(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Le(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Ge(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Eq(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Ne(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Land(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Lor(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
-            Expr::Call(_, _) => true,
+        match &self.kind {
+            ExprKind::Num(_) => false,
+            ExprKind::LVal(_) => false,
+            ExprKind::Pos(sub) => sub.has_side_effect(),
+            ExprKind::Neg(sub) => sub.has_side_effect(),
+            ExprKind::Not(sub) => sub.has_side_effect(),
+            ExprKind::Add(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Sub(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Mul(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Div(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Mod(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Lt(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Gt(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Le(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Ge(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Eq(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Ne(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Land(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Lor(lhs, rhs) => lhs.has_side_effect() || rhs.has_side_effect(),
+            ExprKind::Call(_, _) => true,
+            ExprKind::Str(_) => false,
         }
     }
     
     pub fn try_const_eval(&self, env: &IREnvironment) -> Result<i32, FrontendError> {
-        match self {
-            Expr::Num(num) => Ok(*num),
-            Expr::LVal(lval) => {
-                match env.lookup_lval(lval) {
-                    None => Err(BindingNonConstExpr(lval.ident().into())),
-                    Some(entry) => {
-                        match entry {
-                            SymbolTableEntry::Const(_, num) => Ok(num),
-                            _ => Err(BindingNonConstExpr(lval.ident().into())),
-                        }
-                    }
-                }
-            },
-            Expr::Pos(expr) => expr.try_const_eval(env),
-            Expr::Neg(expr) => expr.try_const_eval(env).map(|val| -val),
-            Expr::Not(expr) => expr.try_const_eval(env).map(|val| if val == 0 { 1 } else { 0 }),
-            Expr::Add(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| lhs + rhs),
-            Expr::Sub(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| lhs - rhs),
-            Expr::Mul(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| lhs * rhs),
-            Expr::Div(lhs, rhs) => {
-                let lhs_val = lhs.try_const_eval(env)?;
-                let rhs_val = rhs.try_const_eval(env)?;
+        let lookup = |lval: &LVal| match env.lookup_lval(lval) {
+            Some(SymbolTableEntry::Const(_, num)) => Some(num),
+            _ => None,
+        };
+        self.const_eval(&lookup, &mut |_, _| {})
+    }
+
+    // Evaluate with the wrapping two's complement semantics of the generated RISC-V code,
+    // e.g. `-2147483648 / -1` is `-2147483648` like `div` computes it.
+    // `lookup` gives the values of constants, `on_overflow` is called with every subexpression
+    // whose exact result does not fit in `i32`, together with the wrapped result.
+    pub fn const_eval<L, O>(&self, lookup: &L, on_overflow: &mut O) -> Result<i32, FrontendError>
+    where
+        L: Fn(&LVal) -> Option<i32>,
+        O: FnMut(&Expr, i32),
+    {
+        match &self.kind {
+            ExprKind::Num(num) => Ok(*num),
+            ExprKind::LVal(lval) => lookup(lval).ok_or_else(|| BindingNonConstExpr(lval.ident().into())),
+            ExprKind::Pos(expr) => expr.const_eval(lookup, on_overflow),
+            ExprKind::Neg(expr) => {
+                let val = expr.const_eval(lookup, on_overflow)?;
+                Ok(val.checked_neg().unwrap_or_else(|| {
+                    on_overflow(self, val.wrapping_neg());
+                    val.wrapping_neg()
+                }))
+            }
+            ExprKind::Not(expr) => expr.const_eval(lookup, on_overflow).map(|val| if val == 0 { 1 } else { 0 }),
+            ExprKind::Add(lhs, rhs) => wrapping_expr_eval_rule!(self, lookup, on_overflow, lhs, rhs, checked_add, wrapping_add),
+            ExprKind::Sub(lhs, rhs) => wrapping_expr_eval_rule!(self, lookup, on_overflow, lhs, rhs, checked_sub, wrapping_sub),
+            ExprKind::Mul(lhs, rhs) => wrapping_expr_eval_rule!(self, lookup, on_overflow, lhs, rhs, checked_mul, wrapping_mul),
+            ExprKind::Div(lhs, rhs) => {
+                let lhs_val = lhs.const_eval(lookup, on_overflow)?;
+                let rhs_val = rhs.const_eval(lookup, on_overflow)?;
                 if rhs_val == 0 {
                     return Err(ConstEvalDivZero);
                 }
-                Ok(lhs_val / rhs_val)
+                // Only `i32::MIN / -1` overflows
+                Ok(lhs_val.checked_div(rhs_val).unwrap_or_else(|| {
+                    on_overflow(self, lhs_val.wrapping_div(rhs_val));
+                    lhs_val.wrapping_div(rhs_val)
+                }))
             }
-            Expr::Mod(lhs, rhs) => {
-                let lhs_val = lhs.try_const_eval(env)?;
-                let rhs_val = rhs.try_const_eval(env)?;
+            ExprKind::Mod(lhs, rhs) => {
+                let lhs_val = lhs.const_eval(lookup, on_overflow)?;
+                let rhs_val = rhs.const_eval(lookup, on_overflow)?;
                 if rhs_val == 0 {
                     return Err(ConstEvalDivZero);
                 }
-                Ok(lhs_val % rhs_val)
+                // The remainder always fits, `i32::MIN % -1` is 0 like `rem` computes it
+                Ok(lhs_val.wrapping_rem(rhs_val))
+            }
+            ExprKind::Lt(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs < rhs { 1 } else { 0 }),
+            ExprKind::Gt(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs > rhs { 1 } else { 0 }),
+            ExprKind::Le(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs <= rhs { 1 } else { 0 }),
+            ExprKind::Ge(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs >= rhs { 1 } else { 0 }),
+            ExprKind::Eq(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs == rhs { 1 } else { 0 }),
+            ExprKind::Ne(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs != rhs { 1 } else { 0 }),
+            ExprKind::Land(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs != 0 && rhs != 0 { 1 } else { 0 }),
+            ExprKind::Lor(lhs, rhs) => binary_expr_eval_rule!(lookup, on_overflow, lhs, rhs, |lhs, rhs| if lhs != 0 || rhs != 0 { 1 } else { 0 }),
+            ExprKind::Call(ident, _) => Err(BindingNonConstExpr(ident.into())),
+            ExprKind::Str(_) => Err(FrontendError::MisplacedStringLiteral),
+        }
+    }
+}
+impl std::fmt::Display for LVal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.ident())
+    }
+}
+
+impl Expr {
+    // Binding strength of the expression, used to decide where parentheses are needed
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Lor(_, _) => 1,
+            ExprKind::Land(_, _) => 2,
+            ExprKind::Eq(_, _) | ExprKind::Ne(_, _) => 3,
+            ExprKind::Lt(_, _) | ExprKind::Gt(_, _) | ExprKind::Le(_, _) | ExprKind::Ge(_, _) => 4,
+            ExprKind::Add(_, _) | ExprKind::Sub(_, _) => 5,
+            ExprKind::Mul(_, _) | ExprKind::Div(_, _) | ExprKind::Mod(_, _) => 6,
+            ExprKind::Pos(_) | ExprKind::Neg(_) | ExprKind::Not(_) => 7,
+            ExprKind::Num(_) | ExprKind::LVal(_) | ExprKind::Call(_, _) | ExprKind::Str(_) => 8,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter, min_precedence: u8) -> std::fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Expr {
+    // Symbol of the unary or binary operator at the root of the expression
+    pub fn operator(&self) -> Option<&'static str> {
+        match &self.kind {
+            ExprKind::Num(_) | ExprKind::LVal(_) | ExprKind::Call(_, _) | ExprKind::Str(_) => None,
+            ExprKind::Pos(_) | ExprKind::Add(_, _) => Some("+"),
+            ExprKind::Neg(_) | ExprKind::Sub(_, _) => Some("-"),
+            ExprKind::Not(_) => Some("!"),
+            ExprKind::Mul(_, _) => Some("*"),
+            ExprKind::Div(_, _) => Some("/"),
+            ExprKind::Mod(_, _) => Some("%"),
+            ExprKind::Lt(_, _) => Some("<"),
+            ExprKind::Gt(_, _) => Some(">"),
+            ExprKind::Le(_, _) => Some("<="),
+            ExprKind::Ge(_, _) => Some(">="),
+            ExprKind::Eq(_, _) => Some("=="),
+            ExprKind::Ne(_, _) => Some("!="),
+            ExprKind::Land(_, _) => Some("&&"),
+            ExprKind::Lor(_, _) => Some("||"),
+        }
+    }
+}
+
+// Prints the expression back in SysY syntax, with only the necessary parentheses
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = self.operator().unwrap_or_default();
+        let (lhs, rhs) = match &self.kind {
+            ExprKind::Num(num) => return write!(f, "{}", num),
+            ExprKind::LVal(lval) => return write!(f, "{}", lval),
+            ExprKind::Str(string) => {
+                write!(f, "\"")?;
+                for c in string.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\\' | '"' => write!(f, "\\{}", c)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                return write!(f, "\"");
             }
-            Expr::Lt(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs < rhs { 1 } else { 0 }),
-            Expr::Gt(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs > rhs { 1 } else { 0 }),
-            Expr::Le(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs <= rhs { 1 } else { 0 }),
-            Expr::Ge(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs >= rhs { 1 } else { 0 }),
-            Expr::Eq(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs == rhs { 1 } else { 0 }),
-            Expr::Ne(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs != rhs { 1 } else { 0 }),
-            Expr::Land(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs != 0 && rhs != 0 { 1 } else { 0 }),
-            Expr::Lor(lhs, rhs) => binary_expr_eval_rule!(env, lhs, rhs, |lhs, rhs| if lhs != 0 || rhs != 0 { 1 } else { 0 }),
-            Expr::Call(ident, _) => Err(BindingNonConstExpr(ident.into())),
+            ExprKind::Pos(sub) | ExprKind::Neg(sub) | ExprKind::Not(sub) => {
+                write!(f, "{}", op)?;
+                return sub.fmt_operand(f, self.precedence());
+            }
+            ExprKind::Call(ident, args) => {
+                write!(f, "{}(", ident)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                return write!(f, ")");
+            }
+            ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) | ExprKind::Mul(lhs, rhs) |
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) | ExprKind::Lt(lhs, rhs) |
+            ExprKind::Gt(lhs, rhs) | ExprKind::Le(lhs, rhs) | ExprKind::Ge(lhs, rhs) |
+            ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) | ExprKind::Land(lhs, rhs) |
+            ExprKind::Lor(lhs, rhs) => (lhs, rhs),
+        };
+        // All binary operators are left-associative
+        lhs.fmt_operand(f, self.precedence())?;
+        write!(f, " {} ", op)?;
+        rhs.fmt_operand(f, self.precedence() + 1)
+    }
+}
+
+impl Stmt {
+    // A one-line rendering of the statement's own source text, without nested bodies.
+    // Used to annotate the IR generated for the statement.
+    pub fn source_text(&self) -> Option<String> {
+        match &self.kind {
+            StmtKind::Return(None) => Some("return;".into()),
+            StmtKind::Return(Some(expr)) => Some(format!("return {};", expr)),
+            StmtKind::Assign(lval, expr) => Some(format!("{} = {};", lval, expr)),
+            StmtKind::Expr(expr) => Some(format!("{};", expr)),
+            StmtKind::If(cond, _) => Some(format!("if ({})", cond)),
+            StmtKind::IfElse(cond, _, _) => Some(format!("if ({}) ... else", cond)),
+            StmtKind::While(cond, _) => Some(format!("while ({})", cond)),
+            StmtKind::Break => Some("break;".into()),
+            StmtKind::Continue => Some("continue;".into()),
+            StmtKind::Empty | StmtKind::Block(_) => None,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,207 @@
+use std::fmt::Write;
+use crate::common::diagnostic::Span;
+use crate::frontend::ast::*;
+
+// Human-readable dumps of the AST, as an indented tree or as JSON.
+// Both are rendered from the same generic tree of `Node`s.
+pub fn dump_text(comp_unit: &CompUnit, source: &str) -> String {
+    let mut out = String::new();
+    comp_unit.to_node().write_text(&mut out, source, 0);
+    out
+}
+
+pub fn dump_json(comp_unit: &CompUnit, source: &str) -> String {
+    let mut out = String::new();
+    comp_unit.to_node().write_json(&mut out, source, 0);
+    out.push('\n');
+    out
+}
+
+struct Node {
+    kind: &'static str,
+    // e.g. the identifier of a definition or the operator of an expression
+    attrs: Vec<(&'static str, String)>,
+    span: Option<Span>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(kind: &'static str, span: Option<Span>) -> Self {
+        Node { kind, attrs: Vec::new(), span, children: Vec::new() }
+    }
+
+    fn attr(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.attrs.push((name, value.to_string()));
+        self
+    }
+
+    fn child(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn children(mut self, children: impl IntoIterator<Item = Node>) -> Self {
+        self.children.extend(children);
+        self
+    }
+
+    // `FuncDef name=main type=int @1:1`, children indented by two more spaces
+    fn write_text(&self, out: &mut String, source: &str, depth: usize) {
+        write!(out, "{:indent$}{}", "", self.kind, indent = depth * 2).unwrap();
+        for (name, value) in self.attrs.iter() {
+            write!(out, " {}={}", name, value).unwrap();
+        }
+        if let Some(span) = self.span {
+            let (line, column) = span.line_col(source);
+            write!(out, " @{}:{}", line, column).unwrap();
+        }
+        out.push('\n');
+        for child in self.children.iter() {
+            child.write_text(out, source, depth + 1);
+        }
+    }
+
+    fn write_json(&self, out: &mut String, source: &str, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        write!(out, "{{\n{}\"kind\": \"{}\"", indent, self.kind).unwrap();
+        for (name, value) in self.attrs.iter() {
+            write!(out, ",\n{}\"{}\": {}", indent, name, json_string(value)).unwrap();
+        }
+        if let Some(span) = self.span {
+            let (line, column) = span.line_col(source);
+            write!(out, ",\n{}\"span\": {{ \"start\": {}, \"end\": {}, \"line\": {}, \"column\": {} }}",
+                   indent, span.start, span.end, line, column).unwrap();
+        }
+        if !self.children.is_empty() {
+            write!(out, ",\n{}\"children\": [", indent).unwrap();
+            for (i, child) in self.children.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                write!(out, "{}  ", indent).unwrap();
+                child.write_json(out, source, depth + 2);
+            }
+            write!(out, "\n{}]", indent).unwrap();
+        }
+        write!(out, "\n{}}}", "  ".repeat(depth)).unwrap();
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+trait ToNode {
+    fn to_node(&self) -> Node;
+}
+
+impl ToNode for CompUnit {
+    fn to_node(&self) -> Node {
+        Node::new("CompUnit", None).children(self.elements.iter().map(|element| match element {
+            CompElement::Decl(decl) => decl.to_node(),
+            CompElement::FuncDecl(func_decl) => Node::new("FuncDecl", Some(func_decl.span))
+                .attr("name", &func_decl.ident)
+                .attr("type", func_decl.func_type.ty())
+                .children(func_decl.params.iter().map(ToNode::to_node)),
+            CompElement::FuncDef(func_def) => func_def.to_node(),
+        }))
+    }
+}
+
+impl ToNode for FuncDef {
+    fn to_node(&self) -> Node {
+        Node::new("FuncDef", Some(self.span))
+            .attr("name", &self.ident)
+            .attr("type", self.func_type.ty())
+            .children(self.params.iter().map(ToNode::to_node))
+            .child(self.block.to_node())
+    }
+}
+
+impl ToNode for FuncFParam {
+    fn to_node(&self) -> Node {
+        Node::new("Param", Some(self.span)).attr("name", &self.ident).attr("type", self.btype.ty())
+    }
+}
+
+impl ToNode for Block {
+    fn to_node(&self) -> Node {
+        Node::new("Block", None).children(self.items.iter().map(|item| match item {
+            BlockItem::Decl(decl) => decl.to_node(),
+            BlockItem::Stmt(stmt) => stmt.to_node(),
+        }))
+    }
+}
+
+impl ToNode for Decl {
+    fn to_node(&self) -> Node {
+        match self {
+            Decl::ConstDecl(const_decl) => Node::new("ConstDecl", None)
+                .attr("type", const_decl.btype.ty())
+                .children(const_decl.defs.iter().map(|def| match &def.init_val {
+                    ConstInitVal::Expr(expr) => Node::new("ConstDef", Some(def.span)).attr("name", &def.ident).child(expr.to_node()),
+                })),
+            Decl::VarDecl(var_decl) => Node::new("VarDecl", None)
+                .attr("type", var_decl.btype.ty())
+                .children(var_decl.defs.iter().map(|def| {
+                    let node = Node::new("VarDef", Some(def.span)).attr("name", &def.ident);
+                    match &def.init_val {
+                        Some(InitVal::Expr(expr)) => node.child(expr.to_node()),
+                        None => node,
+                    }
+                })),
+        }
+    }
+}
+
+impl ToNode for Stmt {
+    fn to_node(&self) -> Node {
+        let span = Some(self.span);
+        match &self.kind {
+            StmtKind::Return(expr) => Node::new("Return", span).children(expr.iter().map(ToNode::to_node)),
+            StmtKind::Assign(lval, expr) => Node::new("Assign", span).attr("target", lval).child(expr.to_node()),
+            StmtKind::Expr(expr) => Node::new("ExprStmt", span).child(expr.to_node()),
+            StmtKind::Empty => Node::new("Empty", span),
+            StmtKind::Block(block) => Node { span, ..block.to_node() },
+            StmtKind::If(cond, then_stmt) => Node::new("If", span).child(cond.to_node()).child(then_stmt.to_node()),
+            StmtKind::IfElse(cond, then_stmt, else_stmt) => Node::new("If", span)
+                .child(cond.to_node())
+                .child(then_stmt.to_node())
+                .child(else_stmt.to_node()),
+            StmtKind::While(cond, body) => Node::new("While", span).child(cond.to_node()).child(body.to_node()),
+            StmtKind::Break => Node::new("Break", span),
+            StmtKind::Continue => Node::new("Continue", span),
+        }
+    }
+}
+
+impl ToNode for Expr {
+    fn to_node(&self) -> Node {
+        let span = Some(self.span);
+        match &self.kind {
+            ExprKind::Num(num) => Node::new("Number", span).attr("value", num),
+            ExprKind::LVal(lval) => Node::new("LVal", span).attr("name", lval),
+            ExprKind::Str(_) => Node::new("String", span).attr("value", self),
+            ExprKind::Call(ident, args) => Node::new("Call", span).attr("name", ident).children(args.iter().map(ToNode::to_node)),
+            ExprKind::Pos(sub) | ExprKind::Neg(sub) | ExprKind::Not(sub) => Node::new("Unary", span)
+                .attr("op", self.operator().unwrap())
+                .child(sub.to_node()),
+            ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) | ExprKind::Mul(lhs, rhs) |
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) | ExprKind::Lt(lhs, rhs) |
+            ExprKind::Gt(lhs, rhs) | ExprKind::Le(lhs, rhs) | ExprKind::Ge(lhs, rhs) |
+            ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) | ExprKind::Land(lhs, rhs) |
+            ExprKind::Lor(lhs, rhs) => Node::new("Binary", span)
+                .attr("op", self.operator().unwrap())
+                .child(lhs.to_node())
+                .child(rhs.to_node()),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use koopa::ir::{BasicBlock, FunctionData, TypeKind, ValueKind};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+
+// Koopa IR requires every basic block to end with exactly one terminator. The generator
+// stops a block at its first terminator, leaving the blocks that fall off the end of the
+// function, e.g. the end of a function returning a value without a last `return`, or a
+// merge block both branches of which return. This terminates them with a `ret`. It completes
+// IR generation and runs at every `-O` level.
+pub fn terminate_blocks(func_data: &mut FunctionData) {
+    let unterminated: Vec<BasicBlock> = func_data.layout().bbs().iter()
+        .filter(|(_, node)| !node.insts().back_key().is_some_and(|&inst| {
+            matches!(func_data.dfg().value(inst).kind(), ValueKind::Branch(_) | ValueKind::Return(_) | ValueKind::Jump(_))
+        }))
+        .map(|(&bb, _)| bb)
+        .collect();
+
+    // A function returning a value returns 0 when it falls off its end. The value is only
+    // specified for `main`, by C++, `void` functions already end with a `ret`.
+    let TypeKind::Function(_, ret_ty) = func_data.ty().kind() else { unreachable!() };
+    let returns_value = !ret_ty.is_unit();
+    for bb in unterminated {
+        let value = returns_value.then(|| func_data.dfg_mut().new_value().integer(0));
+        let ret_inst = func_data.dfg_mut().new_value().ret(value);
+        let bb_node = func_data.layout_mut().bbs_mut().node_mut(&bb).unwrap();
+        bb_node.insts_mut().push_key_back(ret_inst).unwrap();
+    }
+}
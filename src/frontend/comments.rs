@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use koopa::ir::{Program, Value};
+use crate::common::diagnostic::Span;
+
+// Source-level annotations attached to IR instructions, e.g. the statement
+// an instruction group was generated from. Koopa IR has no metadata slot,
+// so they are kept in a side table and woven into the text form on output.
+pub struct IRComments {
+    enabled: bool,
+    pending: Option<String>,
+    comments: HashMap<Value, Vec<String>>,
+    // The statement every instruction was generated for, which outlives the optimizations
+    // removing the first instruction of a statement, e.g. the `alloc` of a declaration
+    unit: usize,
+    statements: Vec<Statement>,
+    current: Option<usize>,
+    origins: HashMap<Value, usize>,
+}
+
+struct Statement {
+    unit: usize,
+    span: Span,
+    text: String,
+}
+
+impl IRComments {
+    pub fn new(enabled: bool) -> Self {
+        IRComments {
+            enabled,
+            pending: None,
+            comments: HashMap::new(),
+            unit: 0,
+            statements: Vec::new(),
+            current: None,
+            origins: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The translation unit the following statements are in
+    pub fn enter_unit(&mut self, unit: usize) {
+        self.unit = unit;
+        self.current = None;
+    }
+
+    // The comment will be attached to the next instruction added to the IR, the statement at
+    // `span` to every instruction up to the next comment
+    pub fn set_pending(&mut self, comment: String, span: Span) {
+        self.statements.push(Statement { unit: self.unit, span, text: comment.clone() });
+        self.current = Some(self.statements.len() - 1);
+        self.pending = Some(comment);
+    }
+
+    pub fn attach_pending(&mut self, inst: Value) {
+        if let Some(comment) = self.pending.take() {
+            self.comments.entry(inst).or_default().push(comment);
+        }
+        if let Some(statement) = self.current {
+            self.origins.insert(inst, statement);
+        }
+    }
+
+    pub fn get(&self, inst: Value) -> Option<&Vec<String>> {
+        self.comments.get(&inst)
+    }
+
+    // `line 12: while (i < n)` for every instruction generated for a statement, given the
+    // (file, text) of every unit. The file is named as well when there are several.
+    pub fn source_lines(&self, sources: &[(String, String)]) -> HashMap<Value, String> {
+        let lines: Vec<String> = self.statements.iter().map(|statement| {
+            let (file, source) = &sources[statement.unit];
+            let (line, _) = statement.span.line_col(source);
+            if sources.len() > 1 {
+                format!("{} line {}: {}", file, line, statement.text)
+            } else {
+                format!("line {}: {}", line, statement.text)
+            }
+        }).collect();
+        self.origins.iter().map(|(&inst, &statement)| (inst, lines[statement].clone())).collect()
+    }
+
+    // Insert the comments into the text form IR generated by `KoopaGenerator` from `program`.
+    // The generator prints every local instruction on its own line indented by two spaces,
+    // in layout order, so the n-th such line belongs to the n-th instruction of the layout.
+    pub fn annotate(&self, program: &Program, text_form_ir: &str) -> String {
+        let mut insts = program.func_layout().iter().flat_map(|&func_h| {
+            let func = program.func(func_h);
+            func.layout().bbs().iter().flat_map(|(_, node)| node.insts().keys().copied().collect::<Vec<_>>()).collect::<Vec<_>>()
+        });
+
+        let mut result = String::with_capacity(text_form_ir.len());
+        for line in text_form_ir.lines() {
+            if line.starts_with("  ") {
+                if let Some(comments) = insts.next().and_then(|inst| self.get(inst)) {
+                    for comment in comments {
+                        result.push_str("  // ");
+                        result.push_str(comment);
+                        result.push('\n');
+                    }
+                }
+            }
+            result.push_str(line);
+            result.push('\n');
+        }
+        result
+    }
+}
@@ -1,10 +1,19 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
-use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value};
-use koopa::ir::builder::BasicBlockBuilder;
-use crate::frontend::ast::LVal;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use koopa::ir::builder::{BasicBlockBuilder, GlobalInstBuilder, ValueBuilder};
+use crate::common::alloc_scopes::AllocScopes;
+use crate::common::diagnostic::Span;
+use crate::frontend::ast::{FuncFParam, FuncType, LVal};
+use crate::frontend::comments::IRComments;
 use crate::frontend::FrontendError;
-use crate::frontend::symbol::{NestedSymbolTable, SymbolTableEntry};
+use crate::frontend::loops::LoopStack;
+use crate::frontend::lowering::lower_signature;
+use crate::frontend::types::Signature;
+use crate::ir::library::{pack_string, STRING_PREFIX};
+use crate::frontend::symbol::{SymbolTable, SymbolTableEntry};
+use crate::util::interner::Name;
 use crate::util::name_generator::NameGenerator;
 
 #[macro_export]
@@ -32,63 +41,155 @@ pub struct IRContext {
     pub program: Rc<RefCell<Program>>,
     pub current_func: Option<Function>,
     pub current_bb: Option<BasicBlock>,
+    // Whether the current block ends with a terminator, nothing may follow it
+    sealed: bool,
+    pub comments: Rc<RefCell<IRComments>>,
+    // Integer constants of the current function, shared by all the environments inside it
+    pub constants: Rc<RefCell<HashMap<i32, Value>>>,
+    // The globals holding the string literals of the program, by text
+    pub strings: Rc<RefCell<HashMap<String, Value>>>,
+    // The scope of every local variable, for the backend to share their stack slots
+    pub alloc_scopes: Rc<RefCell<AllocScopes>>,
 }
 
 impl IRContext {
-    pub fn create_block(&mut self, name: Option<String>) -> BasicBlock {
+    pub fn create_block(&mut self, name: Option<Name>) -> BasicBlock {
         let mut binding = self.program.borrow_mut();
         let func_data = binding.func_mut(self.current_func.unwrap());
-        let bb = func_data.dfg_mut().new_bb().basic_block(name);
+        let bb = func_data.dfg_mut().new_bb().basic_block(name.map(|name| name.to_string()));
         // Add to the function's list of basic blocks
         func_data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
         // Do not set the current block in Context
         bb
     }
 
+    pub fn is_jumped_to(&self, bb: BasicBlock) -> bool {
+        !self.program.borrow().func(self.current_func.unwrap()).dfg().bb(bb).used_by().is_empty()
+    }
+
+    // Drops a block nothing jumps to, e.g. the merge block of an `if` both arms of which return
+    pub fn remove_block(&mut self, bb: BasicBlock) {
+        let mut binding = self.program.borrow_mut();
+        let func_data = binding.func_mut(self.current_func.unwrap());
+        func_data.layout_mut().bbs_mut().remove(&bb);
+        func_data.dfg_mut().remove_bb(bb);
+    }
+
     // This is created to avoid borrowing issues of disjoint fields in IRContext
     pub fn add_instruction(&mut self, inst: Value) {
-        self.program.borrow_mut()
-            .func_mut(self.current_func.unwrap())
-            .layout_mut()
-            .bb_mut(self.current_bb.unwrap().clone())
+        assert!(!self.sealed, "an instruction is added after the terminator of its block");
+        let mut binding = self.program.borrow_mut();
+        let func_data = binding.func_mut(self.current_func.unwrap());
+        let kind = func_data.dfg().value(inst).kind();
+        self.sealed = matches!(kind, ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_));
+        if let ValueKind::Alloc(_) = kind {
+            self.alloc_scopes.borrow_mut().record(inst);
+        }
+        func_data.layout_mut()
+            .bb_mut(self.current_bb.unwrap())
             .insts_mut()
             .push_key_back(inst)
             .unwrap();
+        self.comments.borrow_mut().attach_pending(inst);
+    }
+
+    // The code following a `return`, `break` or `continue` in the same block is unreachable
+    // and not generated
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    // The current block is not reached, as after an `if` both arms of which end with a terminator
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Names local values after the source identifiers, e.g. `@x = alloc i32`, see
+    // `IREnvironment::variable_name`
+    pub fn set_value_name(&mut self, value: Value, name: String) {
+        self.program.borrow_mut()
+            .func_mut(self.current_func.unwrap())
+            .dfg_mut()
+            .set_value_name(value, Some(name));
+    }
+
+    // Every use of the same integer in a function refers to a single value in its DFG
+    pub fn integer(&mut self, value: i32) -> Value {
+        if let Some(&integer) = self.constants.borrow().get(&value) {
+            return integer;
+        }
+        let integer = self.program.borrow_mut().func_mut(self.current_func.unwrap()).dfg_mut().new_value().integer(value);
+        self.constants.borrow_mut().insert(value, integer);
+        integer
+    }
+
+    // The global holding the string literal `text`, one for all its uses in the program,
+    // see `ir::library`
+    pub fn string_literal(&mut self, text: &str) -> Value {
+        if let Some(&global) = self.strings.borrow().get(text) {
+            return global;
+        }
+        let mut program = self.program.borrow_mut();
+        let words: Vec<Value> = pack_string(text.as_bytes()).into_iter().map(|word| program.new_value().integer(word)).collect();
+        let init = program.new_value().aggregate(words);
+        let global = program.new_value().global_alloc(init);
+        let name = format!("@{}{}", STRING_PREFIX, self.strings.borrow().len());
+        program.set_value_name(global, Some(name));
+        self.strings.borrow_mut().insert(text.to_string(), global);
+        global
     }
 }
 
 pub struct IREnvironment {
     pub context: IRContext,
     pub name_generator: Rc<RefCell<NameGenerator>>,
-    pub while_stack: Vec<(BasicBlock, BasicBlock)>,
-    symbol_table: Rc<RefCell<NestedSymbolTable>>,
+    // Shared by all the environments inside the current function
+    pub loops: Rc<RefCell<LoopStack>>,
+    // Shared by all the environments, scopes are entered and exited as the source is walked
+    symbol_table: Rc<RefCell<SymbolTable>>,
+    // The depth of the scope of the current function's parameters and body
+    func_depth: usize,
 }
 
 impl IREnvironment {
-    pub fn new(program: &Rc<RefCell<Program>>) -> Self {
+    pub fn new(program: &Rc<RefCell<Program>>, comments: &Rc<RefCell<IRComments>>) -> Self {
         IREnvironment {
             context: IRContext {
                 program: program.clone(),
                 current_func: None,
                 current_bb: None,
+                sealed: false,
+                comments: comments.clone(),
+                constants: Rc::new(RefCell::new(HashMap::new())),
+                strings: Rc::new(RefCell::new(HashMap::new())),
+                alloc_scopes: Rc::new(RefCell::new(AllocScopes::default())),
             },
             name_generator: Rc::new(RefCell::from(NameGenerator::new())),
-            while_stack: Vec::new(),
-            symbol_table: Rc::new(RefCell::new(NestedSymbolTable::new())),
+            loops: Rc::new(RefCell::new(LoopStack::default())),
+            symbol_table: Rc::new(RefCell::new(SymbolTable::new())),
+            func_depth: 0,
         }
     }
 
+    // Enters the scope of the parameters and the body of `func`, which is exited by `exit_scope`
     pub fn enter_func(&self, func: Function) -> Self {
+        self.symbol_table.borrow_mut().enter_scope();
+        self.context.alloc_scopes.borrow_mut().enter_scope();
         IREnvironment {
             context: IRContext {
                 program: self.context.program.clone(),
                 current_func: Some(func),
                 current_bb: None,
+                sealed: false,
+                comments: self.context.comments.clone(),
+                constants: Rc::new(RefCell::new(HashMap::new())),
+                strings: self.context.strings.clone(),
+                alloc_scopes: self.context.alloc_scopes.clone(),
             },
             name_generator: self.name_generator.clone(),
-            while_stack: Vec::new(),
-            // A new symbol table as a child of the current symbol table
-            symbol_table: Rc::new(RefCell::new(NestedSymbolTable::new_child(self.symbol_table.clone()))),
+            loops: Rc::new(RefCell::new(LoopStack::default())),
+            symbol_table: self.symbol_table.clone(),
+            func_depth: self.symbol_table.borrow().depth(),
         }
     }
 
@@ -100,27 +201,40 @@ impl IREnvironment {
                 program: self.context.program.clone(),
                 current_func: self.context.current_func,
                 current_bb: Some(bb),
+                sealed: false,
+                comments: self.context.comments.clone(),
+                constants: self.context.constants.clone(),
+                strings: self.context.strings.clone(),
+                alloc_scopes: self.context.alloc_scopes.clone(),
             },
             name_generator: self.name_generator.clone(),
-            while_stack: self.while_stack.clone(),
+            loops: self.loops.clone(),
             symbol_table: self.symbol_table.clone(),
+            func_depth: self.func_depth,
         }
     }
 
     pub fn enter_bb(&mut self, bb: BasicBlock) {
         self.context.current_bb = Some(bb);
+        self.context.sealed = false;
     }
 
-    pub fn enter_scope(&self) -> Self {
-        IREnvironment {
-            context: IRContext {
-                program: self.context.program.clone(),
-                current_func: self.context.current_func,
-                current_bb: self.context.current_bb,
-            },
-            name_generator: self.name_generator.clone(),
-            while_stack: self.while_stack.clone(),
-            symbol_table: Rc::new(RefCell::new(NestedSymbolTable::new_child(self.symbol_table.clone()))),
+    pub fn enter_scope(&mut self) {
+        self.symbol_table.borrow_mut().enter_scope();
+        self.context.alloc_scopes.borrow_mut().enter_scope();
+    }
+
+    pub fn exit_scope(&mut self) {
+        self.symbol_table.borrow_mut().exit_scope();
+        self.context.alloc_scopes.borrow_mut().exit_scope();
+    }
+
+    // The name of a local variable in the IR, `@x`, or `@x_<depth>` when it shadows another `x`.
+    // Names left equal, e.g. of variables in sibling blocks, are made unique by the generator.
+    pub fn variable_name(&self, ident: &str) -> String {
+        match self.lookup_ident(ident) {
+            Some(_) => format!("@{}_{}", ident, self.symbol_table.borrow().depth() - self.func_depth),
+            None => format!("@{}", ident),
         }
     }
 
@@ -136,17 +250,47 @@ impl IREnvironment {
         self.symbol_table.borrow_mut().bind(ident, entry)
     }
 
-    pub fn generate_decl(&mut self, name: &str, params_ty: Vec<Type>, ret_ty: Type) -> Result<(), FrontendError> {
-        let function = self.context.program.borrow_mut().new_func(FunctionData::new_decl(name.to_string(), params_ty.clone(), ret_ty.clone()));
+    // The variadic arguments are passed after the parameters, see `ir::library`
+    pub fn generate_decl(&mut self, name: &str, signature: Signature) -> Result<(), FrontendError> {
+        let (params_ty, ret_ty) = lower_signature(&signature.params, &signature.ret);
+        let function = self.context.program.borrow_mut().new_func(FunctionData::new_decl(name.to_string(), params_ty, ret_ty));
         // Add to symbol table
-        self.bind(&*name[1..].to_string(), SymbolTableEntry::Func {
-            handle: function,
-            params: params_ty.iter().zip(0..).map(|(ty, i)| (format!("_arg{}", i), ty.clone())).collect(),
-            ret_type: ret_ty
-        })?;
+        self.bind(&name[1..], SymbolTableEntry::Func { handle: function, signature })?;
         Ok(())
     }
 
+    // Look up a function declared earlier, or add a new function without a body to the program.
+    // The body is filled in later by the definition; until then it is emitted as a `decl`.
+    pub fn declare_func(&mut self, ident: &str, params: &[FuncFParam], func_type: &FuncType) -> Result<Function, FrontendError> {
+        if let Some(SymbolTableEntry::Func { handle, .. }) = self.lookup_ident(ident) {
+            return Ok(handle);
+        }
+
+        let signature = Signature {
+            params: params.iter().map(|param| param.btype.ty()).collect(),
+            ret: func_type.ty(),
+            variadic: false,
+        };
+        let (param_types, ret_type) = lower_signature(&signature.params, &signature.ret);
+        let func_data = FunctionData::new(format!("@{}", ident), param_types, ret_type);
+        let func = self.context.program.borrow_mut().new_func(func_data);
+
+        // Register the function in the outermost symbol table, where the other units find it
+        self.symbol_table.borrow_mut().bind_root(ident, SymbolTableEntry::Func { handle: func, signature })?;
+        Ok(func)
+    }
+
+    // Annotate the next generated instruction with a source-level comment,
+    // which is only rendered when IR or assembly comments are enabled
+    pub fn comment(&self, span: Span, comment: impl FnOnce() -> Option<String>) {
+        let mut comments = self.context.comments.borrow_mut();
+        if comments.is_enabled() {
+            if let Some(comment) = comment() {
+                comments.set_pending(comment, span);
+            }
+        }
+    }
+
     pub fn is_global(&self) -> bool {
         self.context.current_func.is_none() && self.context.current_bb.is_none()
     }
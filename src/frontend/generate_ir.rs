@@ -1,10 +1,14 @@
-use koopa::ir::{BinaryOp, FunctionData, Type, Value};
+use koopa::ir::{BasicBlock, BinaryOp, Value};
 use koopa::ir::builder::{GlobalInstBuilder, LocalInstBuilder, ValueBuilder};
-use crate::backend::generate_asm::GenerateAsm;
-use crate::frontend::ast::{Block, BlockItem, CompElement, CompUnit, ConstInitVal, Decl, Expr, FuncDef, LVal, Stmt, VarDef};
+use crate::frontend::ast::{Block, BlockItem, CompElement, CompUnit, ConstInitVal, Decl, Expr, ExprKind, FuncDef, InitVal, LVal, Stmt, StmtKind};
+use crate::frontend::cleanup;
 use crate::frontend::environment::IREnvironment;
+use crate::frontend::loops::LoopContext;
 use crate::frontend::FrontendError;
-use crate::frontend::symbol::{SymbolTableEntry};
+use crate::frontend::lowering::lower_type;
+use crate::frontend::types::Ty;
+use crate::frontend::symbol::SymbolTableEntry;
+use crate::util::interner::Name;
 use crate::{global_value_builder, local_value_builder};
 
 pub trait IRGenerator {
@@ -16,19 +20,20 @@ impl IRGenerator for CompUnit {
     type Output = ();
 
     fn generate_ir(&self, env: &mut IREnvironment) -> Result<Self::Output, FrontendError> {
-        // Declaration for library functions
-        env.generate_decl("@getint", Vec::new(), Type::get_i32())?;
-        env.generate_decl("@getch", Vec::new(), Type::get_i32())?;
-        env.generate_decl("@getarray", vec![Type::get_pointer(Type::get_i32())], Type::get_i32())?;
-        env.generate_decl("@putint", vec![Type::get_i32()], Type::get_unit())?;
-        env.generate_decl("@putch", vec![Type::get_i32()], Type::get_unit())?;
-        env.generate_decl("@putarray", vec![Type::get_i32(), Type::get_pointer(Type::get_i32())], Type::get_unit())?;
-        env.generate_decl("@starttime", Vec::new(), Type::get_unit())?;
-        env.generate_decl("@stoptime", Vec::new(), Type::get_unit())?;
-
-        // Traverse all the compilation elements
+        // The globals and the functions are declared first, the bodies referring to those
+        // written after them
         for comp_elem in self.elements.iter() {
-            comp_elem.generate_ir(env)?;
+            match comp_elem {
+                CompElement::FuncDef(func_def) => {
+                    env.declare_func(&func_def.ident, &func_def.params, &func_def.func_type)?;
+                }
+                _ => comp_elem.generate_ir(env)?,
+            }
+        }
+        for comp_elem in self.elements.iter() {
+            if let CompElement::FuncDef(func_def) = comp_elem {
+                func_def.generate_ir(env)?;
+            }
         }
         Ok(())
     }
@@ -43,6 +48,10 @@ impl IRGenerator for CompElement {
                 // Global decl
                 decl.generate_ir(env)
             }
+            CompElement::FuncDecl(func_decl) => {
+                env.declare_func(&func_decl.ident, &func_decl.params, &func_decl.func_type)?;
+                Ok(())
+            }
             CompElement::FuncDef(func_def) => {
                 func_def.generate_ir(env)
             }
@@ -54,28 +63,11 @@ impl IRGenerator for FuncDef {
     type Output = ();
 
     fn generate_ir(&self, env: &mut IREnvironment) -> Result<Self::Output, FrontendError> {
-        // name -> @ + name
-        let ir_func_name = format!("@{}", self.ident);
-        let mut param_types = Vec::new();
-        for param in self.params.iter() {
-            param_types.push(param.btype.to());
-        }
-        let func_data = FunctionData::new(ir_func_name, param_types, self.func_type.to());
+        // Reuse the function created by an earlier prototype, if any
+        let func = env.declare_func(&self.ident, &self.params, &self.func_type)?;
         // Zip the `FuncData` with the parameters
-        let mut param_args = Vec::new();
-        for (param, arg) in self.params.iter().zip(func_data.params()) {
-            param_args.push((param.clone(), arg.clone()));
-        }
-
-        // Add the function to the program, and set the context's current function
-        let func = env.context.program.borrow_mut().new_func(func_data);
-
-        // Register the function in the symbol table
-        env.bind(&self.ident, SymbolTableEntry::Func {
-            handle: func,
-            ret_type: self.func_type.to(),
-            params: self.params.iter().map(|param| (param.ident.clone(), param.btype.to())).collect()
-        })?;
+        let args = env.context.program.borrow().func(func).params().to_vec();
+        let param_args: Vec<_> = self.params.iter().cloned().zip(args).collect();
 
         // Recursively generate IR for the block
 
@@ -86,11 +78,17 @@ impl IRGenerator for FuncDef {
 
         // Bind the arguments to symbol table
         for (param, arg) in param_args.iter() {
-            // Here we allocate a new value for the argument, TODO why
-            let var = local_value_builder!(new_env).alloc(param.btype.to());
+            if !self.block.assigns(&param.ident) {
+                new_env.bind(&param.ident, SymbolTableEntry::Param(*arg))?;
+                continue;
+            }
+            // An assigned parameter is a variable like the others
+            let var = local_value_builder!(new_env).alloc(lower_type(&param.btype.ty()));
+            let name = new_env.variable_name(&param.ident);
+            new_env.context.set_value_name(var, name);
             new_env.context.add_instruction(var);
             // Store to var
-            let store = local_value_builder!(new_env).store(arg.clone(), var);
+            let store = local_value_builder!(new_env).store(*arg, var);
             new_env.context.add_instruction(store);
             new_env.bind(&param.ident, SymbolTableEntry::Var(var))?;
         }
@@ -99,10 +97,12 @@ impl IRGenerator for FuncDef {
         self.block.generate_ir(&mut new_env)?;
 
         // Void return
-        if self.func_type.to() == Type::get_unit() {
+        if self.func_type.ty() == Ty::Void && !new_env.context.is_sealed() {
             let ret = local_value_builder!(new_env).ret(None);
             new_env.context.add_instruction(ret);
         }
+        new_env.exit_scope();
+        cleanup::terminate_blocks(env.context.program.borrow_mut().func_mut(func));
 
         Ok(())
     }
@@ -112,8 +112,11 @@ impl IRGenerator for Block {
     type Output = ();
 
     fn generate_ir(&self, env: &mut IREnvironment) -> Result<Self::Output, FrontendError> {
-        // Recursively generate IR for the statement
+        // Recursively generate IR for the statement, up to a terminator
         for block_item in self.items.iter() {
+            if env.context.is_sealed() {
+                break;
+            }
             block_item.generate_ir(env)?;
         }
 
@@ -146,7 +149,7 @@ impl IRGenerator for Decl {
                             let eval_result = expr.try_const_eval(env)?;
 
                             // Eval success, add the constant to the symbol table
-                            env.bind(&const_def.ident, SymbolTableEntry::Const(const_def.ident.clone(), eval_result))?;
+                            env.bind(&const_def.ident, SymbolTableEntry::Const(Name::intern(&const_def.ident), eval_result))?;
                         }
                     }
                 }
@@ -156,41 +159,39 @@ impl IRGenerator for Decl {
                 // TODO: Now assuming BType int
                 for var_def in var_decl.defs.iter() {
                     if env.is_global() {
-                        let (ident, initializer) = match var_def {
-                            VarDef::Ident(ident) => {
-                                (ident, global_value_builder!(env).zero_init(var_decl.btype.to()))
-                            }
-                            VarDef::Init(ident, init) => {
+                        let initializer = match &var_def.init_val {
+                            None => global_value_builder!(env).zero_init(lower_type(&var_decl.btype.ty())),
+                            Some(InitVal::Expr(init)) => {
                                 let init_val = init.try_const_eval(env)?;
-                                (ident, global_value_builder!(env).integer(init_val))
+                                global_value_builder!(env).integer(init_val)
                             }
                         };
 
                         // Global variable
                         let decl = env.context.program.borrow_mut().new_value().global_alloc(initializer);
                         // Format the name with @
-                        let name = format!("@{}", ident);
+                        let name = format!("@{}", var_def.ident);
                         env.context.program.borrow_mut().set_value_name(decl, Some(name));
-                        env.bind(ident, SymbolTableEntry::Var(decl))?;
+                        env.bind(&var_def.ident, SymbolTableEntry::Var(decl))?;
                     } else {
+                        env.comment(var_def.span, || Some(match &var_def.init_val {
+                            None => format!("int {};", var_def.ident),
+                            Some(InitVal::Expr(expr)) => format!("int {} = {};", var_def.ident, expr),
+                        }));
+
                         // Alloc for the variable
-                        // TODO: Any way to assign a name to the value in the IR?
-                        let var = local_value_builder!(env).alloc(Type::get_i32());
+                        let var = local_value_builder!(env).alloc(lower_type(&var_decl.btype.ty()));
+                        let name = env.variable_name(&var_def.ident);
+                        env.context.set_value_name(var, name);
                         env.context.add_instruction(var);
 
-                        match var_def {
-                            VarDef::Ident(ident) => {
-                                env.bind(ident, SymbolTableEntry::Var(var))?;
-                            }
-                            VarDef::Init(ident, expr) => {
-                                // Assign the value
-                                let val = expr.generate_ir(env)?;
-                                let store = local_value_builder!(env).store(val, var);
-                                env.context.add_instruction(store);
-
-                                env.bind(ident, SymbolTableEntry::Var(var))?;
-                            }
+                        if let Some(InitVal::Expr(expr)) = &var_def.init_val {
+                            // Assign the value
+                            let val = expr.generate_ir(env)?;
+                            let store = local_value_builder!(env).store(val, var);
+                            env.context.add_instruction(store);
                         }
+                        env.bind(&var_def.ident, SymbolTableEntry::Var(var))?;
                     }
                 }
                 Ok(())
@@ -203,15 +204,23 @@ impl IRGenerator for Stmt {
     type Output = ();
 
     fn generate_ir(&self, env: &mut IREnvironment) -> Result<Self::Output, FrontendError> {
-        match self {
-            Stmt::Return(expr) => {
-                println!("Return statement");
+        // A discarded result without side effects needs no code at all
+        if let StmtKind::Expr(expr) = &self.kind {
+            if !expr.has_side_effect() {
+                return Ok(());
+            }
+        }
+
+        env.comment(self.span, || self.source_text());
+
+        match &self.kind {
+            StmtKind::Return(expr) => {
                 let return_val = expr.as_ref().map(|expr| expr.generate_ir(env)).transpose()?;
                 let return_stmt = local_value_builder!(env).ret(return_val);
                 env.context.add_instruction(return_stmt);
                 Ok(())
             }
-            Stmt::Assign(lval, expr) => {
+            StmtKind::Assign(lval, expr) => {
                 match lval {
                     LVal::Ident(ident) => {
                         // Assign the value
@@ -223,6 +232,10 @@ impl IRGenerator for Stmt {
                                     env.context.add_instruction(store);
                                     Ok(())
                                 }
+                                // Only their elements are assigned
+                                SymbolTableEntry::Array { ty, .. } | SymbolTableEntry::Pointer { ty, .. } => {
+                                    Err(FrontendError::TypeMismatch { expected: Ty::Int, found: ty })
+                                }
                                 _ => Err(FrontendError::InvalidAssignmentToConst)
                             }
                         } else {
@@ -231,112 +244,122 @@ impl IRGenerator for Stmt {
                     }
                 }
             }
-            Stmt::Expr(expr) => {
-                // TODO: validate the correctness here
+            StmtKind::Expr(expr) => {
                 expr.generate_ir(env)?;
                 Ok(())
             }
-            Stmt::Empty => { Ok(()) }
-            Stmt::Block(block) => {
-                // Enter a new scope
-                let mut new_env = env.enter_scope();
-                let result = block.generate_ir(&mut new_env);
-
-                // IMPORTANT: Exit the scope, updating outer scope's context
-                env.context = new_env.context;
-
+            StmtKind::Empty => { Ok(()) }
+            StmtKind::Block(block) => {
+                env.enter_scope();
+                let result = block.generate_ir(env);
+                env.exit_scope();
                 result
             }
-            Stmt::If(cond, then_stmt) => {
+            StmtKind::If(cond, then_stmt) => {
+                // A constant condition leaves a single arm, generated in place
+                if let Ok(value) = cond.try_const_eval(env) {
+                    return if value != 0 { then_stmt.generate_ir(env) } else { Ok(()) };
+                }
                 let cond_val = cond.generate_ir(env)?;
 
                 let group = env.name_generator.borrow_mut().generate_group(&["%then", "%merge"]);
-                let then_bb = env.context.create_block(Some(group[0].clone()));
-                let merge_bb = env.context.create_block(Some(group[1].clone()));
+                let then_bb = env.context.create_block(Some(group[0]));
+                let merge_bb = env.context.create_block(Some(group[1]));
 
                 let branch = local_value_builder!(env).branch(cond_val, then_bb, merge_bb);
                 env.context.add_instruction(branch);
 
                 // Generate IR for then block
-                let mut then_env = env.switch_bb(then_bb);
-                then_stmt.generate_ir(&mut then_env)?;
-                let then_jump = local_value_builder!(then_env).jump(merge_bb);
-                then_env.context.add_instruction(then_jump);
+                then_stmt.generate_arm(env, then_bb, merge_bb)?;
 
                 // Enter the merge block
                 env.enter_bb(merge_bb);
 
                 Ok(())
             }
-            Stmt::IfElse(cond, then_stmt, else_stmt) => {
+            StmtKind::IfElse(cond, then_stmt, else_stmt) => {
+                if let Ok(value) = cond.try_const_eval(env) {
+                    return if value != 0 { then_stmt.generate_ir(env) } else { else_stmt.generate_ir(env) };
+                }
                 let cond_val = cond.generate_ir(env)?;
 
                 let group = env.name_generator.borrow_mut().generate_group(&["%then", "%else", "%merge"]);
-                let then_bb = env.context.create_block(Some(group[0].clone()));
-                let else_bb = env.context.create_block(Some(group[1].clone()));
-                let merge_bb = env.context.create_block(Some(group[2].clone()));
+                let then_bb = env.context.create_block(Some(group[0]));
+                let else_bb = env.context.create_block(Some(group[1]));
+                let merge_bb = env.context.create_block(Some(group[2]));
 
                 let branch = local_value_builder!(env).branch(cond_val, then_bb, else_bb);
                 env.context.add_instruction(branch);
 
-                // Generate IR for then block
-                let mut then_env = env.switch_bb(then_bb);
-                then_stmt.generate_ir(&mut then_env)?;
-                let then_jump = local_value_builder!(then_env).jump(merge_bb);
-                then_env.context.add_instruction(then_jump);
-
-                // Generate IR for else block
-                let mut else_env = env.switch_bb(else_bb);
-                else_stmt.generate_ir(&mut else_env)?;
-                let else_jump = local_value_builder!(else_env).jump(merge_bb);
-                else_env.context.add_instruction(else_jump);
+                // Generate IR for the then and else blocks
+                let then_terminated = then_stmt.generate_arm(env, then_bb, merge_bb)?;
+                let else_terminated = else_stmt.generate_arm(env, else_bb, merge_bb)?;
+                if then_terminated && else_terminated {
+                    // Nothing reaches the merge block, nor the code following the statement
+                    env.context.remove_block(merge_bb);
+                    env.context.seal();
+                    return Ok(());
+                }
 
                 // Enter the merge block
                 env.enter_bb(merge_bb);
 
                 Ok(())
             }
-            Stmt::While(cond, stmt) => {
+            StmtKind::While(cond, stmt) => {
+                // A loop with a constant condition never runs, or runs its body without testing
+                // the condition, the body being the head of the loop
+                let constant = cond.try_const_eval(env).ok();
+                if constant == Some(0) {
+                    return Ok(());
+                }
                 let group = env.name_generator.borrow_mut().generate_group(&["%entry", "%body", "%end"]);
-                let entry_bb = env.context.create_block(Some(group[0].clone()));
-                let body_bb = env.context.create_block(Some(group[1].clone()));
-                let end_bb = env.context.create_block(Some(group[2].clone()));
+                let entry_bb = constant.is_none().then(|| env.context.create_block(Some(group[0])));
+                let body_bb = env.context.create_block(Some(group[1]));
+                let end_bb = env.context.create_block(Some(group[2]));
+                let head_bb = entry_bb.unwrap_or(body_bb);
 
-                env.while_stack.push((entry_bb, end_bb));
+                env.loops.borrow_mut().push(LoopContext { label: None, head: head_bb, end: end_bb });
 
-                let entry_jump = local_value_builder!(env).jump(entry_bb);
+                let entry_jump = local_value_builder!(env).jump(head_bb);
                 env.context.add_instruction(entry_jump);
 
                 // Generate IR for the entry block
-                let mut entry_env = env.switch_bb(entry_bb);
-                let cond_val = cond.generate_ir(&mut entry_env)?;
-                let branch = local_value_builder!(entry_env).branch(cond_val, body_bb, end_bb);
-                entry_env.context.add_instruction(branch);
+                if let Some(entry_bb) = entry_bb {
+                    let mut entry_env = env.switch_bb(entry_bb);
+                    let cond_val = cond.generate_ir(&mut entry_env)?;
+                    let branch = local_value_builder!(entry_env).branch(cond_val, body_bb, end_bb);
+                    entry_env.context.add_instruction(branch);
+                }
 
                 // Generate IR for the body block
-                let mut body_env = entry_env.switch_bb(body_bb);
-                stmt.generate_ir(&mut body_env)?;
-                let body_jump = local_value_builder!(body_env).jump(entry_bb);
-                body_env.context.add_instruction(body_jump);
+                stmt.generate_arm(env, body_bb, head_bb)?;
+                env.loops.borrow_mut().pop();
+
+                // Without a `break`, a loop that does not test its condition is never left
+                if !env.context.is_jumped_to(end_bb) {
+                    env.context.remove_block(end_bb);
+                    env.context.seal();
+                    return Ok(());
+                }
 
-                // Enter the end block, set the last_while in the context
+                // Enter the end block
                 env.enter_bb(end_bb);
-                env.while_stack.pop();
 
                 Ok(())
             }
-            Stmt::Break => {
-                if let Some((_while_bb, end_bb)) = env.while_stack.last() {
-                    let jump = local_value_builder!(env).jump(*end_bb);
+            StmtKind::Break => {
+                if let Some(context) = env.loops.borrow().find(None) {
+                    let jump = local_value_builder!(env).jump(context.end);
                     env.context.add_instruction(jump);
                     Ok(())
                 } else {
                     Err(FrontendError::BreakOutsideOfLoop)
                 }
             }
-            Stmt::Continue => {
-                if let Some((while_bb, _end_bb)) = env.while_stack.last() {
-                    let jump = local_value_builder!(env).jump(*while_bb);
+            StmtKind::Continue => {
+                if let Some(context) = env.loops.borrow().find(None) {
+                    let jump = local_value_builder!(env).jump(context.head);
                     env.context.add_instruction(jump);
                     Ok(())
                 } else {
@@ -347,6 +370,21 @@ impl IRGenerator for Stmt {
     }
 }
 
+impl Stmt {
+    // Generates the statement into `bb`, continuing to `next` unless it ends with a
+    // terminator, e.g. an arm of an `if` ending with `return`. Returns whether it does.
+    fn generate_arm(&self, env: &IREnvironment, bb: BasicBlock, next: BasicBlock) -> Result<bool, FrontendError> {
+        let mut arm_env = env.switch_bb(bb);
+        self.generate_ir(&mut arm_env)?;
+        let terminated = arm_env.context.is_sealed();
+        if !terminated {
+            let jump = local_value_builder!(arm_env).jump(next);
+            arm_env.context.add_instruction(jump);
+        }
+        Ok(terminated)
+    }
+}
+
 macro_rules! generate_binary_expr {
     ($env:expr, $lhs:expr, $rhs:expr, $op:ident) => {{
         let lhs_val = $lhs.generate_ir($env)?;
@@ -361,62 +399,83 @@ impl IRGenerator for Expr {
     type Output = Value;
 
     fn generate_ir(&self, env: &mut IREnvironment) -> Result<Self::Output, FrontendError> {
-        match self {
-            Expr::Num(num) => Ok(local_value_builder!(env).integer(*num)),
-            Expr::LVal(lval) => {
+        match &self.kind {
+            ExprKind::Num(num) => Ok(env.context.integer(*num)),
+            // The address of its first word, as `putf` takes a `*i32`
+            ExprKind::Str(text) => {
+                let global = env.context.string_literal(text);
+                let zero = env.context.integer(0);
+                let address = local_value_builder!(env).get_elem_ptr(global, zero);
+                env.context.add_instruction(address);
+                Ok(address)
+            }
+            ExprKind::LVal(lval) => {
                 match env.lookup_lval(lval) {
                     None => Err(FrontendError::DefinitionNotFoundForIdentifier(lval.ident().into())),
                     Some(entry) => {
                         match entry {
-                            SymbolTableEntry::Const(_, num) => Ok(local_value_builder!(env).integer(num)),
+                            SymbolTableEntry::Const(_, num) => Ok(env.context.integer(num)),
+                            SymbolTableEntry::Param(arg) => Ok(arg),
                             SymbolTableEntry::Var(var) => {
                                 let load = local_value_builder!(env).load(var);
                                 env.context.add_instruction(load);
                                 Ok(load)
                             }
+                            // An array decays to the address of its first element
+                            SymbolTableEntry::Array { value, .. } => {
+                                let zero = env.context.integer(0);
+                                let first = local_value_builder!(env).get_elem_ptr(value, zero);
+                                env.context.add_instruction(first);
+                                Ok(first)
+                            }
+                            SymbolTableEntry::Pointer { value, .. } => {
+                                let load = local_value_builder!(env).load(value);
+                                env.context.add_instruction(load);
+                                Ok(load)
+                            }
                             SymbolTableEntry::Func { .. } => Err(FrontendError::InvalidFunctionCall),
                         }
                     }
                 }
             }
-            Expr::Pos(expr) => expr.generate_ir(env),
-            Expr::Neg(expr) => {
-                let zero = local_value_builder!(env).integer(0);
+            ExprKind::Pos(expr) => expr.generate_ir(env),
+            ExprKind::Neg(expr) => {
+                let zero = env.context.integer(0);
                 let val = expr.generate_ir(env)?;
                 let op = local_value_builder!(env).binary(BinaryOp::Sub, zero, val);
                 env.context.add_instruction(op);
                 Ok(op)
             }
-            Expr::Not(expr) => {
-                let zero = local_value_builder!(env).integer(0);
+            ExprKind::Not(expr) => {
+                let zero = env.context.integer(0);
                 let val = expr.generate_ir(env)?;
                 let op = local_value_builder!(env).binary(BinaryOp::Eq, val, zero);
                 env.context.add_instruction(op);
                 Ok(op)
             }
             // Binary operations
-            Expr::Add(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Add),
-            Expr::Sub(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Sub),
-            Expr::Mul(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Mul),
-            Expr::Div(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Div),
-            Expr::Mod(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Mod),
+            ExprKind::Add(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Add),
+            ExprKind::Sub(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Sub),
+            ExprKind::Mul(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Mul),
+            ExprKind::Div(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Div),
+            ExprKind::Mod(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Mod),
             // Logical operations
-            Expr::Lt(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Lt),
-            Expr::Gt(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Gt),
-            Expr::Le(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Le),
-            Expr::Ge(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Ge),
-            Expr::Eq(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Eq),
-            Expr::Ne(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, NotEq),
-            Expr::Land(lhs, rhs) => {
+            ExprKind::Lt(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Lt),
+            ExprKind::Gt(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Gt),
+            ExprKind::Le(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Le),
+            ExprKind::Ge(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Ge),
+            ExprKind::Eq(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, Eq),
+            ExprKind::Ne(lhs, rhs) => generate_binary_expr!(env, lhs, rhs, NotEq),
+            ExprKind::Land(lhs, rhs) => {
                 if self.has_side_effect() {
-                    let result = local_value_builder!(env).alloc(Type::get_i32());
+                    let result = local_value_builder!(env).alloc(lower_type(&Ty::Int));
                     env.context.add_instruction(result);
-                    let zero_result_init = local_value_builder!(env).integer(0);
+                    let zero_result_init = env.context.integer(0);
                     let result_init = local_value_builder!(env).store(zero_result_init, result);
                     env.context.add_instruction(result_init);
 
                     let lhs_val = lhs.generate_ir(env)?;
-                    let zero = local_value_builder!(env).integer(0);
+                    let zero = env.context.integer(0);
                     let lhs_neq_z = local_value_builder!(env).binary(BinaryOp::NotEq, lhs_val, zero);
                     env.context.add_instruction(lhs_neq_z);
 
@@ -428,7 +487,7 @@ impl IRGenerator for Expr {
 
                     let mut branch_env = env.switch_bb(bb_branch);
                     let rhs_val = rhs.generate_ir(&mut branch_env)?;
-                    let zero_branch = local_value_builder!(branch_env).integer(0);
+                    let zero_branch = branch_env.context.integer(0);
                     let rhs_neq_z = local_value_builder!(branch_env).binary(BinaryOp::NotEq, rhs_val, zero_branch);
                     branch_env.context.add_instruction(rhs_neq_z);
                     let result_assign = local_value_builder!(branch_env).store(rhs_neq_z, result);
@@ -443,7 +502,7 @@ impl IRGenerator for Expr {
                 } else {
                     let lhs_val = lhs.generate_ir(env)?;
                     let rhs_val = rhs.generate_ir(env)?;
-                    let zero = local_value_builder!(env).integer(0);
+                    let zero = env.context.integer(0);
                     let lhs_neq_z = local_value_builder!(env).binary(BinaryOp::NotEq, lhs_val, zero);
                     let rhs_neq_z = local_value_builder!(env).binary(BinaryOp::NotEq, rhs_val, zero);
                     let op = local_value_builder!(env).binary(BinaryOp::And, lhs_neq_z, rhs_neq_z);
@@ -453,16 +512,16 @@ impl IRGenerator for Expr {
                     Ok(op)
                 }
             }
-            Expr::Lor(lhs, rhs) => {
+            ExprKind::Lor(lhs, rhs) => {
                 if self.has_side_effect() {
-                    let result = local_value_builder!(env).alloc(Type::get_i32());
+                    let result = local_value_builder!(env).alloc(lower_type(&Ty::Int));
                     env.context.add_instruction(result);
-                    let one_result_init = local_value_builder!(env).integer(1);
+                    let one_result_init = env.context.integer(1);
                     let result_init = local_value_builder!(env).store(one_result_init, result);
                     env.context.add_instruction(result_init);
 
                     let lhs_val = lhs.generate_ir(env)?;
-                    let zero = local_value_builder!(env).integer(0);
+                    let zero = env.context.integer(0);
                     let lhs_eq_z = local_value_builder!(env).binary(BinaryOp::Eq, lhs_val, zero);
                     env.context.add_instruction(lhs_eq_z);
 
@@ -474,7 +533,7 @@ impl IRGenerator for Expr {
 
                     let mut branch_env = env.switch_bb(bb_branch);
                     let rhs_val = rhs.generate_ir(&mut branch_env)?;
-                    let zero_branch = local_value_builder!(branch_env).integer(0);
+                    let zero_branch = branch_env.context.integer(0);
                     let rhs_neq_z = local_value_builder!(branch_env).binary(BinaryOp::NotEq, rhs_val, zero_branch);
                     branch_env.context.add_instruction(rhs_neq_z);
                     let result_assign = local_value_builder!(branch_env).store(rhs_neq_z, result);
@@ -489,7 +548,7 @@ impl IRGenerator for Expr {
                 } else {
                     let lhs_val = lhs.generate_ir(env)?;
                     let rhs_val = rhs.generate_ir(env)?;
-                    let zero = local_value_builder!(env).integer(0);
+                    let zero = env.context.integer(0);
                     let op = local_value_builder!(env).binary(BinaryOp::Or, lhs_val, rhs_val);
                     let snez = local_value_builder!(env).binary(BinaryOp::NotEq, op, zero);
                     env.context.add_instruction(op);
@@ -497,7 +556,7 @@ impl IRGenerator for Expr {
                     Ok(snez)
                 }
             }
-            Expr::Call(ident, args) => {
+            ExprKind::Call(ident, args) => {
                 // Lookup the function binding
                 match env.lookup_ident(ident) {
                     None => Err(FrontendError::DefinitionNotFoundForIdentifier(ident.clone())),
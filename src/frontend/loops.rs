@@ -0,0 +1,34 @@
+use koopa::ir::BasicBlock;
+use crate::util::interner::Name;
+
+// A loop being generated, `head` the target of its `continue` and `end` of its `break`
+#[derive(Debug, Clone, Copy)]
+pub struct LoopContext {
+    pub label: Option<Name>,
+    pub head: BasicBlock,
+    pub end: BasicBlock,
+}
+
+// The loops enclosing the statement being generated, innermost last
+#[derive(Default)]
+pub struct LoopStack {
+    loops: Vec<LoopContext>,
+}
+
+impl LoopStack {
+    pub fn push(&mut self, context: LoopContext) {
+        self.loops.push(context);
+    }
+
+    pub fn pop(&mut self) {
+        self.loops.pop().expect("a loop is open");
+    }
+
+    // The innermost loop, or the innermost one named `label`
+    pub fn find(&self, label: Option<Name>) -> Option<LoopContext> {
+        match label {
+            None => self.loops.last().copied(),
+            Some(label) => self.loops.iter().rev().find(|context| context.label == Some(label)).copied(),
+        }
+    }
+}
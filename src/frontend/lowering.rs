@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use koopa::ir::Type;
+use crate::frontend::types::Ty;
+
+thread_local! {
+    // Constructed Koopa types, so nested pointer types are only built once
+    static TYPE_CACHE: RefCell<HashMap<Ty, Type>> = RefCell::new(HashMap::new());
+}
+
+// The single point where frontend types are mapped to Koopa types
+pub fn lower_type(ty: &Ty) -> Type {
+    if let Some(lowered) = TYPE_CACHE.with(|cache| cache.borrow().get(ty).cloned()) {
+        return lowered;
+    }
+
+    let lowered = match ty {
+        Ty::Int => Type::get_i32(),
+        Ty::Void => Type::get_unit(),
+        Ty::Pointer(base) => Type::get_pointer(lower_type(base)),
+        Ty::Array(elem, len) => Type::get_array(lower_type(elem), *len),
+    };
+    TYPE_CACHE.with(|cache| cache.borrow_mut().insert(ty.clone(), lowered.clone()));
+    lowered
+}
+
+// Parameter and return types of a function signature, lowered together
+pub fn lower_signature(params: &[Ty], ret: &Ty) -> (Vec<Type>, Type) {
+    (params.iter().map(lower_type).collect(), lower_type(ret))
+}
+
+// An instruction computing the address of an element from that of its container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexStep {
+    // Steps over the elements a pointer points among, for the first index of a pointer
+    GetPtr,
+    // Steps into an array
+    GetElemPtr,
+}
+
+// The instructions indexing a value of type `ty` `indices` times, and the type of the element
+// reached, `None` when `ty` has fewer dimensions. With fewer indices than dimensions the
+// element is an array, which `decay`s where it is used as a value.
+pub fn index_steps(ty: &Ty, indices: usize) -> Option<(Vec<IndexStep>, Ty)> {
+    let mut steps = Vec::with_capacity(indices);
+    let mut ty = ty;
+    for _ in 0..indices {
+        let (step, elem) = match ty {
+            Ty::Pointer(elem) if steps.is_empty() => (IndexStep::GetPtr, elem),
+            Ty::Array(elem, _) => (IndexStep::GetElemPtr, elem),
+            _ => return None,
+        };
+        steps.push(step);
+        ty = elem;
+    }
+    Some((steps, ty.clone()))
+}
+
+// The type of a value of type `ty` where it is used as a value, e.g. passed to a function:
+// an array decays to a pointer to its first element, a `getelemptr` at index 0
+pub fn decay(ty: &Ty) -> Ty {
+    match ty {
+        Ty::Array(elem, _) => Ty::Pointer(elem.clone()),
+        _ => ty.clone(),
+    }
+}
@@ -1,13 +1,29 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 use koopa::ir::Program;
+use crate::common::alloc_scopes::AllocScopes;
+use crate::common::diagnostic::{Diagnostic, Span};
 use crate::frontend::ast::CompUnit;
+use crate::frontend::comments::IRComments;
 use crate::frontend::environment::IREnvironment;
 use crate::frontend::generate_ir::IRGenerator;
+use crate::frontend::types::{Signature, Ty};
+use crate::frontend::symbol::library_functions;
 
 pub mod ast;
+pub mod ast_dump;
+#[doc(hidden)]
 pub mod symbol;
+pub mod loops;
+pub mod comments;
+pub mod parser;
+pub mod lowering;
+pub mod types;
+pub mod semant;
+pub mod symbol_index;
+pub mod xref;
 mod generate_ir;
+mod cleanup;
 mod environment;
 
 #[derive(Debug)]
@@ -22,10 +38,85 @@ pub enum FrontendError {
     ContinueOutsideOfLoop,
     InvalidFunctionCall,
     GlobalAlloc,
+    TypeMismatch { expected: Ty, found: Ty },
+    ConflictingFunctionSignature { ident: String, previous: Box<Signature>, current: Box<Signature> },
+    ReturnValueFromVoidFunction { ident: String, found: Ty },
+    MissingReturnValue(String),
+    NotAFunction(String),
+    FunctionUsedAsValue(String),
+    ArgumentCountMismatch { ident: String, expected: usize, found: usize },
+    // To a variadic function
+    TooFewArguments { ident: String, expected: usize, found: usize },
+    MisplacedStringLiteral,
+    UnsupportedConversion(String),
+    FormatArgumentMismatch { conversions: usize, found: usize },
+    VoidValueUsed(String),
+    InvalidMain(String),
 }
 
-pub fn generate_ir(comp_unit: &CompUnit) -> Result<Rc<RefCell<Program>>, FrontendError> {
-    let mut program = Rc::from(RefCell::from(Program::new()));
-    comp_unit.generate_ir(&mut IREnvironment::new(&program))?;
-    Ok(program)
+impl FrontendError {
+    pub fn at(self, span: Span) -> Diagnostic {
+        Diagnostic::error(self.to_string(), Some(span))
+    }
+}
+
+impl std::fmt::Display for FrontendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FrontendError::MultipleDefinitionsForIdentifier(ident) => write!(f, "redefinition of `{}`", ident),
+            FrontendError::DefinitionNotFoundForIdentifier(ident) => write!(f, "use of undeclared identifier `{}`", ident),
+            FrontendError::BindingNonConstExpr(ident) => write!(f, "`{}` is not a compile-time constant", ident),
+            FrontendError::ConstEvalDivZero => write!(f, "division by zero in constant expression"),
+            FrontendError::InvalidAssignmentToConst => write!(f, "cannot assign to a constant"),
+            FrontendError::BreakOutsideOfLoop => write!(f, "`break` outside of a loop"),
+            FrontendError::ContinueOutsideOfLoop => write!(f, "`continue` outside of a loop"),
+            FrontendError::InvalidFunctionCall => write!(f, "invalid function call"),
+            FrontendError::GlobalAlloc => write!(f, "invalid global allocation"),
+            FrontendError::TypeMismatch { expected, found } => write!(f, "expected a value of type `{}`, found `{}`", expected, found),
+            FrontendError::ConflictingFunctionSignature { ident, previous, current } => {
+                write!(f, "conflicting types for `{}`: `{}` was previously declared as `{}`", ident, current, previous)
+            }
+            FrontendError::ReturnValueFromVoidFunction { ident, found } => {
+                write!(f, "void function `{}` should not return a value of type `{}`", ident, found)
+            }
+            FrontendError::MissingReturnValue(ident) => write!(f, "non-void function `{}` should return a value", ident),
+            FrontendError::NotAFunction(ident) => write!(f, "called object `{}` is not a function", ident),
+            FrontendError::FunctionUsedAsValue(ident) => write!(f, "function `{}` cannot be used as a value", ident),
+            FrontendError::ArgumentCountMismatch { ident, expected, found } => {
+                write!(f, "function `{}` expects {} argument(s), found {}", ident, expected, found)
+            }
+            FrontendError::TooFewArguments { ident, expected, found } => {
+                write!(f, "function `{}` expects at least {} argument(s), found {}", ident, expected, found)
+            }
+            FrontendError::MisplacedStringLiteral => write!(f, "a string literal can only be the format of `putf`"),
+            FrontendError::UnsupportedConversion(conversion) => {
+                write!(f, "unsupported conversion `{}` in the format, only `%d`, `%c` and `%%` are", conversion)
+            }
+            FrontendError::FormatArgumentMismatch { conversions, found } => {
+                write!(f, "the format has {} conversion(s), but {} value(s) are printed", conversions, found)
+            }
+            FrontendError::VoidValueUsed(ident) => write!(f, "the result of `{}` is used, but it returns `void`", ident),
+            FrontendError::InvalidMain(reason) => write!(f, "invalid entry point: {}", reason),
+        }
+    }
+}
+
+// Translation units are generated into the same program. Functions are shared by all of them,
+// the other global names are scoped to their unit; `semant::check_linkage` ensures they do not clash.
+// The program comes with the scopes of its locals, for `backend::generate_asm_with`.
+pub fn generate_ir(comp_units: &[CompUnit], comments: &Rc<RefCell<IRComments>>) -> Result<(Rc<RefCell<Program>>, AllocScopes), FrontendError> {
+    let program = Rc::from(RefCell::from(Program::new()));
+    let mut env = IREnvironment::new(&program, comments);
+    // Declaration for library functions
+    for (name, signature) in library_functions() {
+        env.generate_decl(&format!("@{}", name), signature)?;
+    }
+    for (unit, comp_unit) in comp_units.iter().enumerate() {
+        comments.borrow_mut().enter_unit(unit);
+        env.enter_scope();
+        comp_unit.generate_ir(&mut env)?;
+        env.exit_scope();
+    }
+    let alloc_scopes = env.context.alloc_scopes.take();
+    Ok((program, alloc_scopes))
 }
\ No newline at end of file
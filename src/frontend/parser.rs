@@ -0,0 +1,71 @@
+use lalrpop_util::lexer::Token;
+use lalrpop_util::{lalrpop_mod, ParseError};
+use crate::common::diagnostic::{Diagnostic, Span};
+use crate::frontend::ast::CompUnit;
+
+// Generated code, not subject to lints
+lalrpop_mod!(#[allow(clippy::all, unused)] sysy, "/sysy.rs");
+
+// Parse a whole compilation unit. Statement-level syntax errors are recovered from,
+// so all of them are reported together instead of only the first one.
+pub fn parse(source: &str) -> Result<CompUnit, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
+    let result = sysy::CompUnitParser::new().parse(&mut errors, source);
+
+    let mut diagnostics: Vec<Diagnostic> = errors.into_iter()
+        .map(|recovery| syntax_error(recovery.error))
+        .collect();
+
+    match result {
+        Ok(comp_unit) if diagnostics.is_empty() => Ok(comp_unit),
+        Ok(_) => Err(diagnostics),
+        Err(error) => {
+            diagnostics.push(syntax_error(error));
+            Err(diagnostics)
+        }
+    }
+}
+
+fn syntax_error(error: ParseError<usize, Token, &str>) -> Diagnostic {
+    match error {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error("invalid token", Some(Span::new(location, location)))
+        }
+        ParseError::UnrecognizedEOF { location, expected } => {
+            Diagnostic::error(format!("unexpected end of file{}", format_expected(&expected)), Some(Span::new(location, location)))
+        }
+        ParseError::UnrecognizedToken { token: (start, token, end), expected } => {
+            Diagnostic::error(format!("unexpected token `{}`{}", token, format_expected(&expected)), Some(Span::new(start, end)))
+        }
+        ParseError::ExtraToken { token: (start, token, end) } => {
+            Diagnostic::error(format!("extra token `{}`", token), Some(Span::new(start, end)))
+        }
+        ParseError::User { error } => Diagnostic::error(error, None),
+    }
+}
+
+fn format_expected(expected: &[String]) -> String {
+    if expected.is_empty() {
+        String::new()
+    } else {
+        format!(", expected one of {}", expected.join(", "))
+    }
+}
+
+// The text of a string literal between its quotes, whose escapes the lexer has checked
+pub(crate) fn unescape(literal: &str) -> String {
+    let mut text = String::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        text.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                escaped => escaped.expect("the lexer checks the escapes"),
+            },
+            c => c,
+        });
+    }
+    text
+}
@@ -0,0 +1,693 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use crate::common::diagnostic::{Diagnostic, Span};
+use crate::common::session::{Lint, Session};
+use crate::frontend::ast::{Block, BlockItem, CompElement, CompUnit, ConstInitVal, Decl, Expr, ExprKind, FuncDef, FuncFParam, FuncType, InitVal, LVal, Stmt, StmtKind};
+use crate::frontend::FrontendError;
+use crate::frontend::types::{Signature, Ty};
+use crate::frontend::symbol::library_functions;
+use crate::frontend::symbol_index::{Role, ScopeId, ScopeKind, SymbolEntry, SymbolIndex, SymbolKind, SymbolType};
+use crate::ir::library::{parse_format, FormatPiece};
+use crate::util::interner::Name;
+
+// Semantic analysis, run between parsing and IR generation.
+// Everything rejected here is a user error, so `generate_ir` may assume a well-typed program.
+// Errors and lints are reported to `session` as they are found. An error stops the check of
+// its declaration, statement or condition only, so that independent errors are all reported;
+// names whose declaration has an error are still bound to keep their uses from being reported.
+// The entry point and the names shared between translation units are checked afterwards,
+// by `check_linkage` over all the units.
+pub fn check(comp_unit: &CompUnit, session: &mut Session) -> CheckedUnit {
+    let mut checker = SemanticChecker::new(session);
+    checker.check_comp_unit(comp_unit);
+    let interface = checker.interface();
+    CheckedUnit { symbol_index: checker.index, interface }
+}
+
+pub struct CheckedUnit {
+    // The definitions and references found, complete only if there is no error
+    pub symbol_index: SymbolIndex,
+    pub interface: UnitInterface,
+}
+
+// The names a translation unit shares with the others: its functions and global variables.
+// Constants are folded into their uses and stay private to the unit.
+#[derive(Debug, Clone, Default)]
+pub struct UnitInterface {
+    pub functions: Vec<FunctionItem>,
+    pub variables: Vec<(String, Span)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionItem {
+    pub name: String,
+    pub signature: Signature,
+    // The definition if any, else the first declaration
+    pub span: Span,
+    pub defined: bool,
+}
+
+// A translation unit as seen by `check_linkage`: its file name, its source and its interface
+pub type LinkedUnit<'a> = (&'a str, &'a str, &'a UnitInterface);
+
+// Across all the units, a function or global variable is defined at most once, every
+// declaration of a function agrees with its definition, and `main` is defined as `int main()`.
+// Each error comes with the index of the unit it is located in.
+pub fn check_linkage(units: &[LinkedUnit]) -> Vec<(usize, Diagnostic)> {
+    // e.g. `a.c:3:5`, for notes pointing into another file
+    let location = |unit: usize, span: Span| {
+        let (file, source, _) = units[unit];
+        let (line, column) = span.line_col(source);
+        format!("{}:{}:{}", file, line, column)
+    };
+    let mut errors = Vec::new();
+
+    // Name to the unit and location of its definition, and its signature for functions
+    let mut definitions: HashMap<&str, (usize, Span, Option<&Signature>)> = HashMap::new();
+    for (i, (_, _, interface)) in units.iter().enumerate() {
+        let functions = interface.functions.iter().filter(|func| func.defined).map(|func| (&func.name, func.span, Some(&func.signature)));
+        let variables = interface.variables.iter().map(|(name, span)| (name, *span, None));
+        for (name, span, signature) in functions.chain(variables) {
+            match definitions.get(name.as_str()) {
+                Some(&(unit, previous, _)) => errors.push((i, FrontendError::MultipleDefinitionsForIdentifier(name.clone()).at(span)
+                    .with_note(format!("previous definition is at {}", location(unit, previous)), None))),
+                None => {
+                    definitions.insert(name, (i, span, signature));
+                }
+            }
+        }
+    }
+
+    for (i, (_, _, interface)) in units.iter().enumerate() {
+        for func in interface.functions.iter().filter(|func| !func.defined) {
+            if let Some(&(unit, span, Some(signature))) = definitions.get(func.name.as_str()) {
+                if *signature != func.signature {
+                    errors.push((i, FrontendError::ConflictingFunctionSignature {
+                        ident: func.name.clone(),
+                        previous: Box::new(signature.clone()),
+                        current: Box::new(func.signature.clone()),
+                    }.at(func.span).with_note(format!("the definition is at {}", location(unit, span)), None)));
+                }
+            }
+        }
+    }
+
+    if let Err(error) = check_main(units, &definitions) {
+        errors.push(error);
+    }
+    errors
+}
+
+// The runtime calls `int main()`, anything else would not link or would misbehave
+fn check_main(units: &[LinkedUnit], definitions: &HashMap<&str, (usize, Span, Option<&Signature>)>) -> Result<(), (usize, Diagnostic)> {
+    let entry = Signature { params: vec![], ret: Ty::Int, variadic: false };
+    let declaration = units.iter().enumerate().find_map(|(i, (_, _, interface))| {
+        interface.functions.iter().find(|func| func.name == "main").map(|func| (i, func))
+    });
+    match (definitions.get("main"), declaration) {
+        (Some(&(unit, span, Some(signature))), _) if *signature != entry => {
+            Err((unit, FrontendError::InvalidMain(format!("`main` must have type `{}`, found `{}`", entry, signature)).at(span)))
+        }
+        (Some((_, _, Some(_))), _) => Ok(()),
+        (Some(&(unit, _, None)), _) => Err((unit, Diagnostic::error(FrontendError::InvalidMain("`main` must be a function".into()).to_string(), None))),
+        (None, Some((unit, func))) if func.signature != entry => {
+            Err((unit, FrontendError::InvalidMain(format!("`main` must have type `{}`, found `{}`", entry, func.signature)).at(func.span)))
+        }
+        (None, Some((unit, func))) => Err((unit, FrontendError::InvalidMain("`main` is declared but never defined".into()).at(func.span))),
+        (None, None) => Err((0, Diagnostic::error(FrontendError::InvalidMain("no `main` function is defined".into()).to_string(), None))),
+    }
+}
+
+#[derive(Clone)]
+enum Symbol {
+    // `None` if the initializer has an error
+    Const(Option<i32>),
+    Var(Ty),
+    // `span` is the definition if any, else the first declaration; `None` for the runtime library
+    Func { signature: Signature, span: Option<Span>, defined: bool },
+}
+
+impl Symbol {
+    fn ty(&self) -> SymbolType {
+        match self {
+            Symbol::Const(_) => SymbolType::Value(Ty::Int),
+            Symbol::Var(ty) => SymbolType::Value(ty.clone()),
+            Symbol::Func { signature, .. } => SymbolType::Func(signature.clone()),
+        }
+    }
+}
+
+struct Binding {
+    symbol: Symbol,
+    kind: SymbolKind,
+    span: Span,
+    // The identifier references resolve to in the symbol index, `None` for the runtime library
+    site: Option<Span>,
+    // Whether the value has been read, set by `lookup`
+    used: Cell<bool>,
+}
+
+struct SemanticChecker<'s> {
+    // Innermost scope last, mirroring the scopes of `SymbolTable`
+    scopes: Vec<HashMap<Name, Binding>>,
+    // The scopes of `scopes` in the index
+    scope_ids: Vec<ScopeId>,
+    loop_depth: usize,
+    // Name, signature and header of the function being checked
+    current_func: Option<(String, Signature, Span)>,
+    session: &'s mut Session,
+    index: SymbolIndex,
+}
+
+impl<'s> SemanticChecker<'s> {
+    fn new(session: &'s mut Session) -> Self {
+        let mut index = SymbolIndex::default();
+        let global = index.open_scope(ScopeKind::Global, None);
+        SemanticChecker {
+            scopes: vec![HashMap::new()],
+            scope_ids: vec![global],
+            loop_depth: 0,
+            current_func: None,
+            session,
+            index,
+        }
+    }
+
+    fn enter_scope(&mut self, kind: ScopeKind) {
+        self.scopes.push(HashMap::new());
+        let id = self.index.open_scope(kind, Some(self.scope()));
+        self.scope_ids.push(id);
+    }
+
+    fn scope(&self) -> ScopeId {
+        *self.scope_ids.last().unwrap()
+    }
+
+    // Local variables and constants never read are reported, in the order of declaration.
+    // Parameters are not, as the signature may be given by the callers.
+    fn exit_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        self.scope_ids.pop();
+        let mut unused: Vec<(&Name, &Binding)> = scope.iter()
+            .filter(|(_, binding)| !binding.used.get() && binding.kind != SymbolKind::Param)
+            .filter(|(_, binding)| matches!(binding.symbol, Symbol::Var(_) | Symbol::Const(_)))
+            .collect();
+        unused.sort_by_key(|(_, binding)| binding.span.start);
+        for (ident, binding) in unused {
+            let kind = if let Symbol::Const(_) = binding.symbol { "constant" } else { "variable" };
+            self.session.lint(Lint::UnusedVariable, Diagnostic::warning(format!("unused {} `{}`", kind, ident), Some(binding.span)));
+        }
+    }
+
+    // Variables, constants and parameters are recorded in the index, functions by `declare_func`
+    fn bind(&mut self, ident: &str, symbol: Symbol, kind: SymbolKind, span: Span) -> Result<(), Diagnostic> {
+        if self.scopes.last().unwrap().contains_key(&Name::intern(ident)) {
+            return Err(FrontendError::MultipleDefinitionsForIdentifier(ident.into()).at(span));
+        }
+        if !self.is_global() {
+            self.check_shadowing(ident, span);
+        }
+        // Globals may be used by other translation units, they are never reported as unused
+        let site = match symbol {
+            Symbol::Func { span: None, .. } => None,
+            Symbol::Func { .. } => Some(span),
+            _ => {
+                self.index.push(SymbolEntry {
+                    name: ident.into(),
+                    kind,
+                    role: Role::Definition,
+                    ty: symbol.ty(),
+                    scope: self.scope(),
+                    span,
+                    target: None,
+                });
+                Some(span)
+            }
+        };
+        let binding = Binding { symbol, kind, span, site, used: Cell::new(self.is_global()) };
+        self.scopes.last_mut().unwrap().insert(Name::intern(ident), binding);
+        Ok(())
+    }
+
+    fn check_shadowing(&mut self, ident: &str, span: Span) {
+        let name = Name::intern(ident);
+        let outer = self.scopes.iter().rev().skip(1).find_map(|scope| scope.get(&name));
+        let note = match outer {
+            // Library functions are not written by the user, hiding them is deliberate
+            None | Some(Binding { symbol: Symbol::Func { span: None, .. }, .. }) => return,
+            Some(Binding { span, symbol: Symbol::Func { .. }, .. }) => ("the function is declared here", *span),
+            Some(Binding { span, .. }) => ("the shadowed declaration is here", *span),
+        };
+        self.session.lint(Lint::Shadow, Diagnostic::warning(format!("declaration of `{}` shadows a previous declaration", ident), Some(span))
+            .with_note(note.0, Some(note.1)));
+    }
+
+    // Resolves `ident` as a read, marking it used
+    fn lookup(&self, ident: &str, span: Span) -> Result<Symbol, Diagnostic> {
+        let binding = self.resolve(ident, span)?;
+        binding.used.set(true);
+        Ok(binding.symbol.clone())
+    }
+
+    // Records a use of `ident` in the index. `start` is where the identifier begins.
+    fn reference(&mut self, ident: &str, start: usize) -> Result<(), Diagnostic> {
+        let span = Span::new(start, start + ident.len());
+        let binding = self.resolve(ident, span)?;
+        let entry = SymbolEntry {
+            name: ident.into(),
+            kind: binding.kind,
+            role: Role::Reference,
+            ty: binding.symbol.ty(),
+            scope: self.scope(),
+            span,
+            target: binding.site,
+        };
+        self.index.push(entry);
+        Ok(())
+    }
+
+    fn resolve(&self, ident: &str, span: Span) -> Result<&Binding, Diagnostic> {
+        let name = Name::intern(ident);
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.get(&name))
+            .ok_or_else(|| FrontendError::DefinitionNotFoundForIdentifier(ident.into()).at(span))
+    }
+
+    fn is_global(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    // Reports the error of a check, the analysis goes on with the next one
+    fn recover(&mut self, result: Result<(), Diagnostic>) {
+        if let Err(error) = result {
+            self.session.report(error);
+        }
+    }
+
+    fn check_comp_unit(&mut self, comp_unit: &CompUnit) {
+        for (name, signature) in library_functions() {
+            let result = self.bind(name, Symbol::Func { signature, span: None, defined: false }, SymbolKind::Func, Span::default());
+            self.recover(result);
+        }
+
+        // The globals and the functions are all declared before the bodies are checked, so that
+        // a function may call those defined after it, e.g. mutually recursive ones, and use the
+        // globals declared after it, as in `generate_ir`. Globals see those declared before them.
+        for comp_elem in comp_unit.elements.iter() {
+            let result = match comp_elem {
+                CompElement::Decl(decl) => {
+                    self.check_decl(decl);
+                    Ok(())
+                }
+                CompElement::FuncDecl(func_decl) => {
+                    self.declare_func(&func_decl.ident, &func_decl.params, &func_decl.func_type, func_decl.span, func_decl.ident_span, false)
+                }
+                CompElement::FuncDef(func_def) => {
+                    self.declare_func(&func_def.ident, &func_def.params, &func_def.func_type, func_def.span, func_def.ident_span, true)
+                }
+            };
+            self.recover(result);
+        }
+        for comp_elem in comp_unit.elements.iter() {
+            if let CompElement::FuncDef(func_def) = comp_elem {
+                self.check_func_def(func_def);
+            }
+        }
+    }
+
+    // Functions written in the unit and its global variables, in the order of the source
+    fn interface(&self) -> UnitInterface {
+        let mut interface = UnitInterface::default();
+        for (name, binding) in self.scopes[0].iter() {
+            match &binding.symbol {
+                Symbol::Func { signature, span: Some(span), defined } => interface.functions.push(FunctionItem {
+                    name: name.to_string(),
+                    signature: signature.clone(),
+                    span: *span,
+                    defined: *defined,
+                }),
+                Symbol::Var(_) => interface.variables.push((name.to_string(), binding.span)),
+                _ => {}
+            }
+        }
+        interface.functions.sort_by_key(|func| func.span.start);
+        interface.variables.sort_by_key(|(_, span)| span.start);
+        interface
+    }
+
+    // Every declaration and the definition of a function must agree on its signature,
+    // and there can be at most one definition
+    // `span` is the header, reported in diagnostics, `ident_span` the name, recorded in the index
+    fn declare_func(&mut self, ident: &str, params: &[FuncFParam], func_type: &FuncType, span: Span, ident_span: Span, is_definition: bool) -> Result<(), Diagnostic> {
+        let signature = signature(params, func_type);
+
+        let name = Name::intern(ident);
+        let previous = self.scopes[0].get(&name).map(|binding| binding.symbol.clone());
+        match previous {
+            None => {
+                self.bind(ident, Symbol::Func { signature, span: Some(span), defined: is_definition }, SymbolKind::Func, span)?;
+                self.scopes[0].get_mut(&name).unwrap().site = Some(ident_span);
+            }
+            Some(Symbol::Func { signature: previous_signature, span: previous_span, defined }) => {
+                let previous_note = match previous_span {
+                    Some(_) if defined => "previous definition is here",
+                    Some(_) => "previous declaration is here",
+                    None => "declared by the SysY runtime library",
+                };
+                if previous_signature != signature {
+                    return Err(FrontendError::ConflictingFunctionSignature {
+                        ident: ident.into(),
+                        previous: Box::new(previous_signature),
+                        current: Box::new(signature),
+                    }.at(span).with_note(previous_note, previous_span));
+                }
+                // Library functions are already defined by the runtime
+                if is_definition && (defined || previous_span.is_none()) {
+                    return Err(FrontendError::MultipleDefinitionsForIdentifier(ident.into())
+                        .at(span).with_note(previous_note, previous_span));
+                }
+                if is_definition {
+                    let binding = self.scopes[0].get_mut(&name).unwrap();
+                    binding.symbol = Symbol::Func { signature, span: Some(span), defined: true };
+                    binding.site = Some(ident_span);
+                }
+            }
+            Some(_) => return Err(FrontendError::MultipleDefinitionsForIdentifier(ident.into()).at(span)),
+        }
+        let role = if is_definition { Role::Definition } else { Role::Declaration };
+        let ty = self.scopes[0][&name].symbol.ty();
+        self.index.push(SymbolEntry {
+            name: ident.into(),
+            kind: SymbolKind::Func,
+            role,
+            ty,
+            scope: self.scope(),
+            span: ident_span,
+            target: None,
+        });
+        Ok(())
+    }
+
+    // The function is already declared by `check_comp_unit`. The body is checked even if the
+    // declaration conflicts with another.
+    fn check_func_def(&mut self, func_def: &FuncDef) {
+        // Parameters share the scope of the function body, as in `generate_ir`
+        self.enter_scope(ScopeKind::Function(func_def.ident.clone()));
+        for param in func_def.params.iter() {
+            let result = self.bind(&param.ident, Symbol::Var(param.btype.ty()), SymbolKind::Param, param.span);
+            self.recover(result);
+        }
+        self.current_func = Some((func_def.ident.clone(), signature(&func_def.params, &func_def.func_type), func_def.span));
+        self.check_block_items(&func_def.block);
+        self.exit_scope();
+
+        // `main` implicitly returns 0 when control reaches its end
+        if func_def.func_type.ty() != Ty::Void && func_def.ident != "main" && func_def.block.can_complete_normally() {
+            self.session.lint(Lint::MissingReturn, Diagnostic::warning(
+                format!("control may reach the end of non-void function `{}` without returning a value", func_def.ident),
+                Some(func_def.span),
+            ));
+        }
+    }
+
+    fn check_block_items(&mut self, block: &Block) {
+        let mut reported_unreachable = false;
+        for (i, block_item) in block.items.iter().enumerate() {
+            match block_item {
+                BlockItem::Decl(decl) => self.check_decl(decl),
+                BlockItem::Stmt(stmt) => {
+                    let result = self.check_stmt(stmt);
+                    self.recover(result);
+                }
+            }
+            // Only the first unreachable item of a block is reported
+            let completes = match block_item {
+                BlockItem::Decl(_) => true,
+                BlockItem::Stmt(stmt) => stmt.can_complete_normally(),
+            };
+            if let (false, false, Some(next)) = (completes, reported_unreachable, block.items.get(i + 1)) {
+                reported_unreachable = true;
+                self.session.lint(Lint::DeadCode, Diagnostic::warning("unreachable code", next.span()));
+            }
+        }
+    }
+
+    // Every definition is bound, even with an erroneous initializer
+    fn check_decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::ConstDecl(const_decl) => {
+                for const_def in const_decl.defs.iter() {
+                    match &const_def.init_val {
+                        ConstInitVal::Expr(expr) => {
+                            let value = self.check_const_expr(expr).unwrap_or_else(|error| {
+                                self.session.report(error);
+                                None
+                            });
+                            let result = self.bind(&const_def.ident, Symbol::Const(value), SymbolKind::Const, const_def.span);
+                            self.recover(result);
+                        }
+                    }
+                }
+            }
+            Decl::VarDecl(var_decl) => {
+                for var_def in var_decl.defs.iter() {
+                    if let Some(InitVal::Expr(init)) = &var_def.init_val {
+                        // Global initializers are evaluated at compile time
+                        let result = if self.is_global() {
+                            self.check_const_expr(init).map(|_| ())
+                        } else {
+                            self.check_value_expr(init)
+                        };
+                        self.recover(result);
+                    }
+                    let result = self.bind(&var_def.ident, Symbol::Var(var_decl.btype.ty()), SymbolKind::Var, var_def.span);
+                    self.recover(result);
+                }
+            }
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        match &stmt.kind {
+            StmtKind::Return(expr) => {
+                // Checked against the declared return type, there are no implicit conversions
+                let (func_name, signature, span) = self.current_func.clone().unwrap();
+                let note = format!("`{}` is declared as `{}` here", func_name, signature);
+                match (expr, &signature.ret) {
+                    (Some(expr), Ty::Void) => {
+                        let found = self.check_expr(expr)?;
+                        return Err(FrontendError::ReturnValueFromVoidFunction { ident: func_name, found }
+                            .at(expr.span).with_note(note, Some(span)));
+                    }
+                    (Some(expr), _) => self.check_value_expr(expr)?,
+                    (None, Ty::Void) => {}
+                    (None, _) => return Err(FrontendError::MissingReturnValue(func_name).at(stmt.span).with_note(note, Some(span))),
+                }
+            }
+            StmtKind::Assign(lval, expr) => {
+                let result = self.check_assign_target(lval, stmt.span);
+                self.recover(result);
+                self.check_value_expr(expr)?;
+            }
+            StmtKind::Expr(expr) => {
+                // The result of an expression statement is discarded, so it may be void
+                self.check_expr(expr)?;
+                if !expr.has_side_effect() {
+                    self.session.lint(Lint::DeadCode, Diagnostic::warning(format!("result of expression `{}` is unused", expr), Some(expr.span)));
+                }
+            }
+            StmtKind::Empty => {}
+            StmtKind::Block(block) => {
+                self.enter_scope(ScopeKind::Block);
+                self.check_block_items(block);
+                self.exit_scope();
+            }
+            StmtKind::If(cond, then_stmt) => {
+                let result = self.check_value_expr(cond);
+                self.recover(result);
+                self.check_stmt(then_stmt)?;
+            }
+            StmtKind::IfElse(cond, then_stmt, else_stmt) => {
+                let result = self.check_value_expr(cond);
+                self.recover(result);
+                let result = self.check_stmt(then_stmt);
+                self.recover(result);
+                self.check_stmt(else_stmt)?;
+            }
+            StmtKind::While(cond, body) => {
+                let result = self.check_value_expr(cond);
+                self.recover(result);
+                self.loop_depth += 1;
+                let result = self.check_stmt(body);
+                self.loop_depth -= 1;
+                result?;
+            }
+            StmtKind::Break => {
+                if self.loop_depth == 0 {
+                    return Err(FrontendError::BreakOutsideOfLoop.at(stmt.span));
+                }
+            }
+            StmtKind::Continue => {
+                if self.loop_depth == 0 {
+                    return Err(FrontendError::ContinueOutsideOfLoop.at(stmt.span));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Only variables are assignable
+    // Assigning is not a read, the target is not marked used
+    fn check_assign_target(&mut self, lval: &LVal, span: Span) -> Result<(), Diagnostic> {
+        self.reference(lval.ident(), span.start)?;
+        match self.resolve(lval.ident(), span)?.symbol {
+            Symbol::Var(_) => Ok(()),
+            Symbol::Const(_) => Err(FrontendError::InvalidAssignmentToConst.at(span)),
+            Symbol::Func { .. } => Err(FrontendError::FunctionUsedAsValue(lval.ident().into()).at(span)),
+        }
+    }
+
+    // An expression whose value is used, which therefore must be an `int`
+    fn check_value_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match (self.check_expr(expr)?, &expr.kind) {
+            (Ty::Int, _) => Ok(()),
+            // Only calls can produce `void`, point at the callee's declared return type
+            (Ty::Void, ExprKind::Call(ident, _)) => {
+                let diagnostic = FrontendError::VoidValueUsed(ident.clone()).at(expr.span);
+                Err(match self.lookup(ident, expr.span)? {
+                    Symbol::Func { span: Some(span), .. } => diagnostic.with_note(format!("`{}` is declared `void` here", ident), Some(span)),
+                    _ => diagnostic.with_note(format!("`{}` is declared `void` by the SysY runtime library", ident), None),
+                })
+            }
+            (found, _) => Err(FrontendError::TypeMismatch { expected: Ty::Int, found }.at(expr.span)),
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Result<Ty, Diagnostic> {
+        match &expr.kind {
+            ExprKind::Num(_) => Ok(Ty::Int),
+            // The format of `putf` is checked with the call
+            ExprKind::Str(_) => Err(FrontendError::MisplacedStringLiteral.at(expr.span)),
+            ExprKind::LVal(lval) => match self.lookup(lval.ident(), expr.span)? {
+                Symbol::Const(_) | Symbol::Var(_) => {
+                    self.reference(lval.ident(), expr.span.start)?;
+                    Ok(Ty::Int)
+                }
+                Symbol::Func { .. } => Err(FrontendError::FunctionUsedAsValue(lval.ident().into()).at(expr.span)),
+            },
+            ExprKind::Pos(sub) | ExprKind::Neg(sub) | ExprKind::Not(sub) => {
+                self.check_value_expr(sub)?;
+                Ok(Ty::Int)
+            }
+            ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) | ExprKind::Mul(lhs, rhs) |
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) | ExprKind::Lt(lhs, rhs) |
+            ExprKind::Gt(lhs, rhs) | ExprKind::Le(lhs, rhs) | ExprKind::Ge(lhs, rhs) |
+            ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) | ExprKind::Land(lhs, rhs) |
+            ExprKind::Lor(lhs, rhs) => {
+                self.check_value_expr(lhs)?;
+                self.check_value_expr(rhs)?;
+                Ok(Ty::Int)
+            }
+            ExprKind::Call(ident, args) => {
+                self.reference(ident, expr.span.start)?;
+                let (signature, decl_span) = match self.lookup(ident, expr.span)? {
+                    Symbol::Func { signature, span, .. } => (signature, span),
+                    _ => return Err(FrontendError::NotAFunction(ident.clone()).at(expr.span)),
+                };
+                let (expected, found) = (signature.params.len(), args.len());
+                let error = match signature.variadic {
+                    false if found != expected => Some(FrontendError::ArgumentCountMismatch { ident: ident.clone(), expected, found }),
+                    true if found < expected => Some(FrontendError::TooFewArguments { ident: ident.clone(), expected, found }),
+                    _ => None,
+                };
+                if let Some(error) = error {
+                    let note = match decl_span {
+                        Some(_) => format!("`{}` is declared here", ident),
+                        None => format!("`{}` is declared by the SysY runtime library as `{}`", ident, signature),
+                    };
+                    return Err(error.at(expr.span).with_note(note, decl_span));
+                }
+                // The only pointers are string literals, which only the format of `putf` takes
+                for (i, arg) in args.iter().enumerate() {
+                    match (signature.params.get(i), &arg.kind) {
+                        (Some(Ty::Pointer(_)), ExprKind::Str(_)) if signature.variadic => {}
+                        (Some(param @ Ty::Pointer(_)), _) => {
+                            let found = self.check_expr(arg)?;
+                            return Err(FrontendError::TypeMismatch { expected: param.clone(), found }.at(arg.span));
+                        }
+                        _ => self.check_value_expr(arg)?,
+                    }
+                }
+                if let Some(Expr { kind: ExprKind::Str(format), span }) = args.first().filter(|_| signature.variadic) {
+                    let pieces = parse_format(format.as_bytes())
+                        .map_err(|conversion| FrontendError::UnsupportedConversion(conversion).at(*span))?;
+                    let conversions = pieces.iter().filter(|piece| !matches!(piece, FormatPiece::Byte(_))).count();
+                    if conversions != found - expected {
+                        return Err(FrontendError::FormatArgumentMismatch { conversions, found: found - expected }.at(expr.span));
+                    }
+                }
+                Ok(signature.ret)
+            }
+        }
+    }
+
+    // Initializers of constants and globals must be computable at compile time.
+    // The value is `None` if it depends on a constant whose initializer has an error.
+    fn check_const_expr(&mut self, expr: &Expr) -> Result<Option<i32>, Diagnostic> {
+        self.check_value_expr(expr)?;
+        self.check_const_operands(expr)?;
+
+        let unknown = Cell::new(false);
+        let lookup = |lval: &LVal| match self.lookup(lval.ident(), expr.span) {
+            Ok(Symbol::Const(Some(value))) => Some(value),
+            Ok(Symbol::Const(None)) => {
+                unknown.set(true);
+                None
+            }
+            _ => None,
+        };
+        let mut overflows = Vec::new();
+        let value = match expr.const_eval(&lookup, &mut |sub: &Expr, wrapped| overflows.push((sub.to_string(), sub.span, wrapped))) {
+            Ok(value) => value,
+            // Already reported with the constant
+            Err(_) if unknown.get() => return Ok(None),
+            Err(error) => return Err(error.at(expr.span)),
+        };
+        for (text, span, wrapped) in overflows {
+            self.session.lint(Lint::ConstOverflow, Diagnostic::warning(
+                format!("integer overflow in constant expression `{}`, the result wraps around to {}", text, wrapped),
+                Some(span),
+            ));
+        }
+        Ok(Some(value))
+    }
+
+    fn check_const_operands(&self, expr: &Expr) -> Result<(), Diagnostic> {
+        match &expr.kind {
+            ExprKind::Num(_) => Ok(()),
+            ExprKind::LVal(lval) => match self.lookup(lval.ident(), expr.span)? {
+                Symbol::Const(_) => Ok(()),
+                _ => Err(FrontendError::BindingNonConstExpr(lval.ident().into()).at(expr.span)),
+            },
+            ExprKind::Pos(sub) | ExprKind::Neg(sub) | ExprKind::Not(sub) => self.check_const_operands(sub),
+            ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) | ExprKind::Mul(lhs, rhs) |
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) | ExprKind::Lt(lhs, rhs) |
+            ExprKind::Gt(lhs, rhs) | ExprKind::Le(lhs, rhs) | ExprKind::Ge(lhs, rhs) |
+            ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) | ExprKind::Land(lhs, rhs) |
+            ExprKind::Lor(lhs, rhs) => {
+                self.check_const_operands(lhs)?;
+                self.check_const_operands(rhs)
+            }
+            ExprKind::Call(ident, _) => Err(FrontendError::BindingNonConstExpr(ident.clone()).at(expr.span)),
+            ExprKind::Str(_) => Err(FrontendError::MisplacedStringLiteral.at(expr.span)),
+        }
+    }
+}
+
+fn signature(params: &[FuncFParam], func_type: &FuncType) -> Signature {
+    Signature {
+        params: params.iter().map(|param| param.btype.ty()).collect(),
+        ret: func_type.ty(),
+        variadic: false,
+    }
+}
@@ -1,56 +1,114 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::{Rc};
-use koopa::ir::{Function, Type, Value};
+use koopa::ir::{Function, Value};
 use crate::frontend::FrontendError;
+use crate::frontend::types::{Signature, Ty};
+use crate::ir::library::VARIADIC_FUNCTIONS;
+use crate::util::interner::Name;
 
 #[derive(Clone)]
 pub enum SymbolTableEntry {
-    Const(String, i32),
+    Const(Name, i32),
     Var(Value),
-    Func { handle: Function, ret_type: Type, params: Vec<(String, Type)> },
+    // A parameter never assigned, read from its argument directly
+    Param(Value),
+    // A local or global array of type `ty`, `value` being its `alloc`
+    Array { value: Value, ty: Ty },
+    // An array parameter, `value` being the `alloc` holding its pointer of type `ty`
+    Pointer { value: Value, ty: Ty },
+    Func { handle: Function, signature: Signature },
 }
 
 
-type SymbolTable = Rc<RefCell<NestedSymbolTable>>;
+// The bindings of all the open scopes, innermost last. Every identifier maps to its bindings
+// from the outermost to the innermost, so a lookup is a single hash lookup, and a scope
+// records the identifiers it binds to drop them when it is exited.
+pub struct SymbolTable {
+    bindings: HashMap<Name, Vec<(usize, SymbolTableEntry)>>,
+    scopes: Vec<Vec<Name>>,
+}
 
-pub struct NestedSymbolTable {
-    entries: std::collections::HashMap<String, SymbolTableEntry>,
-    parent: Option<Rc<RefCell<NestedSymbolTable>>>,
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl NestedSymbolTable {
+impl SymbolTable {
+    // With the outermost scope open
     pub fn new() -> Self {
-        NestedSymbolTable {
-            entries: std::collections::HashMap::new(),
-            parent: None,
+        SymbolTable {
+            bindings: HashMap::new(),
+            scopes: vec![Vec::new()],
         }
     }
 
-    pub fn new_child(parent: Rc<RefCell<NestedSymbolTable>>) -> Self {
-        NestedSymbolTable {
-            entries: std::collections::HashMap::new(),
-            parent: Some(parent)
-        }
+    // How many scopes enclose the innermost one, 0 for the outermost
+    pub fn depth(&self) -> usize {
+        self.scopes.len() - 1
     }
 
-    pub fn lookup(&self, ident: &str) -> Option<SymbolTableEntry> {
-        match self.entries.get(ident) {
-            Some(entry) => Some(entry.clone()),
-            None => {
-                match &self.parent {
-                    Some(parent) => parent.borrow().lookup(ident),
-                    None => None
-                }
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    pub fn exit_scope(&mut self) {
+        let scope = self.scopes.pop().expect("a scope is open");
+        assert!(!self.scopes.is_empty(), "the outermost scope is never exited");
+        for ident in scope {
+            let bindings = self.bindings.get_mut(&ident).unwrap();
+            bindings.pop();
+            if bindings.is_empty() {
+                self.bindings.remove(&ident);
             }
         }
     }
 
+    pub fn lookup(&self, ident: &str) -> Option<SymbolTableEntry> {
+        self.bindings.get(&Name::intern(ident)).and_then(|bindings| bindings.last()).map(|(_, entry)| entry.clone())
+    }
+
     pub fn bind(&mut self, ident: &str, entry: SymbolTableEntry) -> Result<(), FrontendError> {
-        if self.entries.contains_key(ident) {
+        let depth = self.depth();
+        let name = Name::intern(ident);
+        let bindings = self.bindings.entry(name).or_default();
+        if bindings.last().is_some_and(|&(bound, _)| bound == depth) {
             return Err(FrontendError::MultipleDefinitionsForIdentifier(ident.into()));
         }
-        self.entries.insert(ident.into(), entry);
+        bindings.push((depth, entry));
+        self.scopes[depth].push(name);
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Binds in the outermost scope, shared by all the translation units, under the bindings
+    // of the inner scopes
+    pub fn bind_root(&mut self, ident: &str, entry: SymbolTableEntry) -> Result<(), FrontendError> {
+        let name = Name::intern(ident);
+        let bindings = self.bindings.entry(name).or_default();
+        if bindings.first().is_some_and(|&(bound, _)| bound == 0) {
+            return Err(FrontendError::MultipleDefinitionsForIdentifier(ident.into()));
+        }
+        bindings.insert(0, (0, entry));
+        self.scopes[0].push(name);
+        Ok(())
+    }
+}
+
+// Signatures of the SysY runtime library, implicitly declared in every program
+pub fn library_functions() -> Vec<(&'static str, Signature)> {
+    // Strings are only literals, which are packed into words, see `ir::library`
+    let int_ptr = Ty::Pointer(Box::new(Ty::Int));
+    let functions = vec![
+        ("getint", vec![], Ty::Int),
+        ("getch", vec![], Ty::Int),
+        ("getarray", vec![int_ptr.clone()], Ty::Int),
+        ("putint", vec![Ty::Int], Ty::Void),
+        ("putch", vec![Ty::Int], Ty::Void),
+        ("putarray", vec![Ty::Int, int_ptr.clone()], Ty::Void),
+        ("putf", vec![int_ptr], Ty::Void),
+        ("starttime", vec![], Ty::Void),
+        ("stoptime", vec![], Ty::Void),
+    ];
+    functions.into_iter()
+        .map(|(name, params, ret)| (name, Signature { params, ret, variadic: VARIADIC_FUNCTIONS.contains(&name) }))
+        .collect()
+}
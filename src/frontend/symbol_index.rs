@@ -0,0 +1,191 @@
+use std::fmt::Write;
+use crate::common::diagnostic::Span;
+use crate::frontend::types::{Signature, Ty};
+
+// Every definition and reference of a name in the source, collected by semantic analysis
+// for editors to navigate without running a language server.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Var,
+    Const,
+    Func,
+    Param,
+}
+
+impl std::fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymbolKind::Var => write!(f, "var"),
+            SymbolKind::Const => write!(f, "const"),
+            SymbolKind::Func => write!(f, "func"),
+            SymbolKind::Param => write!(f, "param"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Definition,
+    // A function prototype
+    Declaration,
+    Reference,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Role::Definition => write!(f, "definition"),
+            Role::Declaration => write!(f, "declaration"),
+            Role::Reference => write!(f, "reference"),
+        }
+    }
+}
+
+// The type of the value a name stands for, or the signature of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolType {
+    Value(Ty),
+    Func(Signature),
+}
+
+impl std::fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymbolType::Value(ty) => write!(f, "{}", ty),
+            SymbolType::Func(signature) => write!(f, "{}", signature),
+        }
+    }
+}
+
+// The position of a scope in `SymbolIndex::scopes`, the global scope being 0
+pub type ScopeId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeKind {
+    Global,
+    // The parameters and the body of the function
+    Function(String),
+    Block,
+}
+
+impl std::fmt::Display for ScopeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScopeKind::Global => write!(f, "global"),
+            ScopeKind::Function(_) => write!(f, "function"),
+            ScopeKind::Block => write!(f, "block"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    // `None` for the global scope
+    pub parent: Option<ScopeId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub role: Role,
+    // Of the name, the same for its definition and its references
+    pub ty: SymbolType,
+    // The scope binding the name, or the one the reference is in
+    pub scope: ScopeId,
+    // The identifier itself
+    pub span: Span,
+    // Where a reference resolves to: the definition, else the first declaration.
+    // `None` for the runtime library, and for definitions and declarations themselves.
+    pub target: Option<Span>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    pub entries: Vec<SymbolEntry>,
+    // In the order they are opened, a scope after its parent
+    pub scopes: Vec<Scope>,
+}
+
+impl SymbolIndex {
+    pub fn push(&mut self, entry: SymbolEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn open_scope(&mut self, kind: ScopeKind, parent: Option<ScopeId>) -> ScopeId {
+        self.scopes.push(Scope { kind, parent });
+        self.scopes.len() - 1
+    }
+
+    // The identifier at byte `offset` of the source
+    pub fn at(&self, offset: usize) -> Option<&SymbolEntry> {
+        self.entries.iter().find(|entry| entry.span.start <= offset && offset < entry.span.end)
+    }
+
+    // The definition, else the first declaration, of the name at `offset`. `None` for the
+    // runtime library.
+    pub fn definition_of(&self, offset: usize) -> Option<&SymbolEntry> {
+        let entry = self.at(offset)?;
+        if entry.role != Role::Reference {
+            return Some(entry);
+        }
+        let target = entry.target?;
+        self.declarations().find(|declaration| declaration.span == target)
+    }
+
+    // The references resolving to the definition or declaration at `site`
+    pub fn references_to(&self, site: Span) -> impl Iterator<Item = &SymbolEntry> {
+        self.entries.iter().filter(move |entry| entry.role == Role::Reference && entry.target == Some(site))
+    }
+
+    // The definitions and declarations, in the order they are checked
+    pub fn declarations(&self) -> impl Iterator<Item = &SymbolEntry> {
+        self.entries.iter().filter(|entry| entry.role != Role::Reference)
+    }
+
+    pub fn declarations_in(&self, scope: ScopeId) -> impl Iterator<Item = &SymbolEntry> {
+        self.declarations().filter(move |entry| entry.scope == scope)
+    }
+
+    // `scope` and those enclosing it, innermost first
+    pub fn enclosing_scopes(&self, scope: ScopeId) -> impl Iterator<Item = ScopeId> + '_ {
+        std::iter::successors(Some(scope), |&scope| self.scopes[scope].parent)
+    }
+
+    // Entries in source order. Identifiers only contain `[_a-zA-Z0-9]`, so the strings need no escaping.
+    pub fn to_json(&self, source: &str) -> String {
+        let mut entries: Vec<&SymbolEntry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| entry.span.start);
+
+        let span_json = |span: Span| {
+            let (line, column) = span.line_col(source);
+            format!("{{ \"start\": {}, \"end\": {}, \"line\": {}, \"column\": {} }}", span.start, span.end, line, column)
+        };
+        let mut json = String::from("{\n  \"symbols\": [");
+        for (i, entry) in entries.iter().enumerate() {
+            json.push_str(if i == 0 { "\n" } else { ",\n" });
+            write!(json, "    {{ \"name\": \"{}\", \"kind\": \"{}\", \"role\": \"{}\", \"type\": \"{}\", \"scope\": {}, \"span\": {}",
+                   entry.name, entry.kind, entry.role, entry.ty, entry.scope, span_json(entry.span)).unwrap();
+            if entry.role == Role::Reference {
+                let target = entry.target.map_or("null".to_string(), span_json);
+                write!(json, ", \"target\": {}", target).unwrap();
+            }
+            json.push_str(" }");
+        }
+        json.push_str(if entries.is_empty() { "],\n" } else { "\n  ],\n" });
+        json.push_str("  \"scopes\": [");
+        for (id, scope) in self.scopes.iter().enumerate() {
+            json.push_str(if id == 0 { "\n" } else { ",\n" });
+            let parent = scope.parent.map_or("null".to_string(), |parent| parent.to_string());
+            write!(json, "    {{ \"id\": {}, \"kind\": \"{}\", \"parent\": {}", id, scope.kind, parent).unwrap();
+            if let ScopeKind::Function(name) = &scope.kind {
+                write!(json, ", \"function\": \"{}\"", name).unwrap();
+            }
+            json.push_str(" }");
+        }
+        json.push_str(if self.scopes.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        json
+    }
+}
@@ -0,0 +1,55 @@
+// Types as the source sees them, checked by `semant` and bound in the symbol tables.
+// They are mapped to Koopa types at a single point, `lowering::lower_type`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int,
+    Void,
+    Pointer(Box<Ty>),
+    // An array of the given length, `int a[2][3]` being an array of 2 arrays of 3 `int`s
+    Array(Box<Ty>, usize),
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Void => write!(f, "void"),
+            Ty::Pointer(base) => write!(f, "{}*", base),
+            // The dimensions are written outermost first, as declared
+            Ty::Array(..) => {
+                let mut ty = self;
+                let mut dims = String::new();
+                while let Ty::Array(elem, len) = ty {
+                    dims.push_str(&format!("[{}]", len));
+                    ty = elem;
+                }
+                write!(f, "{}{}", ty, dims)
+            }
+        }
+    }
+}
+
+// Parameter and return types of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+    // Taking `int`s after the parameters, only `putf` of the runtime library
+    pub variadic: bool,
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}(", self.ret)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        if self.variadic {
+            write!(f, ", ...")?;
+        }
+        write!(f, ")")
+    }
+}
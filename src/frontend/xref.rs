@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::common::diagnostic::Span;
+use crate::frontend::ast_dump::json_string;
+use crate::frontend::symbol_index::{Role, SymbolEntry, SymbolIndex, SymbolKind};
+
+// The cross-references of a whole program, for a language server to wrap: the symbol index
+// of every file, with the prototypes of a function resolved to its definition in another file.
+
+// The path, the source and the symbol index of a unit
+pub type XrefUnit<'a> = (&'a str, &'a str, &'a SymbolIndex);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    // The position of the file in the units
+    pub unit: usize,
+    pub span: Span,
+}
+
+pub struct CrossReference<'a> {
+    units: &'a [XrefUnit<'a>],
+    // Of the functions, by name
+    definitions: HashMap<&'a str, Location>,
+    // Of the definitions and the declarations standing for them
+    references: HashMap<Location, Vec<Location>>,
+}
+
+impl<'a> CrossReference<'a> {
+    pub fn new(units: &'a [XrefUnit<'a>]) -> Self {
+        let mut definitions = HashMap::new();
+        for (unit, (_, _, index)) in units.iter().enumerate() {
+            for entry in index.declarations().filter(|entry| entry.kind == SymbolKind::Func && entry.role == Role::Definition) {
+                definitions.insert(entry.name.as_str(), Location { unit, span: entry.span });
+            }
+        }
+        let mut xref = CrossReference { units, definitions, references: HashMap::new() };
+
+        let mut references: HashMap<Location, Vec<Location>> = HashMap::new();
+        for (unit, (_, _, index)) in units.iter().enumerate() {
+            for entry in index.entries.iter().filter(|entry| entry.role == Role::Reference) {
+                if let Some(site) = xref.definition(unit, entry) {
+                    references.entry(site).or_default().push(Location { unit, span: entry.span });
+                }
+            }
+        }
+        xref.references = references;
+        xref
+    }
+
+    // Where the name of `entry` in `unit` is defined: a function defined in another file
+    // rather than its prototype, which is kept when there is no definition. `None` for the
+    // runtime library.
+    pub fn definition(&self, unit: usize, entry: &SymbolEntry) -> Option<Location> {
+        let site = match entry.role {
+            Role::Definition => return Some(Location { unit, span: entry.span }),
+            Role::Declaration => entry.span,
+            Role::Reference => entry.target?,
+        };
+        let defined = (entry.kind == SymbolKind::Func).then(|| self.definitions.get(entry.name.as_str())).flatten();
+        Some(defined.copied().unwrap_or(Location { unit, span: site }))
+    }
+
+    // The references, in any file, resolving to the definition or declaration at `site`
+    pub fn references_to(&self, site: Location) -> &[Location] {
+        self.references.get(&site).map_or(&[], |references| references.as_slice())
+    }
+
+    // Every identifier of every file in source order, with its type, a reference or declaration
+    // with its definition, and a definition or declaration with its references.
+    pub fn to_json(&self) -> String {
+        let location_json = |location: Location| {
+            let (line, column) = location.span.line_col(self.units[location.unit].1);
+            format!("{{ \"file\": {}, \"start\": {}, \"end\": {}, \"line\": {}, \"column\": {} }}",
+                    location.unit, location.span.start, location.span.end, line, column)
+        };
+        let mut json = String::from("{\n  \"files\": [");
+        for (unit, (path, _, index)) in self.units.iter().enumerate() {
+            json.push_str(if unit == 0 { "\n" } else { ",\n" });
+            write!(json, "    {{\n      \"path\": {},\n      \"symbols\": [", json_string(path)).unwrap();
+            let mut entries: Vec<&SymbolEntry> = index.entries.iter().collect();
+            entries.sort_by_key(|entry| entry.span.start);
+            for (i, entry) in entries.iter().enumerate() {
+                json.push_str(if i == 0 { "\n" } else { ",\n" });
+                let location = Location { unit, span: entry.span };
+                write!(json, "        {{ \"name\": \"{}\", \"kind\": \"{}\", \"role\": \"{}\", \"type\": \"{}\", \"span\": {}",
+                       entry.name, entry.kind, entry.role, entry.ty, location_json(location)).unwrap();
+                if entry.role != Role::Definition {
+                    let definition = self.definition(unit, entry).map_or("null".to_string(), location_json);
+                    write!(json, ", \"definition\": {}", definition).unwrap();
+                }
+                // A prototype lists those of the definition it stands for
+                if let Some(site) = self.definition(unit, entry).filter(|_| entry.role != Role::Reference) {
+                    let references: Vec<String> = self.references_to(site).iter().map(|&reference| location_json(reference)).collect();
+                    write!(json, ", \"references\": [{}]", references.join(", ")).unwrap();
+                }
+                json.push_str(" }");
+            }
+            json.push_str(if entries.is_empty() { "]\n    }" } else { "\n      ]\n    }" });
+        }
+        json.push_str(if self.units.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        json
+    }
+}
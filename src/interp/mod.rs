@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, TypeKind, Value, ValueKind};
+
+pub mod runtime;
+
+use runtime::Runtime;
+use crate::opt::const_fold;
+
+// Executes Koopa IR directly, so that programs can be tested without a RISC-V toolchain.
+// Memory is an array of 32-bit words and pointers are indices into it, globals first.
+// Calls keep their own stack of frames, deep recursion in the program does not overflow ours.
+pub fn run(program: &Program, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<i32, InterpError> {
+    let mut interpreter = Interpreter {
+        program,
+        memory: Vec::new(),
+        globals: HashMap::new(),
+        block_insts: HashMap::new(),
+        runtime: Runtime::new(input, output),
+    };
+    interpreter.init_globals();
+    let main = program.func_layout().iter().copied()
+        .find(|&func| program.func(func).name() == "@main")
+        .ok_or(InterpError::NoMain)?;
+    let result = interpreter.call_main(main);
+    interpreter.runtime.flush()?;
+    result
+}
+
+#[derive(Debug)]
+pub enum InterpError {
+    NoMain,
+    DivisionByZero,
+    UnknownFunction(String),
+    InvalidInput(String),
+    InvalidAddress(i32),
+    // In the format of `putf`
+    UnsupportedConversion(String),
+    StackOverflow,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for InterpError {
+    fn from(error: std::io::Error) -> Self {
+        InterpError::Io(error)
+    }
+}
+
+impl std::fmt::Display for InterpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InterpError::NoMain => write!(f, "the program has no `main` function"),
+            InterpError::DivisionByZero => write!(f, "division by zero"),
+            InterpError::UnknownFunction(name) => write!(f, "call to `{}`, which has no body and is not in the runtime library", name),
+            InterpError::InvalidInput(expected) => write!(f, "invalid input, expected {}", expected),
+            InterpError::InvalidAddress(address) => write!(f, "access to invalid address {}", address),
+            InterpError::UnsupportedConversion(conversion) => write!(f, "unsupported conversion `{}` in the format of `putf`", conversion),
+            InterpError::StackOverflow => write!(f, "stack overflow, the call depth exceeds {}", MAX_CALL_DEPTH),
+            InterpError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// Deeper recursion would crash the compiled program as well
+const MAX_CALL_DEPTH: usize = 1 << 16;
+
+struct Frame {
+    func: Function,
+    bb: BasicBlock,
+    // Position of the next instruction in `bb`
+    next: usize,
+    values: HashMap<Value, i32>,
+    // Where the memory of the frame begins, released on return
+    stack_base: usize,
+    // The call instruction of the caller receiving the return value
+    call: Option<Value>,
+}
+
+struct Interpreter<'p, 'io> {
+    program: &'p Program,
+    memory: Vec<i32>,
+    globals: HashMap<Value, i32>,
+    // Instructions of each basic block, the layout is a linked list
+    block_insts: HashMap<(Function, BasicBlock), Vec<Value>>,
+    runtime: Runtime<'io>,
+}
+
+// Words taken by a value of the type pointed to by `ty`
+fn pointee_words(ty: &koopa::ir::Type) -> usize {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.size().div_ceil(4),
+        _ => unreachable!("not a pointer type: {}", ty),
+    }
+}
+
+impl<'p, 'io> Interpreter<'p, 'io> {
+    fn init_globals(&mut self) {
+        for &global in self.program.inst_layout() {
+            let data = self.program.borrow_value(global);
+            if let ValueKind::GlobalAlloc(alloc) = data.kind() {
+                let address = self.memory.len();
+                self.memory.resize(address + pointee_words(data.ty()), 0);
+                self.write_init(alloc.init(), address);
+                self.globals.insert(global, address as i32);
+            }
+        }
+    }
+
+    // Writes a global initializer at `address`, returning the number of words written
+    fn write_init(&mut self, init: Value, address: usize) -> usize {
+        let data = self.program.borrow_value(init);
+        match data.kind() {
+            ValueKind::Integer(int) => {
+                self.memory[address] = int.value();
+                1
+            }
+            ValueKind::Aggregate(aggregate) => {
+                let elems = aggregate.elems().to_vec();
+                drop(data);
+                elems.into_iter().fold(0, |written, elem| written + self.write_init(elem, address + written))
+            }
+            // Memory starts zeroed
+            _ => data.ty().size().div_ceil(4),
+        }
+    }
+
+    fn insts(&mut self, func: Function, bb: BasicBlock) -> &[Value] {
+        let program = self.program;
+        self.block_insts.entry((func, bb)).or_insert_with(|| {
+            program.func(func).layout().bbs().node(&bb).unwrap().insts().keys().copied().collect()
+        })
+    }
+
+    fn new_frame(&self, func: Function, args: &[i32], call: Option<Value>) -> Frame {
+        let func_data = self.program.func(func);
+        let values = func_data.params().iter().copied().zip(args.iter().copied()).collect();
+        Frame {
+            func,
+            bb: func_data.layout().entry_bb().unwrap(),
+            next: 0,
+            values,
+            stack_base: self.memory.len(),
+            call,
+        }
+    }
+
+    // The value of an operand: a constant, a global, or the result of an earlier instruction
+    fn operand(&self, frame: &Frame, value: Value) -> i32 {
+        if let Some(&address) = self.globals.get(&value) {
+            return address;
+        }
+        let func_data = self.program.func(frame.func);
+        match func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => int.value(),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => 0,
+            _ => frame.values[&value],
+        }
+    }
+
+    fn load(&self, address: i32) -> Result<i32, InterpError> {
+        self.memory.get(address as usize).copied().ok_or(InterpError::InvalidAddress(address))
+    }
+
+    fn store(&mut self, address: i32, value: i32) -> Result<(), InterpError> {
+        let slot = self.memory.get_mut(address as usize).ok_or(InterpError::InvalidAddress(address))?;
+        *slot = value;
+        Ok(())
+    }
+
+    fn call_main(&mut self, main: Function) -> Result<i32, InterpError> {
+        let program = self.program;
+        let mut stack = vec![self.new_frame(main, &[], None)];
+        loop {
+            let frame = stack.last_mut().unwrap();
+            let inst = self.insts(frame.func, frame.bb)[frame.next];
+            frame.next += 1;
+
+            let func_data: &FunctionData = program.func(frame.func);
+            let data = func_data.dfg().value(inst);
+            let result = match data.kind() {
+                ValueKind::Alloc(_) => {
+                    // A slot per alloc and call, reused when a loop runs the alloc again
+                    if frame.values.contains_key(&inst) {
+                        continue;
+                    }
+                    let address = self.memory.len();
+                    self.memory.resize(address + pointee_words(data.ty()), 0);
+                    address as i32
+                }
+                ValueKind::Load(load) => self.load(self.operand(frame, load.src()))?,
+                ValueKind::Store(store) => {
+                    let (value, dest) = (self.operand(frame, store.value()), self.operand(frame, store.dest()));
+                    self.store(dest, value)?;
+                    continue;
+                }
+                ValueKind::GetPtr(get_ptr) => {
+                    let stride = pointee_words(data.ty()) as i32;
+                    self.operand(frame, get_ptr.src()).wrapping_add(self.operand(frame, get_ptr.index()).wrapping_mul(stride))
+                }
+                ValueKind::GetElemPtr(get_elem_ptr) => {
+                    let stride = pointee_words(data.ty()) as i32;
+                    self.operand(frame, get_elem_ptr.src()).wrapping_add(self.operand(frame, get_elem_ptr.index()).wrapping_mul(stride))
+                }
+                ValueKind::Binary(binary) => {
+                    let (lhs, rhs) = (self.operand(frame, binary.lhs()), self.operand(frame, binary.rhs()));
+                    binary_op(binary.op(), lhs, rhs)?
+                }
+                ValueKind::Branch(branch) => {
+                    let (target, args) = if self.operand(frame, branch.cond()) != 0 {
+                        (branch.true_bb(), branch.true_args())
+                    } else {
+                        (branch.false_bb(), branch.false_args())
+                    };
+                    self.jump(frame, target, args);
+                    continue;
+                }
+                ValueKind::Jump(jump) => {
+                    self.jump(frame, jump.target(), jump.args());
+                    continue;
+                }
+                ValueKind::Call(call) => {
+                    let args: Vec<i32> = call.args().iter().map(|&arg| self.operand(frame, arg)).collect();
+                    let callee = program.func(call.callee());
+                    if callee.layout().entry_bb().is_none() {
+                        let name = &callee.name()[1..];
+                        self.runtime.call(name, &args, &mut self.memory)?.unwrap_or(0)
+                    } else {
+                        if stack.len() >= MAX_CALL_DEPTH {
+                            return Err(InterpError::StackOverflow);
+                        }
+                        let new_frame = self.new_frame(call.callee(), &args, Some(inst));
+                        stack.push(new_frame);
+                        continue;
+                    }
+                }
+                ValueKind::Return(ret) => {
+                    let value = ret.value().map_or(0, |value| self.operand(frame, value));
+                    let frame = stack.pop().unwrap();
+                    self.memory.truncate(frame.stack_base);
+                    match (stack.last_mut(), frame.call) {
+                        (Some(caller), Some(call)) => {
+                            caller.values.insert(call, value);
+                            continue;
+                        }
+                        _ => return Ok(value),
+                    }
+                }
+                kind => unreachable!("unexpected instruction in a function body: {:?}", kind),
+            };
+            frame.values.insert(inst, result);
+        }
+    }
+
+    // Passes the block arguments and moves to the start of `target`
+    fn jump(&self, frame: &mut Frame, target: BasicBlock, args: &[Value]) {
+        let args: Vec<i32> = args.iter().map(|&arg| self.operand(frame, arg)).collect();
+        let params = self.program.func(frame.func).dfg().bb(target).params();
+        for (&param, arg) in params.iter().zip(args) {
+            frame.values.insert(param, arg);
+        }
+        frame.bb = target;
+        frame.next = 0;
+    }
+}
+
+// The same arithmetic as constant folding, wrapping around as on the target
+fn binary_op(op: BinaryOp, lhs: i32, rhs: i32) -> Result<i32, InterpError> {
+    const_fold::evaluate(op, lhs, rhs).ok_or(InterpError::DivisionByZero)
+}
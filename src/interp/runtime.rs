@@ -0,0 +1,126 @@
+use std::io::{BufRead, Write};
+use crate::interp::InterpError;
+use crate::ir::library::{parse_format, FormatPiece};
+
+// The SysY runtime library, reading the standard input and writing the standard output
+// of the interpreted program
+pub struct Runtime<'io> {
+    input: &'io mut dyn BufRead,
+    output: &'io mut dyn Write,
+}
+
+impl<'io> Runtime<'io> {
+    pub fn new(input: &'io mut dyn BufRead, output: &'io mut dyn Write) -> Self {
+        Runtime { input, output }
+    }
+
+    pub fn flush(&mut self) -> Result<(), InterpError> {
+        self.output.flush()?;
+        Ok(())
+    }
+
+    // Returns the result of `name`, `None` for the `void` functions.
+    // Arrays are passed as addresses into `memory`.
+    pub fn call(&mut self, name: &str, args: &[i32], memory: &mut [i32]) -> Result<Option<i32>, InterpError> {
+        let result = match name {
+            "getint" => Some(self.read_int()?),
+            "getch" => Some(self.read_byte()?.map_or(-1, i32::from)),
+            "getarray" => {
+                let len = self.read_int()?;
+                for i in 0..len {
+                    let value = self.read_int()?;
+                    *word(memory, args[0].wrapping_add(i))? = value;
+                }
+                Some(len)
+            }
+            "putint" => {
+                write!(self.output, "{}", args[0])?;
+                None
+            }
+            "putch" => {
+                self.output.write_all(&[args[0] as u8])?;
+                None
+            }
+            "putarray" => {
+                write!(self.output, "{}:", args[0])?;
+                for i in 0..args[0] {
+                    write!(self.output, " {}", *word(memory, args[1].wrapping_add(i))?)?;
+                }
+                writeln!(self.output)?;
+                None
+            }
+            "putf" => {
+                self.putf(args, memory)?;
+                None
+            }
+            // Timing is only meaningful for the compiled program
+            "starttime" | "stoptime" => None,
+            _ => return Err(InterpError::UnknownFunction(name.to_string())),
+        };
+        Ok(result)
+    }
+
+    // The format is packed four bytes a word, see `ir::library`. Semantic analysis ensures it has
+    // a value for each conversion, hand-written IR gets `0` for the missing ones.
+    fn putf(&mut self, args: &[i32], memory: &mut [i32]) -> Result<(), InterpError> {
+        let mut format = Vec::new();
+        for address in args[0].. {
+            let bytes = word(memory, address)?.to_le_bytes();
+            format.extend(bytes.iter().take_while(|&&byte| byte != 0));
+            if bytes.contains(&0) {
+                break;
+            }
+        }
+        let pieces = parse_format(&format).map_err(InterpError::UnsupportedConversion)?;
+        let mut values = args[1..].iter().copied();
+        for piece in pieces {
+            match piece {
+                FormatPiece::Byte(byte) => self.output.write_all(&[byte])?,
+                FormatPiece::Int => write!(self.output, "{}", values.next().unwrap_or(0))?,
+                FormatPiece::Char => self.output.write_all(&[values.next().unwrap_or(0) as u8])?,
+            }
+        }
+        Ok(())
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>, InterpError> {
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, InterpError> {
+        let byte = self.peek_byte()?;
+        if byte.is_some() {
+            self.input.consume(1);
+        }
+        Ok(byte)
+    }
+
+    // A decimal integer after optional whitespace, like `scanf("%d")`
+    fn read_int(&mut self) -> Result<i32, InterpError> {
+        while self.peek_byte()?.is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.input.consume(1);
+        }
+        let negative = match self.peek_byte()? {
+            Some(sign @ (b'-' | b'+')) => {
+                self.input.consume(1);
+                sign == b'-'
+            }
+            _ => false,
+        };
+        let mut value: i32 = 0;
+        let mut digits = 0;
+        while let Some(digit @ b'0'..=b'9') = self.peek_byte()? {
+            self.input.consume(1);
+            value = value.wrapping_mul(10).wrapping_add((digit - b'0') as i32);
+            digits += 1;
+        }
+        if digits == 0 {
+            return Err(InterpError::InvalidInput("an integer".into()));
+        }
+        Ok(if negative { value.wrapping_neg() } else { value })
+    }
+}
+
+fn word(memory: &mut [i32], address: i32) -> Result<&mut i32, InterpError> {
+    memory.get_mut(address as usize).ok_or(InterpError::InvalidAddress(address))
+}
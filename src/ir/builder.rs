@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Type, Value};
+
+// A small DSL building Koopa programs in Rust, so that the optimization passes and the
+// backend can be tested without going through the SysY parser:
+//
+//     let program = ProgramBuilder::new()
+//         .declare("putint", 1, false)
+//         .func(func("main").block("entry", |b| {
+//             b.call("putint", &[b.int(42)]);
+//             b.ret(b.int(0));
+//         }))
+//         .build();
+//
+// All values are `i32` or pointers to `i32`. Names are given without their `@` or `%` sigil.
+
+// A function definition, its blocks are built when the function is added to a program
+pub struct FuncBuilder {
+    name: String,
+    params: Vec<String>,
+    returns_value: bool,
+    blocks: Vec<(String, Box<BlockBody>)>,
+}
+
+type BlockBody = dyn FnOnce(&BlockBuilder);
+
+// A function returning `i32`, without parameters until `param` is called
+pub fn func(name: &str) -> FuncBuilder {
+    FuncBuilder {
+        name: name.to_string(),
+        params: Vec::new(),
+        returns_value: true,
+        blocks: Vec::new(),
+    }
+}
+
+impl FuncBuilder {
+    // Appends an `i32` parameter, read in the blocks with `BlockBuilder::param`
+    pub fn param(mut self, name: &str) -> Self {
+        self.params.push(name.to_string());
+        self
+    }
+
+    pub fn void(mut self) -> Self {
+        self.returns_value = false;
+        self
+    }
+
+    // The first block is the entry. Every block can jump to any other by name.
+    pub fn block(mut self, name: &str, body: impl FnOnce(&BlockBuilder) + 'static) -> Self {
+        self.blocks.push((name.to_string(), Box::new(body)));
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct ProgramBuilder {
+    program: Program,
+    functions: HashMap<String, Function>,
+    globals: HashMap<String, Value>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A global `i32` initialized to `init`
+    pub fn global(mut self, name: &str, init: i32) -> Self {
+        let init = self.program.new_value().integer(init);
+        let global = self.program.new_value().global_alloc(init);
+        self.program.set_value_name(global, Some(format!("@{}", name)));
+        self.globals.insert(name.to_string(), global);
+        self
+    }
+
+    // A function defined elsewhere, e.g. in the runtime library, taking `params` integers
+    pub fn declare(mut self, name: &str, params: usize, returns_value: bool) -> Self {
+        let data = FunctionData::new_decl(format!("@{}", name), vec![Type::get_i32(); params], return_type(returns_value));
+        let function = self.program.new_func(data);
+        self.functions.insert(name.to_string(), function);
+        self
+    }
+
+    // The function can call itself and the functions added before it
+    pub fn func(mut self, builder: FuncBuilder) -> Self {
+        let params = builder.params.iter().map(|name| (Some(format!("@{}", name)), Type::get_i32())).collect();
+        let data = FunctionData::with_param_names(format!("@{}", builder.name), params, return_type(builder.returns_value));
+        let function = self.program.new_func(data);
+        self.functions.insert(builder.name.clone(), function);
+
+        let data = self.program.func_mut(function);
+        let mut blocks = HashMap::new();
+        for (name, _) in builder.blocks.iter() {
+            let bb = data.dfg_mut().new_bb().basic_block(Some(format!("%{}", name)));
+            data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+            blocks.insert(name.clone(), bb);
+        }
+        let params = builder.params.iter().cloned().zip(data.params().iter().copied()).collect();
+
+        let data = RefCell::new(data);
+        let locals = RefCell::new(HashMap::new());
+        for (name, body) in builder.blocks {
+            body(&BlockBuilder {
+                data: &data,
+                bb: blocks[&name],
+                blocks: &blocks,
+                params: &params,
+                locals: &locals,
+                functions: &self.functions,
+                globals: &self.globals,
+            });
+        }
+        self
+    }
+
+    pub fn build(self) -> Program {
+        self.program
+    }
+}
+
+fn return_type(returns_value: bool) -> Type {
+    if returns_value { Type::get_i32() } else { Type::get_unit() }
+}
+
+// Appends instructions to one block. Methods take `&self`, so that operands can be built
+// in the arguments: `b.ret(b.int(0))`.
+pub struct BlockBuilder<'a> {
+    data: &'a RefCell<&'a mut FunctionData>,
+    bb: BasicBlock,
+    blocks: &'a HashMap<String, BasicBlock>,
+    params: &'a HashMap<String, Value>,
+    // Allocations by name, shared by the blocks of the function
+    locals: &'a RefCell<HashMap<String, Value>>,
+    functions: &'a HashMap<String, Function>,
+    globals: &'a HashMap<String, Value>,
+}
+
+impl BlockBuilder<'_> {
+    pub fn int(&self, value: i32) -> Value {
+        self.data.borrow_mut().dfg_mut().new_value().integer(value)
+    }
+
+    pub fn param(&self, name: &str) -> Value {
+        *self.params.get(name).unwrap_or_else(|| panic!("no parameter `{}`", name))
+    }
+
+    pub fn global(&self, name: &str) -> Value {
+        *self.globals.get(name).unwrap_or_else(|| panic!("no global `{}`", name))
+    }
+
+    // An `i32` slot, found in any block of the function with `local`
+    pub fn alloc(&self, name: &str) -> Value {
+        let alloc = self.push(|data| data.dfg_mut().new_value().alloc(Type::get_i32()));
+        self.data.borrow_mut().dfg_mut().set_value_name(alloc, Some(format!("@{}", name)));
+        self.locals.borrow_mut().insert(name.to_string(), alloc);
+        alloc
+    }
+
+    pub fn local(&self, name: &str) -> Value {
+        *self.locals.borrow().get(name).unwrap_or_else(|| panic!("no local `{}` allocated so far", name))
+    }
+
+    pub fn load(&self, src: Value) -> Value {
+        self.push(|data| data.dfg_mut().new_value().load(src))
+    }
+
+    pub fn store(&self, value: Value, dest: Value) {
+        self.push(|data| data.dfg_mut().new_value().store(value, dest));
+    }
+
+    pub fn binary(&self, op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        self.push(|data| data.dfg_mut().new_value().binary(op, lhs, rhs))
+    }
+
+    pub fn add(&self, lhs: Value, rhs: Value) -> Value {
+        self.binary(BinaryOp::Add, lhs, rhs)
+    }
+
+    pub fn sub(&self, lhs: Value, rhs: Value) -> Value {
+        self.binary(BinaryOp::Sub, lhs, rhs)
+    }
+
+    pub fn lt(&self, lhs: Value, rhs: Value) -> Value {
+        self.binary(BinaryOp::Lt, lhs, rhs)
+    }
+
+    // The result is unit-typed for `void` functions
+    pub fn call(&self, callee: &str, args: &[Value]) -> Value {
+        let callee = *self.functions.get(callee).unwrap_or_else(|| panic!("no function `{}` added so far", callee));
+        self.push(|data| data.dfg_mut().new_value().call(callee, args.to_vec()))
+    }
+
+    pub fn branch(&self, cond: Value, true_bb: &str, false_bb: &str) {
+        let (true_bb, false_bb) = (self.block(true_bb), self.block(false_bb));
+        self.push(|data| data.dfg_mut().new_value().branch(cond, true_bb, false_bb));
+    }
+
+    pub fn jump(&self, target: &str) {
+        let target = self.block(target);
+        self.push(|data| data.dfg_mut().new_value().jump(target));
+    }
+
+    pub fn ret(&self, value: Value) {
+        self.push(|data| data.dfg_mut().new_value().ret(Some(value)));
+    }
+
+    pub fn ret_void(&self) {
+        self.push(|data| data.dfg_mut().new_value().ret(None));
+    }
+
+    fn block(&self, name: &str) -> BasicBlock {
+        *self.blocks.get(name).unwrap_or_else(|| panic!("no block `{}`", name))
+    }
+
+    // Creates an instruction and appends it to the block
+    fn push(&self, build: impl FnOnce(&mut FunctionData) -> Value) -> Value {
+        let mut data = self.data.borrow_mut();
+        let inst = build(&mut data);
+        data.layout_mut().bb_mut(self.bb).insts_mut().push_key_back(inst).unwrap();
+        inst
+    }
+}
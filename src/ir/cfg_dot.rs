@@ -0,0 +1,82 @@
+use std::fmt::Write;
+use koopa::ir::{FunctionData, Program, ValueKind};
+use crate::ir::KoopaGenerator;
+use crate::opt::dominators::{successors, DominatorTree};
+
+// The control flow graph of every function with a body as a Graphviz digraph, for
+// `--dump-cfg`, with the name of the function without its `@`. A block is drawn with its
+// label and terminator as written by `KoopaGenerator`, returning blocks with a double border
+// and unreachable ones in gray. Edges are labeled `true`, `false` or `jump`, and those going
+// back to a block dominating their source, the back edges of loops, are dashed.
+pub fn cfg_dots(program: &Program) -> Vec<(String, String)> {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).expect("writing to memory does not fail");
+    let text = String::from_utf8(gen.writer()).expect("the text form IR is UTF-8");
+    let mut blocks = block_texts(&text).into_iter();
+
+    program.func_layout().iter()
+        .map(|&func| program.func(func))
+        .filter(|func_data| func_data.layout().entry_bb().is_some())
+        .map(|func_data| (func_data.name()[1..].to_string(), function_dot(func_data, &blocks.next().unwrap_or_default())))
+        .collect()
+}
+
+// The label and the terminator of every block, function by function. The generator writes
+// the functions and their blocks in layout order, a block starting with its label on a line
+// of its own and ending with its terminator.
+fn block_texts(text: &str) -> Vec<Vec<(String, String)>> {
+    let mut funcs: Vec<Vec<(String, String)>> = Vec::new();
+    for line in text.lines() {
+        if line.starts_with("fun ") {
+            funcs.push(Vec::new());
+        } else if let Some(blocks) = funcs.last_mut() {
+            if line.ends_with(':') && !line.starts_with(' ') {
+                blocks.push((line.to_string(), String::new()));
+            } else if let (Some(inst), Some(block)) = (line.strip_prefix("  "), blocks.last_mut()) {
+                block.1 = inst.to_string();
+            }
+        }
+    }
+    funcs
+}
+
+fn function_dot(func_data: &FunctionData, blocks: &[(String, String)]) -> String {
+    let dominators = DominatorTree::compute(func_data);
+    let bbs: Vec<_> = func_data.layout().bbs().keys().copied().collect();
+    let index_of = |bb| bbs.iter().position(|&other| other == bb).expect("the targets are blocks of the layout");
+
+    let mut dot = format!("digraph \"{}\" {{\n  node [shape=box, fontname=\"monospace\"];\n", &func_data.name()[1..]);
+    for (index, (&bb, node)) in func_data.layout().bbs().iter().enumerate() {
+        let (label, terminator) = blocks.get(index).cloned().unwrap_or_default();
+        let mut text = format!("{}\\l", escape(&label));
+        if node.insts().len() > 1 {
+            write!(text, "  ... {} more\\l", node.insts().len() - 1).unwrap();
+        }
+        write!(text, "  {}\\l", escape(&terminator)).unwrap();
+        let mut attributes = String::new();
+        let returns = node.insts().back_key().is_some_and(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Return(_)));
+        if returns {
+            attributes.push_str(", peripheries=2");
+        }
+        if !dominators.is_reachable(bb) {
+            attributes.push_str(", color=gray, fontcolor=gray");
+        }
+        writeln!(dot, "  bb{} [label=\"{}\"{}];", index, text, attributes).unwrap();
+    }
+
+    for (index, &bb) in bbs.iter().enumerate() {
+        let targets = successors(func_data, bb);
+        let kinds: &[&str] = if targets.len() == 2 { &["true", "false"] } else { &["jump"] };
+        for (&target, kind) in targets.iter().zip(kinds) {
+            let back = dominators.is_reachable(bb) && dominators.dominates(target, bb);
+            let style = if back { ", style=dashed" } else { "" };
+            writeln!(dot, "  bb{} -> bb{} [label=\"{}\"{}];", index, index_of(target), kind, style).unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
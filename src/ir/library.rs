@@ -0,0 +1,77 @@
+use koopa::ir::FunctionData;
+
+// What the IR of a program calling the runtime library relies on beyond Koopa IR, which has
+// neither variadic functions nor bytes.
+//
+// A call to a variadic function passes its variadic arguments after the parameters of its
+// declaration, e.g. `call @putf(%format, %x)` to `decl @putf(*i32)`. The koopa parser drops
+// them when the text form is read back.
+//
+// A string literal is a global `[i32, n]` named with `STRING_PREFIX`, holding the bytes of the
+// string and a NUL packed four a word, little-endian, which is their layout in memory on RISC-V.
+// The backend puts it in `.rodata`.
+
+pub const VARIADIC_FUNCTIONS: &[&str] = &["putf"];
+
+// Reserved along with the other names of the runtime library starting with `__sysy_`
+pub const STRING_PREFIX: &str = "__sysy_str_";
+
+pub fn is_variadic(func_data: &FunctionData) -> bool {
+    VARIADIC_FUNCTIONS.contains(&&func_data.name()[1..])
+}
+
+// The name of a global, without its `@`
+pub fn is_string_literal(name: &str) -> bool {
+    name.starts_with(STRING_PREFIX)
+}
+
+// The words of a string literal, its NUL included
+pub fn pack_string(bytes: &[u8]) -> Vec<i32> {
+    bytes.iter().copied().chain([0]).collect::<Vec<u8>>()
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            i32::from_le_bytes(word)
+        })
+        .collect()
+}
+
+// The byte at `index` of a packed string
+pub fn string_byte(words: &[i32], index: usize) -> u8 {
+    words[index / 4].to_le_bytes()[index % 4]
+}
+
+// The bytes of a packed string up to its NUL
+pub fn unpack_string(words: &[i32]) -> Vec<u8> {
+    (0..words.len() * 4).map(|index| string_byte(words, index)).take_while(|&byte| byte != 0).collect()
+}
+
+// A piece of the format of `putf`: `%d` prints an argument in decimal, `%c` the character it
+// is and `%%` a `%`, the other bytes being printed as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatPiece {
+    Byte(u8),
+    Int,
+    Char,
+}
+
+// `Err` is the text of the first unsupported conversion
+pub fn parse_format(format: &[u8]) -> Result<Vec<FormatPiece>, String> {
+    let mut pieces = Vec::new();
+    let mut bytes = format.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            pieces.push(FormatPiece::Byte(byte));
+            continue;
+        }
+        pieces.push(match bytes.next() {
+            Some(b'd') => FormatPiece::Int,
+            Some(b'c') => FormatPiece::Char,
+            Some(b'%') => FormatPiece::Byte(b'%'),
+            Some(&other) => return Err(format!("%{}", other as char)),
+            None => return Err("%".to_string()),
+        });
+    }
+    Ok(pieces)
+}
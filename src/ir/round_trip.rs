@@ -0,0 +1,199 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use koopa::front::Driver;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value, ValueKind};
+use crate::ir::{library, KoopaGenerator};
+use crate::opt::{map_operands, map_targets};
+
+// A self-check of the frontend: the text form of `program` is parsed back and compared with it
+// value by value, so that IR the koopa parser rejects, or reads as another program, is caught
+// where it is generated. Only the names of the local values and blocks may differ, those
+// without one being numbered by the generator, and the variadic arguments of calls are left
+// out, as the parser drops them, see `ir::library`. `Err` describes the first difference found.
+pub fn check(program: &Program) -> Result<(), String> {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).map_err(|error| format!("the IR cannot be written: {}", error))?;
+    let text = String::from_utf8(gen.writer()).expect("the text form IR is UTF-8");
+    // The parser prints where it failed
+    let parsed = Driver::from(text).generate_program().map_err(|_| "the koopa parser rejects the text form of the IR".to_string())?;
+    Comparison::default().programs(program, &parsed)
+}
+
+// The values, blocks and functions of the parsed program matching those of the original
+#[derive(Default)]
+struct Comparison {
+    values: HashMap<Value, Value>,
+    blocks: HashMap<BasicBlock, BasicBlock>,
+    funcs: HashMap<Function, Function>,
+    variadic: HashSet<Function>,
+}
+
+type Lookup<'a> = dyn Fn(Value) -> (Type, ValueKind) + 'a;
+
+impl Comparison {
+    fn programs(mut self, original: &Program, parsed: &Program) -> Result<(), String> {
+        let global_name = |program: &Program, value: Value| program.borrow_value(value).name().clone().unwrap_or_default();
+        if original.inst_layout().len() != parsed.inst_layout().len() {
+            return Err(format!("{} globals read back as {}", original.inst_layout().len(), parsed.inst_layout().len()));
+        }
+        for (&value, &read) in original.inst_layout().iter().zip(parsed.inst_layout()) {
+            let (name, read_name) = (global_name(original, value), global_name(parsed, read));
+            let same = match (global(original, value), global(parsed, read)) {
+                ((ty, ValueKind::GlobalAlloc(alloc)), (read_ty, ValueKind::GlobalAlloc(read_alloc))) => {
+                    ty == read_ty && name == read_name
+                        && self.same(alloc.init(), read_alloc.init(), &|value| global(original, value), &|value| global(parsed, value))
+                }
+                _ => false,
+            };
+            if !same {
+                return Err(format!("global `{}` reads back as `{}`", name, read_name));
+            }
+            self.values.insert(value, read);
+        }
+
+        if original.func_layout().len() != parsed.func_layout().len() {
+            return Err(format!("{} functions read back as {}", original.func_layout().len(), parsed.func_layout().len()));
+        }
+        for (&func, &read) in original.func_layout().iter().zip(parsed.func_layout()) {
+            let (func_data, read_data) = (original.func(func), parsed.func(read));
+            if func_data.name() != read_data.name() || func_data.ty() != read_data.ty() {
+                return Err(format!("function `{}` reads back as `{}`", func_data.name(), read_data.name()));
+            }
+            self.funcs.insert(func, read);
+            if library::is_variadic(func_data) {
+                self.variadic.insert(func);
+            }
+        }
+        for (&func, &read) in original.func_layout().iter().zip(parsed.func_layout()) {
+            self.function(original.func(func), parsed.func(read), &|value| local(original, func, value), &|value| local(parsed, read, value))
+                .map_err(|message| format!("in `{}`, {}", original.func(func).name(), message))?;
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, func_data: &FunctionData, read_data: &FunctionData, original: &Lookup, parsed: &Lookup) -> Result<(), String> {
+        self.values.extend(func_data.params().iter().copied().zip(read_data.params().iter().copied()));
+        let block_name = |func_data: &FunctionData, bb: BasicBlock| func_data.dfg().bb(bb).name().clone().unwrap_or_default();
+        let (Some(entry), Some(read_entry)) = (func_data.layout().entry_bb(), read_data.layout().entry_bb()) else {
+            return Ok(());
+        };
+        // The parser lays the blocks out in the order they are reached from the entry, leaving
+        // out the unreachable ones, so they are matched by following the branches. The
+        // instructions are matched before they are compared, as they may use values of later blocks.
+        let mut queue = VecDeque::from([(entry, read_entry)]);
+        self.blocks.insert(entry, read_entry);
+        let mut reachable = 1;
+        let mut insts = Vec::new();
+        while let Some((bb, read_bb)) = queue.pop_front() {
+            let (node, read_node) = (func_data.layout().bbs().node(&bb).unwrap(), read_data.layout().bbs().node(&read_bb).unwrap());
+            let (params, read_params) = (func_data.dfg().bb(bb).params(), read_data.dfg().bb(read_bb).params());
+            if params.len() != read_params.len() || node.insts().len() != read_node.insts().len() {
+                return Err(format!("block `{}` reads back as `{}`", block_name(func_data, bb), block_name(read_data, read_bb)));
+            }
+            self.values.extend(params.iter().copied().zip(read_params.iter().copied()));
+            for (&inst, &read_inst) in node.insts().keys().zip(read_node.insts().keys()) {
+                self.values.insert(inst, read_inst);
+                insts.push((bb, inst, read_inst));
+            }
+
+            let (mut targets, mut read_targets) = (Vec::new(), Vec::new());
+            if let (Some(&last), Some(&read_last)) = (node.insts().back_key(), read_node.insts().back_key()) {
+                map_targets(&mut original(last).1, |&mut target| targets.push(target));
+                map_targets(&mut parsed(read_last).1, |&mut target| read_targets.push(target));
+            }
+            for (target, read_target) in targets.into_iter().zip(read_targets) {
+                if let Entry::Vacant(entry) = self.blocks.entry(target) {
+                    entry.insert(read_target);
+                    queue.push_back((target, read_target));
+                    reachable += 1;
+                }
+            }
+        }
+        if reachable != read_data.layout().bbs().len() {
+            return Err(format!("{} reachable blocks read back as {}", reachable, read_data.layout().bbs().len()));
+        }
+
+        for (bb, inst, read_inst) in insts {
+            let ((ty, mut kind), (read_ty, mut read_kind)) = (original(inst), parsed(read_inst));
+            let (mut operands, mut read_operands) = (Vec::new(), Vec::new());
+            map_operands(&mut kind, |&mut operand| operands.push(operand));
+            map_operands(&mut read_kind, |&mut operand| read_operands.push(operand));
+            let (mut targets, mut read_targets) = (Vec::new(), Vec::new());
+            map_targets(&mut kind, |&mut target| targets.push(target));
+            map_targets(&mut read_kind, |&mut target| read_targets.push(target));
+            let same_callee = match (&kind, &read_kind) {
+                (ValueKind::Call(call), ValueKind::Call(read_call)) => self.funcs.get(&call.callee()) == Some(&read_call.callee()),
+                _ => true,
+            };
+            if matches!(&kind, ValueKind::Call(call) if self.variadic.contains(&call.callee())) {
+                operands.truncate(read_operands.len());
+            }
+            let same = ty == read_ty && shape(&kind) == shape(&read_kind) && same_callee
+                && operands.len() == read_operands.len()
+                && operands.iter().zip(read_operands.iter()).all(|(&operand, &read)| self.same(operand, read, original, parsed))
+                && targets.iter().map(|target| self.blocks.get(target)).eq(read_targets.iter().map(Some));
+            if !same {
+                return Err(format!(
+                    "{} of block `{}` reads back as {}",
+                    describe(func_data, inst, &kind), block_name(func_data, bb), describe(read_data, read_inst, &read_kind),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `read` is the value matching `value`, or a constant equal to it
+    fn same(&self, value: Value, read: Value, original: &Lookup, parsed: &Lookup) -> bool {
+        if let Some(&matching) = self.values.get(&value) {
+            return matching == read;
+        }
+        let ((ty, kind), (read_ty, read_kind)) = (original(value), parsed(read));
+        ty == read_ty && match (kind, read_kind) {
+            (ValueKind::Integer(int), ValueKind::Integer(read_int)) => int.value() == read_int.value(),
+            (ValueKind::ZeroInit(_), ValueKind::ZeroInit(_)) | (ValueKind::Undef(_), ValueKind::Undef(_)) => true,
+            (ValueKind::Aggregate(aggregate), ValueKind::Aggregate(read_aggregate)) => {
+                aggregate.elems().len() == read_aggregate.elems().len()
+                    && aggregate.elems().iter().zip(read_aggregate.elems()).all(|(&elem, &read)| self.same(elem, read, original, parsed))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn global(program: &Program, value: Value) -> (Type, ValueKind) {
+    let data = program.borrow_value(value);
+    (data.ty().clone(), data.kind().clone())
+}
+
+// A value used in `func`, which may be a global
+fn local(program: &Program, func: Function, value: Value) -> (Type, ValueKind) {
+    if value.is_global() {
+        return global(program, value);
+    }
+    let data = program.func(func).dfg().value(value);
+    (data.ty().clone(), data.kind().clone())
+}
+
+// The instruction an instruction is, without its operands
+fn shape(kind: &ValueKind) -> String {
+    match kind {
+        ValueKind::Alloc(_) => "alloc".to_string(),
+        ValueKind::Load(_) => "load".to_string(),
+        ValueKind::Store(_) => "store".to_string(),
+        ValueKind::GetPtr(_) => "getptr".to_string(),
+        ValueKind::GetElemPtr(_) => "getelemptr".to_string(),
+        ValueKind::Binary(binary) => binary.op().to_string(),
+        ValueKind::Branch(_) => "br".to_string(),
+        ValueKind::Jump(_) => "jump".to_string(),
+        ValueKind::Call(_) => "call".to_string(),
+        ValueKind::Return(_) => "ret".to_string(),
+        _ => "a constant".to_string(),
+    }
+}
+
+fn describe(func_data: &FunctionData, inst: Value, kind: &ValueKind) -> String {
+    match func_data.dfg().value(inst).name() {
+        Some(name) => format!("`{}`, {}", name, shape(kind)),
+        None => format!("`{}`", shape(kind)),
+    }
+}
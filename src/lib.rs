@@ -0,0 +1,24 @@
+// SysY compiler: `frontend` parses and checks SysY source and lowers it to Koopa IR,
+// `opt` runs passes over the IR and `backend` emits RISC-V assembly from it.
+// `interp` executes the IR instead. `Compiler` runs the whole pipeline for embedders.
+pub mod compiler;
+pub mod frontend;
+pub mod opt;
+pub mod backend;
+pub mod interp;
+pub mod common;
+#[doc(hidden)]
+pub mod util;
+
+pub use compiler::{Compiled, CompileError, Compiler};
+
+// Koopa IR, the interface between `frontend`, `opt` and `backend`
+pub mod ir {
+    pub use koopa::ir::*;
+    pub use koopa::back::KoopaGenerator;
+
+    pub mod builder;
+    pub mod cfg_dot;
+    pub mod library;
+    pub mod round_trip;
+}
@@ -1,105 +1,366 @@
-mod frontend;
-mod backend;
-mod common;
-mod util;
-mod opt;
-
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
-use koopa::back::KoopaGenerator;
-use lalrpop_util::lalrpop_mod;
-use backend::environment::{AsmEnvironment};
-use crate::backend::asm::AsmEmitter;
-use crate::backend::generate_asm::GenerateAsm;
-use crate::opt::dead_code_elimination::DeadCodeEliminationPass;
-use crate::opt::OptPassFunction;
+use std::path::Path;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+use sysy_compiler::{backend, frontend, interp, opt, Compiler};
+use sysy_compiler::backend::asm::AsmEmitter;
+use sysy_compiler::backend::call_graph::CallGraph;
+use sysy_compiler::backend::regalloc::AllocationReport;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::common::stats::{IrStats, ProgramCounts};
+use sysy_compiler::frontend::comments::IRComments;
+use sysy_compiler::frontend::xref::{CrossReference, XrefUnit};
+use sysy_compiler::ir::{cfg_dot, round_trip, KoopaGenerator};
+use sysy_compiler::opt::pass_manager::PassManager;
 
-lalrpop_mod!(sysy);
+mod cli;
+mod toolchain;
 
-fn main() -> std::io::Result<()> {
-    let (mode, input_file, output_file) = parse_args(std::env::args().collect());
+use cli::{Command, Emit, Options, RunMode};
+use toolchain::Toolchain;
 
-    let input = std::fs::read_to_string(input_file)?;
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
-    println!("AST Dump: {:?}", ast);
-    let ir = frontend::generate_ir(&ast).unwrap();
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::parse_args(&args) {
+        Ok(Command::Compile(options)) => options,
+        Ok(Command::Test(options)) => {
+            let passed = run_tests(options)?;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(Command::PrintPasses(pipeline)) => {
+            for pass in PassManager::from_pipeline(&pipeline).pipeline().0 {
+                println!("{}", pass);
+            }
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprintln!("Run with --help to see the available options");
+            std::process::exit(1);
+        }
+    };
+    let Options { emit, input_files, output_file, opt_level, opt_limits, passes, ir_comments, asm_comments, check_round_trip, dump_cfg, dump_callgraph, stack_map, backend: backend_options, linker_script, run_mode, verbose, stats, ir_stats, mut session } = options;
 
-    // IR Optimization pass
-    let mut dce = DeadCodeEliminationPass::new();
-    let func_layout = ir.borrow().func_layout().to_vec();
-    for func_h in func_layout {
-        dce.run_on(ir.borrow_mut().func_mut(func_h)).unwrap();
+    // File name for diagnostics and source text of every unit
+    let mut sources = Vec::new();
+    for input_file in input_files {
+        let input = read_input(&input_file)?;
+        let input_file = if input_file == "-" { "<stdin>".to_string() } else { input_file };
+        sources.push((input_file, input));
     }
 
-    match mode {
-        Mode::Koopa => {
-            let mut output = File::create(&output_file)?;
-            let mut gen = KoopaGenerator::new(Vec::new());
-            gen.generate_on(&*ir.borrow())?;
-            let text_form_ir = std::str::from_utf8(&gen.writer()).unwrap().to_string();
-            println!("Writing IR to file: {}", output_file);
-            output.write_all(text_form_ir.as_bytes())?;
+    // All the files are parsed before stopping, so that every syntax error is reported
+    let mut asts = Vec::new();
+    let mut has_errors = false;
+    for (input_file, input) in sources.iter() {
+        match session.stats.time("parse", || frontend::parser::parse(input)) {
+            Ok(ast) => asts.push(ast),
+            Err(diagnostics) => {
+                for diagnostic in diagnostics.iter() {
+                    eprintln!("{}", diagnostic.render(input_file, input));
+                }
+                has_errors = true;
+            }
         }
-        Mode::Riscv => {
-            let mut asm_program = backend::asm::AsmProgram {
-                sections: Vec::new(),
-            };
-            let program = &*ir.borrow();
-            let mut env = AsmEnvironment::new(program);
-            program.generate(&mut asm_program, &mut env);
+    }
+    if has_errors {
+        std::process::exit(1);
+    }
+    if let Emit::Ast | Emit::AstJson = emit {
+        let dump = match emit {
+            Emit::Ast => frontend::ast_dump::dump_text(&asts[0], &sources[0].1),
+            _ => frontend::ast_dump::dump_json(&asts[0], &sources[0].1),
+        };
+        open_output(&output_file)?.write_all(dump.as_bytes())?;
+        print_stats(stats, &session, None);
+        return Ok(());
+    }
 
-            let mut riscv_output = File::create(output_file)?;
-            println!("{:?}", asm_program);
-            asm_program.emit(&mut riscv_output).expect("Failed to emit target code");
+    let start = Instant::now();
+    let mut checked_units = Vec::new();
+    for (ast, (input_file, input)) in asts.iter().zip(sources.iter()) {
+        checked_units.push(frontend::semant::check(ast, &mut session));
+        has_errors |= report_diagnostics(&mut session, input_file, input);
+    }
+    if has_errors {
+        std::process::exit(1);
+    }
+    let linked_units: Vec<_> = sources.iter().zip(checked_units.iter())
+        .map(|((input_file, input), checked)| (input_file.as_str(), input.as_str(), &checked.interface))
+        .collect();
+    let errors = frontend::semant::check_linkage(&linked_units);
+    session.stats.record("semantic analysis", start.elapsed());
+    for (unit, error) in errors.iter() {
+        let (input_file, input) = &sources[*unit];
+        eprintln!("{}", error.render(input_file, input));
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+    if emit == Emit::SymbolsJson {
+        open_output(&output_file)?.write_all(checked_units[0].symbol_index.to_json(&sources[0].1).as_bytes())?;
+        print_stats(stats, &session, None);
+        return Ok(());
+    }
+    if emit == Emit::Xref {
+        let units: Vec<XrefUnit> = sources.iter().zip(checked_units.iter())
+            .map(|((input_file, input), checked)| (input_file.as_str(), input.as_str(), &checked.symbol_index))
+            .collect();
+        open_output(&output_file)?.write_all(CrossReference::new(&units).to_json().as_bytes())?;
+        print_stats(stats, &session, None);
+        return Ok(());
+    }
+    let comments = Rc::new(RefCell::new(IRComments::new(ir_comments || asm_comments)));
+    let (ir, alloc_scopes) = session.stats.time("IR generation", || frontend::generate_ir(&asts, &comments)).unwrap();
+    if check_round_trip {
+        if let Err(message) = session.stats.time("IR round trip", || round_trip::check(&ir.borrow())) {
+            eprintln!("error: internal compiler error: the generated IR does not round-trip: {}", message);
+            std::process::exit(1);
         }
-        Mode::Unknown => unreachable!()
     }
 
-    Ok(())
-}
-
-enum Mode {
-    Koopa,
-    Riscv,
-    Unknown,
-}
+    // IR Optimization passes
+    if verbose {
+        eprintln!("Optimizing at {}", opt_level);
+    }
+    let pipeline = passes.unwrap_or_else(|| opt::pipeline(opt_level));
+    if let Err(error) = opt::run_pipeline(&mut ir.borrow_mut(), &pipeline, &opt_limits, &mut session) {
+        eprintln!("error: internal compiler error: {}", error);
+        std::process::exit(1);
+    }
+    // The IR has no source locations, the diagnostics of the passes name the first file
+    if report_diagnostics(&mut session, &sources[0].0, &sources[0].1) {
+        std::process::exit(1);
+    }
+    let counts = ProgramCounts::of(&ir.borrow());
+    if ir_stats {
+        eprint!("{}", IrStats::of(&ir.borrow()).render());
+    }
+    if let Some(dir) = dump_cfg {
+        std::fs::create_dir_all(&dir)?;
+        for (name, dot) in cfg_dot::cfg_dots(&ir.borrow()) {
+            std::fs::write(Path::new(&dir).join(format!("{}.dot", name)), dot)?;
+        }
+    }
+    if let Some(file) = dump_callgraph {
+        std::fs::write(&file, CallGraph::build(&ir.borrow()).to_dot(&ir.borrow()))?;
+    }
 
-fn parse_args(args: Vec<String>) -> (Mode, String, String) {
-    let mut mode = Mode::Unknown;
-    let mut input_file = String::new();
-    let mut output_file = String::new();
+    match emit {
+        Emit::Check => {}
+        Emit::Koopa => {
+            let mut output = open_output(&output_file)?;
+            let mut gen = KoopaGenerator::new(Vec::new());
+            gen.generate_on(&ir.borrow())?;
+            let mut text_form_ir = std::str::from_utf8(&gen.writer()).unwrap().to_string();
+            if ir_comments {
+                text_form_ir = comments.borrow().annotate(&ir.borrow(), &text_form_ir);
+            }
+            if verbose {
+                eprintln!("Writing IR to file: {}", output_file);
+            }
+            output.write_all(text_form_ir.as_bytes())?;
+        }
+        Emit::Riscv | Emit::Obj | Emit::Exe => {
+            let mut asm_program = session.stats.time("codegen", || {
+                let comments = comments.borrow();
+                let source_lines = if asm_comments { comments.source_lines(&sources) } else { HashMap::new() };
+                backend::generate_code(&mut ir.borrow_mut(), &backend_options, &source_lines, &alloc_scopes)
+            });
+            // Of the functions of the program, not of the runtime library
+            if let Some(stack_map_file) = stack_map {
+                std::fs::write(&stack_map_file, asm_program.stack_map().to_json())?;
+            }
+            if backend_options.report_allocation {
+                eprint!("{}", AllocationReport::render(asm_program.allocation_reports()));
+            }
+            session.stats.time("codegen", || backend::link(&mut asm_program, &backend_options, emit == Emit::Exe));
 
-    for i in 1..args.len() {
-        match args[i].as_str() {
-            "-koopa" => {
-                mode = Mode::Koopa;
+            let mut output = open_output(&output_file)?;
+            if emit == Emit::Riscv {
+                if verbose {
+                    eprintln!("Writing assembly to file: {}", output_file);
+                }
+                asm_program.emit(&mut output).expect("Failed to emit target code");
+            } else {
+                let binary = session.stats.time("codegen", || if emit == Emit::Exe {
+                    backend::object::write_executable(&asm_program)
+                } else {
+                    backend::object::write_object(&asm_program)
+                });
+                let binary = match binary {
+                    Ok(binary) => binary,
+                    Err(error) => {
+                        eprintln!("error: {}", error);
+                        std::process::exit(1);
+                    }
+                };
+                if verbose {
+                    eprintln!("Writing {} to file: {}", if emit == Emit::Exe { "executable" } else { "object" }, output_file);
+                }
+                output.write_all(&binary)?;
+                #[cfg(unix)]
+                if emit == Emit::Exe && output_file != "-" {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(&output_file, std::fs::Permissions::from_mode(0o755))?;
+                }
             }
-            "-riscv" => {
-                mode = Mode::Riscv;
+            if let (Some(layout), Some(linker_script)) = (backend_options.memory_layout, linker_script) {
+                if verbose {
+                    eprintln!("Writing linker script to file: {}", linker_script);
+                }
+                std::fs::write(&linker_script, layout.linker_script())?;
+            }
+        }
+        Emit::Run => {
+            let toolchain = match run_mode {
+                RunMode::Interpret => None,
+                RunMode::Native => match Toolchain::find() {
+                    Ok(toolchain) => Some(toolchain),
+                    Err(message) => {
+                        eprintln!("error: {}", message);
+                        std::process::exit(1);
+                    }
+                },
+                RunMode::Auto => Toolchain::find().ok(),
+            };
+            if let Some(toolchain) = toolchain {
+                let asm_program = session.stats.time("codegen", || {
+                    backend::generate_code(&mut ir.borrow_mut(), &backend_options, &HashMap::new(), &alloc_scopes)
+                });
+                let mut assembly = Vec::new();
+                asm_program.emit(&mut assembly)?;
+                print_stats(stats, &session, Some(&counts));
+                match toolchain.run(&assembly, verbose) {
+                    Ok(status) => std::process::exit(status),
+                    Err(message) => {
+                        eprintln!("error: {}", message);
+                        std::process::exit(1);
+                    }
+                }
             }
-            "-o" => {
-                output_file = args[i + 1].clone();
+            if verbose {
+                eprintln!("Interpreting the program");
             }
-            _ => {
-                if i >= 2 && args[i - 1] != "-o" {
-                    input_file = args[i].clone();
+            // The run itself is the program's time, not the compiler's
+            print_stats(stats, &session, Some(&counts));
+            let mut input = BufReader::new(std::io::stdin());
+            let mut output = BufWriter::new(std::io::stdout());
+            match interp::run(&ir.borrow(), &mut input, &mut output) {
+                // Only the low 8 bits are visible to the parent, as for the compiled program
+                Ok(status) => std::process::exit(status & 0xff),
+                Err(error) => {
+                    output.flush()?;
+                    eprintln!("error: {}", error);
+                    std::process::exit(1);
                 }
             }
         }
+        Emit::Ast | Emit::AstJson | Emit::SymbolsJson | Emit::Xref => unreachable!(),
     }
+    print_stats(stats, &session, Some(&counts));
 
-    match mode {
-        Mode::Unknown => {
-            println!("One of -koopa or -riscv must be specified");
-            std::process::exit(1);
+    Ok(())
+}
+
+// Runs every input file as a program of its own, returning whether all of them passed
+fn run_tests(options: Options) -> std::io::Result<bool> {
+    let mut compiler = Compiler::new().opt_level(options.opt_level).opt_limits(options.opt_limits).lint_levels_of(&options.session);
+    if let Some(pipeline) = options.passes.clone() {
+        compiler = compiler.passes(pipeline);
+    }
+    let mut failed = Vec::new();
+    for test_file in options.input_files.iter() {
+        if options.verbose {
+            eprintln!("Running test: {}", test_file);
         }
-        _ => {}
+        match run_test(&compiler, test_file)? {
+            Ok(()) => println!("test {} ... ok", test_file),
+            Err(reason) => {
+                println!("test {} ... FAILED", test_file);
+                failed.push((test_file, reason));
+            }
+        }
+    }
+    for (test_file, reason) in failed.iter() {
+        println!("\n---- {} ----\n{}", test_file, reason);
     }
+    println!("\ntest result: {} passed; {} failed", options.input_files.len() - failed.len(), failed.len());
+    Ok(failed.is_empty())
+}
 
-    if input_file.is_empty() || output_file.is_empty() {
-        println!("Usage: {} [-koopa|-riscv] <input_file> -o <output_file>", args[0]);
-        std::process::exit(1);
+// `Err` with the reason of a failure, I/O errors only for the test file itself
+fn run_test(compiler: &Compiler, test_file: &str) -> std::io::Result<Result<(), String>> {
+    let source = std::fs::read_to_string(test_file)?;
+    let path = Path::new(test_file);
+    let expected = match std::fs::read_to_string(path.with_extension("out")) {
+        Ok(expected) => expected,
+        Err(_) => return Ok(Err(format!("no expected output, `{}` cannot be read", path.with_extension("out").display()))),
+    };
+    let input = std::fs::read(path.with_extension("in")).unwrap_or_default();
+
+    let program = match compiler.compile_to_program(&source) {
+        Ok(compiled) => compiled.output,
+        Err(error) => return Ok(Err(error.render(test_file, &source))),
+    };
+    let mut output = Vec::new();
+    let status = match interp::run(&program.borrow(), &mut input.as_slice(), &mut output) {
+        Ok(status) => status & 0xff,
+        Err(error) => return Ok(Err(format!("error: {}", error))),
+    };
+
+    // The exit status follows on a line of its own
+    let mut actual = String::from_utf8_lossy(&output).into_owned();
+    if !actual.is_empty() && !actual.ends_with('\n') {
+        actual.push('\n');
+    }
+    actual.push_str(&format!("{}\n", status));
+    if actual.trim_end() == expected.trim_end() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(format!("expected:\n{}\nactual:\n{}", expected.trim_end(), actual.trim_end())))
+    }
+}
+
+// The IR counts are only known once the IR is generated
+fn print_stats(stats: bool, session: &Session, counts: Option<&ProgramCounts>) {
+    if stats {
+        eprint!("{}", session.stats.render(counts));
+    }
+}
+
+// Prints the diagnostics reported so far, returning whether any of them is an error
+fn report_diagnostics(session: &mut Session, input_file: &str, input: &str) -> bool {
+    let has_errors = session.has_errors();
+    for diagnostic in session.take_diagnostics() {
+        eprintln!("{}", diagnostic.render(input_file, input));
+    }
+    has_errors
+}
+
+// `-` stands for the standard input
+fn read_input(path: &str) -> std::io::Result<String> {
+    if path == "-" {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        Ok(input)
+    } else {
+        std::fs::read_to_string(path)
     }
+}
 
-    (mode, input_file, output_file)
-}
\ No newline at end of file
+// `-` stands for the standard output
+fn open_output(path: &str) -> std::io::Result<Box<dyn Write>> {
+    if path == "-" {
+        Ok(Box::new(std::io::stdout().lock()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use koopa::ir::{FunctionData, Value, ValueKind};
+
+// Which addresses of a function may refer to the same memory, for the memory forwarding of
+// `gvn`. An address is traced back through `getelemptr` and `getptr` to its base: a local
+// `alloc`, a global, or a pointer from elsewhere, i.e. a parameter or a loaded pointer, which
+// may point to any global and to the locals whose address escapes. Different bases never
+// overlap, and addresses from the same base only do when their indices may be equal.
+pub struct AliasAnalysis {
+    // Locals whose address, or one computed from it, is stored, passed to a call, returned or
+    // given to a block
+    escaped: HashSet<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+    Local(Value),
+    Global(Value),
+    Unknown,
+}
+
+// A step from the base to an address, with its index when it is an integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Elem(Option<i32>),
+    Ptr(Option<i32>),
+}
+
+impl AliasAnalysis {
+    pub fn compute(func_data: &FunctionData) -> Self {
+        let mut escaped = HashSet::new();
+        let allocs = func_data.layout().bbs().iter()
+            .flat_map(|(_, node)| node.insts().keys().copied())
+            .filter(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Alloc(_)));
+        for alloc in allocs {
+            // The alloc and the addresses computed from it
+            let mut addresses = vec![alloc];
+            while let Some(address) = addresses.pop() {
+                for &user in func_data.dfg().value(address).used_by() {
+                    match func_data.dfg().value(user).kind() {
+                        ValueKind::Load(_) => {}
+                        ValueKind::Store(store) if store.dest() == address && store.value() != address => {}
+                        ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) => addresses.push(user),
+                        _ => {
+                            escaped.insert(alloc);
+                        }
+                    }
+                }
+            }
+        }
+        AliasAnalysis { escaped }
+    }
+
+    pub fn base(func_data: &FunctionData, ptr: Value) -> Base {
+        Self::trace(func_data, ptr).0
+    }
+
+    pub fn may_alias(&self, func_data: &FunctionData, a: Value, b: Value) -> bool {
+        if a == b {
+            return true;
+        }
+        let ((base_a, steps_a), (base_b, steps_b)) = (Self::trace(func_data, a), Self::trace(func_data, b));
+        match (base_a, base_b) {
+            (Base::Unknown, Base::Unknown) | (Base::Unknown, Base::Global(_)) | (Base::Global(_), Base::Unknown) => true,
+            (Base::Unknown, Base::Local(local)) | (Base::Local(local), Base::Unknown) => self.escaped.contains(&local),
+            _ if base_a != base_b => false,
+            // The same base: apart when the steps are alike and some index is known to differ.
+            // A `getptr` after the first step may undo those before it, e.g. `a[0]` + 1 is `a[1]`.
+            _ => {
+                let alike = steps_a.len() == steps_b.len()
+                    && steps_a.iter().skip(1).chain(steps_b.iter().skip(1)).all(|step| matches!(step, Step::Elem(_)))
+                    && steps_b.first().map(std::mem::discriminant) == steps_a.first().map(std::mem::discriminant);
+                let differ = steps_a.iter().zip(steps_b.iter()).any(|pair| match pair {
+                    (Step::Elem(Some(x)), Step::Elem(Some(y))) | (Step::Ptr(Some(x)), Step::Ptr(Some(y))) => x != y,
+                    _ => false,
+                });
+                !(alike && differ)
+            }
+        }
+    }
+
+    // Whether a call may read or write `ptr`: all but the locals whose address does not escape
+    pub fn is_visible_to_calls(&self, func_data: &FunctionData, ptr: Value) -> bool {
+        match Self::base(func_data, ptr) {
+            Base::Local(local) => self.escaped.contains(&local),
+            Base::Global(_) | Base::Unknown => true,
+        }
+    }
+
+    // The base of `ptr` and the steps from it, in order
+    fn trace(func_data: &FunctionData, mut ptr: Value) -> (Base, Vec<Step>) {
+        let integer = |value: Value| match func_data.dfg().values().get(&value).map(|data| data.kind()) {
+            Some(ValueKind::Integer(int)) => Some(int.value()),
+            _ => None,
+        };
+        let mut steps = Vec::new();
+        let base = loop {
+            if ptr.is_global() {
+                break Base::Global(ptr);
+            }
+            match func_data.dfg().value(ptr).kind() {
+                ValueKind::Alloc(_) => break Base::Local(ptr),
+                ValueKind::GetElemPtr(gep) => {
+                    steps.push(Step::Elem(integer(gep.index())));
+                    ptr = gep.src();
+                }
+                ValueKind::GetPtr(gp) => {
+                    steps.push(Step::Ptr(integer(gp.index())));
+                    ptr = gp.src();
+                }
+                _ => break Base::Unknown,
+            }
+        };
+        steps.reverse();
+        (base, steps)
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use koopa::ir::{Function, FunctionData, Program};
+use crate::common::session::Session;
+use crate::opt::alias::AliasAnalysis;
+use crate::opt::call_graph::CallGraph;
+use crate::opt::dominators::DominatorTree;
+use crate::opt::liveness::Liveness;
+use crate::opt::loops::LoopInfo;
+use crate::opt::{OptError, OptPassFunction};
+
+// Keeps the analyses queried by the passes, so that they are only computed again once a pass
+// has changed the function they describe. The call graph is kept up to date instead, only
+// the calls of the changed function are collected again.
+#[derive(Default)]
+pub struct AnalysisManager {
+    functions: HashMap<Function, FunctionCache>,
+    call_graph: Option<CallGraph>,
+}
+
+#[derive(Default)]
+struct FunctionCache {
+    dominators: Option<Rc<DominatorTree>>,
+    liveness: Option<Rc<Liveness>>,
+    loops: Option<Rc<LoopInfo>>,
+    alias: Option<Rc<AliasAnalysis>>,
+}
+
+impl AnalysisManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs `pass` on the body of `func`, returning whether it changed it
+    pub fn run_pass(&mut self, pass: &mut dyn OptPassFunction, program: &mut Program, func: Function, session: &mut Session) -> Result<bool, OptError> {
+        let call_graph = self.call_graph.get_or_insert_with(|| CallGraph::compute(program));
+        let mut analyses = FunctionAnalyses {
+            cache: self.functions.entry(func).or_default(),
+            call_graph,
+        };
+        let changed = pass.run_on(program.func_mut(func), &mut analyses, session)?;
+        if changed {
+            self.functions.remove(&func);
+            self.call_graph.as_mut().unwrap().update(func, program.func(func));
+        }
+        Ok(changed)
+    }
+
+    pub fn dominators(&mut self, program: &Program, func: Function) -> Rc<DominatorTree> {
+        let func_data = program.func(func);
+        self.functions.entry(func).or_default().dominators.get_or_insert_with(|| Rc::new(DominatorTree::compute(func_data))).clone()
+    }
+
+    // For module passes, the function passes being given theirs by `run_pass`
+    pub fn call_graph(&mut self, program: &Program) -> &CallGraph {
+        self.call_graph.get_or_insert_with(|| CallGraph::compute(program))
+    }
+
+    // After a change to any part of the program, e.g. by a module pass
+    pub fn invalidate_all(&mut self) {
+        self.functions.clear();
+        self.call_graph = None;
+    }
+}
+
+// The analyses of the function a pass runs on. They describe the function as it was when
+// queried, a pass querying again after changing it calls `invalidate` first.
+pub struct FunctionAnalyses<'a> {
+    cache: &'a mut FunctionCache,
+    call_graph: &'a CallGraph,
+}
+
+impl FunctionAnalyses<'_> {
+    pub fn dominators(&mut self, func_data: &FunctionData) -> Rc<DominatorTree> {
+        self.cache.dominators.get_or_insert_with(|| Rc::new(DominatorTree::compute(func_data))).clone()
+    }
+
+    pub fn liveness(&mut self, func_data: &FunctionData) -> Rc<Liveness> {
+        self.cache.liveness.get_or_insert_with(|| Rc::new(Liveness::compute(func_data))).clone()
+    }
+
+    pub fn loops(&mut self, func_data: &FunctionData) -> Rc<LoopInfo> {
+        if let Some(loops) = &self.cache.loops {
+            return loops.clone();
+        }
+        let loops = Rc::new(LoopInfo::compute(func_data, &self.dominators(func_data)));
+        self.cache.loops = Some(loops.clone());
+        loops
+    }
+
+    pub fn alias(&mut self, func_data: &FunctionData) -> Rc<AliasAnalysis> {
+        self.cache.alias.get_or_insert_with(|| Rc::new(AliasAnalysis::compute(func_data))).clone()
+    }
+
+    // Up to date but for the changes of the running pass to this function
+    pub fn call_graph(&self) -> &CallGraph {
+        self.call_graph
+    }
+
+    pub fn invalidate(&mut self) {
+        *self.cache = FunctionCache::default();
+    }
+}
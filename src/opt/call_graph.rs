@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use koopa::ir::{Function, FunctionData, Program, ValueKind};
+
+// Which functions each function of a program calls
+pub struct CallGraph {
+    // In the order of the first call, without repetitions
+    callees: HashMap<Function, Vec<Function>>,
+}
+
+impl CallGraph {
+    pub fn compute(program: &Program) -> Self {
+        let mut call_graph = CallGraph { callees: HashMap::new() };
+        for &func in program.func_layout() {
+            call_graph.update(func, program.func(func));
+        }
+        call_graph
+    }
+
+    // Takes the calls of `func` again from its body
+    pub fn update(&mut self, func: Function, func_data: &FunctionData) {
+        let mut callees = Vec::new();
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Call(call) = func_data.dfg().value(inst).kind() {
+                    if !callees.contains(&call.callee()) {
+                        callees.push(call.callee());
+                    }
+                }
+            }
+        }
+        self.callees.insert(func, callees);
+    }
+
+    pub fn callees(&self, func: Function) -> &[Function] {
+        self.callees.get(&func).map_or(&[], Vec::as_slice)
+    }
+
+    // In no particular order
+    pub fn callers(&self, func: Function) -> Vec<Function> {
+        self.callees.iter().filter(|(_, callees)| callees.contains(&func)).map(|(&caller, _)| caller).collect()
+    }
+
+    // Calls nothing, not even the runtime library
+    pub fn is_leaf(&self, func: Function) -> bool {
+        self.callees(func).is_empty()
+    }
+}
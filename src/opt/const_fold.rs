@@ -0,0 +1,116 @@
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::{OptError, OptPassFunction};
+
+// Evaluates `binary` instructions whose operands are both integers. The instruction is turned
+// into the integer in place, so that its uses see the constant and may fold in turn, and it
+// is taken out of its block. Repeats until nothing changes, then turns the branches on an
+// integer into jumps, leaving the blocks no longer reached to `unreachable-blocks`.
+pub struct ConstFoldPass;
+
+impl OptPassFunction for ConstFoldPass {
+    fn name(&self) -> &'static str {
+        "const-fold"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let mut changed = false;
+        loop {
+            let foldable = Self::find_foldable(func_data);
+            if foldable.is_empty() {
+                break;
+            }
+            changed = true;
+            for (bb, inst, result) in foldable {
+                func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                func_data.dfg_mut().replace_value_with(inst).integer(result);
+            }
+        }
+        Ok(Self::fold_branches(func_data) || changed)
+    }
+
+    // A chain of `n` foldable instructions may take `n` rounds
+    fn instruction_limit(&self) -> Option<usize> {
+        Some(200_000)
+    }
+}
+
+impl Default for ConstFoldPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstFoldPass {
+    pub fn new() -> Self {
+        ConstFoldPass
+    }
+
+    // `if (1)`, `while (0)` and the like, known once their conditions are folded
+    fn fold_branches(func_data: &mut FunctionData) -> bool {
+        let branches: Vec<Value> = func_data.layout().bbs().nodes()
+            .filter_map(|node| node.insts().back_key().copied())
+            .collect();
+        let mut changed = false;
+        for inst in branches {
+            let ValueKind::Branch(branch) = func_data.dfg().value(inst).kind() else { continue };
+            let ValueKind::Integer(cond) = func_data.dfg().value(branch.cond()).kind() else { continue };
+            let (target, args) = if cond.value() != 0 {
+                (branch.true_bb(), branch.true_args().to_vec())
+            } else {
+                (branch.false_bb(), branch.false_args().to_vec())
+            };
+            func_data.dfg_mut().replace_value_with(inst).jump_with_args(target, args);
+            changed = true;
+        }
+        changed
+    }
+
+    fn find_foldable(func_data: &FunctionData) -> Vec<(BasicBlock, Value, i32)> {
+        let integer = |value: Value| match func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Some(int.value()),
+            _ => None,
+        };
+        let mut foldable = Vec::new();
+        for (&bb, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Binary(binary) = func_data.dfg().value(inst).kind() {
+                    if let (Some(lhs), Some(rhs)) = (integer(binary.lhs()), integer(binary.rhs())) {
+                        if let Some(result) = evaluate(binary.op(), lhs, rhs) {
+                            foldable.push((bb, inst, result));
+                        }
+                    }
+                }
+            }
+        }
+        foldable
+    }
+}
+
+// Arithmetic wraps around, as on the target. `None` for a division by zero,
+// which is left for the program to run into.
+pub fn evaluate(op: BinaryOp, lhs: i32, rhs: i32) -> Option<i32> {
+    let value = match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div | BinaryOp::Mod if rhs == 0 => return None,
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    };
+    Some(value)
+}
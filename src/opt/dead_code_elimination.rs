@@ -1,100 +1,59 @@
-use std::collections::{HashSet, VecDeque};
-use koopa::ir::{FunctionData, Value, ValueKind};
-use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
-use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
 use crate::opt::{OptError, OptPassFunction};
 
-pub struct DeadCodeEliminationPass {
-    terminators: HashSet<Value>,
-}
+// Removes instructions whose results are never used and that have no side effects.
+// Removing one may leave its operands unused as well, so this repeats until nothing changes.
+pub struct DeadCodeEliminationPass;
 
 impl OptPassFunction for DeadCodeEliminationPass {
-    fn run_on(&mut self, func_data: &mut FunctionData) -> Result<(), OptError> {
-        self.mark(func_data);
-        self.sweep(func_data);
-        Ok(())
-    }
-}
-
-impl DeadCodeEliminationPass {
-    pub fn new() -> Self {
-        DeadCodeEliminationPass {
-            terminators: HashSet::new(),
-        }
+    fn name(&self) -> &'static str {
+        "dce"
     }
 
-    fn mark(&mut self, func_data: &mut FunctionData) {
-        for (value_h, value) in func_data.dfg().values() {
-            if Self::is_terminator(value) {
-                self.terminators.insert(*value_h);
+    // Unused source variables are already reported by the frontend, the IR has no locations
+    fn run_on(&mut self, func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let mut changed = false;
+        loop {
+            let dead = Self::find_dead(func_data);
+            if dead.is_empty() {
+                return Ok(changed);
             }
-        }
-    }
-
-    fn sweep(&mut self, func_data: &mut FunctionData) {
-        let mut worklist = VecDeque::new();
-        let mut bb_worklist = Vec::new();
-
-        let mut bb_cursor = func_data.layout_mut().bbs_mut().cursor_front_mut();
-        while let Some(bb) = bb_cursor.node_mut() {
-            let mut inst_cursor = bb.insts_mut().cursor_front_mut();
-            'inst: while let Some(inst) = inst_cursor.key() {
-                if self.terminators.contains(inst) {
-                    // Remove all the following instructions
-                    inst_cursor.move_next();
-                    while let Some((inst, _)) = inst_cursor.remove_current() {
-                        worklist.push_back(inst);
-                    }
-
-                    // Check if the basic block is empty
-                    drop(inst_cursor);
-
-                    break 'inst;
-                }
-
-                inst_cursor.move_next();
+            changed = true;
+            for (bb, inst) in dead {
+                func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                func_data.dfg_mut().remove_value(inst);
             }
-
-            if !bb.insts().back_key().is_some_and(|inst| self.terminators.contains(inst)) {
-                // The basic block is not terminated by a terminator instruction
-                // They are pushed into a worklist to avoid Rust's borrowing mechanism
-                // Finally, we follow the C++ rule:
-                // "if control reaches the end of the main function, return 0; is executed."
-                bb_worklist.push(bb_cursor.key().unwrap().clone());
-            }
-
-            bb_cursor.move_next();
         }
+    }
 
-        // Remove all the instructions in the worklist, iteratively
-        while let Some(inst) = worklist.pop_front() {
-            if func_data.dfg().value(inst).used_by().is_empty() {
-                // Not referenced by any other instruction, safe to remove
-                drop(func_data.dfg_mut().remove_value(inst));
-            } else {
-                worklist.push_back(inst);
-            }
-        }
+    // A chain of `n` dead instructions takes `n` rounds
+    fn instruction_limit(&self) -> Option<usize> {
+        Some(200_000)
+    }
+}
 
-        // remove empty basic blocks
-        // for bb in bb_worklist {
-        //     drop(func_data.layout_mut().bbs_mut().remove(&bb));
-        // }
+impl Default for DeadCodeEliminationPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        if func_data.name() == "@main" {
-            for bb in bb_worklist {
-                let zero = func_data.dfg_mut().new_value().integer(0).clone();
-                let ret_inst = func_data.dfg_mut().new_value().ret(Some(zero)).clone();
-                let bb_node = func_data.layout_mut().bbs_mut().node_mut(&bb).unwrap();
-                bb_node.insts_mut().push_key_back(ret_inst).unwrap();
-            }
-        }
+impl DeadCodeEliminationPass {
+    pub fn new() -> Self {
+        DeadCodeEliminationPass
     }
 
-    fn is_terminator(inst: &ValueData) -> bool {
-        matches!(
-            inst.kind(),
-            ValueKind::Branch(_) | ValueKind::Return(_) | ValueKind::Jump(_)
-        )
+    fn find_dead(func_data: &FunctionData) -> Vec<(BasicBlock, Value)> {
+        func_data.layout().bbs().iter()
+            .flat_map(|(&bb, node)| node.insts().keys().map(move |&inst| (bb, inst)))
+            .filter(|&(_, inst)| {
+                let value = func_data.dfg().value(inst);
+                // Stores, calls and terminators are kept for their effects
+                let is_pure = matches!(value.kind(), ValueKind::Alloc(_) | ValueKind::Load(_) | ValueKind::Binary(_));
+                is_pure && value.used_by().is_empty()
+            })
+            .collect()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use koopa::ir::{BasicBlock, FunctionData, ValueKind};
+
+// The dominator tree of the blocks reachable from the entry, computed with the iterative
+// algorithm of Cooper, Harvey and Kennedy ("A Simple, Fast Dominance Algorithm"), along with
+// the dominance frontiers of the same paper. Passes get it from `FunctionAnalyses`, which
+// keeps it until the function changes.
+pub struct DominatorTree {
+    // The reachable blocks in reverse postorder, the entry first
+    order: Vec<BasicBlock>,
+    // Immediate dominator of every reachable block but the entry
+    idom: HashMap<BasicBlock, BasicBlock>,
+    children: HashMap<BasicBlock, Vec<BasicBlock>>,
+    frontiers: HashMap<BasicBlock, Vec<BasicBlock>>,
+}
+
+impl DominatorTree {
+    // `func_data` must have a body
+    pub fn compute(func_data: &FunctionData) -> Self {
+        let entry = func_data.layout().entry_bb().expect("a function declaration has no blocks");
+        let order = reverse_postorder(func_data, entry);
+        let index: HashMap<BasicBlock, usize> = order.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); order.len()];
+        for (i, &bb) in order.iter().enumerate() {
+            for succ in successors(func_data, bb) {
+                preds[index[&succ]].push(i);
+            }
+        }
+
+        // Indices into `order`, a dominator always comes before the blocks it dominates
+        let mut idom: Vec<Option<usize>> = vec![None; order.len()];
+        idom[0] = Some(0);
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while a > b {
+                    a = idom[a].unwrap();
+                }
+                while b > a {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 1..order.len() {
+                let mut new_idom = None;
+                for &pred in preds[i].iter().filter(|&&pred| idom[pred].is_some()) {
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, pred, other),
+                    });
+                }
+                if idom[i] != new_idom {
+                    idom[i] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        // A join block is in the frontier of the blocks from each of its predecessors up to,
+        // but not including, its immediate dominator
+        let mut frontiers: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for (i, preds) in preds.iter().enumerate().filter(|(_, preds)| preds.len() > 1) {
+            for &pred in preds {
+                let mut runner = pred;
+                while runner != idom[i].unwrap() {
+                    let frontier = frontiers.entry(order[runner]).or_default();
+                    if !frontier.contains(&order[i]) {
+                        frontier.push(order[i]);
+                    }
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+
+        let mut tree = DominatorTree { order, idom: HashMap::new(), children: HashMap::new(), frontiers };
+        for (i, dominator) in idom.into_iter().enumerate().skip(1) {
+            let (bb, dominator) = (tree.order[i], tree.order[dominator.unwrap()]);
+            tree.idom.insert(bb, dominator);
+            tree.children.entry(dominator).or_default().push(bb);
+        }
+        tree
+    }
+
+    pub fn entry(&self) -> BasicBlock {
+        self.order[0]
+    }
+
+    pub fn reverse_postorder(&self) -> &[BasicBlock] {
+        &self.order
+    }
+
+    // `None` for the entry and the unreachable blocks
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idom.get(&bb).copied()
+    }
+
+    // The blocks immediately dominated by `bb`, in reverse postorder
+    pub fn children(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.children.get(&bb).map_or(&[], Vec::as_slice)
+    }
+
+    // The blocks `bb` does not strictly dominate but dominates a predecessor of, where the
+    // values defined along the paths through `bb` meet others, in reverse postorder
+    pub fn frontier(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.frontiers.get(&bb).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        bb == self.entry() || self.idom.contains_key(&bb)
+    }
+
+    // Every block dominates itself
+    pub fn dominates(&self, a: BasicBlock, mut b: BasicBlock) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(dominator) => b = dominator,
+                None => return false,
+            }
+        }
+    }
+}
+
+// The targets of the terminator of `bb`, none if it returns
+pub fn successors(func_data: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let last = func_data.layout().bbs().node(&bb).and_then(|node| node.insts().back_key());
+    match last.map(|&inst| func_data.dfg().value(inst).kind()) {
+        Some(ValueKind::Branch(branch)) => vec![branch.true_bb(), branch.false_bb()],
+        Some(ValueKind::Jump(jump)) => vec![jump.target()],
+        _ => Vec::new(),
+    }
+}
+
+fn reverse_postorder(func_data: &FunctionData, entry: BasicBlock) -> Vec<BasicBlock> {
+    let mut postorder = Vec::new();
+    let mut visited = vec![entry];
+    // Blocks being visited, with the successors left to visit
+    let mut stack = vec![(entry, successors(func_data, entry))];
+    while let Some((bb, pending)) = stack.last_mut() {
+        match pending.pop() {
+            Some(succ) if !visited.contains(&succ) => {
+                visited.push(succ);
+                stack.push((succ, successors(func_data, succ)));
+            }
+            Some(_) => {}
+            None => {
+                postorder.push(*bb);
+                stack.pop();
+            }
+        }
+    }
+    postorder.reverse();
+    postorder
+}
@@ -0,0 +1,109 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::common::value_number::ExprKey;
+use crate::opt::alias::AliasAnalysis;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::dominators::DominatorTree;
+use crate::opt::{replace_uses, OptError, OptPassFunction};
+
+// Dominator-based global value numbering. Walking the dominator tree from the entry, a
+// `binary`, `getptr` or `getelemptr` computing the same as one in a dominating block, or
+// earlier in its own block, is replaced by it, as told by `ExprKey`.
+// Memory is only followed within a block: a load gives the value last stored to or loaded
+// from the same place, until a call or a store that may write there, as told by `alias`.
+pub struct GlobalValueNumberingPass;
+
+impl OptPassFunction for GlobalValueNumberingPass {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    // Instructions are only removed, the dominator tree stays valid throughout
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let tree = analyses.dominators(func_data);
+        let alias = analyses.alias(func_data);
+        let mut available = HashMap::new();
+        Ok(Self::number_block(func_data, &tree, &alias, tree.entry(), &mut available))
+    }
+
+    // The dominator tree is walked recursively
+    fn instruction_limit(&self) -> Option<usize> {
+        Some(50_000)
+    }
+}
+
+impl Default for GlobalValueNumberingPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GlobalValueNumberingPass {
+    pub fn new() -> Self {
+        GlobalValueNumberingPass
+    }
+
+    // `available` holds the expressions computed in the dominating blocks.
+    // Returns whether an instruction of `bb` or of the blocks it dominates was replaced.
+    fn number_block(
+        func_data: &mut FunctionData,
+        tree: &DominatorTree,
+        alias: &AliasAnalysis,
+        bb: BasicBlock,
+        available: &mut HashMap<ExprKey, Value>,
+    ) -> bool {
+        let mut changed = false;
+        // What is in memory at the current instruction, by address
+        let mut memory: HashMap<Value, Value> = HashMap::new();
+        let mut added = Vec::new();
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            let known = if let Some(key) = ExprKey::of(func_data, inst) {
+                match available.entry(key) {
+                    Entry::Occupied(entry) => Some(*entry.get()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(inst);
+                        added.push(key);
+                        None
+                    }
+                }
+            } else {
+                match func_data.dfg().value(inst).kind().clone() {
+                    ValueKind::Load(load) => match memory.get(&load.src()) {
+                        Some(&value) => Some(value),
+                        None => {
+                            memory.insert(load.src(), inst);
+                            None
+                        }
+                    },
+                    ValueKind::Store(store) => {
+                        memory.retain(|&address, _| !alias.may_alias(func_data, address, store.dest()));
+                        memory.insert(store.dest(), store.value());
+                        None
+                    }
+                    ValueKind::Call(_) => {
+                        memory.retain(|&address, _| !alias.is_visible_to_calls(func_data, address));
+                        None
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(leader) = known {
+                changed = true;
+                replace_uses(func_data, inst, leader);
+                func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                func_data.dfg_mut().remove_value(inst);
+            }
+        }
+
+        for &child in tree.children(bb) {
+            changed |= Self::number_block(func_data, tree, alias, child, available);
+        }
+        for key in added {
+            available.remove(&key);
+        }
+        changed
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::{BasicBlock, FunctionData, Value};
+use crate::opt::dominators::successors;
+
+// The values live at the start and at the end of every block, and through `instructions`, of
+// every instruction. Values are the parameters of the function and of the blocks, and the
+// results of instructions; constants and globals are not tracked.
+pub struct Liveness {
+    live_in: HashMap<BasicBlock, HashSet<Value>>,
+    live_out: HashMap<BasicBlock, HashSet<Value>>,
+}
+
+impl Liveness {
+    pub fn compute(func_data: &FunctionData) -> Self {
+        let mut defined: HashSet<Value> = func_data.params().iter().copied().collect();
+        let bbs: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        for &bb in bbs.iter() {
+            defined.extend(func_data.dfg().bb(bb).params().iter().copied());
+            defined.extend(func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied());
+        }
+
+        // Values read in a block before being defined there, and values defined there
+        let mut uses: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        let mut defs: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        for &bb in bbs.iter() {
+            let (bb_uses, bb_defs) = (uses.entry(bb).or_default(), defs.entry(bb).or_default());
+            bb_defs.extend(func_data.dfg().bb(bb).params().iter().copied());
+            for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                for value in func_data.dfg().value(inst).kind().value_uses() {
+                    if defined.contains(&value) && !bb_defs.contains(&value) {
+                        bb_uses.insert(value);
+                    }
+                }
+                bb_defs.insert(inst);
+            }
+        }
+
+        let mut liveness = Liveness { live_in: HashMap::new(), live_out: HashMap::new() };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // Backwards, so that most blocks see their successors' final sets
+            for &bb in bbs.iter().rev() {
+                let live_out: HashSet<Value> = successors(func_data, bb).iter()
+                    .flat_map(|succ| liveness.live_in.get(succ).into_iter().flatten().copied())
+                    .collect();
+                let mut live_in = uses[&bb].clone();
+                live_in.extend(live_out.difference(&defs[&bb]).copied());
+                if liveness.live_in.get(&bb) != Some(&live_in) {
+                    liveness.live_in.insert(bb, live_in);
+                    changed = true;
+                }
+                liveness.live_out.insert(bb, live_out);
+            }
+        }
+        liveness
+    }
+
+    pub fn live_in(&self, bb: BasicBlock) -> &HashSet<Value> {
+        &self.live_in[&bb]
+    }
+
+    pub fn live_out(&self, bb: BasicBlock) -> &HashSet<Value> {
+        &self.live_out[&bb]
+    }
+
+    // The values live before and after every instruction of `bb`, from the last one back. The
+    // sets of a whole block taking too much room to keep, those of a single instruction are
+    // updated in place, see `InstructionLiveness::next_instruction`.
+    pub fn instructions<'a>(&self, func_data: &'a FunctionData, bb: BasicBlock) -> InstructionLiveness<'a> {
+        InstructionLiveness {
+            func_data,
+            insts: func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect(),
+            inst: None,
+            live_in: self.live_out(bb).clone(),
+            live_out: self.live_out(bb).clone(),
+        }
+    }
+}
+
+pub struct InstructionLiveness<'a> {
+    func_data: &'a FunctionData,
+    // Those not walked yet, the last one at the end
+    insts: Vec<Value>,
+    inst: Option<Value>,
+    // Read by the instruction or live after it, but its result
+    live_in: HashSet<Value>,
+    live_out: HashSet<Value>,
+}
+
+impl InstructionLiveness<'_> {
+    // Moves to the instruction before the current one, the last of the block at first
+    pub fn next_instruction(&mut self) -> Option<Value> {
+        let inst = self.insts.pop()?;
+        // What is live after it is what was live before the instruction following it
+        if let Some(after) = self.inst.replace(inst) {
+            Self::step(self.func_data, &mut self.live_out, after);
+        }
+        Self::step(self.func_data, &mut self.live_in, inst);
+        Some(inst)
+    }
+
+    pub fn live_in(&self) -> &HashSet<Value> {
+        &self.live_in
+    }
+
+    pub fn live_out(&self) -> &HashSet<Value> {
+        &self.live_out
+    }
+
+    // From the values live after `inst` to those live before it
+    fn step(func_data: &FunctionData, live: &mut HashSet<Value>, inst: Value) {
+        live.remove(&inst);
+        live.extend(func_data.dfg().value(inst).kind().value_uses().filter(|&value| is_tracked(func_data, value)));
+    }
+}
+
+fn is_tracked(func_data: &FunctionData, value: Value) -> bool {
+    !value.is_global() && !func_data.dfg().value(value).kind().is_const()
+}
@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::dominators::{successors, DominatorTree};
+use crate::opt::loops::{copy_blocks, insert_block_after, Loop};
+use crate::opt::{map_targets, push_inst, OptError, OptPassFunction};
+
+// Unrolls the innermost loops counting a local variable by a constant step up to a bound,
+// as `while (i < n) { ...; i = i + 1; }` lowers. A loop running a small constant number of
+// times is replaced by that many copies of its body. Otherwise the body is repeated
+// `FACTOR` times after a single check that `FACTOR` more iterations are due, the original
+// loop running the iterations left over.
+pub struct LoopUnrollingPass;
+
+// Copies of the body of a partially unrolled loop
+const FACTOR: i32 = 4;
+// Fully unrolled loops run at most that many times...
+const MAX_FULL_TRIPS: usize = 16;
+// ...and at most that many instructions are copied for a loop
+const MAX_COPIED: usize = 256;
+
+// A loop the pass knows how many times it runs, see `counted_loop`
+struct CountedLoop {
+    header: BasicBlock,
+    // The target of the header while the condition holds, the other one being the exit
+    body: BasicBlock,
+    exit: BasicBlock,
+    // The only block entering the loop, ending with a jump to the header
+    preheader: BasicBlock,
+    // The alloc of the counter, and its value when the loop is entered if known
+    counter: Value,
+    init: Option<i32>,
+    step: i32,
+    // The loop goes on while `counter op bound`
+    op: BinaryOp,
+    bound: Bound,
+}
+
+// A bound unchanged in the loop
+#[derive(Clone, Copy)]
+enum Bound {
+    Integer(i32),
+    // Computed before the loop
+    Value(Value),
+    // Loaded in the header from a variable the loop does not store to
+    Load(Value),
+}
+
+impl OptPassFunction for LoopUnrollingPass {
+    fn name(&self) -> &'static str {
+        "unroll"
+    }
+
+    // Innermost loops share no blocks, so the analyses stay valid for the loops not yet unrolled
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let tree = analyses.dominators(func_data);
+        let loops = analyses.loops(func_data);
+        let mut changed = false;
+        for l in loops.loops() {
+            if !loops.is_innermost(l) {
+                continue;
+            }
+            let Some(counted) = Self::counted_loop(func_data, &tree, l) else { continue };
+            let body: Vec<BasicBlock> = tree.reverse_postorder().iter().copied()
+                .filter(|bb| *bb != l.header && l.blocks.contains(bb))
+                .collect();
+            let size: usize = body.iter().map(|bb| func_data.layout().bbs().node(bb).unwrap().insts().len()).sum();
+            if let Some(trips) = Self::trip_count(&counted).filter(|&trips| trips * size <= MAX_COPIED) {
+                Self::unroll_fully(func_data, l, &counted, &body, trips);
+                changed = true;
+            } else if size * FACTOR as usize <= MAX_COPIED && Self::unroll_partially(func_data, l, &counted, &body) {
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn instruction_limit(&self) -> Option<usize> {
+        Some(50_000)
+    }
+}
+
+impl Default for LoopUnrollingPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoopUnrollingPass {
+    pub fn new() -> Self {
+        LoopUnrollingPass
+    }
+
+    // Recognizes a loop whose header only loads, compares the counter with the bound and
+    // branches, with a single back edge, where the counter is stored once, to itself plus the
+    // step, in a block every iteration reaching the back edge goes through
+    fn counted_loop(func_data: &FunctionData, tree: &DominatorTree, l: &Loop) -> Option<CountedLoop> {
+        let dfg = func_data.dfg();
+        let in_loop = |value: Value| func_data.layout().parent_bb(value).is_some_and(|bb| l.blocks.contains(&bb));
+        if l.latches.len() != 1 || l.blocks.iter().any(|bb| !dfg.bb(*bb).params().is_empty()) {
+            return None;
+        }
+        let latch = l.latches[0];
+        if !matches!(dfg.value(*func_data.layout().bbs().node(&latch)?.insts().back_key()?).kind(), ValueKind::Jump(_)) {
+            return None;
+        }
+
+        // Entered through a jump from a single block
+        let mut preheaders = dfg.bb(l.header).used_by().iter()
+            .filter_map(|&user| func_data.layout().parent_bb(user))
+            .filter(|bb| !l.blocks.contains(bb));
+        let preheader = preheaders.next()?;
+        if preheaders.next().is_some() || successors(func_data, preheader) != [l.header] {
+            return None;
+        }
+
+        // The header: loads, a comparison and the branch, used nowhere else
+        let header_insts: Vec<Value> = func_data.layout().bbs().node(&l.header)?.insts().keys().copied().collect();
+        let (&branch, rest) = header_insts.split_last()?;
+        let ValueKind::Branch(branch) = dfg.value(branch).kind() else { return None };
+        let (body, exit) = (branch.true_bb(), branch.false_bb());
+        if !l.blocks.contains(&body) || l.blocks.contains(&exit) || body == l.header {
+            return None;
+        }
+        let (&compare, loads) = rest.split_last()?;
+        let ValueKind::Binary(compare) = dfg.value(compare).kind() else { return None };
+        if branch.cond() != *rest.last()? {
+            return None;
+        }
+        let header_only = header_insts.iter().all(|inst| {
+            dfg.value(*inst).used_by().iter().all(|user| func_data.layout().parent_bb(*user) == Some(l.header))
+        });
+        if !header_only || !loads.iter().all(|load| matches!(dfg.value(*load).kind(), ValueKind::Load(_))) {
+            return None;
+        }
+
+        // Stores of the loop, and whether it calls anything
+        let mut stores = Vec::new();
+        let mut calls = false;
+        for bb in l.blocks.iter() {
+            for &inst in func_data.layout().bbs().node(bb)?.insts().keys() {
+                match dfg.value(inst).kind() {
+                    ValueKind::Store(store) => stores.push((*bb, store.value(), store.dest())),
+                    ValueKind::Call(_) => calls = true,
+                    _ => {}
+                }
+            }
+        }
+        let stored: HashSet<Value> = stores.iter().map(|&(_, _, dest)| dest).collect();
+        let loaded = |value: Value| match dfg.value(value).kind() {
+            ValueKind::Load(load) if loads.contains(&value) => Some(load.src()),
+            _ => None,
+        };
+
+        // One side of the comparison loads the counter
+        let (counter, other, op) = match (loaded(compare.lhs()), loaded(compare.rhs())) {
+            (Some(src), _) if stored.contains(&src) => (src, compare.rhs(), compare.op()),
+            (_, Some(src)) if stored.contains(&src) => (src, compare.lhs(), swapped(compare.op())?),
+            _ => return None,
+        };
+        if !matches!(op, BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::NotEq) {
+            return None;
+        }
+        // A local variable, nothing else writes to
+        let is_local = dfg.values().get(&counter).is_some_and(|data| matches!(data.kind(), ValueKind::Alloc(_)));
+        if !is_local || in_loop(counter) {
+            return None;
+        }
+
+        let bound = match dfg.value(other).kind() {
+            ValueKind::Integer(int) => Bound::Integer(int.value()),
+            ValueKind::Load(load) if loads.contains(&other) => {
+                let src = load.src();
+                let unchanged = match dfg.values().get(&src) {
+                    Some(data) => matches!(data.kind(), ValueKind::Alloc(_)) && !in_loop(src),
+                    None => !calls,
+                };
+                if !unchanged || stored.contains(&src) {
+                    return None;
+                }
+                Bound::Load(src)
+            }
+            _ if !in_loop(other) => Bound::Value(other),
+            _ => return None,
+        };
+
+        // counter = counter + step, once per iteration
+        let mut counter_stores = stores.iter().filter(|&&(_, _, dest)| dest == counter);
+        let &(store_bb, next, _) = counter_stores.next()?;
+        if counter_stores.next().is_some() || !tree.dominates(store_bb, latch) {
+            return None;
+        }
+        let ValueKind::Binary(update) = dfg.value(next).kind() else { return None };
+        let integer = |value: Value| match dfg.value(value).kind() {
+            ValueKind::Integer(int) => Some(int.value()),
+            _ => None,
+        };
+        let loads_counter = |value: Value| matches!(dfg.value(value).kind(), ValueKind::Load(load) if load.src() == counter);
+        let step = match update.op() {
+            BinaryOp::Add if loads_counter(update.lhs()) => integer(update.rhs())?,
+            BinaryOp::Add if loads_counter(update.rhs()) => integer(update.lhs())?,
+            BinaryOp::Sub if loads_counter(update.lhs()) => integer(update.rhs())?.checked_neg()?,
+            _ => return None,
+        };
+        if step == 0 {
+            return None;
+        }
+
+        // The value stored last before the loop is entered
+        let init = func_data.layout().bbs().node(&preheader)?.insts().keys()
+            .filter_map(|&inst| match dfg.value(inst).kind() {
+                ValueKind::Store(store) if store.dest() == counter => Some(integer(store.value())),
+                _ => None,
+            })
+            .last()
+            .flatten();
+
+        Some(CountedLoop { header: l.header, body, exit, preheader, counter, init, step, op, bound })
+    }
+
+    // The number of iterations, if small enough to unroll all of them
+    fn trip_count(counted: &CountedLoop) -> Option<usize> {
+        let (Some(init), Bound::Integer(bound)) = (counted.init, counted.bound) else { return None };
+        let mut counter = init as i64;
+        let mut trips = 0;
+        while compare(counted.op, counter, bound as i64) {
+            trips += 1;
+            counter += counted.step as i64;
+            // Wrapping around is left to the loop
+            if trips > MAX_FULL_TRIPS || i32::try_from(counter).is_err() {
+                return None;
+            }
+        }
+        Some(trips)
+    }
+
+    // `trips` copies of the body in a row, the last one leaving to the exit
+    fn unroll_fully(func_data: &mut FunctionData, l: &Loop, counted: &CountedLoop, body: &[BasicBlock], trips: usize) {
+        let mut entries = Vec::new();
+        let mut copies = Vec::new();
+        let mut last = l.last_block(func_data);
+        for i in 0..trips {
+            let copy = copy_blocks(func_data, body, &format!("_{}", i), last, |_, _, _, _| None);
+            entries.push(copy[&counted.body]);
+            last = copy[body.last().unwrap()];
+            copies.push(copy);
+        }
+        entries.push(counted.exit);
+        for (i, copy) in copies.iter().enumerate() {
+            Self::retarget(func_data, copy.values(), counted.header, entries[i + 1]);
+        }
+        Self::retarget(func_data, [counted.preheader].iter(), counted.header, entries[0]);
+
+        // Nothing leads to the loop anymore
+        let blocks: Vec<BasicBlock> = l.blocks.iter().copied().collect();
+        Self::remove_blocks(func_data, &blocks);
+    }
+
+    // `FACTOR` copies of the body entered when at least as many iterations are due, the
+    // original loop running the rest. Returns `false` for loops it cannot check that way.
+    fn unroll_partially(func_data: &mut FunctionData, l: &Loop, counted: &CountedLoop, body: &[BasicBlock]) -> bool {
+        // The counter after `FACTOR - 1` more steps must not wrap around, and still satisfy the
+        // condition, which then held at all the steps before
+        let (op, no_wrap) = match counted.op {
+            BinaryOp::Lt | BinaryOp::Le if counted.step > 0 => (counted.op, BinaryOp::Gt),
+            BinaryOp::Gt | BinaryOp::Ge if counted.step < 0 => (counted.op, BinaryOp::Lt),
+            _ => return false,
+        };
+        let Some(advance) = counted.step.checked_mul(FACTOR - 1) else { return false };
+
+        let name = func_data.dfg().bb(counted.header).name().as_ref().map(|name| format!("{}_unrolled", name));
+        let check = func_data.dfg_mut().new_bb().basic_block(name);
+        insert_block_after(func_data, check, l.last_block(func_data));
+        let mut copies: Vec<HashMap<BasicBlock, BasicBlock>> = Vec::new();
+        let mut last = check;
+        for i in 0..FACTOR {
+            let copy = copy_blocks(func_data, body, &format!("_{}", i), last, |_, _, _, _| None);
+            last = copy[body.last().unwrap()];
+            copies.push(copy);
+        }
+
+        let counter = func_data.dfg_mut().new_value().load(counted.counter);
+        let counter = push_inst(func_data, check, counter);
+        let bound = match counted.bound {
+            Bound::Integer(value) => func_data.dfg_mut().new_value().integer(value),
+            Bound::Value(value) => value,
+            Bound::Load(src) => {
+                let load = func_data.dfg_mut().new_value().load(src);
+                push_inst(func_data, check, load)
+            }
+        };
+        let advance = func_data.dfg_mut().new_value().integer(advance);
+        let last = func_data.dfg_mut().new_value().binary(BinaryOp::Add, counter, advance);
+        let last = push_inst(func_data, check, last);
+        let due = func_data.dfg_mut().new_value().binary(op, last, bound);
+        let due = push_inst(func_data, check, due);
+        let no_wrap = func_data.dfg_mut().new_value().binary(no_wrap, last, counter);
+        let no_wrap = push_inst(func_data, check, no_wrap);
+        let cond = func_data.dfg_mut().new_value().binary(BinaryOp::And, due, no_wrap);
+        let cond = push_inst(func_data, check, cond);
+        let branch = func_data.dfg_mut().new_value().branch(cond, copies[0][&counted.body], counted.header);
+        push_inst(func_data, check, branch);
+
+        for (i, copy) in copies.iter().enumerate() {
+            let next = copies.get(i + 1).map_or(check, |next| next[&counted.body]);
+            Self::retarget(func_data, copy.values(), counted.header, next);
+        }
+        Self::retarget(func_data, [counted.preheader].iter(), counted.header, check);
+        true
+    }
+
+    // Makes the terminators of `blocks` go to `to` instead of `from`
+    fn retarget<'a>(func_data: &mut FunctionData, blocks: impl Iterator<Item = &'a BasicBlock>, from: BasicBlock, to: BasicBlock) {
+        for &bb in blocks {
+            let &term = func_data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+            let mut data = func_data.dfg().value(term).clone();
+            map_targets(data.kind_mut(), |target| if *target == from { *target = to });
+            func_data.dfg_mut().replace_value_with(term).raw(data);
+        }
+    }
+
+    // Removes unreachable blocks whose values are only used among them
+    fn remove_blocks(func_data: &mut FunctionData, blocks: &[BasicBlock]) {
+        let mut insts = Vec::new();
+        for bb in blocks {
+            let (_, node) = func_data.layout_mut().bbs_mut().remove(bb).unwrap();
+            insts.extend(node.insts().keys().copied());
+        }
+        // Users first
+        while !insts.is_empty() {
+            let (unused, used): (Vec<Value>, Vec<Value>) = insts.iter()
+                .partition(|&&inst| func_data.dfg().value(inst).used_by().is_empty());
+            assert!(!unused.is_empty(), "removed blocks use each other's values in a cycle");
+            for inst in unused {
+                func_data.dfg_mut().remove_value(inst);
+            }
+            insts = used;
+        }
+        for &bb in blocks {
+            func_data.dfg_mut().remove_bb(bb);
+        }
+    }
+}
+
+// The comparison with its operands exchanged
+fn swapped(op: BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::Lt => Some(BinaryOp::Gt),
+        BinaryOp::Gt => Some(BinaryOp::Lt),
+        BinaryOp::Le => Some(BinaryOp::Ge),
+        BinaryOp::Ge => Some(BinaryOp::Le),
+        BinaryOp::Eq | BinaryOp::NotEq => Some(op),
+        _ => None,
+    }
+}
+
+fn compare(op: BinaryOp, lhs: i64, rhs: i64) -> bool {
+    match op {
+        BinaryOp::Lt => lhs < rhs,
+        BinaryOp::Le => lhs <= rhs,
+        BinaryOp::Gt => lhs > rhs,
+        BinaryOp::Ge => lhs >= rhs,
+        _ => lhs != rhs,
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::dominators::{successors, DominatorTree};
+use crate::opt::loops::{copy_blocks, insert_block_after, Loop};
+use crate::opt::{map_targets, push_inst, OptError, OptPassFunction};
+
+// Innermost loops taking a remainder by a divisor that does not change in the loop get a
+// second copy, entered when the divisor is a positive power of two. There the `mod` becomes a
+// mask, corrected for negative dividends, instead of a division, which is slow on the target.
+// The check runs once before the loop, the original loop is kept for the other divisors.
+pub struct LoopVersioningPass;
+
+// Loops with more instructions are not copied
+const MAX_LOOP_SIZE: usize = 200;
+
+// A divisor unchanged in a loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Divisor {
+    // Computed before the loop
+    Value(Value),
+    // Loaded in the loop from a variable the loop does not store to
+    Load(Value),
+}
+
+impl OptPassFunction for LoopVersioningPass {
+    fn name(&self) -> &'static str {
+        "loop-version"
+    }
+
+    // Innermost loops share no blocks, so the analyses stay valid for the loops not yet copied
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let tree = analyses.dominators(func_data);
+        let loops = analyses.loops(func_data);
+        let mut changed = false;
+        for l in loops.loops() {
+            if !loops.is_innermost(l) || !Self::can_copy(func_data, l) {
+                continue;
+            }
+            if let Some(divisor) = Self::find_divisor(func_data, l) {
+                Self::version(func_data, &tree, l, divisor);
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn instruction_limit(&self) -> Option<usize> {
+        Some(50_000)
+    }
+}
+
+impl Default for LoopVersioningPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoopVersioningPass {
+    pub fn new() -> Self {
+        LoopVersioningPass
+    }
+
+    // Small enough, entered from outside, and with nothing but memory carrying its values out
+    fn can_copy(func_data: &FunctionData, l: &Loop) -> bool {
+        if func_data.layout().entry_bb() == Some(l.header) {
+            return false;
+        }
+        let mut size = 0;
+        for &bb in l.blocks.iter() {
+            if !func_data.dfg().bb(bb).params().is_empty() {
+                return false;
+            }
+            for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                size += 1;
+                let escapes = func_data.dfg().value(inst).used_by().iter()
+                    .any(|user| !Self::in_loop(func_data, l, *user));
+                if escapes {
+                    return false;
+                }
+            }
+        }
+        size <= MAX_LOOP_SIZE
+    }
+
+    fn in_loop(func_data: &FunctionData, l: &Loop, inst: Value) -> bool {
+        func_data.layout().parent_bb(inst).is_some_and(|bb| l.blocks.contains(&bb))
+    }
+
+    // The divisor of the first remainder in the loop that can be versioned on
+    fn find_divisor(func_data: &FunctionData, l: &Loop) -> Option<Divisor> {
+        let (stored, calls) = Self::writes(func_data, l);
+        for (&bb, node) in func_data.layout().bbs() {
+            if !l.blocks.contains(&bb) {
+                continue;
+            }
+            for &inst in node.insts().keys() {
+                if let Some(divisor) = Self::divisor_of(func_data, l, inst, &stored, calls) {
+                    return Some(divisor);
+                }
+            }
+        }
+        None
+    }
+
+    // The variables stored to in the loop, and whether it calls anything
+    fn writes(func_data: &FunctionData, l: &Loop) -> (HashSet<Value>, bool) {
+        let mut stored = HashSet::new();
+        let mut calls = false;
+        for &bb in l.blocks.iter() {
+            for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Store(store) => { stored.insert(store.dest()); }
+                    ValueKind::Call(_) => calls = true,
+                    _ => {}
+                }
+            }
+        }
+        (stored, calls)
+    }
+
+    // The divisor of `inst` if it is a remainder by a value unchanged in the loop
+    fn divisor_of(func_data: &FunctionData, l: &Loop, inst: Value, stored: &HashSet<Value>, calls: bool) -> Option<Divisor> {
+        let ValueKind::Binary(binary) = func_data.dfg().value(inst).kind() else { return None };
+        if binary.op() != BinaryOp::Mod {
+            return None;
+        }
+        let rhs = binary.rhs();
+        match func_data.dfg().value(rhs).kind() {
+            ValueKind::Integer(_) => None,
+            ValueKind::Load(load) if Self::in_loop(func_data, l, rhs) => {
+                let src = load.src();
+                // Scalar variables have no other names, a store writes them only through them.
+                // Globals may also be written by the functions called.
+                let unchanged = match func_data.dfg().values().get(&src) {
+                    Some(data) => matches!(data.kind(), ValueKind::Alloc(_)) && !Self::in_loop(func_data, l, src),
+                    None => !calls,
+                };
+                (unchanged && !stored.contains(&src)).then_some(Divisor::Load(src))
+            }
+            _ if !Self::in_loop(func_data, l, rhs) => Some(Divisor::Value(rhs)),
+            _ => None,
+        }
+    }
+
+    fn version(func_data: &mut FunctionData, tree: &DominatorTree, l: &Loop, divisor: Divisor) {
+        // Taken before anything is added, the conditions are the same in the copy
+        let (stored, calls) = Self::writes(func_data, l);
+        let mut fast = HashSet::new();
+        for &bb in l.blocks.iter() {
+            for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                if Self::divisor_of(func_data, l, inst, &stored, calls) == Some(divisor) {
+                    fast.insert(inst);
+                }
+            }
+        }
+
+        // Definitions come before their uses in reverse postorder
+        let blocks: Vec<BasicBlock> = tree.reverse_postorder().iter().copied().filter(|bb| l.blocks.contains(bb)).collect();
+        let entering: Vec<(BasicBlock, Value)> = func_data.layout().bbs().iter()
+            .filter(|(bb, _)| !l.blocks.contains(bb))
+            .filter_map(|(&bb, node)| node.insts().back_key().map(|&term| (bb, term)))
+            .filter(|&(bb, _)| successors(func_data, bb).contains(&l.header))
+            .collect();
+
+        let check_name = func_data.dfg().bb(l.header).name().as_ref().map(|name| format!("{}_check", name));
+        let check = func_data.dfg_mut().new_bb().basic_block(check_name);
+        insert_block_after(func_data, check, l.last_block(func_data));
+
+        for (_, term) in entering {
+            let mut data = func_data.dfg().value(term).clone();
+            map_targets(data.kind_mut(), |target| if *target == l.header { *target = check });
+            func_data.dfg_mut().replace_value_with(term).raw(data);
+        }
+
+        // if (d > 0 && (d & (d - 1)) == 0), with the mask `d - 1` kept for the copy
+        let d = match divisor {
+            Divisor::Value(value) => value,
+            Divisor::Load(src) => {
+                let load = func_data.dfg_mut().new_value().load(src);
+                push_inst(func_data, check, load)
+            }
+        };
+        let mask = Self::binary(func_data, check, BinaryOp::Sub, d, 1);
+        let bits = func_data.dfg_mut().new_value().binary(BinaryOp::And, d, mask);
+        let bits = push_inst(func_data, check, bits);
+        let single_bit = Self::binary(func_data, check, BinaryOp::Eq, bits, 0);
+        let positive = Self::binary(func_data, check, BinaryOp::Gt, d, 0);
+        let cond = func_data.dfg_mut().new_value().binary(BinaryOp::And, single_bit, positive);
+        let cond = push_inst(func_data, check, cond);
+
+        let copies = copy_blocks(func_data, &blocks, "_pow2", check, |func_data, copy, inst, data| match data.kind() {
+            ValueKind::Binary(binary) if fast.contains(&inst) => Some(Self::masked_rem(func_data, copy, binary.lhs(), d, mask)),
+            _ => None,
+        });
+        let branch = func_data.dfg_mut().new_value().branch(cond, copies[&l.header], l.header);
+        push_inst(func_data, check, branch);
+    }
+
+    // x % d for d a power of two: the low bits of x, less d for a negative x with any set,
+    // as the remainder takes the sign of the dividend. The borrow of 0 or 1 selects d through
+    // a mask of all zeros or all ones, a multiplication being a call without the M extension.
+    fn masked_rem(func_data: &mut FunctionData, bb: BasicBlock, x: Value, d: Value, mask: Value) -> Value {
+        let low = func_data.dfg_mut().new_value().binary(BinaryOp::And, x, mask);
+        let low = push_inst(func_data, bb, low);
+        let negative = Self::binary(func_data, bb, BinaryOp::Lt, x, 0);
+        let nonzero = Self::binary(func_data, bb, BinaryOp::NotEq, low, 0);
+        let borrow = func_data.dfg_mut().new_value().binary(BinaryOp::And, negative, nonzero);
+        let borrow = push_inst(func_data, bb, borrow);
+        let zero = func_data.dfg_mut().new_value().integer(0);
+        let select = func_data.dfg_mut().new_value().binary(BinaryOp::Sub, zero, borrow);
+        let select = push_inst(func_data, bb, select);
+        let adjust = func_data.dfg_mut().new_value().binary(BinaryOp::And, select, d);
+        let adjust = push_inst(func_data, bb, adjust);
+        let rem = func_data.dfg_mut().new_value().binary(BinaryOp::Sub, low, adjust);
+        push_inst(func_data, bb, rem)
+    }
+
+    // `lhs op rhs` for an integer `rhs`, at the end of `bb`
+    fn binary(func_data: &mut FunctionData, bb: BasicBlock, op: BinaryOp, lhs: Value, rhs: i32) -> Value {
+        let rhs = func_data.dfg_mut().new_value().integer(rhs);
+        let inst = func_data.dfg_mut().new_value().binary(op, lhs, rhs);
+        push_inst(func_data, bb, inst)
+    }
+}
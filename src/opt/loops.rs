@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, FunctionData, Value};
+use crate::opt::dominators::{successors, DominatorTree};
+use crate::opt::{map_operands, map_targets, push_inst};
+
+// The natural loops of a function. An edge to a block dominating its source is a back edge,
+// the loop of a header is made of the blocks reaching one of its back edges without going
+// through the header. Loops sharing a header are one loop.
+pub struct LoopInfo {
+    // Outer loops before the loops nested in them
+    loops: Vec<Loop>,
+}
+
+pub struct Loop {
+    pub header: BasicBlock,
+    // The header included
+    pub blocks: HashSet<BasicBlock>,
+    // The sources of the back edges
+    pub latches: Vec<BasicBlock>,
+}
+
+impl Loop {
+    // The block of the loop laid out last, after which copies of the loop are laid out
+    pub fn last_block(&self, func_data: &FunctionData) -> BasicBlock {
+        *func_data.layout().bbs().keys().filter(|bb| self.blocks.contains(bb)).last().unwrap()
+    }
+}
+
+impl LoopInfo {
+    pub fn compute(func_data: &FunctionData, dominators: &DominatorTree) -> Self {
+        let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        let mut latches: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for &bb in dominators.reverse_postorder() {
+            for succ in successors(func_data, bb) {
+                preds.entry(succ).or_default().push(bb);
+                if dominators.dominates(succ, bb) {
+                    latches.entry(succ).or_default().push(bb);
+                }
+            }
+        }
+
+        // Headers come in reverse postorder, an outer header before the inner ones
+        let mut loops = Vec::new();
+        for &header in dominators.reverse_postorder() {
+            let Some(latches) = latches.remove(&header) else { continue };
+            let mut blocks = HashSet::from([header]);
+            let mut pending = latches.clone();
+            while let Some(bb) = pending.pop() {
+                if blocks.insert(bb) {
+                    pending.extend(preds.get(&bb).into_iter().flatten().copied());
+                }
+            }
+            loops.push(Loop { header, blocks, latches });
+        }
+        LoopInfo { loops }
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    // The number of loops containing `bb`, 0 outside of loops
+    pub fn depth(&self, bb: BasicBlock) -> usize {
+        self.loops.iter().filter(|l| l.blocks.contains(&bb)).count()
+    }
+
+    // Whether no other loop is nested in `l`. Innermost loops share no blocks.
+    pub fn is_innermost(&self, l: &Loop) -> bool {
+        !self.loops.iter().any(|inner| inner.header != l.header && l.blocks.contains(&inner.header))
+    }
+
+    // The innermost loop containing `bb`
+    pub fn innermost(&self, bb: BasicBlock) -> Option<&Loop> {
+        self.loops.iter().rev().find(|l| l.blocks.contains(&bb))
+    }
+}
+
+// Copies `blocks`, given with definitions before uses, laid out in that order after `after`,
+// naming the copies with `suffix`. Edges between them go to the copies, the others are kept. `rewrite`
+// may put in the copy of a block something else than the copy of an instruction, given with
+// its operands already mapped, returning what stands for it.
+pub fn copy_blocks(
+    func_data: &mut FunctionData,
+    blocks: &[BasicBlock],
+    suffix: &str,
+    after: BasicBlock,
+    mut rewrite: impl FnMut(&mut FunctionData, BasicBlock, Value, &ValueData) -> Option<Value>,
+) -> HashMap<BasicBlock, BasicBlock> {
+    let mut copies = HashMap::new();
+    let mut last = after;
+    for &bb in blocks {
+        let name = func_data.dfg().bb(bb).name().as_ref().map(|name| format!("{}{}", name, suffix));
+        let copy = func_data.dfg_mut().new_bb().basic_block(name);
+        insert_block_after(func_data, copy, last);
+        copies.insert(bb, copy);
+        last = copy;
+    }
+    let mut values: HashMap<Value, Value> = HashMap::new();
+    for &bb in blocks {
+        let copy = copies[&bb];
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            let mut data = func_data.dfg().value(inst).clone();
+            map_operands(data.kind_mut(), |value| if let Some(&new) = values.get(value) { *value = new });
+            map_targets(data.kind_mut(), |target| if let Some(&new) = copies.get(target) { *target = new });
+            let new = match rewrite(func_data, copy, inst, &data) {
+                Some(new) => new,
+                None => {
+                    let new = func_data.dfg_mut().new_value().raw(data);
+                    // Names are left to the originals
+                    func_data.dfg_mut().set_value_name(new, None);
+                    push_inst(func_data, copy, new)
+                }
+            };
+            values.insert(inst, new);
+        }
+    }
+    copies
+}
+
+// Lays out the new block `bb` right after `after`. The backend needs the definitions of
+// values laid out before their uses: blocks added after the last block of a loop follow every
+// definition the loop uses, which blocks added at the end of the function or before one of
+// its exits may not.
+pub fn insert_block_after(func_data: &mut FunctionData, bb: BasicBlock, after: BasicBlock) {
+    func_data.layout_mut().bbs_mut().cursor_mut(after).insert_key_after(bb).unwrap();
+}
@@ -1,12 +1,190 @@
-use koopa::ir::FunctionData;
+use std::time::Duration;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Program, Value, ValueKind};
+use crate::common::session::Session;
 
+pub mod alias;
+pub mod analysis;
+pub mod call_graph;
+pub mod const_fold;
 pub mod dead_code_elimination;
+pub mod dominators;
+pub mod gvn;
+pub mod liveness;
+pub mod loop_unrolling;
+pub mod loop_versioning;
+pub mod loops;
+pub mod pass_manager;
+pub mod simplify_cfg;
+pub mod unreachable_blocks;
+pub mod verify;
+
+use analysis::FunctionAnalyses;
+use pass_manager::{PassManager, PassSpec, Pipeline};
 
 #[derive(Debug)]
 pub enum OptError {
     Unimplemented,
+    // The IR broke an invariant after the pass, `None` for the IR given to the first one.
+    // The message comes from `verify`.
+    InvalidIr { pass: Option<&'static str>, message: String },
+}
+
+impl std::fmt::Display for OptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptError::Unimplemented => write!(f, "unimplemented optimization"),
+            OptError::InvalidIr { pass: Some(pass), message } => write!(f, "invalid IR after `{}`: {}", pass, message),
+            OptError::InvalidIr { pass: None, message } => write!(f, "invalid IR before optimization: {}", message),
+        }
+    }
 }
 
+impl std::error::Error for OptError {}
+
 pub trait OptPassFunction {
-    fn run_on(&mut self, func_data: &mut FunctionData) -> Result<(), OptError>;
-}
\ No newline at end of file
+    // Short name shown by `--print-passes`
+    fn name(&self) -> &'static str;
+
+    // Returns whether the function was changed, dropping its cached analyses.
+    // Lints found by the pass are reported to `session`.
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, session: &mut Session) -> Result<bool, OptError>;
+
+    // Functions with more instructions are left alone, for passes whose cost grows faster
+    // than the size of the function
+    fn instruction_limit(&self) -> Option<usize> {
+        None
+    }
+
+    // Names of the passes this one relies on having run before it, added to a pipeline that
+    // lacks them, see `PassManager::add`
+    fn requires(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+// Bounds on the work of the optimizer, so that huge inputs are compiled without some of the
+// passes rather than not at all. The skipped work is reported as a warning.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptLimits {
+    // For all the passes together. A running pass is not interrupted, the time is checked
+    // before each function.
+    pub timeout: Option<Duration>,
+    // Replaces the instruction limit of every pass
+    pub instruction_limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl std::fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptLevel::O0 => write!(f, "-O0"),
+            OptLevel::O1 => write!(f, "-O1"),
+            OptLevel::O2 => write!(f, "-O2"),
+        }
+    }
+}
+
+// The passes run at `level`, in order. `-O0` runs nothing, the IR from the frontend is
+// already well-formed. `-O2` is the place for the passes too expensive for `-O1`.
+pub fn pipeline(level: OptLevel) -> Pipeline {
+    let mut passes = Vec::new();
+    if level >= OptLevel::O1 {
+        passes.push(PassSpec::Pass("const-fold"));
+        passes.push(PassSpec::Pass("unreachable-blocks"));
+    }
+    if level >= OptLevel::O2 {
+        // Longer blocks for the block-local memory forwarding of `gvn`
+        passes.push(PassSpec::Pass("simplify-cfg"));
+        passes.push(PassSpec::Pass("unroll"));
+        // The copies of an unrolled body follow each other
+        passes.push(PassSpec::Pass("simplify-cfg"));
+        passes.push(PassSpec::Pass("loop-version"));
+        // Loads replaced by the integers stored before them make more to fold, and folded
+        // branches leave fewer paths to the loads
+        passes.push(PassSpec::FixedPoint(vec![
+            PassSpec::Pass("gvn"),
+            PassSpec::Pass("const-fold"),
+            PassSpec::Pass("unreachable-blocks"),
+        ]));
+    }
+    if level >= OptLevel::O1 {
+        passes.push(PassSpec::Pass("dce"));
+    }
+    Pipeline(passes)
+}
+
+// Makes every user of `old` use `new` instead
+pub fn replace_uses(func_data: &mut FunctionData, old: Value, new: Value) {
+    let users: Vec<Value> = func_data.dfg().value(old).used_by().iter().copied().collect();
+    for user in users {
+        let mut data = func_data.dfg().value(user).clone();
+        map_operands(data.kind_mut(), |value| if *value == old { *value = new });
+        func_data.dfg_mut().replace_value_with(user).raw(data);
+    }
+}
+
+// Appends `inst` to `bb`, returning it
+pub fn push_inst(func_data: &mut FunctionData, bb: BasicBlock, inst: Value) -> Value {
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+    inst
+}
+
+// Calls `f` on every value operand of an instruction, block arguments included
+pub fn map_operands(kind: &mut ValueKind, mut f: impl FnMut(&mut Value)) {
+    match kind {
+        ValueKind::Load(load) => f(load.src_mut()),
+        ValueKind::Store(store) => {
+            f(store.value_mut());
+            f(store.dest_mut());
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            f(get_ptr.src_mut());
+            f(get_ptr.index_mut());
+        }
+        ValueKind::GetElemPtr(get_elem_ptr) => {
+            f(get_elem_ptr.src_mut());
+            f(get_elem_ptr.index_mut());
+        }
+        ValueKind::Binary(binary) => {
+            f(binary.lhs_mut());
+            f(binary.rhs_mut());
+        }
+        ValueKind::Branch(branch) => {
+            f(branch.cond_mut());
+            branch.true_args_mut().iter_mut().for_each(&mut f);
+            branch.false_args_mut().iter_mut().for_each(&mut f);
+        }
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(f),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(f),
+        ValueKind::Return(ret) => {
+            if let Some(value) = ret.value_mut() {
+                f(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Calls `f` on every block a terminator goes to
+pub fn map_targets(kind: &mut ValueKind, mut f: impl FnMut(&mut BasicBlock)) {
+    match kind {
+        ValueKind::Branch(branch) => {
+            f(branch.true_bb_mut());
+            f(branch.false_bb_mut());
+        }
+        ValueKind::Jump(jump) => f(jump.target_mut()),
+        _ => {}
+    }
+}
+
+// Runs `pipeline` on every function of `program`, see `PassManager`
+pub fn run_pipeline(program: &mut Program, pipeline: &Pipeline, limits: &OptLimits, session: &mut Session) -> Result<(), OptError> {
+    PassManager::from_pipeline(pipeline).run(program, limits, session)
+}
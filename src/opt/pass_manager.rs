@@ -0,0 +1,352 @@
+use std::time::Instant;
+use koopa::ir::{Function, Program};
+use crate::common::diagnostic::Diagnostic;
+use crate::common::session::Session;
+use crate::opt::analysis::AnalysisManager;
+use crate::opt::const_fold::ConstFoldPass;
+use crate::opt::dead_code_elimination::DeadCodeEliminationPass;
+use crate::opt::gvn::GlobalValueNumberingPass;
+use crate::opt::loop_unrolling::LoopUnrollingPass;
+use crate::opt::loop_versioning::LoopVersioningPass;
+use crate::opt::simplify_cfg::SimplifyCfgPass;
+use crate::opt::unreachable_blocks::UnreachableBlockEliminationPass;
+use crate::opt::verify::verify_function;
+use crate::opt::{OptError, OptLimits, OptPassFunction};
+
+// A pass over the whole program at once, e.g. across calls. The analyses of every function
+// are dropped when it changes the program.
+pub trait ModulePass {
+    fn name(&self) -> &'static str;
+
+    // Returns whether the program was changed
+    fn run(&mut self, program: &mut Program, analyses: &mut AnalysisManager, session: &mut Session) -> Result<bool, OptError>;
+
+    // See `OptPassFunction::requires`
+    fn requires(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+pub enum Pass {
+    // Run on every function with a body, in the order of the program
+    Function(Box<dyn OptPassFunction>),
+    Module(Box<dyn ModulePass>),
+    // Runs its passes again while any of them changes the program, at most `MAX_ROUNDS` times
+    FixedPoint(Vec<Pass>),
+}
+
+const MAX_ROUNDS: usize = 8;
+
+impl Pass {
+    // `None` for a group
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Pass::Function(pass) => Some(pass.name()),
+            Pass::Module(pass) => Some(pass.name()),
+            Pass::FixedPoint(_) => None,
+        }
+    }
+
+    fn requires(&self) -> &'static [&'static str] {
+        match self {
+            Pass::Function(pass) => pass.requires(),
+            Pass::Module(pass) => pass.requires(),
+            Pass::FixedPoint(_) => &[],
+        }
+    }
+}
+
+// The passes known by name, for `--passes`
+pub const PASS_NAMES: [&str; 7] = ["const-fold", "unreachable-blocks", "simplify-cfg", "unroll", "loop-version", "gvn", "dce"];
+
+pub fn create_pass(name: &str) -> Option<Pass> {
+    let pass: Box<dyn OptPassFunction> = match name {
+        "const-fold" => Box::new(ConstFoldPass::new()),
+        "unreachable-blocks" => Box::new(UnreachableBlockEliminationPass::new()),
+        "simplify-cfg" => Box::new(SimplifyCfgPass::new()),
+        "unroll" => Box::new(LoopUnrollingPass::new()),
+        "loop-version" => Box::new(LoopVersioningPass::new()),
+        "gvn" => Box::new(GlobalValueNumberingPass::new()),
+        "dce" => Box::new(DeadCodeEliminationPass::new()),
+        _ => return None,
+    };
+    Some(Pass::Function(pass))
+}
+
+// A pipeline by the names of its passes, as `--passes` takes it: names separated by commas,
+// a group repeated to a fixed point in parentheses, e.g. `const-fold,(gvn,const-fold),dce`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline(pub Vec<PassSpec>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassSpec {
+    Pass(&'static str),
+    FixedPoint(Vec<PassSpec>),
+}
+
+impl std::str::FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut rest = text;
+        Ok(Pipeline(parse_specs(&mut rest, 0)?))
+    }
+}
+
+// Up to the end of `text` or of the group at `depth`, leaving `text` at its `)`
+fn parse_specs(text: &mut &str, depth: usize) -> Result<Vec<PassSpec>, String> {
+    let mut specs = Vec::new();
+    loop {
+        *text = text.trim_start();
+        if let Some(group) = text.strip_prefix('(') {
+            *text = group;
+            let passes = parse_specs(text, depth + 1)?;
+            *text = text.strip_prefix(')').ok_or("unclosed `(` in passes")?;
+            specs.push(PassSpec::FixedPoint(passes));
+        } else {
+            let end = text.find([',', '(', ')']).unwrap_or(text.len());
+            let name = text[..end].trim();
+            if name.is_empty() {
+                return Err("missing pass name in passes".into());
+            }
+            let name = PASS_NAMES.iter().find(|&&known| known == name)
+                .ok_or_else(|| format!("unknown pass `{}`, expected one of: {}", name, PASS_NAMES.join(", ")))?;
+            specs.push(PassSpec::Pass(name));
+            *text = &text[end..];
+        }
+        *text = text.trim_start();
+        match text.chars().next() {
+            Some(',') => *text = &text[1..],
+            Some(')') if depth > 0 => return Ok(specs),
+            None if depth == 0 => return Ok(specs),
+            None => return Err("unclosed `(` in passes".into()),
+            Some(')') => return Err("unbalanced `)` in passes".into()),
+            Some(_) => return Err("passes must be separated by `,`".into()),
+        }
+    }
+}
+
+impl std::fmt::Display for PassSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PassSpec::Pass(name) => write!(f, "{}", name),
+            PassSpec::FixedPoint(passes) => {
+                let passes: Vec<String> = passes.iter().map(|pass| pass.to_string()).collect();
+                write!(f, "({})", passes.join(","))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let passes: Vec<String> = self.0.iter().map(|pass| pass.to_string()).collect();
+        write!(f, "{}", passes.join(","))
+    }
+}
+
+// Runs passes in order, each of them after the passes it requires. The analyses of a function
+// are kept from one pass to the next until one changes it, see `AnalysisManager`.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+    // The names of `passes`, groups included
+    scheduled: Vec<&'static str>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_pipeline(pipeline: &Pipeline) -> Self {
+        fn instantiate(spec: &PassSpec) -> Pass {
+            match spec {
+                PassSpec::Pass(name) => create_pass(name).expect("the names of a pipeline are checked"),
+                PassSpec::FixedPoint(specs) => Pass::FixedPoint(specs.iter().map(instantiate).collect()),
+            }
+        }
+        let mut manager = Self::new();
+        for spec in pipeline.0.iter() {
+            manager.add(instantiate(spec));
+        }
+        manager
+    }
+
+    // Adds `pass` at the end, preceded by the passes it requires that do not come earlier
+    pub fn add(&mut self, pass: Pass) {
+        schedule(pass, &mut self.scheduled, &mut self.passes);
+    }
+
+    // The passes to run, the required ones included
+    pub fn pipeline(&self) -> Pipeline {
+        fn spec(pass: &Pass) -> PassSpec {
+            match pass {
+                Pass::FixedPoint(passes) => PassSpec::FixedPoint(passes.iter().map(spec).collect()),
+                pass => PassSpec::Pass(pass.name().unwrap()),
+            }
+        }
+        Pipeline(self.passes.iter().map(spec).collect())
+    }
+
+    pub fn run(&mut self, program: &mut Program, limits: &OptLimits, session: &mut Session) -> Result<(), OptError> {
+        let mut run = Run {
+            analyses: AnalysisManager::new(),
+            limits,
+            start: Instant::now(),
+            timed_out: false,
+            skipped: Vec::new(),
+        };
+        run.verify(None, program, None)?;
+        run.passes(&mut self.passes, program, session)?;
+        for Skipped { func, size, passes } in run.skipped {
+            let passes: Vec<String> = passes.iter().map(|(name, limit)| format!("`{}` (limit {})", name, limit)).collect();
+            session.report(Diagnostic::warning(format!(
+                "optimization passes skipped for `{}`, which has {} instructions: {}",
+                &program.func(func).name()[1..], size, passes.join(", "),
+            ), None));
+        }
+        Ok(())
+    }
+}
+
+fn schedule(pass: Pass, scheduled: &mut Vec<&'static str>, passes: &mut Vec<Pass>) {
+    match pass {
+        Pass::FixedPoint(group) => {
+            let mut inner = Vec::new();
+            for pass in group {
+                schedule(pass, scheduled, &mut inner);
+            }
+            passes.push(Pass::FixedPoint(inner));
+        }
+        pass => {
+            for &required in pass.requires() {
+                if !scheduled.contains(&required) {
+                    schedule(create_pass(required).expect("required passes are known by name"), scheduled, passes);
+                }
+            }
+            scheduled.push(pass.name().unwrap());
+            passes.push(pass);
+        }
+    }
+}
+
+// A function too large for some of the passes, with the names and limits of these
+struct Skipped {
+    func: Function,
+    size: usize,
+    passes: Vec<(&'static str, usize)>,
+}
+
+// The state of `PassManager::run`
+struct Run<'a> {
+    analyses: AnalysisManager,
+    limits: &'a OptLimits,
+    start: Instant,
+    // Nothing runs after the timeout
+    timed_out: bool,
+    skipped: Vec<Skipped>,
+}
+
+impl Run<'_> {
+    // Returns whether any of `passes` changed the program
+    fn passes(&mut self, passes: &mut [Pass], program: &mut Program, session: &mut Session) -> Result<bool, OptError> {
+        let mut changed = false;
+        for pass in passes.iter_mut() {
+            if self.timed_out {
+                break;
+            }
+            changed |= match pass {
+                Pass::Function(pass) => self.function_pass(pass.as_mut(), program, session)?,
+                Pass::Module(pass) => self.module_pass(pass.as_mut(), program, session)?,
+                Pass::FixedPoint(group) => {
+                    let mut changed = false;
+                    for _ in 0..MAX_ROUNDS {
+                        if !self.passes(group, program, session)? || self.timed_out {
+                            break;
+                        }
+                        changed = true;
+                    }
+                    changed
+                }
+            };
+        }
+        Ok(changed)
+    }
+
+    fn function_pass(&mut self, pass: &mut dyn OptPassFunction, program: &mut Program, session: &mut Session) -> Result<bool, OptError> {
+        let start = Instant::now();
+        let mut changed = false;
+        for func_h in program.func_layout().to_vec() {
+            let func_data = program.func(func_h);
+            // Library functions are only declared
+            if func_data.layout().entry_bb().is_none() {
+                continue;
+            }
+            let name = &func_data.name()[1..];
+            if self.check_timeout(pass.name(), Some(name), session) {
+                break;
+            }
+            let size: usize = func_data.layout().bbs().nodes().map(|node| node.insts().len()).sum();
+            if let Some(limit) = self.limits.instruction_limit.or(pass.instruction_limit()).filter(|&limit| size > limit) {
+                let index = match self.skipped.iter().position(|skipped| skipped.func == func_h) {
+                    Some(index) => index,
+                    None => {
+                        self.skipped.push(Skipped { func: func_h, size, passes: Vec::new() });
+                        self.skipped.len() - 1
+                    }
+                };
+                if !self.skipped[index].passes.contains(&(pass.name(), limit)) {
+                    self.skipped[index].passes.push((pass.name(), limit));
+                }
+                continue;
+            }
+            if self.analyses.run_pass(pass, program, func_h, session)? {
+                changed = true;
+                self.verify(Some(pass.name()), program, Some(func_h))?;
+            }
+        }
+        session.stats.record(&format!("opt: {}", pass.name()), start.elapsed());
+        Ok(changed)
+    }
+
+    fn module_pass(&mut self, pass: &mut dyn ModulePass, program: &mut Program, session: &mut Session) -> Result<bool, OptError> {
+        if self.check_timeout(pass.name(), None, session) {
+            return Ok(false);
+        }
+        let start = Instant::now();
+        let changed = pass.run(program, &mut self.analyses, session)?;
+        if changed {
+            self.analyses.invalidate_all();
+            self.verify(Some(pass.name()), program, None)?;
+        }
+        session.stats.record(&format!("opt: {}", pass.name()), start.elapsed());
+        Ok(changed)
+    }
+
+    // Checks `func`, or every function, after `pass` changed it. The dominators are those the
+    // next pass would compute, they are kept for it.
+    fn verify(&mut self, pass: Option<&'static str>, program: &Program, func: Option<Function>) -> Result<(), OptError> {
+        let funcs = match func {
+            Some(func) => vec![func],
+            None => program.func_layout().iter().copied().filter(|&func| program.func(func).layout().entry_bb().is_some()).collect(),
+        };
+        for func in funcs {
+            let dominators = self.analyses.dominators(program, func);
+            verify_function(program, func, &dominators).map_err(|message| OptError::InvalidIr { pass, message })?;
+        }
+        Ok(())
+    }
+
+    // Checked before each function, a running pass is not interrupted
+    fn check_timeout(&mut self, pass: &str, func: Option<&str>, session: &mut Session) -> bool {
+        let Some(timeout) = self.limits.timeout.filter(|&timeout| self.start.elapsed() > timeout) else { return false };
+        let from = func.map(|func| format!(" from `{}` on", func)).unwrap_or_default();
+        session.report(Diagnostic::warning(format!(
+            "optimization took longer than {}ms, `{}` and the passes after it are skipped{}",
+            timeout.as_millis(), pass, from,
+        ), None));
+        self.timed_out = true;
+        true
+    }
+}
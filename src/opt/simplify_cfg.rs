@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::{map_targets, OptError, OptPassFunction};
+
+// Cleans up the blocks left by the lowering of `if`, `while` and `&&`/`||`. Edges to a block
+// holding nothing but a jump go to the target of that jump instead, and a block jumping to a
+// successor with no other predecessor takes in its instructions. Repeats until nothing changes.
+pub struct SimplifyCfgPass;
+
+impl OptPassFunction for SimplifyCfgPass {
+    fn name(&self) -> &'static str {
+        "simplify-cfg"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let mut changed = false;
+        loop {
+            let threaded = Self::thread_jumps(func_data);
+            let merged = Self::merge_blocks(func_data);
+            if !threaded && !merged {
+                return Ok(changed);
+            }
+            changed = true;
+        }
+    }
+}
+
+impl Default for SimplifyCfgPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimplifyCfgPass {
+    pub fn new() -> Self {
+        SimplifyCfgPass
+    }
+
+    // Removes the blocks only forwarding to another one, after redirecting the edges to them
+    fn thread_jumps(func_data: &mut FunctionData) -> bool {
+        let entry = func_data.layout().entry_bb();
+        let bbs: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let mut changed = false;
+        for bb in bbs {
+            if Some(bb) == entry {
+                continue;
+            }
+            // The jump of a block threaded earlier may go here now, `target` is read again
+            let Some(target) = Self::forwarding_target(func_data, bb) else { continue };
+            let users: Vec<Value> = func_data.dfg().bb(bb).used_by().iter().copied().collect();
+            for user in users {
+                let mut data = func_data.dfg().value(user).clone();
+                map_targets(data.kind_mut(), |succ| if *succ == bb { *succ = target });
+                func_data.dfg_mut().replace_value_with(user).raw(data);
+            }
+            Self::remove_block(func_data, bb);
+            changed = true;
+        }
+        changed
+    }
+
+    // The target of `bb` if it is a block with nothing but a jump, without arguments, elsewhere
+    fn forwarding_target(func_data: &FunctionData, bb: BasicBlock) -> Option<BasicBlock> {
+        if !func_data.dfg().bb(bb).params().is_empty() {
+            return None;
+        }
+        let insts = func_data.layout().bbs().node(&bb).unwrap().insts();
+        if insts.len() != 1 {
+            return None;
+        }
+        match func_data.dfg().value(*insts.front_key().unwrap()).kind() {
+            ValueKind::Jump(jump) if jump.args().is_empty() && jump.target() != bb => Some(jump.target()),
+            _ => None,
+        }
+    }
+
+    // Appends to a block ending in a jump the block it jumps to, when it is its only predecessor
+    // and laid out after it. Blocks in between may use the values of the successor, which the
+    // backend needs defined before their uses in layout order.
+    fn merge_blocks(func_data: &mut FunctionData) -> bool {
+        let entry = func_data.layout().entry_bb();
+        let bbs: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let position: HashMap<BasicBlock, usize> = bbs.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let mut changed = false;
+        for bb in bbs {
+            // Gone into a block before it
+            if func_data.layout().bbs().node(&bb).is_none() {
+                continue;
+            }
+            while let Some((jump, succ)) = Self::sole_successor(func_data, bb) {
+                if Some(succ) == entry || position[&succ] < position[&bb] {
+                    break;
+                }
+                func_data.layout_mut().bb_mut(bb).insts_mut().remove(&jump);
+                func_data.dfg_mut().remove_value(jump);
+                let insts: Vec<Value> = func_data.layout().bbs().node(&succ).unwrap().insts().keys().copied().collect();
+                for inst in insts {
+                    func_data.layout_mut().bb_mut(succ).insts_mut().remove(&inst);
+                    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+                }
+                Self::remove_block(func_data, succ);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // The jump ending `bb` and its target, if nothing else goes there
+    fn sole_successor(func_data: &FunctionData, bb: BasicBlock) -> Option<(Value, BasicBlock)> {
+        let &jump = func_data.layout().bbs().node(&bb).unwrap().insts().back_key()?;
+        let ValueKind::Jump(data) = func_data.dfg().value(jump).kind() else { return None };
+        let succ = data.target();
+        let succ_data = func_data.dfg().bb(succ);
+        let only_edge = succ_data.used_by().len() == 1 && succ_data.used_by().contains(&jump);
+        (succ != bb && only_edge && data.args().is_empty() && succ_data.params().is_empty()).then_some((jump, succ))
+    }
+
+    fn remove_block(func_data: &mut FunctionData, bb: BasicBlock) {
+        let (_, node) = func_data.layout_mut().bbs_mut().remove(&bb).unwrap();
+        for &inst in node.insts().keys() {
+            func_data.dfg_mut().remove_value(inst);
+        }
+        func_data.dfg_mut().remove_bb(bb);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use koopa::ir::{BasicBlock, FunctionData, Value};
+use crate::common::session::Session;
+use crate::opt::analysis::FunctionAnalyses;
+use crate::opt::{OptError, OptPassFunction};
+
+// Removes the blocks not reachable from the entry, such as the bodies of `if (0)` and
+// `while (0)` once `const-fold` has turned their branches into jumps.
+pub struct UnreachableBlockEliminationPass;
+
+impl OptPassFunction for UnreachableBlockEliminationPass {
+    fn name(&self) -> &'static str {
+        "unreachable-blocks"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let dominators = analyses.dominators(func_data);
+        let unreachable: Vec<BasicBlock> = func_data.layout().bbs().keys().copied()
+            .filter(|&bb| !dominators.is_reachable(bb))
+            .collect();
+        if unreachable.is_empty() {
+            return Ok(false);
+        }
+        let mut insts: Vec<Value> = unreachable.iter()
+            .flat_map(|bb| func_data.layout().bbs().node(bb).unwrap().insts().keys().copied())
+            .collect();
+        // A value of a dead block reaching a live one means the IR does not follow dominance,
+        // it is left as it is
+        let dead: HashSet<Value> = insts.iter().copied().collect();
+        if insts.iter().any(|&inst| func_data.dfg().value(inst).used_by().iter().any(|user| !dead.contains(user))) {
+            return Ok(false);
+        }
+
+        for bb in unreachable.iter() {
+            func_data.layout_mut().bbs_mut().remove(bb);
+        }
+        // Users first, the values of the dead blocks use each other without cycles
+        while !insts.is_empty() {
+            let (unused, used): (Vec<Value>, Vec<Value>) = insts.iter()
+                .partition(|&&inst| func_data.dfg().value(inst).used_by().is_empty());
+            assert!(!unused.is_empty(), "unreachable blocks use each other's values in a cycle");
+            for inst in unused {
+                func_data.dfg_mut().remove_value(inst);
+            }
+            insts = used;
+        }
+        for bb in unreachable {
+            func_data.dfg_mut().remove_bb(bb);
+        }
+        Ok(true)
+    }
+
+    // Blocks are left unreachable as it folds branches
+    fn requires(&self) -> &'static [&'static str] {
+        &["const-fold"]
+    }
+}
+
+impl Default for UnreachableBlockEliminationPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UnreachableBlockEliminationPass {
+    pub fn new() -> Self {
+        UnreachableBlockEliminationPass
+    }
+}
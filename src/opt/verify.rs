@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use crate::ir::library;
+use crate::opt::dominators::DominatorTree;
+use crate::opt::map_operands;
+
+// Checks the invariants the passes and the backend rely on, so that a pass breaking them is
+// caught right after it runs: every block ends in its only terminator, whose targets are
+// blocks of the function given as many arguments as they have parameters, every value is
+// defined before its uses (in a block dominating theirs, and laid out before it, as the
+// backend generates the blocks in layout order), and calls match the signature of their
+// callee, followed by `i32`s for a variadic one, see `ir::library`. `Err` describes the first
+// violation found.
+pub fn verify_function(program: &Program, func: Function, dominators: &DominatorTree) -> Result<(), String> {
+    let func_data = program.func(func);
+    let context = |message: String| format!("in `{}`, {}", func_data.name(), message);
+    let params: HashMap<Value, BasicBlock> = func_data.dfg().bbs().iter()
+        .flat_map(|(&bb, data)| data.params().iter().map(move |&param| (param, bb)))
+        .collect();
+    let order: HashMap<BasicBlock, usize> = func_data.layout().bbs().keys().enumerate().map(|(i, &bb)| (bb, i)).collect();
+
+    for (&bb, node) in func_data.layout().bbs() {
+        let name = block_name(func_data, bb);
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        let Some((&last, body)) = insts.split_last() else {
+            return Err(context(format!("block `{}` is empty", name)));
+        };
+        if !is_terminator(func_data.dfg().value(last).kind()) {
+            return Err(context(format!("block `{}` does not end in a terminator", name)));
+        }
+        if body.iter().any(|&inst| is_terminator(func_data.dfg().value(inst).kind())) {
+            return Err(context(format!("block `{}` has a terminator before its end", name)));
+        }
+        check_targets(func_data, last).map_err(|message| context(format!("block `{}` {}", name, message)))?;
+
+        // The uses in unreachable blocks have nothing to be dominated by
+        if !dominators.is_reachable(bb) {
+            continue;
+        }
+        let positions: HashMap<Value, usize> = insts.iter().enumerate().map(|(index, &inst)| (inst, index)).collect();
+        for (index, &inst) in insts.iter().enumerate() {
+            let mut kind = func_data.dfg().value(inst).kind().clone();
+            let mut operands = Vec::new();
+            map_operands(&mut kind, |&mut operand| operands.push(operand));
+            for operand in operands {
+                check_definition(func_data, &params, dominators, &order, (bb, &positions, index), operand)
+                    .map_err(|message| context(format!("{} in block `{}` {}", describe(func_data, inst), name, message)))?;
+            }
+            if let ValueKind::Call(call) = func_data.dfg().value(inst).kind() {
+                check_call(program, func_data, inst, call.callee(), call.args())
+                    .map_err(|message| context(format!("{} in block `{}` {}", describe(func_data, inst), name, message)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Every function with a body, for tools and tests
+pub fn verify_program(program: &Program) -> Result<(), String> {
+    for &func in program.func_layout() {
+        if program.func(func).layout().entry_bb().is_some() {
+            verify_function(program, func, &DominatorTree::compute(program.func(func)))?;
+        }
+    }
+    Ok(())
+}
+
+fn is_terminator(kind: &ValueKind) -> bool {
+    matches!(kind, ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_))
+}
+
+fn check_targets(func_data: &FunctionData, terminator: Value) -> Result<(), String> {
+    let targets = match func_data.dfg().value(terminator).kind() {
+        ValueKind::Branch(branch) => vec![(branch.true_bb(), branch.true_args()), (branch.false_bb(), branch.false_args())],
+        ValueKind::Jump(jump) => vec![(jump.target(), jump.args())],
+        _ => Vec::new(),
+    };
+    for (target, args) in targets {
+        if func_data.layout().bbs().node(&target).is_none() {
+            return Err("goes to a block not in the function".into());
+        }
+        let params = func_data.dfg().bb(target).params().len();
+        if args.len() != params {
+            return Err(format!("gives {} arguments to `{}`, which has {} parameters", args.len(), block_name(func_data, target), params));
+        }
+    }
+    Ok(())
+}
+
+// `operand` of the instruction at `index` in `bb`, whose instructions are at `positions`,
+// the blocks being at `order` in the layout
+fn check_definition(
+    func_data: &FunctionData,
+    params: &HashMap<Value, BasicBlock>,
+    dominators: &DominatorTree,
+    order: &HashMap<BasicBlock, usize>,
+    (bb, positions, index): (BasicBlock, &HashMap<Value, usize>, usize),
+    operand: Value,
+) -> Result<(), String> {
+    // Globals are not in the function
+    let Some(data) = func_data.dfg().values().get(&operand) else { return Ok(()) };
+    let def_bb = match data.kind() {
+        ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) | ValueKind::Aggregate(_) | ValueKind::FuncArgRef(_) => return Ok(()),
+        ValueKind::BlockArgRef(_) => match params.get(&operand) {
+            Some(&def_bb) => def_bb,
+            None => return Err("uses a parameter of no block".into()),
+        },
+        _ => match func_data.layout().parent_bb(operand) {
+            Some(def_bb) if def_bb == bb => {
+                return match positions.get(&operand) {
+                    Some(&position) if position < index => Ok(()),
+                    _ => Err(format!("uses {} before it is defined", describe(func_data, operand))),
+                };
+            }
+            Some(def_bb) => def_bb,
+            None => return Err(format!("uses {}, which is in no block", describe(func_data, operand))),
+        },
+    };
+    if !dominators.dominates(def_bb, bb) {
+        Err(format!("uses {} of block `{}`, which does not dominate it", describe(func_data, operand), block_name(func_data, def_bb)))
+    } else if order[&def_bb] > order[&bb] {
+        Err(format!("uses {} of block `{}`, which is laid out after it", describe(func_data, operand), block_name(func_data, def_bb)))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_call(program: &Program, func_data: &FunctionData, inst: Value, callee: Function, args: &[Value]) -> Result<(), String> {
+    let callee_data = program.func(callee);
+    let TypeKind::Function(params, ret) = callee_data.ty().kind() else { unreachable!() };
+    let variadic = library::is_variadic(callee_data);
+    if args.len() < params.len() || (args.len() > params.len() && !variadic) {
+        return Err(format!("gives {} arguments to `{}`, which takes {}", args.len(), callee_data.name(), params.len()));
+    }
+    let int = Type::get_i32();
+    for (i, (&arg, param)) in args.iter().zip(params.iter().chain(std::iter::repeat(&int))).enumerate() {
+        let ty = value_type(program, func_data, arg);
+        if ty != *param {
+            return Err(format!("gives a `{}` as argument {} of `{}`, which takes a `{}`", ty, i + 1, callee_data.name(), param));
+        }
+    }
+    let ty = func_data.dfg().value(inst).ty();
+    if ty != ret {
+        return Err(format!("has type `{}`, but `{}` returns `{}`", ty, callee_data.name(), ret));
+    }
+    Ok(())
+}
+
+fn value_type(program: &Program, func_data: &FunctionData, value: Value) -> Type {
+    match func_data.dfg().values().get(&value) {
+        Some(data) => data.ty().clone(),
+        None => program.borrow_value(value).ty().clone(),
+    }
+}
+
+fn block_name(func_data: &FunctionData, bb: BasicBlock) -> String {
+    func_data.dfg().bb(bb).name().clone().unwrap_or_else(|| format!("{:?}", bb))
+}
+
+// The name of a value if it has one, its kind otherwise
+fn describe(func_data: &FunctionData, value: Value) -> String {
+    let data = func_data.dfg().value(value);
+    match data.name() {
+        Some(name) => format!("`{}`", name),
+        None => format!("a `{}`", kind_name(data.kind())),
+    }
+}
+
+fn kind_name(kind: &ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Alloc(_) => "alloc",
+        ValueKind::Load(_) => "load",
+        ValueKind::Store(_) => "store",
+        ValueKind::GetPtr(_) => "getptr",
+        ValueKind::GetElemPtr(_) => "getelemptr",
+        ValueKind::Binary(_) => "binary",
+        ValueKind::Branch(_) => "br",
+        ValueKind::Jump(_) => "jump",
+        ValueKind::Call(_) => "call",
+        ValueKind::Return(_) => "ret",
+        _ => "value",
+    }
+}
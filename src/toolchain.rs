@@ -0,0 +1,101 @@
+// The RISC-V toolchain of the course environment, to run compiled programs natively:
+// `clang` assembles, `ld.lld` links with the runtime library found under
+// `$CDE_LIBRARY_PATH/riscv32`, and `qemu-riscv32-static` runs the executable.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub struct Toolchain {
+    clang: PathBuf,
+    linker: PathBuf,
+    emulator: PathBuf,
+    // Holds `libsysy.a`
+    library_dir: PathBuf,
+}
+
+impl Toolchain {
+    // `Err` names what is missing
+    pub fn find() -> Result<Toolchain, String> {
+        let clang = find_program(&["clang"]);
+        let linker = find_program(&["ld.lld"]);
+        let emulator = find_program(&["qemu-riscv32-static", "qemu-riscv32"]);
+        let library_dir = std::env::var_os("CDE_LIBRARY_PATH")
+            .map(|dir| PathBuf::from(dir).join("riscv32"))
+            .filter(|dir| dir.join("libsysy.a").is_file());
+        match (clang, linker, emulator, library_dir) {
+            (Some(clang), Some(linker), Some(emulator), Some(library_dir)) => Ok(Toolchain { clang, linker, emulator, library_dir }),
+            (clang, linker, emulator, library_dir) => {
+                let mut missing = Vec::new();
+                if clang.is_none() {
+                    missing.push("`clang`");
+                }
+                if linker.is_none() {
+                    missing.push("`ld.lld`");
+                }
+                if emulator.is_none() {
+                    missing.push("`qemu-riscv32-static`");
+                }
+                if library_dir.is_none() {
+                    missing.push("`libsysy.a` in `$CDE_LIBRARY_PATH/riscv32`");
+                }
+                Err(format!("no RISC-V toolchain found, missing {}", missing.join(", ")))
+            }
+        }
+    }
+
+    // Builds `assembly` into an executable and runs it with the standard streams of the
+    // compiler, returning its exit status. `Err` for a failure to build or start it.
+    pub fn run(&self, assembly: &[u8], verbose: bool) -> Result<i32, String> {
+        let dir = std::env::temp_dir().join(format!("sysy-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|error| format!("cannot create `{}`: {}", dir.display(), error))?;
+        let result = self.build_and_run(&dir, assembly, verbose);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    fn build_and_run(&self, dir: &Path, assembly: &[u8], verbose: bool) -> Result<i32, String> {
+        let (source, object, executable) = (dir.join("program.S"), dir.join("program.o"), dir.join("program"));
+        std::fs::write(&source, assembly).map_err(|error| format!("cannot write `{}`: {}", source.display(), error))?;
+
+        let mut assemble = Command::new(&self.clang);
+        assemble.arg(&source).arg("-c").arg("-o").arg(&object)
+            .args(["-target", "riscv32-unknown-linux-elf", "-march=rv32im", "-mabi=ilp32"]);
+        run_step(assemble, verbose)?;
+        let mut link = Command::new(&self.linker);
+        link.arg(&object).arg("-L").arg(&self.library_dir).arg("-lsysy").arg("-o").arg(&executable);
+        run_step(link, verbose)?;
+
+        if verbose {
+            eprintln!("Running: {} {}", self.emulator.display(), executable.display());
+        }
+        let status = Command::new(&self.emulator).arg(&executable).status()
+            .map_err(|error| format!("cannot run `{}`: {}", self.emulator.display(), error))?;
+        // Killed by a signal, as a shell reports it
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Ok(128 + signal);
+        }
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+// A step of the build, its output shown only if it fails
+fn run_step(mut command: Command, verbose: bool) -> Result<(), String> {
+    if verbose {
+        eprintln!("Running: {:?}", command);
+    }
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.stdin(Stdio::null()).output().map_err(|error| format!("cannot run `{}`: {}", program, error))?;
+    if !output.status.success() {
+        return Err(format!("`{}` failed:\n{}", program, String::from_utf8_lossy(&output.stderr).trim_end()));
+    }
+    Ok(())
+}
+
+// The first of `names` found on `PATH`
+fn find_program(names: &[&str]) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    names.iter()
+        .flat_map(|name| std::env::split_paths(&path).map(move |dir| dir.join(name)))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+// An interned string: identifiers, labels and generated names are compared, hashed and
+// copied as a single integer. The text is kept for the whole run of the compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Name(u32);
+
+struct Interner {
+    names: HashMap<&'static str, Name>,
+    strings: Vec<&'static str>,
+}
+
+// Shared by all the threads, as the compiler may be embedded in a multithreaded host
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| Mutex::new(Interner { names: HashMap::new(), strings: Vec::new() }))
+}
+
+impl Name {
+    pub fn intern(string: &str) -> Self {
+        let mut interner = interner().lock().unwrap();
+        if let Some(&name) = interner.names.get(string) {
+            return name;
+        }
+        let string: &'static str = Box::leak(string.to_owned().into_boxed_str());
+        let name = Name(interner.strings.len() as u32);
+        interner.strings.push(string);
+        interner.names.insert(string, name);
+        name
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().lock().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl From<&str> for Name {
+    fn from(string: &str) -> Self {
+        Name::intern(string)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
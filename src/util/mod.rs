@@ -1 +1,2 @@
+pub mod interner;
 pub mod name_generator;
\ No newline at end of file
@@ -1,9 +1,17 @@
+use crate::util::interner::Name;
+
 // A unique name generator for basic block
 
 pub struct NameGenerator {
     counter: u32,
 }
 
+impl Default for NameGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NameGenerator {
     pub fn new() -> Self {
         NameGenerator {
@@ -11,17 +19,17 @@ impl NameGenerator {
         }
     }
 
-    pub fn generate_group(&mut self, prefixes: &[&str]) -> Vec<String> {
-        let group: Vec<String> = prefixes
+    pub fn generate_group(&mut self, prefixes: &[&str]) -> Vec<Name> {
+        let group: Vec<Name> = prefixes
             .iter()
-            .map(|prefix| format!("{}{}", prefix, self.counter))
+            .map(|prefix| Name::intern(&format!("{}{}", prefix, self.counter)))
             .collect();
         self.counter += 1;
         group
     }
 
-    pub fn generate(&mut self, prefix: &str) -> String {
-        let name = format!("{}{}", prefix, self.counter);
+    pub fn generate(&mut self, prefix: &str) -> Name {
+        let name = Name::intern(&format!("{}{}", prefix, self.counter));
         self.counter += 1;
         name
     }
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+use koopa::ir::{BasicBlock, FunctionData, Program};
+use sysy_compiler::common::session::Session;
+use sysy_compiler::ir::builder::{func, ProgramBuilder};
+use sysy_compiler::opt::analysis::{AnalysisManager, FunctionAnalyses};
+use sysy_compiler::opt::call_graph::CallGraph;
+use sysy_compiler::opt::const_fold::ConstFoldPass;
+use sysy_compiler::opt::dominators::DominatorTree;
+use sysy_compiler::opt::liveness::Liveness;
+use sysy_compiler::opt::loops::LoopInfo;
+use sysy_compiler::opt::{OptError, OptPassFunction};
+
+fn block(func_data: &FunctionData, name: &str) -> BasicBlock {
+    *func_data.dfg().bbs().iter()
+        .find(|(_, data)| data.name().as_deref() == Some(&format!("%{}", name)))
+        .unwrap_or_else(|| panic!("no block `{}`", name)).0
+}
+
+// for (i = 0; i < n; i++) for (j = 0; j < n; j++) work(j); with a block nothing jumps to
+fn nested_loops() -> Program {
+    ProgramBuilder::new()
+        .declare("work", 1, false)
+        .func(func("main").param("n")
+            .block("entry", |b| {
+                b.store(b.int(0), b.alloc("i"));
+                b.alloc("j");
+                b.jump("outer");
+            })
+            .block("outer", |b| b.branch(b.lt(b.load(b.local("i")), b.param("n")), "outer_body", "end"))
+            .block("outer_body", |b| {
+                b.store(b.int(0), b.local("j"));
+                b.jump("inner");
+            })
+            .block("inner", |b| {
+                let j = b.load(b.local("j"));
+                b.branch(b.lt(j, b.param("n")), "inner_body", "outer_latch");
+            })
+            .block("inner_body", |b| {
+                let j = b.load(b.local("j"));
+                b.call("work", &[j]);
+                b.store(b.add(j, b.int(1)), b.local("j"));
+                b.jump("inner");
+            })
+            .block("outer_latch", |b| {
+                b.store(b.add(b.load(b.local("i")), b.int(1)), b.local("i"));
+                b.jump("outer");
+            })
+            .block("end", |b| b.ret(b.int(0)))
+            .block("dead", |b| b.jump("end")))
+        .build()
+}
+
+fn main_of(program: &Program) -> &FunctionData {
+    program.funcs().values().find(|data| data.name() == "@main").unwrap()
+}
+
+#[test]
+fn dominators_of_nested_loops() {
+    let program = nested_loops();
+    let func_data = main_of(&program);
+    let tree = DominatorTree::compute(func_data);
+    let bb = |name: &str| block(func_data, name);
+    assert_eq!(tree.entry(), bb("entry"));
+    assert_eq!(tree.idom(bb("outer")), Some(bb("entry")));
+    assert_eq!(tree.idom(bb("inner")), Some(bb("outer_body")));
+    assert_eq!(tree.idom(bb("outer_latch")), Some(bb("inner")));
+    assert_eq!(tree.idom(bb("end")), Some(bb("outer")));
+    assert!(tree.dominates(bb("outer"), bb("inner_body")) && !tree.dominates(bb("inner_body"), bb("outer_latch")));
+    assert!(!tree.is_reachable(bb("dead")) && tree.idom(bb("dead")).is_none());
+    assert_eq!(tree.reverse_postorder().len(), 7);
+}
+
+#[test]
+fn dominance_frontiers_of_nested_loops() {
+    let program = nested_loops();
+    let func_data = main_of(&program);
+    let tree = DominatorTree::compute(func_data);
+    let bb = |name: &str| block(func_data, name);
+    // The loop headers, where the values changed in the loops meet those from before them
+    assert_eq!(tree.frontier(bb("inner_body")), &[bb("inner")]);
+    assert_eq!(tree.frontier(bb("inner")), &[bb("outer"), bb("inner")]);
+    assert_eq!(tree.frontier(bb("outer_latch")), &[bb("outer")]);
+    assert_eq!(tree.frontier(bb("outer")), &[bb("outer")]);
+    assert!(tree.frontier(bb("entry")).is_empty() && tree.frontier(bb("end")).is_empty() && tree.frontier(bb("dead")).is_empty());
+}
+
+#[test]
+fn loops_are_nested() {
+    let program = nested_loops();
+    let func_data = main_of(&program);
+    let loops = LoopInfo::compute(func_data, &DominatorTree::compute(func_data));
+    let bb = |name: &str| block(func_data, name);
+    let blocks = |names: &[&str]| names.iter().map(|&name| bb(name)).collect::<HashSet<_>>();
+
+    assert_eq!(loops.loops().len(), 2);
+    let (outer, inner) = (&loops.loops()[0], &loops.loops()[1]);
+    assert_eq!((outer.header, &outer.latches), (bb("outer"), &vec![bb("outer_latch")]));
+    assert_eq!(outer.blocks, blocks(&["outer", "outer_body", "inner", "inner_body", "outer_latch"]));
+    assert_eq!((inner.header, &inner.latches), (bb("inner"), &vec![bb("inner_body")]));
+    assert_eq!(inner.blocks, blocks(&["inner", "inner_body"]));
+
+    assert_eq!([loops.depth(bb("entry")), loops.depth(bb("outer_latch")), loops.depth(bb("inner_body"))], [0, 1, 2]);
+    assert_eq!(loops.innermost(bb("inner_body")).unwrap().header, bb("inner"));
+}
+
+#[test]
+fn values_live_across_blocks() {
+    let program = ProgramBuilder::new()
+        .func(func("main").param("x")
+            .block("entry", |b| {
+                let y = b.add(b.param("x"), b.int(1));
+                b.store(y, b.alloc("r"));
+                b.branch(y, "then", "end");
+            })
+            .block("then", |b| b.jump("end"))
+            .block("end", |b| b.ret(b.param("x"))))
+        .build();
+    let func_data = main_of(&program);
+    let liveness = Liveness::compute(func_data);
+    let x = func_data.params()[0];
+    let only_x = HashSet::from([x]);
+    assert_eq!(liveness.live_in(block(func_data, "entry")), &only_x);
+    assert_eq!(liveness.live_out(block(func_data, "entry")), &only_x);
+    assert_eq!(liveness.live_in(block(func_data, "then")), &only_x);
+    assert!(liveness.live_out(block(func_data, "end")).is_empty());
+
+    // y = x + 1; r = alloc; store y, r; br y
+    let mut insts = liveness.instructions(func_data, block(func_data, "entry"));
+    let mut sets = Vec::new();
+    while insts.next_instruction().is_some() {
+        sets.push((insts.live_in().clone(), insts.live_out().clone()));
+    }
+    sets.reverse();
+    let entry = func_data.layout().bbs().node(&block(func_data, "entry")).unwrap();
+    let (y, r) = (*entry.insts().front_key().unwrap(), *entry.insts().keys().nth(1).unwrap());
+    assert_eq!(sets, [
+        (HashSet::from([x]), HashSet::from([x, y])),
+        (HashSet::from([x, y]), HashSet::from([x, y, r])),
+        (HashSet::from([x, y, r]), HashSet::from([x, y])),
+        (HashSet::from([x, y]), HashSet::from([x])),
+    ]);
+}
+
+#[test]
+fn call_graph_of_a_program() {
+    let program = nested_loops();
+    let call_graph = CallGraph::compute(&program);
+    let function = |name: &str| *program.funcs().iter().find(|(_, data)| data.name() == name).unwrap().0;
+    assert_eq!(call_graph.callees(function("@main")), [function("@work")]);
+    assert_eq!(call_graph.callers(function("@work")), [function("@main")]);
+    assert!(call_graph.is_leaf(function("@work")) && !call_graph.is_leaf(function("@main")));
+}
+
+// Queries the dominators and records whether they were computed again
+struct QueryDominators {
+    computed: Vec<bool>,
+    previous: Option<Rc<DominatorTree>>,
+}
+
+impl OptPassFunction for QueryDominators {
+    fn name(&self) -> &'static str {
+        "query-dominators"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let tree = analyses.dominators(func_data);
+        self.computed.push(!self.previous.as_ref().is_some_and(|previous| Rc::ptr_eq(previous, &tree)));
+        self.previous = Some(tree);
+        Ok(false)
+    }
+}
+
+#[test]
+fn analyses_are_kept_until_a_change() {
+    let mut program = ProgramBuilder::new()
+        .func(func("main").block("entry", |b| b.ret(b.add(b.int(1), b.int(2)))))
+        .build();
+    let main = program.func_layout()[0];
+    let (mut analyses, mut session) = (AnalysisManager::new(), Session::new());
+    let mut query = QueryDominators { computed: Vec::new(), previous: None };
+
+    analyses.run_pass(&mut query, &mut program, main, &mut session).unwrap();
+    analyses.run_pass(&mut query, &mut program, main, &mut session).unwrap();
+    assert!(analyses.run_pass(&mut ConstFoldPass::new(), &mut program, main, &mut session).unwrap());
+    analyses.run_pass(&mut query, &mut program, main, &mut session).unwrap();
+    assert!(!analyses.run_pass(&mut ConstFoldPass::new(), &mut program, main, &mut session).unwrap());
+    analyses.run_pass(&mut query, &mut program, main, &mut session).unwrap();
+    assert_eq!(query.computed, [true, false, true, false]);
+}
+
+#[test]
+fn control_flow_graphs_as_graphviz() {
+    let dots = sysy_compiler::ir::cfg_dot::cfg_dots(&nested_loops());
+    assert_eq!(dots.len(), 1);
+    let (name, dot) = &dots[0];
+    assert_eq!(name, "main");
+    assert!(dot.starts_with("digraph \"main\" {\n"), "{}", dot);
+    assert!(dot.contains("  bb1 -> bb2 [label=\"true\"];\n  bb1 -> bb6 [label=\"false\"];\n"), "{}", dot);
+    // The back edges of both loops
+    assert!(dot.contains("  bb4 -> bb3 [label=\"jump\", style=dashed];\n"), "{}", dot);
+    assert!(dot.contains("  bb5 -> bb1 [label=\"jump\", style=dashed];\n"), "{}", dot);
+    assert!(dot.contains("  bb6 [label=\"%end:\\l  ret 0\\l\", peripheries=2];\n"), "{}", dot);
+    assert!(dot.contains("  bb7 [label=\"%dead:\\l  jump %end\\l\", color=gray, fontcolor=gray];\n"), "{}", dot);
+    assert_eq!(dot.matches("->").count(), 9);
+}
+
+#[test]
+fn ir_statistics_per_function() {
+    let stats = sysy_compiler::common::stats::IrStats::of(&nested_loops());
+    // `work` is only declared
+    assert_eq!(stats.functions.len(), 1);
+    let main = &stats.functions[0];
+    assert_eq!((main.basic_blocks, main.instructions, main.max_block_size), (8, 23, 5));
+    assert_eq!(main.kinds, [("alloc", 2), ("load", 4), ("store", 4), ("binary", 4), ("branch", 2), ("jump", 5), ("call", 1), ("return", 1)]);
+    assert_eq!(
+        stats.render(),
+        "IR statistics:\n  @main: 8 basic blocks, 23 instructions, at most 5 in a block\n    \
+         alloc 2, load 4, store 4, binary 4, branch 2, jump 5, call 1, return 1\n",
+    );
+}
@@ -0,0 +1,47 @@
+use sysy_compiler::interp;
+use sysy_compiler::opt::OptLevel;
+use sysy_compiler::Compiler;
+
+fn koopa(source: &str, level: OptLevel) -> String {
+    Compiler::new().opt_level(level).compile_to_koopa(source).unwrap().output
+}
+
+fn run(source: &str, level: OptLevel) -> (String, i32) {
+    let program = Compiler::new().opt_level(level).compile_to_program(source).unwrap().output;
+    let mut output = Vec::new();
+    let status = interp::run(&program.borrow(), &mut "".as_bytes(), &mut output).unwrap();
+    (String::from_utf8(output).unwrap(), status)
+}
+
+#[test]
+fn constant_conditions_leave_no_branches() {
+    let source = "
+const int DEBUG = 0;
+int main() {
+  int a = 0;
+  if (1) a = 3; else a = 4;
+  while (0) { int x = a; a = x + 1; }
+  if (DEBUG) { putint(a); return 1; }
+  if (2 > 1 && !DEBUG) putint(a);
+  return a;
+}
+";
+    let koopa = koopa(source, OptLevel::O1);
+    assert!(!koopa.contains("br "), "{}", koopa);
+    assert!(!koopa.contains("else") && !koopa.contains("body"), "{}", koopa);
+    assert_eq!(run(source, OptLevel::O1), run(source, OptLevel::O0));
+}
+
+#[test]
+fn loops_on_a_constant_true_condition_are_kept() {
+    let source = "
+int main() {
+  int i = 0;
+  while (1) { i = i + 1; if (i == 10) break; }
+  return i;
+}
+";
+    let koopa = koopa(source, OptLevel::O1);
+    assert_eq!(koopa.matches("br ").count(), 1, "{}", koopa);
+    assert_eq!(run(source, OptLevel::O1), (String::new(), 10));
+}
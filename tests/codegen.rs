@@ -0,0 +1,975 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use koopa::front::Driver;
+use koopa::ir::Program;
+use sysy_compiler::backend::{self, BackendOptions};
+use sysy_compiler::backend::bare_metal::MemoryLayout;
+use sysy_compiler::backend::division;
+use sysy_compiler::backend::encode::EncodeError;
+use sysy_compiler::backend::regalloc::{self, AllocationReport, RegisterAllocator};
+use sysy_compiler::backend::instruction::Instruction;
+use sysy_compiler::backend::register::{RVRegister, RVRegisterPool};
+use sysy_compiler::backend::asm::{AsmEmitter, AsmProgram};
+use sysy_compiler::backend::stack_map::FrameLayout;
+use sysy_compiler::backend::target::{Riscv32, Target};
+use sysy_compiler::common::session::Session;
+use sysy_compiler::frontend;
+use sysy_compiler::interp;
+use sysy_compiler::frontend::comments::IRComments;
+use sysy_compiler::opt::{self, OptLevel, OptLimits};
+use sysy_compiler::Compiler;
+
+// Compiles a single unit at -O1, the source is expected to be valid
+fn compile(source: &str) -> AsmProgram {
+    let program = optimized_ir(source);
+    let program = program.borrow();
+    backend::generate_asm(&program, &BackendOptions::default())
+}
+
+fn optimized_ir(source: &str) -> Rc<RefCell<Program>> {
+    let ast = frontend::parser::parse(source).unwrap_or_else(|_| panic!("syntax error in:\n{}", source));
+    let mut session = Session::new();
+    frontend::semant::check(&ast, &mut session);
+    assert!(!session.has_errors(), "semantic errors in:\n{}", source);
+    let comments = Rc::new(RefCell::new(IRComments::new(false)));
+    let (ir, _) = frontend::generate_ir(&[ast], &comments).unwrap();
+    opt::run_pipeline(&mut ir.borrow_mut(), &opt::pipeline(OptLevel::O1), &OptLimits::default(), &mut session).unwrap();
+    ir
+}
+
+// Generates code for a program written in Koopa IR, reaching shapes the frontend does not produce
+fn compile_ir(ir: &str) -> AsmProgram {
+    compile_ir_with(ir, &BackendOptions::default())
+}
+
+fn compile_ir_with(ir: &str, options: &BackendOptions) -> AsmProgram {
+    let program = Driver::from(ir).generate_program().unwrap();
+    backend::generate_asm(&program, options)
+}
+
+fn assembly(program: &AsmProgram) -> String {
+    let mut out = Vec::new();
+    program.emit(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn frame_size(program: &AsmProgram, function: &str) -> i32 {
+    program.stack_map().frames.iter().find(|frame| frame.function == function).unwrap().frame_size
+}
+
+#[test]
+fn void_calls_in_loop_store_nothing() {
+    let asm = assembly(&compile("
+        int main() {
+            int i = 0;
+            while (i < 10) {
+                putint(i);
+                putch(32);
+                i = i + 1;
+            }
+            return 0;
+        }
+    "));
+    assert!(asm.contains("call putint") && asm.contains("call putch"));
+    assert!(!asm.contains("sw a0"), "void call results are stored:\n{}", asm);
+}
+
+#[test]
+fn ignored_result_is_not_stored() {
+    let asm = assembly(&compile("
+        int main() {
+            int i = 0;
+            while (i < 3) {
+                getint();
+                i = i + 1;
+            }
+            return 0;
+        }
+    "));
+    assert!(asm.contains("call getint"));
+    assert!(!asm.contains("sw a0"), "an unused call result is stored:\n{}", asm);
+}
+
+#[test]
+fn void_calls_take_no_stack() {
+    let one = compile("int main() { putint(1); return 0; }");
+    let many = compile("
+        void f() {
+            putint(1); putint(2); putint(3); putint(4); putint(5);
+            putint(6); putint(7); putint(8); putint(9); putint(10);
+        }
+        int main() { f(); f(); f(); f(); f(); return 0; }
+    ");
+    assert_eq!(frame_size(&many, "f"), frame_size(&one, "main"));
+    assert_eq!(frame_size(&many, "main"), frame_size(&one, "main"));
+}
+
+// Follows the straight-line code of `function` up to its call of `callee`, returning what
+// `a0`-`a7` hold there. Registers start out holding their own names, a call leaves
+// `<callee>()` in `a0` and unknown values in the other caller-saved registers.
+fn arguments_at_call(asm: &str, function: &str, callee: &str) -> Vec<String> {
+    let (registers, _) = state_at_call(asm, function, callee);
+    (0..8).map(|i| registers[i].clone()).collect()
+}
+
+// The values in `a0`-`a7`, then those in the stack slots by their addresses, e.g. `4(sp)`
+fn state_at_call(asm: &str, function: &str, callee: &str) -> (Vec<String>, HashMap<String, String>) {
+    let mut registers: HashMap<String, String> = HashMap::new();
+    let mut stack: HashMap<String, String> = HashMap::new();
+    let read = |registers: &HashMap<String, String>, register: &str| match register {
+        "x0" => "0".to_string(),
+        _ => registers.get(register).cloned().unwrap_or(register.to_string()),
+    };
+    let mut lines = asm.lines().map(str::trim).skip_while(|line| *line != format!("{}:", function)).skip(1);
+    loop {
+        let line = lines.next().unwrap_or_else(|| panic!("`{}` does not call `{}`:\n{}", function, callee, asm));
+        let (op, operands) = line.split_once(' ').unwrap_or((line, ""));
+        let operands: Vec<&str> = operands.split(", ").collect();
+        match op {
+            "call" if operands[0] == callee => break,
+            "call" => {
+                for register in ["a1", "a2", "a3", "a4", "a5", "a6", "a7", "t0", "t1", "t2", "t3", "t4", "t5", "t6"] {
+                    registers.insert(register.to_string(), "?".to_string());
+                }
+                registers.insert("a0".to_string(), format!("{}()", operands[0]));
+            }
+            "mv" => {
+                let value = read(&registers, operands[1]);
+                registers.insert(operands[0].to_string(), value);
+            }
+            "li" => {
+                registers.insert(operands[0].to_string(), operands[1].to_string());
+            }
+            "lw" => {
+                let value = stack.get(operands[1]).cloned().unwrap_or("?".to_string());
+                registers.insert(operands[0].to_string(), value);
+            }
+            "sw" => {
+                stack.insert(operands[1].to_string(), read(&registers, operands[0]));
+            }
+            _ if op.starts_with('#') || op.ends_with(':') => {}
+            _ => {
+                registers.insert(operands[0].to_string(), "?".to_string());
+            }
+        }
+    }
+    ((0..8).map(|i| read(&registers, &format!("a{}", i))).collect(), stack)
+}
+
+#[test]
+fn used_result_is_kept() {
+    let asm = assembly(&compile("
+        int main() {
+            int x = getint();
+            putint(x);
+            return x;
+        }
+    "));
+    assert_eq!(arguments_at_call(&asm, "main", "putint")[0], "getint()", "the call result is lost:\n{}", asm);
+}
+
+#[test]
+fn nested_calls_keep_earlier_arguments() {
+    let asm = assembly(&compile("
+        int g(int x) { return x; }
+        int h(int x) { return x; }
+        int f(int a, int b) { return a - b; }
+        int main() { return f(g(1), h(2)); }
+    "));
+    assert_eq!(arguments_at_call(&asm, "main", "g")[0], "1");
+    assert_eq!(arguments_at_call(&asm, "main", "h")[0], "2");
+    assert_eq!(arguments_at_call(&asm, "main", "f")[..2], ["g()", "h()"], "{}", asm);
+}
+
+#[test]
+fn nested_calls_as_later_arguments() {
+    let asm = assembly(&compile("
+        int g(int x) { return x; }
+        int f(int a, int b, int c) { return a - b + c; }
+        int k(int a, int b, int c) { return a * b + c; }
+        int main() { return k(3, g(f(1, g(2), 4)), g(5)); }
+    "));
+    assert_eq!(arguments_at_call(&asm, "main", "f")[..3], ["1", "g()", "4"], "{}", asm);
+    assert_eq!(arguments_at_call(&asm, "main", "k")[..3], ["3", "g()", "g()"], "{}", asm);
+}
+
+#[test]
+fn swapped_parameters_are_not_clobbered() {
+    let asm = assembly(&compile_ir("
+        fun @f(%a: i32, %b: i32): i32 {
+        %entry:
+          ret %a
+        }
+
+        fun @g(%a: i32, %b: i32): i32 {
+        %entry:
+          %r = call @f(%b, %a)
+          ret %r
+        }
+    "));
+    assert_eq!(arguments_at_call(&asm, "g", "f")[..2], ["a1", "a0"], "{}", asm);
+}
+
+#[test]
+fn rotated_and_repeated_parameters() {
+    let asm = assembly(&compile_ir("
+        fun @f(%a: i32, %b: i32, %c: i32, %d: i32): i32 {
+        %entry:
+          ret %a
+        }
+
+        fun @g(%a: i32, %b: i32, %c: i32): i32 {
+        %entry:
+          %r = call @f(%c, %a, %b, %a)
+          %s = call @f(%r, 7, %r, %r)
+          ret %s
+        }
+    "));
+    assert_eq!(arguments_at_call(&asm, "g", "f")[..4], ["a2", "a0", "a1", "a0"], "{}", asm);
+}
+
+#[test]
+fn identical_input_gives_identical_output() {
+    let source = "
+        int g = 3;
+        int f(int a, int b, int c) { return a * b - c / (a + 1); }
+        int main() {
+            int i = 0, s = 0;
+            while (i < 4) {
+                s = s + f(i, s, getint()) + g;
+                i = i + 1;
+            }
+            putint(s);
+            return s;
+        }
+    ";
+    let first = assembly(&compile(source));
+    for _ in 0..4 {
+        assert_eq!(assembly(&compile(source)), first);
+    }
+}
+
+#[test]
+fn bare_metal_programs_start_at_start() {
+    let layout: MemoryLayout = "flash:0x20000000,ram:0x80000000+16K".parse().unwrap();
+    let options = BackendOptions { memory_layout: Some(layout), ..BackendOptions::default() };
+    let asm = Compiler::new().backend_options(options).compile_to_riscv("int main() { return 3; }").unwrap().output;
+    assert!(asm.starts_with("   .section .text.start"), "{}", asm);
+    assert!(asm.contains("_start:\n    la sp, __stack_top\n") && asm.contains("    call main\n"), "{}", asm);
+
+    let script = layout.linker_script();
+    assert!(script.contains("FLASH (rx) : ORIGIN = 0x20000000, LENGTH = 0x40000"), "{}", script);
+    assert!(script.contains("RAM (rwx) : ORIGIN = 0x80000000, LENGTH = 0x4000"), "{}", script);
+    assert!(script.contains("KEEP(*(.text.start))"), "{}", script);
+}
+
+#[test]
+fn memory_layouts_are_checked() {
+    let error = |layout: &str| layout.parse::<MemoryLayout>().unwrap_err();
+    assert_eq!(error("flash:0x0"), "the memory layout needs both a flash and a ram region");
+    assert_eq!(error("flash:0x0+1M,ram:0x8000"), "the flash and ram regions overlap");
+    assert_eq!(error("flash:0,ram:0x80000000+0"), "memory region `ram` is empty");
+    assert_eq!(error("flash:0,rom:4"), "unknown memory region `rom`, expected flash or ram");
+}
+
+#[test]
+fn magic_numbers_divide_exactly() {
+    let dividends = [0, 1, -1, 2, -2, 6, 7, -7, 100, -100, 12345, -99999, i32::MAX, i32::MAX - 1, i32::MIN, i32::MIN + 1];
+    let divisors = (3..200).chain([641, 1000, 1000000007, 65537, i32::MAX, i32::MIN + 1]).flat_map(|d| [d, -d]);
+    for d in divisors.filter(|&d| division::is_magic_divisor(d)) {
+        for x in dividends.iter().copied().chain((-300..300).map(|i| i * 7919)) {
+            assert_eq!(division::divide(x, d), x.wrapping_div(d), "{} / {}", x, d);
+        }
+    }
+}
+
+#[test]
+fn constant_divisors_use_mulh_at_o2() {
+    let source = "int main() { int x = getint(); putint(x % 10); return x / -3 + x / 4 + x / 0; }";
+    let o1 = Compiler::new().compile_to_riscv(source).unwrap().output;
+    assert!(!o1.contains("mulh"), "{}", o1);
+    let o2 = Compiler::new().opt_level(OptLevel::O2).compile_to_riscv(source).unwrap().output;
+    assert_eq!(o2.matches("mulh").count(), 2, "{}", o2);
+    // Powers of two and zero are left to `div`
+    assert_eq!(o2.matches("div ").count(), 2, "{}", o2);
+    assert!(!o2.contains("rem "), "{}", o2);
+}
+
+#[test]
+fn values_without_calls_stay_in_registers() {
+    let asm = assembly(&compile_ir("
+        fun @f(%a: i32, %b: i32): i32 {
+        %entry:
+          %c = mul %a, %b
+          jump %loop
+
+        %loop:
+          %d = add %c, %a
+          %e = sub %d, %b
+          %f = lt %e, 100
+          br %f, %loop, %end
+
+        %end:
+          ret %e
+        }
+    "));
+    assert!(!asm.contains("sw ") && !asm.contains("lw "), "values go through the stack:\n{}", asm);
+}
+
+#[test]
+fn values_live_across_calls_take_callee_saved_registers() {
+    let asm = assembly(&compile_ir("
+        decl @putint(i32)
+
+        fun @f(%a: i32): i32 {
+        %entry:
+          %b = mul %a, 3
+          call @putint(%a)
+          %c = add %b, %a
+          ret %c
+        }
+
+        fun @g(%a: i32): i32 {
+        %entry:
+          %b = mul %a, 3
+          ret %b
+        }
+    "));
+    for register in ["s0", "s1"] {
+        assert!(asm.contains(&format!("sw {}, ", register)) && asm.contains(&format!("lw {}, ", register)), "{} is not saved:\n{}", register, asm);
+    }
+    assert!(!asm.contains("s2"), "{}", asm);
+    // `ra` only
+    assert_eq!(asm.matches("    sw ").count(), 3, "values are spilled:\n{}", asm);
+}
+
+#[test]
+fn values_live_across_calls_are_spilled_once_saved_registers_run_out() {
+    // `%a` and 12 sums are live across the call, one more than the s-registers
+    let sums: String = (0..12).map(|i| format!("          %v{} = add %a, {}\n", i, i)).collect();
+    let total: String = (0..12).map(|i| format!("          %t{} = add %t{}, %v{}\n", i + 1, i, i)).collect();
+    let ir = format!("
+        decl @putint(i32)
+
+        fun @f(%a: i32): i32 {{
+        %entry:
+{}          call @putint(%a)
+          %t0 = add %a, 0
+{}          ret %t12
+        }}
+    ", sums, total);
+    for allocator in RegisterAllocator::ALL {
+        let asm = assembly(&compile_ir_with(&ir, &BackendOptions { register_allocator: allocator, ..Default::default() }));
+        assert!(asm.contains("sw s11, "), "{}", asm);
+        // `ra`, the s-registers and the spilled value
+        assert_eq!(asm.matches("    sw ").count(), 14, "{}", asm);
+    }
+}
+
+#[test]
+fn coloring_coalesces_argument_registers() {
+    let ir = "
+        decl @getint(): i32
+
+        fun @f(%a: i32): i32 {
+        %entry:
+          %b = add %a, 1
+          %c = call @f(%b)
+          %d = mul %c, %c
+          ret %d
+        }
+    ";
+    let linear = assembly(&compile_ir(ir));
+    let color = assembly(&compile_ir_with(ir, &BackendOptions { register_allocator: RegisterAllocator::GraphColoring, ..Default::default() }));
+    assert!(!color.contains("    mv "), "values are moved between registers:\n{}", color);
+    // Linear scan gets there by the registers it prefers
+    assert!(!linear.contains("    mv "), "values are moved between registers:\n{}", linear);
+    assert!(color.contains("addi a0, a0, 1") && color.contains("mul a0, a0, a0"), "{}", color);
+}
+
+#[test]
+fn values_survive_nested_calls() {
+    let program = optimized_ir("
+        int g(int x) { return x + 1; }
+        int main() {
+            int a = getint();
+            return g(a) * g(a + 1) + g(g(a) - a) * (a + g(2));
+        }
+    ");
+    let program = program.borrow();
+    for allocator in RegisterAllocator::ALL {
+        for &func in program.func_layout() {
+            let func_data = program.func(func);
+            if func_data.layout().entry_bb().is_some() {
+                let allocation = regalloc::allocate(func_data, allocator, &regalloc::allocatable(false));
+                regalloc::check(func_data, &allocation).unwrap_or_else(|message| panic!("{:?} in `{}`: {}", allocator, func_data.name(), message));
+            }
+        }
+    }
+    let asm = assembly(&backend::generate_asm(&program, &BackendOptions::default()));
+    assert!(asm.contains("sw s0, "), "the products are not kept in callee-saved registers:\n{}", asm);
+}
+
+#[test]
+fn deep_expressions_spill_what_registers_cannot_hold() {
+    // Every product is live while the expression nested in its sum is computed
+    let expression = (1..=30).fold("a".to_string(), |inner, i| format!("(a * {} + {})", i, inner));
+    let program = optimized_ir(&format!("int main() {{ int a = getint(); return {}; }}", expression));
+    let program = program.borrow();
+    for allocator in RegisterAllocator::ALL {
+        let asm = backend::generate_asm(&program, &BackendOptions { register_allocator: allocator, ..Default::default() });
+        // 23 registers for 30 products
+        assert!(frame_size(&asm, "main") >= 7 * 4, "{:?}:\n{}", allocator, assembly(&asm));
+    }
+}
+
+#[test]
+fn redundant_moves_are_removed() {
+    let ir = "
+        decl @id(i32): i32
+
+        fun @f(%a: i32): i32 {
+        %entry:
+          %c = call @id(%a)
+          %d = call @id(%c)
+          %e = add %c, %d
+          ret %e
+        }
+    ";
+    let kept = assembly(&compile_ir(ir));
+    let removed = assembly(&compile_ir_with(ir, &BackendOptions { eliminate_moves: true, ..Default::default() }));
+    // `%c` is kept in `s0` across the second call, and passed to it from there
+    assert!(kept.contains("    mv s0, a0\n    mv a0, s0\n"), "{}", kept);
+    // It is still in `a0` for the call
+    assert_eq!(removed.matches("    mv ").count(), 1, "{}", removed);
+    assert!(removed.contains("    mv s0, a0\n    call id\n"), "{}", removed);
+}
+
+#[test]
+fn spilled_values_are_loaded_once_between_calls() {
+    // 13 values live across the call, each read twice by one instruction after it
+    let sums: String = (0..12).map(|i| format!("          %v{} = add %a, {}\n", i, i)).collect();
+    let total: String = (0..12).map(|i| format!("          %w{} = mul %v{}, %v{}\n          %t{} = add %t{}, %w{}\n", i, i, i, i + 1, i, i)).collect();
+    let ir = format!("
+        decl @putint(i32)
+
+        fun @f(%a: i32): i32 {{
+        %entry:
+{}          call @putint(%a)
+          %t0 = mul %a, %a
+{}          ret %t12
+        }}
+    ", sums, total);
+    for allocator in RegisterAllocator::ALL {
+        let asm = assembly(&compile_ir_with(&ir, &BackendOptions { register_allocator: allocator, ..Default::default() }));
+        // `ra`, the s-registers, and the spilled value once
+        assert_eq!(asm.matches("    lw ").count(), 14, "{:?}:\n{}", allocator, asm);
+    }
+}
+
+#[test]
+fn segments_defined_and_ended_by_one_instruction_keep_the_value_home() {
+    // `%p` is passed on the stack, its segment for the `mul` starts and ends there
+    let program = optimized_ir("
+        int f(int a, int b, int c, int d, int e, int g, int h, int i, int p) {
+            int x = p * p;
+            int y = getint();
+            return x + y * 2 + p;
+        }
+        int main() { putint(f(0, 0, 0, 0, 0, 0, 0, 0, 3)); return 0; }
+    ");
+    let program = program.borrow();
+    for allocator in RegisterAllocator::ALL {
+        let asm = backend::generate_asm(&program, &BackendOptions { register_allocator: allocator, ..Default::default() });
+        let argument = format!("{}(sp)", frame_size(&asm, "f"));
+        let asm = assembly(&asm);
+        let body = &asm[asm.find("f:\n").unwrap()..asm.find("main:\n").unwrap()];
+        // The register of the segment does not survive the call, `%p` is read from its slot again
+        let after_call = &body[body.find("call getint").unwrap()..];
+        assert!(after_call.contains(&argument), "{:?} reads `p` from a register across the call:\n{}", allocator, body);
+    }
+}
+
+
+#[test]
+fn register_pools_hand_out_registers_by_priority() {
+    let mut pool = RVRegisterPool::new(&[RVRegister::T0, RVRegister::T1, RVRegister::A0, RVRegister::S0]);
+    assert_eq!(pool.acquire(), Some(RVRegister::T0));
+    assert_eq!(pool.acquire_preferring(Some(RVRegister::A0), |_| true), Some(RVRegister::A0));
+    // Taken already, or not the pool's
+    assert_eq!(pool.acquire_preferring(Some(RVRegister::A0), |_| true), Some(RVRegister::T1));
+    assert_eq!(pool.acquire_preferring(Some(RVRegister::A1), |register| register.is_callee_saved()), Some(RVRegister::S0));
+    assert_eq!(pool.acquire(), None);
+    pool.release(RVRegister::T0);
+    pool.release(RVRegister::A1);
+    assert!(pool.is_free(RVRegister::T0) && !pool.is_free(RVRegister::A1));
+    assert_eq!(pool.acquire(), Some(RVRegister::T0));
+}
+
+#[test]
+fn allocation_reports_count_live_values_and_spills() {
+    let expression = (1..=30).fold("a".to_string(), |inner, i| format!("(a * {} + {})", i, inner));
+    let program = optimized_ir(&format!("int id(int x) {{ return x; }} int main() {{ int a = getint(); return id({}); }}", expression));
+    let program = program.borrow();
+    let asm = backend::generate_asm(&program, &BackendOptions { register_allocator: RegisterAllocator::GraphColoring, report_allocation: true, ..Default::default() });
+    let reports = asm.allocation_reports();
+    let id = reports.iter().find(|report| report.function == "id").unwrap();
+    assert_eq!((id.max_live, id.spilled, id.registers.as_slice()), (1, 0, &[RVRegister::A0][..]));
+    // `a` and the 30 products, 7 more than the 24 registers
+    let main = reports.iter().find(|report| report.function == "main").unwrap();
+    assert_eq!((main.max_live, main.spilled, main.registers.len()), (31, 7, 24));
+    let table = AllocationReport::render(reports);
+    assert!(table.contains("  main            31        7  "), "{}", table);
+    // Only computed when asked for
+    let asm = backend::generate_asm(&program, &BackendOptions::default());
+    assert!(asm.allocation_reports().is_empty());
+}
+
+#[test]
+fn element_addresses_are_computed_with_shifts_and_adds() {
+    let ir = "
+        global @g = alloc [i32, 4], zeroinit
+
+        fun @main(): i32 {
+        %entry:
+          %a = alloc [i32, 10]
+          %m = alloc [[i32, 3], 2]
+          %a0 = getelemptr %a, 0
+          store 5, %a0
+          %i = load %a0
+          %ai = getelemptr %a, %i
+          store 7, %ai
+          %a5 = getptr %a0, 5
+          %x = load %a5
+          %g3 = getelemptr @g, 3
+          store %x, %g3
+          %y = load %g3
+          %j = sub %i, 4
+          %mj = getelemptr %m, %j
+          %mj2 = getelemptr %mj, 2
+          store 100, %mj2
+          %m0 = getelemptr %m, 0
+          %m00 = getelemptr %m0, 0
+          %m12 = getptr %m00, 5
+          %z = load %m12
+          %s = add %x, %y
+          %r = add %s, %z
+          ret %r
+        }
+    ";
+    for allocator in RegisterAllocator::ALL {
+        let program = compile_ir_with(ir, &BackendOptions { register_allocator: allocator, eliminate_moves: true, ..Default::default() });
+        let asm = assembly(&program);
+        // The arrays take their whole size in the frame
+        assert_eq!(frame_size(&program, "main"), 64, "{:?}", allocator);
+        // A power-of-two element size is a shift, the rows of `%m` a multiplication
+        assert!(asm.contains("slli "), "{:?}:\n{}", allocator, asm);
+        assert!(asm.contains(", 12\n    mul "), "{:?}:\n{}", allocator, asm);
+        assert!(asm.contains("la "), "{:?}:\n{}", allocator, asm);
+    }
+}
+
+#[test]
+fn aggregate_globals_are_words_and_zero_runs() {
+    let ir = "
+        global @m = alloc [[i32, 3], 4], {{1, 2, 0}, zeroinit, {0, 0, 3}, {0, 0, 0}}
+
+        fun @main(): i32 {
+        %entry:
+          %p = getelemptr @m, 2
+          %q = getelemptr %p, 2
+          %r = load %q
+          ret %r
+        }
+    ";
+    let asm = assembly(&compile_ir(ir));
+    assert!(asm.contains("m:\n   .word 1\n   .word 2\n   .zero 24\n   .word 3\n   .zero 12\n"), "{}", asm);
+}
+
+#[test]
+fn zero_initialized_globals_go_to_bss() {
+    let ir = "
+        global @z = alloc [i32, 1024], zeroinit
+        global @d = alloc i32, 3
+
+        fun @main(): i32 {
+        %entry:
+          %p = getelemptr @z, 1
+          %v = load @d
+          store %v, %p
+          ret %v
+        }
+    ";
+    let asm = assembly(&compile_ir(ir));
+    assert!(asm.starts_with("   .data\n   .globl d\nd:\n   .word 3\n\n   .bss\n   .globl z\nz:\n   .zero 4096\n"), "{}", asm);
+}
+
+#[test]
+fn arguments_beyond_the_eighth_are_passed_on_the_stack() {
+    for n in [9, 12, 20] {
+        let params: Vec<String> = (0..n).map(|i| format!("int p{}", i)).collect();
+        let sum: Vec<String> = (0..n).map(|i| format!("p{}", i)).collect();
+        let args: Vec<String> = (0..n).map(|i| (i * 7 + 1).to_string()).collect();
+        // More locals live across the call than there are registers, so `main` spills too
+        let locals: String = (0..30).map(|i| format!("int v{} = getint(); ", i)).collect();
+        let total: Vec<String> = (0..30).map(|i| format!("v{}", i)).collect();
+        let source = format!(
+            "int f({}) {{ return {}; }} int main() {{ {}int r = f({}); return r + {}; }}",
+            params.join(", "), sum.join(" + "), locals, args.join(", "), total.join(" + "),
+        );
+        let program = compile(&source);
+        let asm = assembly(&program);
+        let frames = program.stack_map().frames;
+        let main = frames.iter().find(|frame| frame.function == "main").unwrap();
+        let f = frames.iter().find(|frame| frame.function == "f").unwrap();
+        assert_eq!(main.outgoing_args_size, (n as i32 - 8) * 4);
+
+        let (registers, stack) = state_at_call(&asm, "main", "f");
+        assert_eq!(registers, args[..8]);
+        for i in 8..n {
+            assert_eq!(stack.get(&format!("{}(sp)", FrameLayout::outgoing_arg_offset(i))), Some(&args[i]), "{} arguments:\n{}", n, asm);
+            assert!(asm.contains(&format!(", {}(sp)\n", f.incoming_arg_offset(i))), "{} arguments:\n{}", n, asm);
+        }
+        // The spilled values of `main` stay clear of the outgoing area
+        let main_asm = &asm[asm.find("main:").unwrap()..];
+        let outgoing_stores = main_asm.lines()
+            .filter_map(|line| line.trim().strip_prefix("sw ")?.split_once(", ")?.1.strip_suffix("(sp)")?.parse::<i32>().ok())
+            .filter(|&offset| offset < main.outgoing_args_size)
+            .count();
+        assert_eq!(outgoing_stores, n - 8, "{} arguments:\n{}", n, asm);
+    }
+}
+
+#[test]
+fn far_branches_are_relaxed() {
+    // The loop branches back over more than 4 KiB of code
+    let adds: String = (1..1200).map(|i| format!("          %a{} = add %a{}, {}\n", i, i - 1, i)).collect();
+    let ir = format!("
+        decl @getint(): i32
+
+        fun @main(): i32 {{
+        %entry:
+          %x = call @getint()
+          jump %body
+        %body:
+          %a0 = add %x, 1
+{}          %c = eq %a1199, 0
+          br %c, %body, %exit
+        %exit:
+          ret %a1199
+        }}
+    ", adds);
+    let program = compile_ir(&ir);
+    let asm = assembly(&program);
+    assert!(!asm.contains("bnez "), "{}", asm);
+    assert!(asm.contains(", .Lmain_far0\n    j func_1\n.Lmain_far0:\n    j func_2\n"), "{}", asm);
+    assert!(backend::object::write_object(&program).is_ok());
+}
+
+#[test]
+fn blocks_are_laid_out_to_fall_through() {
+    let source = "int main() { int i = 0; int s = 0; while (i < getint()) { if (i % 2) s = s + i; else s = s - 1; i = i + 1; } return s; }";
+    let program = optimized_ir(source);
+    let program = program.borrow();
+    let jumps = |layout_blocks: bool| {
+        let asm = assembly(&backend::generate_asm(&program, &BackendOptions { layout_blocks, ..Default::default() }));
+        let lines: Vec<&str> = asm.lines().collect();
+        // No jump is to the next line
+        if layout_blocks {
+            for pair in lines.windows(2) {
+                if let Some(label) = pair[0].trim().strip_prefix("j ") {
+                    assert_ne!(pair[1], format!("{}:", label), "{}", asm);
+                }
+            }
+        }
+        lines.iter().filter(|line| line.trim().starts_with("j ")).count()
+    };
+    let (before, after) = (jumps(false), jumps(true));
+    assert!(after * 2 < before, "{} jumps, then {}", before, after);
+}
+
+#[test]
+fn constant_operands_are_immediates() {
+    let ir = "
+        fun @f(%a: i32): i32 {
+        %entry:
+          %b = sub %a, 5
+          %c = and %b, 255
+          %d = or 16, %c
+          %e = le %d, 100
+          %f = gt %d, 2047
+          %g = eq %d, 7
+          %h = add %e, %f
+          %i = add %h, %g
+          %j = add %i, 4096
+          ret %j
+        }
+    ";
+    let asm = assembly(&compile_ir(ir));
+    for inst in ["addi t0, a0, -5", "andi t0, t0, 255", "ori t0, t0, 16", "slti t1, t0, 101", "xori t0, t0, 7\n    seqz t0, t0"] {
+        assert!(asm.contains(inst), "no `{}`:\n{}", inst, asm);
+    }
+    // Out of range of an I-type immediate
+    assert!(asm.contains("li t4, 2047\n    sgt") && asm.contains("li t4, 4096\n    add"), "{}", asm);
+}
+
+#[test]
+fn loads_are_scheduled_away_from_their_uses() {
+    let ir = "
+        global @g = alloc i32, 5
+
+        fun @f(%a: i32, %b: i32): i32 {
+        %entry:
+          %x = load @g
+          %y = add %x, %a
+          %z = add %a, %b
+          %w = sub %b, %a
+          %v = add %y, %z
+          %r = add %v, %w
+          ret %r
+        }
+    ";
+    // The instruction right after a load reading what it loads
+    let stalls = |schedule: bool| {
+        let asm = assembly(&compile_ir_with(ir, &BackendOptions { schedule, ..Default::default() }));
+        let lines: Vec<&str> = asm.lines().map(str::trim).collect();
+        lines.windows(2)
+            .filter(|pair| pair[0].starts_with("lw ") && !pair[0].contains("(sp)"))
+            .filter(|pair| {
+                let rd = pair[0][3..].split(", ").next().unwrap();
+                pair[1].split_once(", ").is_some_and(|(_, operands)| operands.split(", ").any(|operand| operand == rd || operand.contains(&format!("({})", rd))))
+            })
+            .count()
+    };
+    assert_eq!(stalls(false), 1);
+    assert_eq!(stalls(true), 0);
+}
+
+#[test]
+fn riscv32_passes_eight_arguments_in_registers() {
+    assert_eq!(Riscv32::argument_register(0), Riscv32::RETURN_REGISTER);
+    assert_eq!(Riscv32::argument_register(7), RVRegister::A7);
+    assert!(Riscv32::is_passed_in_register(7) && !Riscv32::is_passed_in_register(8));
+    assert_eq!(Riscv32::outgoing_args_size(12), 16);
+    assert_eq!(Riscv32::outgoing_arg_offset(9), FrameLayout::outgoing_arg_offset(9));
+    assert_eq!(Riscv32::align_frame(20), 32);
+    // Values never take the scratch registers
+    assert!(Riscv32::SCRATCH.iter().all(|register| !Riscv32::CALLER_SAVED.contains(register) && !Riscv32::CALLEE_SAVED.contains(register)));
+}
+
+#[test]
+fn executables_start_by_calling_main() {
+    let mut program = compile("int main() { putint(getint() + 1); return 0; }");
+    backend::runtime::link_runtime(&mut program, &BackendOptions::default());
+    let exe = backend::object::write_executable(&program).unwrap();
+    let half = |at: usize| u16::from_le_bytes([exe[at], exe[at + 1]]);
+    let word = |at: usize| u32::from_le_bytes(exe[at..at + 4].try_into().unwrap());
+    // An executable for RISC-V, loaded at 0x10000 with its headers
+    assert_eq!((&exe[..4], half(16), half(18)), (&b"\x7fELF"[..], 2, 243));
+    let entry = word(24);
+    // `auipc ra` + `jalr ra` to the start of `.text`, where `main` is
+    let (auipc, jalr) = (word(entry as usize - 0x10000), word(entry as usize - 0x10000 + 4));
+    let target = entry.wrapping_add(auipc & 0xfffff000).wrapping_add(((jalr as i32) >> 20) as u32);
+    let text_header = word(32) as usize + 40;
+    assert_eq!(target, word(text_header + 12));
+
+    let mut program = compile("int f(); int main() { return f(); }");
+    backend::runtime::link_runtime(&mut program, &BackendOptions::default());
+    assert_eq!(backend::object::write_executable(&program).unwrap_err(), EncodeError::UndefinedSymbol("f".to_string()));
+}
+
+#[test]
+fn source_lines_precede_their_code() {
+    let source = "int main() {\n  int i = 0, s = 0;\n  while (i < getint()) {\n    s = s + i * i;\n    i = i + 1;\n  }\n  return s;\n}\n";
+    let ast = frontend::parser::parse(source).unwrap();
+    let mut session = Session::new();
+    let comments = Rc::new(RefCell::new(IRComments::new(true)));
+    let (ir, _) = frontend::generate_ir(&[ast], &comments).unwrap();
+    opt::run_pipeline(&mut ir.borrow_mut(), &opt::pipeline(OptLevel::O1), &OptLimits::default(), &mut session).unwrap();
+    let source_lines = comments.borrow().source_lines(&[("a.c".to_string(), source.to_string())]);
+    let options = BackendOptions { eliminate_moves: true, schedule: true, layout_blocks: true, ..BackendOptions::default() };
+    let asm = assembly(&backend::generate_asm_with_source_lines(&ir.borrow(), &options, &source_lines));
+
+    let lines: Vec<&str> = asm.lines().map(str::trim).collect();
+    let position = |line: &str| lines.iter().rposition(|&l| l == line).unwrap_or_else(|| panic!("no `{}` in:\n{}", line, asm));
+    // The code up to the next comment or label
+    let code_after = |at: usize| lines[at + 1..].iter().take_while(|l| !l.starts_with('#') && !l.ends_with(':')).copied().collect::<Vec<_>>();
+    let condition = position("# line 3: while (i < getint())");
+    assert!(code_after(condition).contains(&"call getint"), "{}", asm);
+    let body = position("# line 4: s = s + i * i;");
+    assert!(code_after(body).iter().any(|l| l.starts_with("mul")), "{}", asm);
+    assert!(position("# line 5: i = i + 1;") > body);
+    assert!(position("# line 7: return s;") > condition);
+    // Comments are not code
+    assert_eq!(backend::object::write_object(&backend::generate_asm_with_source_lines(&ir.borrow(), &options, &source_lines)).unwrap(),
+        backend::object::write_object(&backend::generate_asm(&ir.borrow(), &options)).unwrap());
+}
+
+#[test]
+fn position_independent_code_takes_addresses_without_a_got() {
+    let program = optimized_ir("int g; int f(int x) { return x + g; } int main() { g = f(4); return g; }");
+    let options = BackendOptions { pic: true, ..BackendOptions::default() };
+    let pic = backend::generate_asm(&program.borrow(), &options);
+    let asm = assembly(&pic);
+    assert!(asm.contains("lla t0, g") && asm.contains("call f@plt"), "{}", asm);
+    assert!(!asm.lines().any(|line| line.trim_start().starts_with("la ")), "{}", asm);
+    // The same code, as `la` is PC-relative when assembled on its own
+    let plain = backend::generate_asm(&program.borrow(), &BackendOptions::default());
+    assert_eq!(backend::object::write_object(&pic).unwrap(), backend::object::write_object(&plain).unwrap());
+}
+
+#[test]
+fn compressed_forms_need_operands_that_fit() {
+    use sysy_compiler::backend::compress::compressed;
+    let text = |inst: Instruction| compressed(&inst);
+    assert_eq!(text(Instruction::Addi { rd: RVRegister::T0, rs: RVRegister::T0, imm: -32 }).as_deref(), Some("c.addi t0, -32"));
+    assert_eq!(text(Instruction::Addi { rd: RVRegister::T0, rs: RVRegister::T0, imm: 32 }), None);
+    assert_eq!(text(Instruction::Addi { rd: RVRegister::T0, rs: RVRegister::T1, imm: 1 }), None);
+    assert_eq!(text(Instruction::Lw { rd: RVRegister::A0, rs: RVRegister::S0, imm: 124 }).as_deref(), Some("c.lw a0, 124(s0)"));
+    // Only `s0`-`a5` outside of `sp`-relative accesses
+    assert_eq!(text(Instruction::Lw { rd: RVRegister::T0, rs: RVRegister::S0, imm: 0 }), None);
+    assert_eq!(text(Instruction::Lw { rd: RVRegister::T0, rs: RVRegister::Sp, imm: 252 }).as_deref(), Some("c.lwsp t0, 252(sp)"));
+    assert_eq!(text(Instruction::Sw { rs: RVRegister::A0, rd: RVRegister::Sp, imm: 2 }), None);
+    assert_eq!(text(Instruction::Sub { rd: RVRegister::A0, rs1: RVRegister::A1, rs2: RVRegister::A0 }), None);
+    assert_eq!(text(Instruction::Xor { rd: RVRegister::A0, rs1: RVRegister::A1, rs2: RVRegister::A0 }).as_deref(), Some("c.xor a0, a1"));
+    assert_eq!(text(Instruction::Mv { rd: RVRegister::A0, rs: RVRegister::Zero }), None);
+    assert_eq!(text(Instruction::J { label: "f".to_string() }), None);
+
+    let program = optimized_ir("int main() { return getint() + 1; }");
+    let asm = assembly(&backend::generate_asm(&program.borrow(), &BackendOptions { compressed: true, ..BackendOptions::default() }));
+    assert!(asm.contains("c.addi16sp sp, -16") && asm.contains("c.jr ra") && asm.contains("call getint"), "{}", asm);
+}
+
+#[test]
+fn rv32i_calls_routines_for_multiplication_and_division() {
+    use sysy_compiler::backend::soft_mul_div;
+    let program = optimized_ir("int main() { int a = getint(), b = getint(); return a * b + a / b + a % b + a * 8; }");
+    let options = BackendOptions { m_extension: false, ..BackendOptions::default() };
+    soft_mul_div::lower(&mut program.borrow_mut());
+    let mut asm = backend::generate_asm(&program.borrow(), &options);
+    soft_mul_div::link(&mut asm, &options);
+    let asm = assembly(&asm);
+    assert!(asm.contains("call __mulsi3") && asm.contains("call __divsi3") && asm.contains("call __modsi3"), "{}", asm);
+    assert!(asm.contains("__mulsi3:") && asm.contains("__udivmodsi4:") && asm.contains("slli"), "{}", asm);
+    assert!(!asm.lines().any(|line| ["mul ", "div ", "rem "].iter().any(|op| line.trim_start().starts_with(op))), "{}", asm);
+}
+
+#[test]
+fn compiler_api_lowers_multiplication_without_the_m_extension() {
+    let options = BackendOptions { m_extension: false, ..BackendOptions::default() };
+    let source = "int main() { int a = getint(), b = getint(); return a * b + a / b; }";
+    let asm = Compiler::new().opt_level(OptLevel::O2).backend_options(options).compile_to_riscv(source).unwrap().output;
+    assert!(asm.contains("call __mulsi3") && asm.contains("call __divsi3") && asm.contains("__mulsi3:"), "{}", asm);
+    assert!(!asm.lines().any(|line| ["mul ", "div ", "rem "].iter().any(|op| line.trim_start().starts_with(op))), "{}", asm);
+}
+
+#[test]
+fn call_graph_marks_recursion() {
+    use sysy_compiler::backend::call_graph::CallGraph;
+    let program = optimized_ir("
+int even(int n);
+int odd(int n) { if (n == 0) return 0; return even(n - 1); }
+int even(int n) { if (n == 0) return 1; return odd(n - 1); }
+int sum(int a, int b, int c) { return a + b + c; }
+int main() { putint(even(getint()) + sum(1, 2, getint())); return 0; }
+");
+    let program = program.borrow();
+    let dot = CallGraph::build(&program).to_dot(&program);
+    // Functions called or calling, in layout order
+    assert!(dot.contains("  f0 [label=\"getint\", style=dashed];\n  f1 [label=\"putint\", style=dashed];\n"), "{}", dot);
+    assert!(dot.contains("  f2 [label=\"even\\nmax args: 1\", color=red];\n  f3 [label=\"odd\\nmax args: 1\", color=red];\n"), "{}", dot);
+    assert!(dot.contains("  f4 [label=\"sum\"];\n  f5 [label=\"main\\nmax args: 3\"];\n"), "{}", dot);
+    assert!(dot.contains("  f2 -> f3 [color=red];\n  f3 -> f2 [color=red];\n  f5 -> f0;\n  f5 -> f1;\n  f5 -> f2;\n  f5 -> f4;\n}"), "{}", dot);
+}
+
+#[test]
+fn literal_pools_are_shared_by_the_functions() {
+    let ir = "
+fun @f(%x: i32): i32 {
+%entry:
+  %0 = mul %x, 123456789
+  %1 = add %0, 123456789
+  %2 = sub %1, 123456789
+  %3 = mul %2, 123456789
+  %4 = add %3, 123456789
+  %5 = sub %4, 123456789
+  ret %5
+}
+
+fun @g(%x: i32): i32 {
+%entry:
+  %0 = mul %x, 123456789
+  %1 = add %0, 555555555
+  %2 = sub %1, 123456789
+  %3 = mul %2, 555555555
+  %4 = add %3, 123456789
+  %5 = add %4, 555555555
+  ret %5
+}
+";
+    let asm = assembly(&compile_ir_with(ir, &BackendOptions { literal_pools: true, ..Default::default() }));
+    // `g` loads the constant `f` added to the pool from the same slot
+    assert_eq!(asm.matches("la s11, .Lliterals\n").count(), 2, "{}", asm);
+    assert!(asm.contains(".Lliterals:\n   .word 123456789\n   .word 555555555\n"), "{}", asm);
+    assert_eq!(asm.matches("lw t4, 0(s11)").count(), 9, "{}", asm);
+}
+
+#[test]
+fn locals_of_disjoint_scopes_share_stack_slots() {
+    let source = "
+int main() {
+  int s = getint();
+  if (s) { int a = s; int b = a + 1; int c = b + 2; int d = c + 3; s = a + b + c + d; }
+  else { int e = s; int f = e + 1; int g = f + 2; int h = g + 3; s = e + f + g + h; }
+  putint(s);
+  return 0;
+}";
+    let ast = frontend::parser::parse(source).unwrap();
+    let comments = Rc::new(RefCell::new(IRComments::new(false)));
+    let (ir, alloc_scopes) = frontend::generate_ir(&[ast], &comments).unwrap();
+    let generate = |share_stack_slots| {
+        let options = BackendOptions { share_stack_slots, ..BackendOptions::default() };
+        backend::generate_asm_with(&ir.borrow(), &options, &HashMap::new(), &alloc_scopes)
+    };
+    let (separate, shared) = (generate(false), generate(true));
+    assert!(frame_size(&shared, "main") < frame_size(&separate, "main"));
+
+    let stack_map = shared.stack_map();
+    let offset = |name: &str| stack_map.frames[0].locals.iter().find(|slot| slot.name == name).unwrap().offset;
+    // A sibling block reuses the slots, the enclosing scope and the block itself do not
+    assert_eq!(offset("a"), offset("e"));
+    assert_eq!(offset("d"), offset("h"));
+    let mut offsets: Vec<_> = ["s", "a", "b", "c", "d"].iter().map(|name| offset(name)).collect();
+    offsets.sort();
+    offsets.dedup();
+    assert_eq!(offsets.len(), 5);
+}
+
+#[test]
+fn format_strings_are_read_only_data() {
+    let source = r#"
+        int main() {
+            putf("%d%c\n", 7, 33);
+            putf("%d%c\n", 8, 63);
+            putf("%d %d %d %d %d %d %d %d %d \"%%\"\n", 1, 2, 3, 4, 5, 6, 7, 8, 9);
+            return 0;
+        }
+    "#;
+    let mut output = Vec::new();
+    interp::run(&optimized_ir(source).borrow(), &mut "".as_bytes(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "7!\n8?\n1 2 3 4 5 6 7 8 9 \"%\"\n");
+
+    let mut program = compile(source);
+    let asm = assembly(&program);
+    let rodata = &asm[asm.find(".section .rodata").unwrap()..];
+    // Once each, padded to a word, and private to the object
+    assert!(rodata.contains("__sysy_str_0:\n   .asciz \"%d%c\\012\"\n   .zero 2\n"), "{}", asm);
+    assert!(rodata.contains(".asciz \"%d %d %d %d %d %d %d %d %d \\\"%%\\\"\\012\"\n"), "{}", asm);
+    assert!(!asm.contains("__sysy_str_2") && !asm.contains(".globl __sysy_str"), "{}", asm);
+    assert!(asm.lines().any(|line| line.trim_start().starts_with("la ") && line.ends_with(", __sysy_str_0")), "{}", asm);
+    // The values after the eighth argument go on the stack, as for any call
+    assert!(asm.contains("li a7, 7"), "{}", asm);
+    backend::runtime::link_runtime(&mut program, &BackendOptions::default());
+    backend::object::write_executable(&program).unwrap();
+}
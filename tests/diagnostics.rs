@@ -0,0 +1,176 @@
+use std::time::Duration;
+use sysy_compiler::opt::{OptLevel, OptLimits};
+use sysy_compiler::{CompileError, Compiler};
+use sysy_compiler::frontend::symbol_index::{Role, ScopeKind, SymbolKind};
+use sysy_compiler::frontend::xref::{CrossReference, Location, XrefUnit};
+
+// The messages of the errors reported for `source`, in order
+fn errors(source: &str) -> Vec<String> {
+    match Compiler::new().compile_to_koopa(source) {
+        Err(CompileError::Semantic(diagnostics)) => diagnostics.iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .map(|diagnostic| diagnostic.message.clone())
+            .collect(),
+        Err(error) => panic!("not a semantic error: {}", error),
+        Ok(_) => panic!("no error in:\n{}", source),
+    }
+}
+
+#[test]
+fn independent_errors_are_all_reported() {
+    assert_eq!(errors("
+        int g = h;
+        void f(int a, int a) { return 1; }
+        int main() {
+            int x = y;
+            if (u) { break; }
+            while (v) { w = 1; }
+            return x;
+        }
+    "), [
+        "use of undeclared identifier `h`",
+        "redefinition of `a`",
+        "void function `f` should not return a value of type `int`",
+        "use of undeclared identifier `y`",
+        "use of undeclared identifier `u`",
+        "`break` outside of a loop",
+        "use of undeclared identifier `v`",
+        "use of undeclared identifier `w`",
+    ]);
+}
+
+// Functions see all the globals, the initializers of globals only those before them
+#[test]
+fn globals_are_initialized_from_earlier_ones() {
+    assert_eq!(errors("
+        int f() { return N + g; }
+        int g = N;
+        const int N = 2;
+        int main() { return f(); }
+    "), ["use of undeclared identifier `N`"]);
+}
+
+#[test]
+fn erroneous_declarations_are_still_bound() {
+    assert_eq!(errors("
+        const int c = 1 / 0;
+        const int d = c + 1;
+        int main() {
+            int x = y;
+            return x + c + d;
+        }
+    "), [
+        "division by zero in constant expression",
+        "use of undeclared identifier `y`",
+    ]);
+}
+
+#[test]
+fn formats_are_checked_against_their_values() {
+    assert_eq!(errors(r#"
+        int main() {
+            putf("%d %c\n", 1);
+            putf("%x\n", 1);
+            putf();
+            int s = "s";
+            putint("%d");
+            putf("%d %%\n", 1);
+            return 0;
+        }
+    "#), [
+        "the format has 2 conversion(s), but 1 value(s) are printed",
+        "unsupported conversion `%x` in the format, only `%d`, `%c` and `%%` are",
+        "function `putf` expects at least 1 argument(s), found 0",
+        "a string literal can only be the format of `putf`",
+        "a string literal can only be the format of `putf`",
+    ]);
+}
+
+#[test]
+fn returns_point_at_the_signature() {
+    let source = "void f(int a) { return a; }\nint main() { return 0; }\n";
+    let error = Compiler::new().compile_to_koopa(source).unwrap_err();
+    assert_eq!(error.render("t.c", source), "\
+t.c:1:24: error: void function `f` should not return a value of type `int`
+t.c:1:1: note: `f` is declared as `void(int)` here");
+}
+
+#[test]
+fn unused_locals_are_reported_but_not_parameters() {
+    let source = "int f(int a, int b) { int x = 1; const int y = 2; return a; }\nint main() { return f(1, 2); }\n";
+    let compiled = Compiler::new().compile_to_koopa(source).unwrap();
+    let warnings: Vec<String> = compiled.warnings.iter().map(|diagnostic| diagnostic.message.clone()).collect();
+    assert_eq!(warnings, ["unused variable `x` [-W unused-variable]", "unused constant `y` [-W unused-variable]"]);
+}
+
+fn warnings_with_limits(limits: OptLimits) -> Vec<String> {
+    let source = "int f(int x) { return x * 2 + x * 3 + x * 4; }\nint main() { return f(1) + 1; }\n";
+    let compiled = Compiler::new().opt_level(OptLevel::O2).opt_limits(limits).compile_to_koopa(source).unwrap();
+    compiled.warnings.iter().map(|diagnostic| diagnostic.message.clone()).collect()
+}
+
+#[test]
+fn large_functions_skip_passes() {
+    let limits = OptLimits { instruction_limit: Some(5), ..OptLimits::default() };
+    assert_eq!(warnings_with_limits(limits), [
+        "optimization passes skipped for `f`, which has 6 instructions: `const-fold` (limit 5), `unreachable-blocks` (limit 5), `simplify-cfg` (limit 5), `unroll` (limit 5), `loop-version` (limit 5), `gvn` (limit 5), `dce` (limit 5)",
+    ]);
+    assert!(warnings_with_limits(OptLimits::default()).is_empty());
+}
+
+#[test]
+fn timeout_skips_the_remaining_passes() {
+    let limits = OptLimits { timeout: Some(Duration::ZERO), ..OptLimits::default() };
+    assert_eq!(warnings_with_limits(limits), [
+        "optimization took longer than 0ms, `const-fold` and the passes after it are skipped from `f` on",
+    ]);
+}
+
+#[test]
+fn symbol_index_resolves_names() {
+    let source = "int g;\nint f(int a) { { int a = g; return a; } }\n";
+    let index = Compiler::new().symbol_index(source).unwrap().output;
+    let offset = |pattern: &str| source.find(pattern).unwrap();
+
+    // The `a` returned is the one of the inner block, shadowing the parameter
+    let inner = index.definition_of(offset("a; }")).unwrap();
+    assert_eq!((inner.kind, inner.span.start), (SymbolKind::Var, offset("a = g")));
+    assert_eq!(index.scopes[inner.scope].kind, ScopeKind::Block);
+    let enclosing: Vec<_> = index.enclosing_scopes(inner.scope).map(|scope| index.scopes[scope].kind.clone()).collect();
+    assert_eq!(enclosing, [ScopeKind::Block, ScopeKind::Function("f".into()), ScopeKind::Global]);
+
+    let param = index.definition_of(offset("a)")).unwrap();
+    assert_eq!((param.kind, param.ty.to_string()), (SymbolKind::Param, "int".to_string()));
+    assert_eq!(index.references_to(param.span).count(), 0);
+
+    let f = index.definition_of(offset("f(")).unwrap();
+    assert_eq!((f.role, f.ty.to_string()), (Role::Definition, "int(int)".to_string()));
+    let globals: Vec<&str> = index.declarations_in(0).map(|entry| entry.name.as_str()).collect();
+    assert_eq!(globals, ["g", "f"]);
+    let g = index.definition_of(offset("g;")).unwrap();
+    assert_eq!(index.references_to(g.span).map(|entry| entry.span.start).collect::<Vec<_>>(), [offset("g; return")]);
+}
+
+#[test]
+fn cross_references_span_the_files() {
+    let main = "int add(int a, int b);\nint main() { int x = add(1, 2); return add(x, x); }\n";
+    let lib = "int add(int a, int b) { return a + b; }\n";
+    let indexes: Vec<_> = [main, lib].iter().map(|source| Compiler::new().symbol_index(source).unwrap().output).collect();
+    let units: Vec<XrefUnit> = vec![("main.c", main, &indexes[0]), ("lib.c", lib, &indexes[1])];
+    let xref = CrossReference::new(&units);
+
+    // The calls and the prototype resolve to the definition in the other file
+    let definition = Location { unit: 1, span: indexes[1].at(lib.find("add").unwrap()).unwrap().span };
+    let call = indexes[0].at(main.find("add(1").unwrap()).unwrap();
+    assert_eq!(xref.definition(0, call), Some(definition));
+    let prototype = indexes[0].at(main.find("add").unwrap()).unwrap();
+    assert_eq!(xref.definition(0, prototype), Some(definition));
+    let calls: Vec<_> = xref.references_to(definition).iter().map(|location| (location.unit, location.span.start)).collect();
+    assert_eq!(calls, [(0, main.find("add(1").unwrap()), (0, main.find("add(x").unwrap())]);
+
+    let x = indexes[0].at(main.find("x =").unwrap()).unwrap();
+    assert_eq!(xref.references_to(Location { unit: 0, span: x.span }).len(), 2);
+    let json = xref.to_json();
+    assert!(json.contains("\"path\": \"lib.c\""));
+    assert!(json.contains("\"definition\": { \"file\": 1, \"start\": 4, \"end\": 7, \"line\": 1, \"column\": 5 }"));
+}
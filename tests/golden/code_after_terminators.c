@@ -0,0 +1,28 @@
+void sign(int x) {
+    if (x < 0) {
+        putint(-1);
+        return;
+    } else {
+        putint(1);
+        return;
+    }
+    putint(0);
+}
+
+int main() {
+    int i = 0;
+    while (i < 10) {
+        i = i + 1;
+        if (i > 5) {
+            break;
+            i = 0;
+        }
+        continue;
+        sign(i);
+    }
+    {
+        return i;
+    }
+    i = 2;
+    return i;
+}
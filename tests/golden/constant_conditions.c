@@ -0,0 +1,17 @@
+const int DEBUG = 0;
+
+int main() {
+    int i = 0;
+    if (DEBUG) putint(i);
+    if (DEBUG + 1) i = i + 1; else i = 9;
+    while (0) i = 7;
+    while (1) {
+        i = i + 1;
+        if (i > 5) break;
+    }
+    while (!DEBUG) {
+        i = i + 2;
+        if (i > 20) return i;
+    }
+    return 99;
+}
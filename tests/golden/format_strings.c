@@ -0,0 +1,8 @@
+// The same literal is one global, the values after the format are passed as they are
+int main() {
+    int x = getint();
+    putf("x = %d, c = %c\n", x, x + 65);
+    putf("x = %d, c = %c\n", 0, 66);
+    putf("100%% \"done\"\n");
+    return 0;
+}
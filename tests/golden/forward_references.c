@@ -0,0 +1,17 @@
+// `even` calls `odd`, defined after it, and `main` uses a global declared after it
+int even(int n) {
+    if (n == 0) return 1;
+    return odd(n - 1);
+}
+
+int odd(int n) {
+    if (n == 0) return 0;
+    return even(n - 1);
+}
+
+int main() {
+    count = 3;
+    return even(10) + count;
+}
+
+int count;
@@ -0,0 +1,12 @@
+int main() {
+    int x = getint();
+    if (x > 0) {
+        x = 1;
+    } else if (x < 0) {
+        x = 2;
+    } else {
+        x = 3;
+    }
+    if (x == 1) putint(x);
+    return x;
+}
@@ -0,0 +1,15 @@
+// `b` is assigned and keeps a slot, `a` is only hidden by a variable assigned in its place
+int f(int a, int b) {
+    int c = a;
+    {
+        int a = 1;
+        a = a + c;
+        c = a;
+    }
+    b = b + c;
+    return a + b;
+}
+
+int main() {
+    return f(1, 2);
+}
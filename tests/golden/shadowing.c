@@ -0,0 +1,24 @@
+int x = 10;
+
+int f(int x) {
+    x = x + 1;
+    return x;
+}
+
+int main() {
+    int x = 1;
+    {
+        int x = 2;
+        {
+            int x = 3;
+            putint(x);
+        }
+        putint(x);
+    }
+    {
+        int x = 4;
+        int y = x;
+        putint(y);
+    }
+    return f(x);
+}
@@ -0,0 +1,7 @@
+int f(int x) { putint(x); return x; }
+int main() {
+    int a = f(0) && f(1);
+    int b = f(1) || f(2);
+    if (a || !b && f(3)) return 1;
+    return a + b;
+}
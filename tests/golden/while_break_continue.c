@@ -0,0 +1,10 @@
+int main() {
+    int i = 0, sum = 0;
+    while (i < 10) {
+        i = i + 1;
+        if (i % 2 == 0) continue;
+        if (i > 7) break;
+        sum = sum + i;
+    }
+    return sum;
+}
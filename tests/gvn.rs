@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use koopa::front::Driver;
+use koopa::ir::Program;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::common::value_number::ExprKey;
+use sysy_compiler::ir::builder::{func, BlockBuilder, ProgramBuilder};
+use sysy_compiler::ir::KoopaGenerator;
+use sysy_compiler::opt::analysis::AnalysisManager;
+use sysy_compiler::opt::gvn::GlobalValueNumberingPass;
+
+fn koopa_text(program: &Program) -> String {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer()).unwrap()
+}
+
+fn gvn(mut program: Program) -> String {
+    let mut analyses = AnalysisManager::new();
+    for function in program.func_layout().to_vec() {
+        if program.func(function).layout().entry_bb().is_some() {
+            analyses.run_pass(&mut GlobalValueNumberingPass::new(), &mut program, function, &mut Session::new()).unwrap();
+        }
+    }
+    koopa_text(&program)
+}
+
+// if (x < y) { ... } else { ... }, both branches continuing to `end`
+fn diamond(then_body: fn(&BlockBuilder), else_body: fn(&BlockBuilder)) -> Program {
+    ProgramBuilder::new()
+        .func(func("f").param("x").param("y")
+            .block("entry", |b| {
+                b.store(b.add(b.param("x"), b.param("y")), b.alloc("r"));
+                b.branch(b.lt(b.param("x"), b.param("y")), "then", "else");
+            })
+            .block("then", move |b| {
+                then_body(b);
+                b.jump("end");
+            })
+            .block("else", move |b| {
+                else_body(b);
+                b.jump("end");
+            })
+            .block("end", |b| {
+                b.ret(b.load(b.local("r")));
+            }))
+        .build()
+}
+
+#[test]
+fn dominating_computations_are_reused() {
+    let program = diamond(
+        |b| b.store(b.add(b.param("y"), b.param("x")), b.local("r")),
+        |b| b.store(b.lt(b.param("x"), b.param("y")), b.local("r")),
+    );
+    assert_eq!(gvn(program), "\
+fun @f(@x: i32, @y: i32): i32 {
+%entry:
+  %0 = add @x, @y
+  @r = alloc i32
+  store %0, @r
+  %1 = lt @x, @y
+  br %1, %then, %else
+
+%then:
+  store %0, @r
+  jump %end
+
+%else:
+  store %1, @r
+  jump %end
+
+%end:
+  %2 = load @r
+  ret %2
+}
+");
+}
+
+#[test]
+fn sibling_blocks_are_not_merged() {
+    let program = diamond(
+        |b| b.store(b.sub(b.param("x"), b.int(1)), b.local("r")),
+        |b| b.store(b.sub(b.param("x"), b.int(1)), b.local("r")),
+    );
+    let text = gvn(program);
+    assert_eq!(text.matches("sub @x, 1").count(), 2, "{}", text);
+}
+
+#[test]
+fn loads_see_stores_until_a_call() {
+    let program = ProgramBuilder::new()
+        .global("g", 0)
+        .declare("putint", 1, false)
+        .func(func("main").block("entry", |b| {
+            b.store(b.int(3), b.global("g"));
+            let first = b.load(b.global("g"));
+            let second = b.load(b.global("g"));
+            b.call("putint", &[b.add(first, second)]);
+            b.ret(b.load(b.global("g")));
+        }))
+        .build();
+    assert_eq!(gvn(program), "\
+global @g = alloc i32, 0
+
+decl @putint(i32)
+
+fun @main(): i32 {
+%entry:
+  store 3, @g
+  %0 = add 3, 3
+  call @putint(%0)
+  %1 = load @g
+  ret %1
+}
+");
+}
+
+#[test]
+fn locals_whose_address_stays_put_survive_calls() {
+    let program = ProgramBuilder::new()
+        .global("g", 0)
+        .declare("putint", 1, false)
+        .func(func("main").block("entry", |b| {
+            b.store(b.int(1), b.alloc("x"));
+            b.store(b.int(2), b.global("g"));
+            b.call("putint", &[b.int(0)]);
+            b.ret(b.add(b.load(b.local("x")), b.load(b.global("g"))));
+        }))
+        .build();
+    let text = gvn(program);
+    assert!(text.contains("  call @putint(0)\n  %0 = load @g\n  %1 = add 1, %0\n"), "{}", text);
+}
+
+#[test]
+fn stores_through_pointers_only_clobber_what_they_may_point_to() {
+    let program = Driver::from("
+global @g = alloc i32, 0
+
+decl @keep(*i32)
+
+fun @f(%p: *i32): i32 {
+%entry:
+  @a = alloc [i32, 2]
+  @b = alloc [i32, 2]
+  %a0 = getelemptr @a, 0
+  %a1 = getelemptr @a, 1
+  store 1, %a0
+  store 2, %a1
+  store 3, @g
+  %b0 = getelemptr @b, 0
+  call @keep(%b0)
+  store 4, %b0
+  store 5, %p
+  %x = load %a0
+  %y = load @g
+  %z = load %b0
+  %s = add %x, %y
+  %t = add %s, %z
+  ret %t
+}
+").generate_program().unwrap();
+    let text = gvn(program);
+    // `@a` is never handed out and `%a1` is another element, while `%p` may point to `@g` or into `@b`
+    assert!(text.contains("  store 5, %p\n  %y = load @g\n  %z = load %b0\n  %s = add 1, %y\n"), "{}", text);
+}
+
+#[test]
+fn expression_keys_ignore_the_order_of_commutative_operands() {
+    let program = Driver::from(r#"
+global @g = alloc [i32, 4], zeroinit
+
+fun @f(@x: i32, @y: i32): i32 {
+%entry:
+  %0 = add @x, 1
+  %1 = add 1, @x
+  %2 = sub @x, @y
+  %3 = sub @y, @x
+  %4 = getelemptr @g, 2
+  %5 = getelemptr @g, 2
+  %6 = eq @x, @y
+  ret %0
+}
+"#.to_string()).generate_program().unwrap();
+    let func_data = program.funcs().values().next().unwrap();
+    let insts: Vec<_> = func_data.layout().bbs().iter().next().unwrap().1.insts().keys().copied().collect();
+    let keys: Vec<_> = insts.iter().map(|&inst| ExprKey::of(func_data, inst)).collect();
+    assert_eq!(keys[0], keys[1]);
+    assert_ne!(keys[2], keys[3]);
+    assert_eq!(keys[4], keys[5]);
+    assert_ne!(keys[0], keys[6]);
+    assert_eq!(keys[7], None);
+    let distinct: HashSet<_> = keys.iter().flatten().collect();
+    assert_eq!(distinct.len(), 5);
+}
@@ -0,0 +1,139 @@
+use koopa::ir::BinaryOp;
+use sysy_compiler::backend::{self, BackendOptions};
+use sysy_compiler::backend::asm::AsmEmitter;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::interp;
+use sysy_compiler::ir::builder::{func, ProgramBuilder};
+use sysy_compiler::ir::{KoopaGenerator, Program};
+use sysy_compiler::opt::const_fold::ConstFoldPass;
+use sysy_compiler::opt::dead_code_elimination::DeadCodeEliminationPass;
+use sysy_compiler::opt::analysis::AnalysisManager;
+
+fn koopa_text(program: &Program) -> String {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer()).unwrap()
+}
+
+// Exit status and output of the program
+fn run(program: &Program) -> (i32, String) {
+    let mut output = Vec::new();
+    let status = interp::run(program, &mut "".as_bytes(), &mut output).unwrap();
+    (status, String::from_utf8(output).unwrap())
+}
+
+// sum = 0; i = 0; while (i < n) { sum = sum + i; i = i + 1; } putint(sum); return sum;
+fn sum_below(n: i32) -> Program {
+    ProgramBuilder::new()
+        .declare("putint", 1, false)
+        .func(func("sum").param("n")
+            .block("entry", |b| {
+                b.store(b.int(0), b.alloc("sum"));
+                b.store(b.int(0), b.alloc("i"));
+                b.jump("cond");
+            })
+            .block("cond", |b| {
+                let i = b.load(b.local("i"));
+                b.branch(b.lt(i, b.param("n")), "body", "end");
+            })
+            .block("body", |b| {
+                let i = b.load(b.local("i"));
+                b.store(b.add(b.load(b.local("sum")), i), b.local("sum"));
+                b.store(b.add(i, b.int(1)), b.local("i"));
+                b.jump("cond");
+            })
+            .block("end", |b| {
+                let sum = b.load(b.local("sum"));
+                b.call("putint", &[sum]);
+                b.ret(sum);
+            }))
+        .func(func("main").block("entry", move |b| {
+            b.ret(b.call("sum", &[b.int(n)]));
+        }))
+        .build()
+}
+
+#[test]
+fn builds_the_koopa_text() {
+    let program = ProgramBuilder::new()
+        .global("g", 7)
+        .func(func("main").block("entry", |b| {
+            b.ret(b.sub(b.load(b.global("g")), b.int(2)));
+        }))
+        .build();
+    assert_eq!(koopa_text(&program), "\
+global @g = alloc i32, 7
+
+fun @main(): i32 {
+%entry:
+  %0 = load @g
+  %1 = sub %0, 2
+  ret %1
+}
+");
+}
+
+#[test]
+fn loops_and_calls_run() {
+    assert_eq!(run(&sum_below(10)), (45, "45".to_string()));
+    assert_eq!(run(&sum_below(0)), (0, "0".to_string()));
+}
+
+#[test]
+fn dead_code_elimination_on_built_ir() {
+    let mut program = ProgramBuilder::new()
+        .func(func("main").param("x").block("entry", |b| {
+            let unused = b.add(b.param("x"), b.int(1));
+            b.sub(unused, b.int(2));
+            b.ret(b.param("x"));
+        }))
+        .build();
+    let main = program.func_layout()[0];
+    AnalysisManager::new().run_pass(&mut DeadCodeEliminationPass::new(), &mut program, main, &mut Session::new()).unwrap();
+    let func_data = program.func(main);
+    let entry = func_data.layout().entry_bb().unwrap();
+    assert_eq!(func_data.layout().bbs().node(&entry).unwrap().insts().len(), 1);
+}
+
+#[test]
+fn constant_folding_on_built_ir() {
+    let mut program = ProgramBuilder::new()
+        .func(func("main").param("x").block("entry", |b| {
+            let five = b.sub(b.add(b.int(2), b.int(4)), b.int(1));
+            let zero = b.binary(BinaryOp::Div, b.int(1), b.int(0));
+            b.ret(b.add(b.add(b.param("x"), five), zero));
+        }))
+        .build();
+    let main = program.func_layout()[0];
+    AnalysisManager::new().run_pass(&mut ConstFoldPass::new(), &mut program, main, &mut Session::new()).unwrap();
+    assert_eq!(koopa_text(&program), "\
+fun @main(@x: i32): i32 {
+%entry:
+  %0 = div 1, 0
+  %1 = add @x, 5
+  %2 = add %1, %0
+  ret %2
+}
+");
+}
+
+#[test]
+fn void_functions_and_the_backend() {
+    let program = ProgramBuilder::new()
+        .declare("putch", 1, false)
+        .func(func("newline").void().block("entry", |b| {
+            b.call("putch", &[b.int(10)]);
+            b.ret_void();
+        }))
+        .func(func("main").block("entry", |b| {
+            b.call("newline", &[]);
+            b.ret(b.int(0));
+        }))
+        .build();
+    assert_eq!(run(&program), (0, "\n".to_string()));
+
+    let mut asm = Vec::new();
+    backend::generate_asm(&program, &BackendOptions::default()).emit(&mut asm).unwrap();
+    let asm = String::from_utf8(asm).unwrap();
+    assert!(asm.contains("call newline") && asm.contains("call putch"), "{}", asm);
+}
@@ -0,0 +1,103 @@
+use sysy_compiler::interp;
+use sysy_compiler::opt::OptLevel;
+use sysy_compiler::Compiler;
+
+fn koopa(source: &str) -> String {
+    Compiler::new().opt_level(OptLevel::O2).compile_to_koopa(source).unwrap().output
+}
+
+fn run(source: &str, level: OptLevel, input: &str) -> (String, i32) {
+    let program = Compiler::new().opt_level(level).compile_to_program(source).unwrap().output;
+    let mut output = Vec::new();
+    let status = interp::run(&program.borrow(), &mut input.as_bytes(), &mut output).unwrap();
+    (String::from_utf8(output).unwrap(), status)
+}
+
+#[test]
+fn constant_loops_are_unrolled_fully() {
+    let source = "
+int main() {
+  int i = 0;
+  int s = 0;
+  while (i < 5) { s = s + i * i; i = i + 1; }
+  i = 9;
+  while (i >= 0) { s = s + i; i = i - 2; }
+  return s;
+}
+";
+    let koopa = koopa(source);
+    assert!(!koopa.contains("br "), "{}", koopa);
+    assert_eq!(run(source, OptLevel::O2, "").1, 30 + 25);
+}
+
+#[test]
+fn counted_loops_are_unrolled_partially() {
+    let source = "
+int g;
+int main() {
+  int n = getint();
+  int s = 0;
+  int j = 0;
+  while (j < n) { s = s + j; if (s > 1000) break; j = j + 1; }
+  int k = n;
+  while (k > 3) { s = s * 2 % 1007; k = k - 3; }
+  g = n;
+  j = 1;
+  while (j <= g) { s = s + g; j = j + 2; }
+  j = 2147483600;
+  while (j < 2147483640) { s = s + 1; j = j + 7; }
+  putint(s);
+  return s;
+}
+";
+    let koopa = koopa(source);
+    assert_eq!(koopa.matches("_unrolled:").count(), 3, "{}", koopa);
+    for n in [0, 1, 2, 3, 4, 5, 7, 8, 13, 50, 100] {
+        let input = n.to_string();
+        assert_eq!(run(source, OptLevel::O2, &input), run(source, OptLevel::O0, &input), "n = {}", n);
+    }
+}
+
+#[test]
+fn loops_with_continue_are_left_alone() {
+    let source = "
+int main() {
+  int n = getint();
+  int s = 0;
+  int i = 0;
+  while (i < n) {
+    i = i + 1;
+    if (i % 3 == 0) continue;
+    s = s + i;
+  }
+  return s;
+}
+";
+    let koopa = koopa(source);
+    assert!(!koopa.contains("_unrolled") && !koopa.contains("_0:"), "{}", koopa);
+    assert_eq!(run(source, OptLevel::O2, "10"), run(source, OptLevel::O0, "10"));
+}
+
+// The exit holds the temporary of `&&`, the copies are laid out after the loop rather than
+// between the exit and the blocks using it
+#[test]
+fn copies_are_laid_out_after_the_loop() {
+    let source = "int main(){int a=getint();int i=0;while(i<3){if(a>10)a=a-1;i=i+1;} if(a>5&&getint())a=1;return a;}";
+    Compiler::new().opt_level(OptLevel::O2).compile_to_riscv(source).unwrap();
+    let koopa = koopa(source);
+    assert!(koopa.find("%end0:") < koopa.find("%then1_0:") && koopa.find("%merge1_2:") < koopa.find("%logical_and_branch2:"), "{}", koopa);
+    for input in ["12 1", "12 0", "3"] {
+        assert_eq!(run(source, OptLevel::O2, input), run(source, OptLevel::O0, input), "input {}", input);
+    }
+}
+
+// After simplify-cfg the exit of the inner loop is a merge block laid out before the block
+// defining `i`, which the copies must follow
+#[test]
+fn copies_follow_the_definitions_of_the_loop() {
+    let source = "int main(){int a=getint(); if(a){putint(1);}else{if(a+1&&a){int i=1;while(i<5){putint(i);i=i+1;}}} return 0;}";
+    Compiler::new().opt_level(OptLevel::O2).compile_to_riscv(source).unwrap();
+    for input in ["0", "1", "-1"] {
+        assert_eq!(run(source, OptLevel::O2, input), run(source, OptLevel::O0, input), "input {}", input);
+    }
+}
@@ -0,0 +1,68 @@
+use sysy_compiler::interp;
+use sysy_compiler::opt::OptLevel;
+use sysy_compiler::Compiler;
+
+// Sums the remainders of -20..40 by a divisor read from the input, then by a global
+// holding it, which only stays unchanged while the loop calls nothing
+const REMAINDERS: &str = "
+int g;
+int main() {
+  int n = getint();
+  int s = 0;
+  int i = -20;
+  while (i < 40) {
+    s = s + i % n;
+    if (i % n == 1) s = s + 7;
+    i = i + 1;
+  }
+  putint(s);
+  g = n;
+  i = -9;
+  while (i < 9) { s = s * 3 + i % g; i = i + 1; }
+  return s;
+}
+";
+
+fn run(source: &str, level: OptLevel, input: &str) -> (String, i32) {
+    let program = Compiler::new().opt_level(level).compile_to_program(source).unwrap().output;
+    let mut output = Vec::new();
+    let status = interp::run(&program.borrow(), &mut input.as_bytes(), &mut output).unwrap();
+    (String::from_utf8(output).unwrap(), status)
+}
+
+#[test]
+fn remainder_loops_are_versioned() {
+    let koopa = Compiler::new().opt_level(OptLevel::O2).compile_to_koopa(REMAINDERS).unwrap().output;
+    assert!(koopa.contains("%entry0_check:") && koopa.contains("%entry2_check:"), "{}", koopa);
+    let fast_path = &koopa[koopa.find("%body0_pow2:").unwrap()..];
+    let fast_path = &fast_path[..fast_path.find("\n\n").unwrap()];
+    // Nor a multiplication, which is a call without the M extension
+    assert!(!fast_path.contains("mod") && !fast_path.contains("mul"), "{}", fast_path);
+}
+
+#[test]
+fn both_versions_compute_the_remainder() {
+    for divisor in [8, 1, 16, 6, 3, -4, -1] {
+        let input = divisor.to_string();
+        assert_eq!(run(REMAINDERS, OptLevel::O2, &input), run(REMAINDERS, OptLevel::O0, &input), "divisor {}", divisor);
+    }
+}
+
+#[test]
+fn divisors_changed_in_the_loop_are_left_alone() {
+    let source = "
+int main() {
+  int n = 4;
+  int s = 0;
+  int i = 0;
+  while (i < 10) {
+    s = s + i % n;
+    n = n + 1;
+    i = i + 1;
+  }
+  return s;
+}
+";
+    let koopa = Compiler::new().opt_level(OptLevel::O2).compile_to_koopa(source).unwrap().output;
+    assert!(!koopa.contains("_check"), "{}", koopa);
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use koopa::ir::{FunctionData, Program, Type};
+use koopa::ir::builder::BasicBlockBuilder;
+use sysy_compiler::frontend::loops::{LoopContext, LoopStack};
+use sysy_compiler::frontend::lowering::{decay, index_steps, lower_type, IndexStep};
+use sysy_compiler::frontend::symbol::{SymbolTable, SymbolTableEntry};
+use sysy_compiler::frontend::types::Ty;
+use sysy_compiler::opt::OptLevel;
+use sysy_compiler::Compiler;
+use sysy_compiler::util::interner::Name;
+use sysy_compiler::util::name_generator::NameGenerator;
+
+// Compares the Koopa IR generated at -O0 for `tests/golden/<name>.c` with `<name>.koopa`
+// next to it. With `UPDATE_GOLDEN=1` in the environment the expected IR is written instead,
+// to be reviewed in the diff.
+fn check_golden(name: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let source = std::fs::read_to_string(dir.join(format!("{}.c", name))).unwrap();
+    let actual = match Compiler::new().opt_level(OptLevel::O0).compile_to_koopa(&source) {
+        Ok(compiled) => compiled.output,
+        Err(error) => panic!("{}", error.render(&format!("{}.c", name), &source)),
+    };
+    let golden = dir.join(format!("{}.koopa", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("no {}, run with UPDATE_GOLDEN=1 to create it", golden.display()));
+    assert!(actual == expected, "the IR of {}.c changed, run with UPDATE_GOLDEN=1 to accept it:\n{}", name, actual);
+}
+
+#[test]
+fn if_else() {
+    check_golden("if_else");
+}
+
+#[test]
+fn while_break_continue() {
+    check_golden("while_break_continue");
+}
+
+#[test]
+fn short_circuit() {
+    check_golden("short_circuit");
+}
+
+#[test]
+fn many_arguments() {
+    check_golden("many_arguments");
+}
+
+#[test]
+fn parameters() {
+    check_golden("parameters");
+}
+
+#[test]
+fn code_after_terminators() {
+    check_golden("code_after_terminators");
+}
+
+#[test]
+fn shadowing() {
+    check_golden("shadowing");
+}
+
+#[test]
+fn constant_conditions() {
+    check_golden("constant_conditions");
+}
+
+#[test]
+fn array_types_and_their_indexing() {
+    let matrix = Ty::Array(Box::new(Ty::Array(Box::new(Ty::Int), 3)), 2);
+    assert_eq!(matrix.to_string(), "int[2][3]");
+    assert_eq!(lower_type(&matrix).to_string(), "[[i32, 3], 2]");
+
+    assert_eq!(index_steps(&matrix, 2), Some((vec![IndexStep::GetElemPtr, IndexStep::GetElemPtr], Ty::Int)));
+    assert_eq!(index_steps(&matrix, 1), Some((vec![IndexStep::GetElemPtr], Ty::Array(Box::new(Ty::Int), 3))));
+    assert_eq!(index_steps(&matrix, 3), None);
+    // `int m[][3]` as a parameter
+    let param = decay(&matrix);
+    assert_eq!(param.to_string(), "int[3]*");
+    assert_eq!(index_steps(&param, 2), Some((vec![IndexStep::GetPtr, IndexStep::GetElemPtr], Ty::Int)));
+    assert_eq!(index_steps(&Ty::Pointer(Box::new(param.clone())), 2), None);
+    assert_eq!(decay(&Ty::Int), Ty::Int);
+}
+
+#[test]
+fn format_strings() {
+    check_golden("format_strings");
+}
+
+#[test]
+fn forward_references() {
+    check_golden("forward_references");
+}
+
+#[test]
+fn symbol_table_scopes() {
+    let mut table = SymbolTable::new();
+    let value = |table: &SymbolTable, ident| match table.lookup(ident) {
+        Some(SymbolTableEntry::Const(_, value)) => Some(value),
+        _ => None,
+    };
+    table.bind("x", SymbolTableEntry::Const("x".into(), 1)).unwrap();
+    table.enter_scope();
+    table.enter_scope();
+    assert_eq!(table.depth(), 2);
+    table.bind("x", SymbolTableEntry::Const("x".into(), 2)).unwrap();
+    assert!(table.bind("x", SymbolTableEntry::Const("x".into(), 3)).is_err());
+    // Under the inner `x`, and visible once it is gone
+    table.bind_root("y", SymbolTableEntry::Const("y".into(), 4)).unwrap();
+    assert!(table.bind_root("x", SymbolTableEntry::Const("x".into(), 5)).is_err());
+    assert_eq!((value(&table, "x"), value(&table, "y")), (Some(2), Some(4)));
+    table.exit_scope();
+    table.exit_scope();
+    assert_eq!((value(&table, "x"), value(&table, "y")), (Some(1), Some(4)));
+    assert_eq!(table.depth(), 0);
+}
+
+#[test]
+fn interned_names() {
+    let x = Name::intern("x");
+    assert_eq!(x, Name::intern(&String::from("x")));
+    assert_ne!(x, Name::intern("y"));
+    assert_eq!(x.as_str(), "x");
+    let mut generator = NameGenerator::new();
+    let group = generator.generate_group(&["%then", "%merge"]);
+    assert_eq!((group[0].as_str(), group[1].as_str()), ("%then0", "%merge0"));
+    assert_eq!(generator.generate("%end").to_string(), "%end1");
+}
+
+#[test]
+fn loop_stack_finds_labelled_loops() {
+    let mut program = Program::new();
+    let func = program.new_func(FunctionData::new("@f".into(), vec![], Type::get_unit()));
+    let mut block = || program.func_mut(func).dfg_mut().new_bb().basic_block(None);
+    let (outer_head, outer_end, inner_head, inner_end) = (block(), block(), block(), block());
+    let mut loops = LoopStack::default();
+    assert!(loops.find(None).is_none());
+    loops.push(LoopContext { label: Some(Name::intern("outer")), head: outer_head, end: outer_end });
+    loops.push(LoopContext { label: None, head: inner_head, end: inner_end });
+    assert_eq!(loops.find(None).map(|context| context.end), Some(inner_end));
+    assert_eq!(loops.find(Some(Name::intern("outer"))).map(|context| context.head), Some(outer_head));
+    assert!(loops.find(Some(Name::intern("missing"))).is_none());
+    loops.pop();
+    assert_eq!(loops.find(None).map(|context| context.head), Some(outer_head));
+}
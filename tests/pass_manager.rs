@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::interp;
+use sysy_compiler::ir::builder::{func, ProgramBuilder};
+use sysy_compiler::ir::{FunctionData, Program};
+use sysy_compiler::opt::analysis::{AnalysisManager, FunctionAnalyses};
+use sysy_compiler::opt::pass_manager::{ModulePass, Pass, PassManager, PassSpec, Pipeline};
+use sysy_compiler::opt::{self, OptError, OptLevel, OptLimits, OptPassFunction};
+use sysy_compiler::Compiler;
+
+#[test]
+fn pipelines_are_parsed_and_printed() {
+    let pipeline: Pipeline = " const-fold, (gvn ,(const-fold,dce)),dce".parse().unwrap();
+    assert_eq!(pipeline, Pipeline(vec![
+        PassSpec::Pass("const-fold"),
+        PassSpec::FixedPoint(vec![
+            PassSpec::Pass("gvn"),
+            PassSpec::FixedPoint(vec![PassSpec::Pass("const-fold"), PassSpec::Pass("dce")]),
+        ]),
+        PassSpec::Pass("dce"),
+    ]));
+    assert_eq!(pipeline.to_string(), "const-fold,(gvn,(const-fold,dce)),dce");
+    assert_eq!(opt::pipeline(OptLevel::O0), Pipeline(Vec::new()));
+    let o2 = opt::pipeline(OptLevel::O2);
+    assert_eq!(o2.to_string().parse::<Pipeline>().unwrap(), o2);
+
+    for (text, error) in [
+        ("gvn,constfold", "unknown pass `constfold`"),
+        ("gvn,,dce", "missing pass name"),
+        ("", "missing pass name"),
+        ("(gvn,dce", "unclosed `(`"),
+        ("gvn)", "unbalanced `)`"),
+        ("()", "missing pass name"),
+        ("(gvn)dce", "separated by `,`"),
+    ] {
+        let message = text.parse::<Pipeline>().unwrap_err();
+        assert!(message.contains(error), "`{}`: {}", text, message);
+    }
+}
+
+#[test]
+fn required_passes_are_added_once() {
+    let pipeline: Pipeline = "unreachable-blocks,(gvn,unreachable-blocks),dce".parse().unwrap();
+    assert_eq!(PassManager::from_pipeline(&pipeline).pipeline().to_string(), "const-fold,unreachable-blocks,(gvn,unreachable-blocks),dce");
+    let pipeline: Pipeline = "(gvn,unreachable-blocks)".parse().unwrap();
+    assert_eq!(PassManager::from_pipeline(&pipeline).pipeline().to_string(), "(gvn,const-fold,unreachable-blocks)");
+}
+
+// Reports a change on its first two runs, counting them
+struct ChangesTwice {
+    runs: Rc<Cell<usize>>,
+}
+
+impl OptPassFunction for ChangesTwice {
+    fn name(&self) -> &'static str {
+        "changes-twice"
+    }
+
+    fn run_on(&mut self, _func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        self.runs.set(self.runs.get() + 1);
+        Ok(self.runs.get() <= 2)
+    }
+
+    fn requires(&self) -> &'static [&'static str] {
+        &["dce"]
+    }
+}
+
+#[test]
+fn fixed_point_groups_repeat_until_nothing_changes() {
+    let runs = Rc::new(Cell::new(0));
+    let mut manager = PassManager::new();
+    manager.add(Pass::FixedPoint(vec![Pass::Function(Box::new(ChangesTwice { runs: runs.clone() }))]));
+    assert_eq!(manager.pipeline().to_string(), "(dce,changes-twice)");
+    let mut program = ProgramBuilder::new()
+        .func(func("main").block("entry", |b| b.ret(b.add(b.int(1), b.int(2)))))
+        .build();
+    manager.run(&mut program, &OptLimits::default(), &mut Session::new()).unwrap();
+    // Twice with a change, and once more to find none
+    assert_eq!(runs.get(), 3);
+}
+
+// Removes the functions never called, but `main`
+struct RemoveUncalled;
+
+impl ModulePass for RemoveUncalled {
+    fn name(&self) -> &'static str {
+        "remove-uncalled"
+    }
+
+    fn run(&mut self, program: &mut Program, analyses: &mut AnalysisManager, _session: &mut Session) -> Result<bool, OptError> {
+        let call_graph = analyses.call_graph(program);
+        let uncalled: Vec<_> = program.func_layout().iter().copied()
+            .filter(|&func| program.func(func).name() != "@main" && call_graph.callers(func).is_empty())
+            .collect();
+        for &func in uncalled.iter() {
+            program.remove_func(func);
+        }
+        Ok(!uncalled.is_empty())
+    }
+}
+
+#[test]
+fn module_passes_see_the_whole_program() {
+    let mut program = ProgramBuilder::new()
+        .func(func("unused").block("entry", |b| b.ret(b.int(1))))
+        .func(func("used").block("entry", |b| b.ret(b.int(2))))
+        .func(func("main").block("entry", |b| {
+            let value = b.call("used", &[]);
+            b.ret(b.add(value, b.int(3)))
+        }))
+        .build();
+    let mut manager = PassManager::new();
+    manager.add(Pass::Module(Box::new(RemoveUncalled)));
+    manager.add(Pass::Function(Box::new(opt::const_fold::ConstFoldPass::new())));
+    manager.run(&mut program, &OptLimits::default(), &mut Session::new()).unwrap();
+    assert_eq!(program.func_layout().len(), 2);
+    assert_eq!(interp::run(&program, &mut "".as_bytes(), &mut Vec::new()).unwrap(), 5);
+}
+
+#[test]
+fn passes_replace_those_of_the_level() {
+    let source = "int main() { if (0) { putint(1); } return 2 + 3; }";
+    let none = Compiler::new().passes(Pipeline(Vec::new())).compile_to_koopa(source).unwrap().output;
+    // The frontend leaves out the arm that never runs, the passes fold the rest
+    assert!(!none.contains("br ") && !none.contains("putint(1)") && none.contains("add 2, 3"), "{}", none);
+    let pipeline = "unreachable-blocks".parse().unwrap();
+    let folded = Compiler::new().opt_level(OptLevel::O0).passes(pipeline).compile_to_koopa(source).unwrap().output;
+    assert!(!folded.contains("br ") && !folded.contains("add") && !folded.contains("putint(1)"), "{}", folded);
+}
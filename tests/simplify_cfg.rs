@@ -0,0 +1,134 @@
+use koopa::ir::Program;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::ir::builder::{func, ProgramBuilder};
+use sysy_compiler::ir::KoopaGenerator;
+use sysy_compiler::opt::analysis::AnalysisManager;
+use sysy_compiler::opt::simplify_cfg::SimplifyCfgPass;
+
+fn koopa_text(program: &Program) -> String {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer()).unwrap()
+}
+
+fn simplify(mut program: Program) -> String {
+    let mut analyses = AnalysisManager::new();
+    for function in program.func_layout().to_vec() {
+        if program.func(function).layout().entry_bb().is_some() {
+            analyses.run_pass(&mut SimplifyCfgPass::new(), &mut program, function, &mut Session::new()).unwrap();
+        }
+    }
+    koopa_text(&program)
+}
+
+#[test]
+fn jumps_through_empty_blocks_are_threaded() {
+    let program = ProgramBuilder::new()
+        .func(func("f").param("x")
+            .block("entry", |b| {
+                b.store(b.param("x"), b.alloc("r"));
+                b.branch(b.lt(b.param("x"), b.int(0)), "then", "forward");
+            })
+            .block("then", |b| {
+                b.store(b.int(0), b.local("r"));
+                b.jump("forward");
+            })
+            .block("forward", |b| b.jump("again"))
+            .block("again", |b| b.jump("end"))
+            .block("end", |b| b.ret(b.load(b.local("r")))))
+        .build();
+    assert_eq!(simplify(program), "\
+fun @f(@x: i32): i32 {
+%entry:
+  @r = alloc i32
+  store @x, @r
+  %0 = lt @x, 0
+  br %0, %then, %end
+
+%then:
+  store 0, @r
+  jump %end
+
+%end:
+  %1 = load @r
+  ret %1
+}
+");
+}
+
+#[test]
+fn blocks_are_merged_into_their_only_predecessor() {
+    let program = ProgramBuilder::new()
+        .func(func("f").param("x")
+            .block("entry", |b| {
+                b.store(b.param("x"), b.alloc("r"));
+                b.jump("next");
+            })
+            .block("next", |b| {
+                b.store(b.add(b.load(b.local("r")), b.int(1)), b.local("r"));
+                b.jump("last");
+            })
+            .block("last", |b| b.ret(b.load(b.local("r")))))
+        .build();
+    assert_eq!(simplify(program), "\
+fun @f(@x: i32): i32 {
+%entry:
+  @r = alloc i32
+  store @x, @r
+  %0 = load @r
+  %1 = add %0, 1
+  store %1, @r
+  %2 = load @r
+  ret %2
+}
+");
+}
+
+#[test]
+fn empty_loops_are_kept() {
+    // while (1) {}, the header forwarding to the body and back
+    let program = ProgramBuilder::new()
+        .func(func("f")
+            .block("entry", |b| b.jump("header"))
+            .block("header", |b| b.jump("body"))
+            .block("body", |b| b.jump("header")))
+        .build();
+    assert_eq!(simplify(program), "\
+fun @f(): i32 {
+%entry:
+  jump %body
+
+%body:
+  jump %body
+}
+");
+}
+
+#[test]
+fn blocks_laid_out_before_their_predecessor_are_kept() {
+    // Merging `s` into `b` would define `@t` after its use in `use`
+    let program = ProgramBuilder::new()
+        .func(func("f").param("x")
+            .block("entry", |b| {
+                b.store(b.param("x"), b.alloc("r"));
+                b.branch(b.param("x"), "b", "c");
+            })
+            .block("s", |b| {
+                b.store(b.int(1), b.alloc("t"));
+                b.branch(b.load(b.local("r")), "use", "end");
+            })
+            .block("use", |b| b.ret(b.load(b.local("t"))))
+            .block("end", |b| b.ret(b.int(0)))
+            .block("c", |b| {
+                b.store(b.int(0), b.local("r"));
+                b.jump("b");
+            })
+            .block("b", |b| {
+                b.store(b.int(2), b.local("r"));
+                b.jump("s");
+            }))
+        .build();
+    let text = simplify(program);
+    assert!(text.find("%s:").unwrap() < text.find("%use:").unwrap(), "{}", text);
+    assert!(text.find("@t = alloc i32").unwrap() < text.find("%use:").unwrap(), "{}", text);
+}
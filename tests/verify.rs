@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use koopa::ir::builder_traits::*;
+use sysy_compiler::common::session::Session;
+use sysy_compiler::frontend::{self, comments::IRComments};
+use sysy_compiler::ir::builder::{func, ProgramBuilder};
+use sysy_compiler::ir::round_trip;
+use sysy_compiler::ir::{FunctionData, Program, Value};
+use sysy_compiler::opt::analysis::FunctionAnalyses;
+use sysy_compiler::opt::pass_manager::{Pass, PassManager};
+use sysy_compiler::opt::verify::verify_program;
+use sysy_compiler::opt::{OptError, OptLevel, OptLimits, OptPassFunction};
+use sysy_compiler::Compiler;
+
+fn error_of(program: &Program) -> String {
+    verify_program(program).expect_err("the program is expected to be invalid")
+}
+
+#[test]
+fn compiled_programs_are_valid() {
+    let source = "
+int f(int a) { if (a) { return 1; } }
+void g(int x) { putint(x); }
+int main() {
+  int i = 0;
+  while (i < 10) { g(f(i)); i = i + 1; if (i > 5) break; }
+  return 0;
+}
+";
+    let program = Compiler::new().compile_to_program(source).unwrap().output;
+    assert_eq!(verify_program(&program.borrow()), Ok(()));
+}
+
+// Arms ending with a terminator do not jump on to the merge block
+#[test]
+fn generated_arms_end_in_their_own_terminator() {
+    let source = "
+int f(int a) {
+  while (a > 0) {
+    if (a == 3) break; else if (a == 5) return a; else { a = a - 1; continue; }
+  }
+  if (a) return 1; else return 2;
+}
+";
+    let comments = Rc::new(RefCell::new(IRComments::new(false)));
+    let ast = frontend::parser::parse(source).unwrap();
+    let (program, _) = frontend::generate_ir(&[ast], &comments).unwrap();
+    assert_eq!(verify_program(&program.borrow()), Ok(()));
+}
+
+#[test]
+fn blocks_end_in_their_only_terminator() {
+    let program = ProgramBuilder::new()
+        .func(func("f").block("entry", |b| b.store(b.int(1), b.alloc("x"))))
+        .build();
+    assert_eq!(error_of(&program), "in `@f`, block `%entry` does not end in a terminator");
+
+    let program = ProgramBuilder::new()
+        .func(func("f")
+            .block("entry", |b| {
+                b.ret(b.int(0));
+                b.jump("end");
+            })
+            .block("end", |b| b.ret(b.int(1))))
+        .build();
+    assert_eq!(error_of(&program), "in `@f`, block `%entry` has a terminator before its end");
+}
+
+#[test]
+fn definitions_dominate_their_uses() {
+    let program = ProgramBuilder::new()
+        .func(func("f").param("x")
+            .block("entry", |b| b.branch(b.param("x"), "then", "end"))
+            .block("then", |b| {
+                b.store(b.param("x"), b.alloc("y"));
+                b.jump("end");
+            })
+            .block("end", |b| b.ret(b.load(b.local("y")))))
+        .build();
+    assert_eq!(error_of(&program), "in `@f`, a `load` in block `%end` uses `@y` of block `%then`, which does not dominate it");
+}
+
+#[test]
+fn calls_match_their_callee() {
+    let program = ProgramBuilder::new()
+        .declare("putint", 1, false)
+        .func(func("f").block("entry", |b| {
+            b.call("putint", &[b.int(1), b.int(2)]);
+            b.ret(b.int(0));
+        }))
+        .build();
+    assert_eq!(error_of(&program), "in `@f`, a `call` in block `%entry` gives 2 arguments to `@putint`, which takes 1");
+}
+
+// Takes the terminator out of the entry block
+struct DropTerminator;
+
+impl OptPassFunction for DropTerminator {
+    fn name(&self) -> &'static str {
+        "drop-terminator"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let entry = func_data.layout().entry_bb().unwrap();
+        let &last = func_data.layout().bbs().node(&entry).unwrap().insts().back_key().unwrap();
+        func_data.layout_mut().bb_mut(entry).insts_mut().remove(&last);
+        Ok(true)
+    }
+}
+
+#[test]
+fn passes_breaking_the_ir_are_named() {
+    let mut program = ProgramBuilder::new()
+        .func(func("main").block("entry", |b| {
+            b.store(b.int(1), b.alloc("x"));
+            b.ret(b.int(0));
+        }))
+        .build();
+    let mut manager = PassManager::new();
+    manager.add(Pass::Function(Box::new(DropTerminator)));
+    let error = manager.run(&mut program, &OptLimits::default(), &mut Session::new()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid IR after `drop-terminator`: in `@main`, block `%entry` does not end in a terminator");
+}
+
+// Moves the instructions of the second block to a new block at the end of the layout, which
+// the second block jumps to
+struct SinkBlock;
+
+impl OptPassFunction for SinkBlock {
+    fn name(&self) -> &'static str {
+        "sink-block"
+    }
+
+    fn run_on(&mut self, func_data: &mut FunctionData, _analyses: &mut FunctionAnalyses, _session: &mut Session) -> Result<bool, OptError> {
+        let bb = func_data.layout().bbs().keys().copied().nth(1).unwrap();
+        let sunk = func_data.dfg_mut().new_bb().basic_block(Some("%sunk".into()));
+        func_data.layout_mut().bbs_mut().push_key_back(sunk).unwrap();
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            func_data.layout_mut().bb_mut(sunk).insts_mut().push_key_back(inst).unwrap();
+        }
+        let jump = func_data.dfg_mut().new_value().jump(sunk);
+        func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+        Ok(true)
+    }
+}
+
+// The backend generates the blocks in layout order, which must have the definitions first
+#[test]
+fn definitions_are_laid_out_before_their_uses() {
+    let mut program = ProgramBuilder::new()
+        .func(func("main").param("x")
+            .block("entry", |b| b.jump("define"))
+            .block("define", |b| {
+                b.store(b.param("x"), b.alloc("y"));
+                b.jump("use");
+            })
+            .block("use", |b| b.ret(b.load(b.local("y")))))
+        .build();
+    let mut manager = PassManager::new();
+    manager.add(Pass::Function(Box::new(SinkBlock)));
+    let error = manager.run(&mut program, &OptLimits::default(), &mut Session::new()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid IR after `sink-block`: in `@main`, a `load` in block `%use` uses `@y` of block `%sunk`, which is laid out after it");
+}
+
+// Every pass at -O2 keeps the definitions laid out first, on loops nested in the branches
+// simplify-cfg merges, which lay out the exits of the loops before the blocks entering them
+#[test]
+fn optimized_nested_loops_are_laid_out_in_order() {
+    let shapes = [
+        "{}",
+        "if (a) { putint(1); } else { {} }",
+        "if (a + 1 && a) { {} }",
+        "if (a || b) { {} } else { putint(2); }",
+        "while (b < 3) { {} b = b + 1; }",
+    ];
+    let loops = [
+        "int i = 1; while (i < 5) { putint(i); i = i + 1; }",
+        "int i = 0; while (i < a) { s = s + i % d; i = i + 1; }",
+        "int i = b; while (i < 9) { if (i > a) s = s + 1; i = i + 1; }",
+    ];
+    for outer in shapes {
+        for inner in shapes {
+            for l in loops {
+                let body = outer.replacen("{}", &inner.replacen("{}", &format!("{{ {} }}", l), 1), 1);
+                let source = format!("int main() {{ int a = getint(), b = getint(), s = 0, d = getint(); {} putint(s); return 0; }}", body);
+                let program = Compiler::new().opt_level(OptLevel::O2).compile_to_program(&source)
+                    .unwrap_or_else(|error| panic!("{}:\n{}", error, source)).output;
+                assert_eq!(verify_program(&program.borrow()), Ok(()), "{}", source);
+            }
+        }
+    }
+}
+
+#[test]
+fn generated_ir_reads_back_as_itself() {
+    let source = "
+int g = 3, h;
+int f(int a) { if (a) { return 1; } }
+int main() {
+  int i = 0;
+  while (i < 10) { i = i + f(i) * g; if (i > 5) break; }
+  h = i;
+  return h;
+}
+";
+    let comments = Rc::new(RefCell::new(IRComments::new(false)));
+    let ast = frontend::parser::parse(source).unwrap();
+    let (program, _) = frontend::generate_ir(&[ast], &comments).unwrap();
+    assert_eq!(round_trip::check(&program.borrow()), Ok(()));
+
+    // Unreachable blocks are left out by the parser
+    let program = ProgramBuilder::new()
+        .func(func("f")
+            .block("entry", |b| b.jump("end"))
+            .block("dead", |b| b.jump("end"))
+            .block("end", |b| b.ret(b.int(0))))
+        .build();
+    assert_eq!(round_trip::check(&program), Ok(()));
+}
+
+#[test]
+fn names_the_parser_rejects_fail_the_round_trip() {
+    let program = ProgramBuilder::new()
+        .func(func("f").block("entry point", |b| b.ret(b.int(0))))
+        .build();
+    assert_eq!(round_trip::check(&program), Err("the koopa parser rejects the text form of the IR".to_string()));
+}